@@ -1,4 +1,4 @@
-CREATE EXTENSION pgcrypto;
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
 
 CREATE TABLE campaign(
   id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
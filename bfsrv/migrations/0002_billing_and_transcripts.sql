@@ -0,0 +1,53 @@
+-- Databases created from the later schema.sql already have some of these objects,
+-- so every statement tolerates them.
+ALTER TABLE campaign
+  ADD COLUMN IF NOT EXISTS referral_bonus_amount decimal NOT NULL DEFAULT 0,
+  ADD COLUMN IF NOT EXISTS referral_bonus_percent decimal NOT NULL DEFAULT 0,
+  ADD COLUMN IF NOT EXISTS expires_at timestamp with time zone,
+  ADD COLUMN IF NOT EXISTS max_redemptions integer,
+  ADD COLUMN IF NOT EXISTS redemptions integer NOT NULL DEFAULT 0;
+
+ALTER TABLE "user"
+  ADD COLUMN IF NOT EXISTS referral_bonus_paid boolean NOT NULL DEFAULT false,
+  ADD COLUMN IF NOT EXISTS is_admin boolean NOT NULL DEFAULT false,
+  ADD COLUMN IF NOT EXISTS callback_secret text NOT NULL DEFAULT encode(gen_random_bytes(32), 'hex'),
+  DROP CONSTRAINT IF EXISTS user_referrer_fkey,
+  ADD CONSTRAINT user_referrer_fkey FOREIGN KEY(referrer) REFERENCES "user"(id) ON DELETE SET NULL;
+
+ALTER TABLE capability
+  ADD COLUMN IF NOT EXISTS max_segment_duration real,
+  ADD COLUMN IF NOT EXISTS segment_window_duration real;
+
+DROP INDEX IF EXISTS payment_from_user_idx;
+
+CREATE INDEX payment_from_user_idx ON payment(from_user, created_at, id);
+
+CREATE INDEX IF NOT EXISTS payment_pending_created_at_idx ON payment(created_at)
+WHERE
+  status IN ('new', 'approved');
+
+CREATE TABLE IF NOT EXISTS balance_adjustment(
+  id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  "user" uuid NOT NULL,
+  amount decimal NOT NULL,
+  reason text NOT NULL,
+  token uuid NOT NULL,
+  FOREIGN KEY("user") REFERENCES "user"(id),
+  FOREIGN KEY(token) REFERENCES token(id)
+);
+
+CREATE INDEX IF NOT EXISTS balance_adjustment_user_idx ON balance_adjustment("user");
+
+CREATE TABLE IF NOT EXISTS transcript(
+  id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  "user" uuid NOT NULL,
+  tariff text NOT NULL,
+  items jsonb NOT NULL,
+  total_seconds real NOT NULL,
+  total_cost decimal NOT NULL,
+  FOREIGN KEY("user") REFERENCES "user"(id)
+);
+
+CREATE INDEX IF NOT EXISTS transcript_user_idx ON transcript("user");
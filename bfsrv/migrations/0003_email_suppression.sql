@@ -0,0 +1,7 @@
+CREATE TABLE email_suppression(
+  email text NOT NULL,
+  reason text NOT NULL,
+  created_at timestamp with time zone NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX email_suppression_email_idx ON email_suppression(lower(email));
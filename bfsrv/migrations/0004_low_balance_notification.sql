@@ -0,0 +1,5 @@
+ALTER TABLE campaign
+  ADD COLUMN low_balance_threshold decimal;
+
+ALTER TABLE "user"
+  ADD COLUMN low_balance_notified_at timestamp with time zone;
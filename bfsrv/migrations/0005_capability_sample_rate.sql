@@ -0,0 +1,2 @@
+ALTER TABLE capability
+  ADD COLUMN sample_rate integer CHECK (sample_rate > 0);
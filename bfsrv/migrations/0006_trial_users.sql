@@ -0,0 +1,2 @@
+ALTER TABLE "user"
+  ADD COLUMN is_trial boolean NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+ALTER TABLE "user"
+  ADD COLUMN credit_limit decimal NOT NULL DEFAULT 0 CHECK (credit_limit >= 0);
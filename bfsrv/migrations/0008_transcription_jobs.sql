@@ -0,0 +1,31 @@
+CREATE TYPE job_status AS ENUM(
+  'queued',
+  'running',
+  'completed',
+  'failed'
+);
+
+-- Audio is kept only until a job is processed.
+CREATE TABLE job(
+  id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  updated_at timestamp with time zone NOT NULL DEFAULT now(),
+  "user" uuid NOT NULL,
+  tariff text NOT NULL,
+  lang text,
+  status job_status NOT NULL DEFAULT 'queued',
+  audio bytea,
+  items jsonb NOT NULL DEFAULT '[]',
+  total_seconds real,
+  total_cost decimal,
+  transcript uuid,
+  error jsonb,
+  FOREIGN KEY("user") REFERENCES "user"(id),
+  FOREIGN KEY(transcript) REFERENCES transcript(id)
+);
+
+CREATE INDEX job_user_idx ON job("user");
+
+CREATE INDEX job_status_idx ON job(status, created_at)
+WHERE
+  status IN ('queued', 'running');
@@ -0,0 +1,4 @@
+ALTER TYPE task_type ADD VALUE IF NOT EXISTS 'diarize';
+
+ALTER TABLE job
+  ADD COLUMN diarize boolean NOT NULL DEFAULT false;
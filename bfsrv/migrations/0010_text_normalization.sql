@@ -0,0 +1,5 @@
+CREATE TYPE text_casing AS ENUM('original', 'lower');
+
+ALTER TABLE job
+  ADD COLUMN punctuation boolean NOT NULL DEFAULT true,
+  ADD COLUMN casing text_casing NOT NULL DEFAULT 'original';
@@ -0,0 +1,18 @@
+-- Outcome of a streaming transcription session.
+CREATE TABLE session(
+  id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+  "user" uuid NOT NULL,
+  tariff text NOT NULL,
+  task task_type NOT NULL,
+  started_at timestamp with time zone NOT NULL,
+  ended_at timestamp with time zone NOT NULL,
+  total_seconds real NOT NULL,
+  total_cost decimal NOT NULL,
+  close_reason text NOT NULL,
+  nodes inet[] NOT NULL,
+  transcript uuid,
+  FOREIGN KEY("user") REFERENCES "user"(id),
+  FOREIGN KEY(transcript) REFERENCES transcript(id)
+);
+
+CREATE INDEX session_user_idx ON session("user", started_at);
@@ -0,0 +1,12 @@
+-- Balance credits of completed payments, each payment is credited at most once.
+CREATE TABLE ledger_entry(
+  id uuid PRIMARY KEY DEFAULT gen_random_uuid(),
+  created_at timestamp with time zone NOT NULL DEFAULT now(),
+  "user" uuid NOT NULL,
+  payment uuid NOT NULL UNIQUE,
+  amount decimal NOT NULL,
+  FOREIGN KEY("user") REFERENCES "user"(id),
+  FOREIGN KEY(payment) REFERENCES payment(id)
+);
+
+CREATE INDEX ledger_entry_user_idx ON ledger_entry("user");
@@ -0,0 +1,3 @@
+-- RMS energy relative to full scale below which speech edges are trimmed.
+ALTER TABLE capability
+  ADD COLUMN trim_threshold real CHECK (trim_threshold > 0 AND trim_threshold < 1);
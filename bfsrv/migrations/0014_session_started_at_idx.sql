@@ -0,0 +1,2 @@
+-- Speed up exporting sessions of all users within a time range.
+CREATE INDEX session_started_at_idx ON session(started_at);
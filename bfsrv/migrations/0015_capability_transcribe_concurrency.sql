@@ -0,0 +1,3 @@
+-- Number of speech segments of a session transcribed concurrently.
+ALTER TABLE capability
+  ADD COLUMN transcribe_concurrency integer CHECK (transcribe_concurrency > 0);
@@ -0,0 +1,4 @@
+-- Allocation preference of a node hosting a capability (higher weights win,
+-- equally weighted nodes are picked randomly).
+ALTER TABLE node_capability
+  ADD COLUMN weight integer NOT NULL DEFAULT 0;
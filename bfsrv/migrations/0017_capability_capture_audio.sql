@@ -0,0 +1,4 @@
+-- Sessions of tariffs with such capabilities get their audio captured
+-- (if the server has audio capturing configured).
+ALTER TABLE capability
+  ADD COLUMN capture_audio boolean NOT NULL DEFAULT false;
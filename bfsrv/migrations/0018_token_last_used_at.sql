@@ -0,0 +1,4 @@
+-- Existing tokens are considered used at the migration time
+-- (so enabling the inactivity expiry doesn't revoke them at once).
+ALTER TABLE token
+  ADD COLUMN last_used_at timestamptz NOT NULL DEFAULT now();
@@ -0,0 +1,3 @@
+-- Segmentation statistics of a session (absent for earlier sessions).
+ALTER TABLE session
+  ADD COLUMN segment_stats jsonb;
@@ -0,0 +1,4 @@
+-- Tasks of tariffs with such capabilities are placed only on nodes
+-- which host no capabilities beyond the ones of the task.
+ALTER TABLE capability
+  ADD COLUMN strict_placement boolean NOT NULL DEFAULT false;
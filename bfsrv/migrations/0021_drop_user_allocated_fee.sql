@@ -0,0 +1,3 @@
+-- Users are charged for consumed audio, so allocated fees are not tracked anymore.
+DROP INDEX user_allocated_fee_idx;
+ALTER TABLE "user" DROP COLUMN allocated_fee;
@@ -0,0 +1,7 @@
+-- Segmentation durations are limited to ranges supported by infsrv.
+UPDATE capability
+   SET max_segment_duration = LEAST(GREATEST(max_segment_duration, 5), 300),
+       segment_window_duration = LEAST(GREATEST(segment_window_duration, 1), 10);
+ALTER TABLE capability
+  ADD CHECK (max_segment_duration BETWEEN 5 AND 300),
+  ADD CHECK (segment_window_duration BETWEEN 1 AND 10);
@@ -0,0 +1,600 @@
+use crate::{infsrv_pool::SAMPLE_RATE, util::fmt::ErrorChainDisplay};
+use futures::{stream, AsyncRead, AsyncReadExt, Stream, StreamExt};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use log::debug;
+use ogg::{reading::async_api::PacketReader, OggReadError, Packet as OggPacket};
+use rubato::{FastFixedIn, PolynomialDegree, Resampler};
+use std::{io::Cursor, mem::swap, ops::Range};
+use symphonia::{
+    core::{
+        audio::{AudioBuffer, AudioBufferRef, Signal},
+        codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_VORBIS},
+        formats::Packet as SymphoniaPacket,
+    },
+    default::codecs::VorbisDecoder,
+};
+
+/// Audio decoding error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("io")]
+    Io(
+        #[from]
+        #[source]
+        std::io::Error,
+    ),
+    #[error("malformed audio stream")]
+    Malformed,
+    #[error("ogg")]
+    Ogg(
+        #[from]
+        #[source]
+        OggReadError,
+    ),
+    #[error("packet too large")]
+    PacketTooLarge,
+}
+
+/// Audio decoding result.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// PCM decoded from a single audio packet.
+pub struct PcmChunk {
+    /// Mono samples at SAMPLE_RATE.
+    pub samples: Vec<i16>,
+    /// Number of frames in the packet at the source sample rate.
+    pub source_frames: usize,
+    /// Source sample rate.
+    pub source_rate: u32,
+    /// Whether the packet is the last one of its logical stream.
+    pub last_in_stream: bool,
+}
+
+/// Decode a (possibly chained) Ogg/Vorbis stream into mono PCM at SAMPLE_RATE.
+/// The stream ends after the first error.
+pub fn decode_ogg_to_pcm16<R>(
+    reader: R,
+    max_packet_frames: usize,
+) -> impl Stream<Item = Result<PcmChunk>>
+where
+    R: AsyncRead + Unpin,
+{
+    let state = (
+        PacketReader::new_compat(reader),
+        OggVorbisDecoder::default(),
+        PcmConverter::default(),
+    );
+    stream::unfold(Some(state), move |state| async move {
+        let (mut packet_reader, mut decoder, mut converter) = state?;
+        loop {
+            let packet = match packet_reader.next().await? {
+                Ok(packet) => packet,
+                Err(err) => return Some((Err(err.into()), None)),
+            };
+
+            let last_in_stream = packet.last_in_stream();
+            let result = match decoder.decode(packet) {
+                Some(DecodedPacket::Header { new_stream }) => {
+                    if new_stream {
+                        converter.reset();
+                    }
+                    continue;
+                }
+                Some(DecodedPacket::Audio(AudioBufferRef::F32(buf))) => {
+                    if buf.frames() > max_packet_frames {
+                        Err(Error::PacketTooLarge)
+                    } else {
+                        Ok(PcmChunk {
+                            samples: converter
+                                .convert(&buf)
+                                .iter()
+                                .map(|s| to_i16_sample(*s))
+                                .collect(),
+                            source_frames: buf.frames(),
+                            source_rate: buf.spec().rate,
+                            last_in_stream,
+                        })
+                    }
+                }
+                Some(DecodedPacket::Audio(_)) => {
+                    debug!("unsupported type of decoded samples");
+                    Err(Error::Malformed)
+                }
+                None => Err(Error::Malformed),
+            };
+
+            return match result {
+                Ok(chunk) => Some((Ok(chunk), Some((packet_reader, decoder, converter)))),
+                Err(err) => Some((Err(err), None)),
+            };
+        }
+    })
+}
+
+/// Read a raw stream of mono little-endian 16-bit PCM at SAMPLE_RATE
+/// in chunks of up to a given number of frames.
+/// The stream ends after the first error.
+pub fn decode_lpcm_to_pcm16<R>(
+    reader: R,
+    max_chunk_frames: usize,
+) -> impl Stream<Item = Result<PcmChunk>>
+where
+    R: AsyncRead + Unpin,
+{
+    let buf = vec![0; 2 * max_chunk_frames.max(1)];
+    stream::unfold(Some((reader, buf, 0)), |state| async move {
+        let (mut reader, mut buf, mut len) = state?;
+        // A sample may be split between reads, so an odd byte is carried over.
+        while len < 2 {
+            match reader.read(&mut buf[len..]).await {
+                Ok(0) if len == 0 => return None,
+                Ok(0) => return Some((Err(Error::Malformed), None)),
+                Ok(n) => len += n,
+                Err(err) => return Some((Err(err.into()), None)),
+            }
+        }
+
+        let frames = len / 2;
+        let samples = buf[..2 * frames]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        buf.copy_within(2 * frames..len, 0);
+        len -= 2 * frames;
+
+        let chunk = PcmChunk {
+            samples,
+            source_frames: frames,
+            source_rate: SAMPLE_RATE as u32,
+            // Raw PCM has no logical streams, so it can end at any frame.
+            last_in_stream: true,
+        };
+        Some((Ok(chunk), Some((reader, buf, len))))
+    })
+}
+
+/// Converter of decoded audio to mono PCM at SAMPLE_RATE.
+#[derive(Default)]
+pub struct PcmConverter {
+    resampler: Option<FastFixedIn<f32>>,
+    merged: Vec<f32>,
+    resampled: Vec<f32>,
+}
+
+impl PcmConverter {
+    /// Convert a given audio buffer, returns resampled mono samples.
+    /// Samples which don't fill a resampler chunk are kept for the next call.
+    pub fn convert(&mut self, audio_buffer: &AudioBuffer<f32>) -> &[f32] {
+        self.merge_channels(audio_buffer);
+        self.resample(audio_buffer.spec().rate as f32);
+        &self.resampled
+    }
+
+    pub fn reset(&mut self) {
+        // A new logical stream may come with a different sample rate.
+        self.resampler = None;
+        self.merged.clear();
+    }
+
+    fn merge_channels(&mut self, audio_buffer: &AudioBuffer<f32>) {
+        let offset = self.merged.len();
+
+        self.merged.resize(offset + audio_buffer.frames(), 0.0);
+        self.merged[offset..].fill(0.0);
+
+        for i in 0..audio_buffer.spec().channels.count() {
+            self.merged[offset..]
+                .iter_mut()
+                .zip(audio_buffer.chan(i).iter())
+                .for_each(|(m, s)| *m += (*s - *m) / (i + 1) as f32);
+        }
+    }
+
+    fn resample(&mut self, sample_rate: f32) {
+        if sample_rate != SAMPLE_RATE {
+            const CHUNK_SIZE: usize = 1024;
+            if self.resampler.is_none() {
+                self.resampler = Some(
+                    FastFixedIn::<f32>::new(
+                        SAMPLE_RATE as f64 / sample_rate as f64,
+                        1.0,
+                        PolynomialDegree::Linear,
+                        CHUNK_SIZE,
+                        1,
+                    )
+                    .unwrap(),
+                );
+            }
+
+            const OUTPUT_MARGIN: usize = 10;
+            let ratio = SAMPLE_RATE / sample_rate;
+            self.resampled.resize(
+                (self.merged.len() as f32 * ratio) as usize + OUTPUT_MARGIN,
+                0.0,
+            );
+
+            let mut merged_offset = 0;
+            let mut resampled_offset = 0;
+
+            while self.merged.len() - merged_offset >= CHUNK_SIZE {
+                let (in_samples, out_samples) = self
+                    .resampler
+                    .as_mut()
+                    .unwrap()
+                    .process_into_buffer(
+                        &[&self.merged[merged_offset..]],
+                        &mut [&mut self.resampled[resampled_offset..]],
+                        None,
+                    )
+                    .unwrap();
+
+                merged_offset += in_samples;
+                resampled_offset += out_samples;
+            }
+
+            self.merged.drain(..merged_offset);
+            self.resampled.truncate(resampled_offset);
+        } else {
+            self.resampled.clear();
+            swap(&mut self.merged, &mut self.resampled);
+        }
+    }
+}
+
+/// Role of a packet within an Ogg/Vorbis logical stream.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum OggPacketKind {
+    IdHeader,
+    CommentHeader,
+    SetupHeader,
+    Audio,
+}
+
+/// Packet position tracker that restarts on every new (chained) logical stream.
+#[derive(Default)]
+struct OggStreamTracker {
+    packet_index: usize,
+}
+
+impl OggStreamTracker {
+    fn next_packet_kind(&mut self, packet: &OggPacket) -> OggPacketKind {
+        if packet.first_in_stream() {
+            self.packet_index = 0;
+        }
+
+        use OggPacketKind::*;
+        let kind = match self.packet_index {
+            0 => IdHeader,
+            1 => CommentHeader,
+            2 => SetupHeader,
+            _ => Audio,
+        };
+        self.packet_index += 1;
+        kind
+    }
+}
+
+/// Outcome of decoding an Ogg/Vorbis packet.
+enum DecodedPacket<'a> {
+    /// A header packet, new_stream is set if a chained logical stream begins.
+    Header {
+        new_stream: bool,
+    },
+    Audio(AudioBufferRef<'a>),
+}
+
+/// Decoder of (possibly chained) Ogg/Vorbis streams.
+#[derive(Default)]
+struct OggVorbisDecoder {
+    stream_tracker: OggStreamTracker,
+    id_header: Vec<u8>,
+    decoder: Option<VorbisDecoder>,
+}
+
+impl OggVorbisDecoder {
+    /// Decode a given packet, returns None if the stream is malformed.
+    fn decode(&mut self, mut packet: OggPacket) -> Option<DecodedPacket<'_>> {
+        use DecodedPacket::*;
+        match self.stream_tracker.next_packet_kind(&packet) {
+            OggPacketKind::IdHeader => {
+                let new_stream = self.decoder.take().is_some();
+                if new_stream {
+                    debug!(
+                        "detected new ogg logical stream {}, reinitializing decoder",
+                        packet.stream_serial()
+                    );
+                }
+                self.id_header = packet.data;
+                Some(Header { new_stream })
+            }
+            OggPacketKind::CommentHeader => Some(Header { new_stream: false }),
+            OggPacketKind::SetupHeader => {
+                let mut codec_params = CodecParameters::new();
+                codec_params.for_codec(CODEC_TYPE_VORBIS);
+                self.id_header.append(&mut packet.data);
+                swap(&mut self.id_header, &mut packet.data);
+                codec_params.with_extra_data(packet.data.into_boxed_slice());
+
+                let decoder_opts = DecoderOptions::default();
+
+                match VorbisDecoder::try_new(&codec_params, &decoder_opts) {
+                    Ok(decoder) => self.decoder = Some(decoder),
+                    Err(err) => {
+                        debug!(
+                            "failed to create vorbis decoder: {}",
+                            ErrorChainDisplay(&err)
+                        );
+                        return None;
+                    }
+                };
+                Some(Header { new_stream: false })
+            }
+            OggPacketKind::Audio => {
+                let Some(decoder) = self.decoder.as_mut() else {
+                    debug!("received audio packet before vorbis headers");
+                    return None;
+                };
+                let packet =
+                    SymphoniaPacket::new_from_boxed_slice(0, 0, 0, packet.data.into_boxed_slice());
+                match decoder.decode(&packet) {
+                    Ok(buf) => Some(Audio(buf)),
+                    Err(err) => {
+                        debug!("failed to decode packet: {}", ErrorChainDisplay(&err));
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[inline]
+pub fn to_i16_sample(sample: f32) -> i16 {
+    (sample * i16::MAX as f32) as i16
+}
+
+/// Window (in seconds) over which RMS energy is measured for silence trimming.
+const TRIM_WINDOW_SECS: f32 = 0.01;
+
+/// Range of samples from the first to the last window of them with RMS energy
+/// (relative to full scale) reaching a given threshold. Nothing is trimmed
+/// if no window reaches the threshold.
+pub fn trim_silence(samples: &[i16], sample_rate: f32, threshold: f32) -> Range<usize> {
+    let window = ((sample_rate * TRIM_WINDOW_SECS) as usize).max(1);
+    let min_energy = threshold * i16::MAX as f32;
+    let is_loud = |chunk: &[i16]| {
+        let sum: f32 = chunk.iter().map(|s| (*s as f32).powi(2)).sum();
+        (sum / chunk.len() as f32).sqrt() >= min_energy
+    };
+
+    let mut windows = samples.chunks(window).enumerate();
+    let Some((first, _)) = windows.find(|(_, c)| is_loud(c)) else {
+        return 0..samples.len();
+    };
+    let last = windows.rfind(|(_, c)| is_loud(c)).map_or(first, |(i, _)| i);
+    first * window..((last + 1) * window).min(samples.len())
+}
+
+/// Resample mono PCM samples from one sample rate to another.
+pub fn resample_pcm16(samples: &[i16], sample_rate: f32, target_rate: f32) -> Vec<i16> {
+    if sample_rate == target_rate || samples.is_empty() {
+        return samples.to_vec();
+    }
+
+    // The whole interval is resampled as a single chunk.
+    let mut resampler = FastFixedIn::<f32>::new(
+        target_rate as f64 / sample_rate as f64,
+        1.0,
+        PolynomialDegree::Linear,
+        samples.len(),
+        1,
+    )
+    .unwrap();
+
+    let input: Vec<_> = samples
+        .iter()
+        .map(|s| *s as f32 / i16::MAX as f32)
+        .collect();
+    let output = resampler.process(&[input], None).unwrap();
+    output[0].iter().map(|s| to_i16_sample(*s)).collect()
+}
+
+/// Encode mono PCM samples at a given sample rate as a WAV blob.
+pub fn encode_wav(samples: impl ExactSizeIterator<Item = i16>, sample_rate: u32) -> Vec<u8> {
+    const WAV_HEADER_SIZE: usize = 44;
+    let capacity = WAV_HEADER_SIZE + samples.len() * 2;
+    let mut data = Vec::with_capacity(capacity);
+
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::new(Cursor::new(&mut data), spec).unwrap();
+
+    for sample in samples {
+        writer.write_sample(sample).unwrap();
+    }
+
+    writer.finalize().unwrap();
+    assert_eq!(data.len(), capacity);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{io::Cursor as AsyncCursor, TryStreamExt};
+    use hound::WavReader;
+    use ogg::{reading::PacketReader as SyncPacketReader, PacketWriteEndInfo, PacketWriter};
+    use symphonia::core::audio::{Channels, SignalSpec};
+
+    /// One second of 440Hz stereo sine wave at 44.1kHz.
+    const SINE_OGG: &[u8] = include_bytes!("../testdata/sine.ogg");
+
+    #[tokio::test]
+    async fn test_decode_ogg_to_pcm16() {
+        let chunks: Vec<_> = decode_ogg_to_pcm16(AsyncCursor::new(SINE_OGG), 16384)
+            .try_collect()
+            .await
+            .unwrap();
+
+        assert!(chunks.iter().all(|c| c.source_rate == 44100));
+        assert!(chunks.last().unwrap().last_in_stream);
+        // Decoded frames are not trimmed to the final granule position.
+        let source_frames: usize = chunks.iter().map(|c| c.source_frames).sum();
+        assert!((44100..44100 + 2048).contains(&source_frames));
+
+        // Resampling buffers input in chunks, so the tail may be held back.
+        let samples: Vec<_> = chunks.into_iter().flat_map(|c| c.samples).collect();
+        let expected = source_frames * SAMPLE_RATE as usize / 44100;
+        assert!(samples.len() <= expected && samples.len() > expected - 1024);
+        let peak = samples.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak > i16::MAX as u16 / 3 && peak < i16::MAX as u16 * 2 / 3);
+    }
+
+    #[tokio::test]
+    async fn test_decode_ogg_to_pcm16_errors() {
+        let decode = |data: Vec<u8>, max_packet_frames| async move {
+            decode_ogg_to_pcm16(AsyncCursor::new(data), max_packet_frames)
+                .try_collect::<Vec<_>>()
+                .await
+        };
+
+        assert!(matches!(
+            decode(SINE_OGG.to_vec(), 16).await,
+            Err(Error::PacketTooLarge)
+        ));
+        // Bytes before the first page capture pattern are skipped.
+        let garbage = b"not an ogg stream".to_vec();
+        assert!(decode(garbage, 16384).await.unwrap().is_empty());
+
+        let mut data = Vec::new();
+        {
+            let mut writer = PacketWriter::new(&mut data);
+            for i in 0..4u8 {
+                writer
+                    .write_packet(vec![i], 1, PacketWriteEndInfo::EndPage, 0)
+                    .unwrap();
+            }
+        }
+        assert!(matches!(decode(data, 16384).await, Err(Error::Malformed)));
+    }
+
+    #[tokio::test]
+    async fn test_decode_lpcm_to_pcm16() {
+        let decode = |data: Vec<u8>, max_chunk_frames| async move {
+            decode_lpcm_to_pcm16(AsyncCursor::new(data), max_chunk_frames)
+                .try_collect::<Vec<_>>()
+                .await
+        };
+
+        let data: Vec<_> = [1i16, -2, 3, i16::MAX, i16::MIN]
+            .iter()
+            .flat_map(|s| s.to_le_bytes())
+            .collect();
+        let chunks = decode(data.clone(), 2).await.unwrap();
+        let sizes: Vec<_> = chunks.iter().map(|c| c.source_frames).collect();
+        assert_eq!(sizes, [2, 2, 1]);
+        let samples: Vec<_> = chunks.into_iter().flat_map(|c| c.samples).collect();
+        assert_eq!(samples, [1, -2, 3, i16::MAX, i16::MIN]);
+
+        assert!(decode(Vec::new(), 2).await.unwrap().is_empty());
+        assert!(matches!(
+            decode(data[..3].to_vec(), 2).await,
+            Err(Error::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_trim_silence() {
+        // 0.1s of silence, 0.2s of a 440Hz sine and 0.3s of near-silence.
+        let mut samples = vec![0i16; 1600];
+        samples.extend((0..3200).map(|i| {
+            let t = i as f32 / SAMPLE_RATE;
+            to_i16_sample(0.5 * (2.0 * std::f32::consts::PI * 440.0 * t).sin())
+        }));
+        samples.extend((0..4800).map(|i| if i % 2 == 0 { 30 } else { -30 }));
+
+        assert_eq!(trim_silence(&samples, SAMPLE_RATE, 0.01), 1600..4800);
+        assert_eq!(trim_silence(&samples, SAMPLE_RATE, 0.0001), 1600..9600);
+        assert_eq!(trim_silence(&samples, SAMPLE_RATE, 0.9), 0..9600);
+        assert_eq!(
+            trim_silence(&samples[1600..1700], SAMPLE_RATE, 0.01),
+            0..100
+        );
+        assert_eq!(trim_silence(&[], SAMPLE_RATE, 0.01), 0..0);
+    }
+
+    #[test]
+    fn test_ogg_stream_tracker_chained_streams() {
+        let mut data = Vec::new();
+        {
+            let mut writer = PacketWriter::new(&mut data);
+            for serial in [1, 2] {
+                for i in 0..5u8 {
+                    let end_info = if i == 4 {
+                        PacketWriteEndInfo::EndStream
+                    } else {
+                        PacketWriteEndInfo::EndPage
+                    };
+                    writer.write_packet(vec![i], serial, end_info, 0).unwrap();
+                }
+            }
+        }
+
+        let mut reader = SyncPacketReader::new(Cursor::new(data));
+        let mut tracker = OggStreamTracker::default();
+        let mut kinds = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            kinds.push((packet.stream_serial(), tracker.next_packet_kind(&packet)));
+        }
+
+        use OggPacketKind::*;
+        let expected: Vec<_> = [1, 2]
+            .into_iter()
+            .flat_map(|s| {
+                [IdHeader, CommentHeader, SetupHeader, Audio, Audio]
+                    .into_iter()
+                    .map(move |k| (s, k))
+            })
+            .collect();
+        assert_eq!(kinds, expected);
+    }
+
+    #[test]
+    fn test_pcm_converter_merges_channels() {
+        let spec = SignalSpec::new(16000, Channels::FRONT_LEFT | Channels::FRONT_RIGHT);
+        let mut buf = AudioBuffer::<f32>::new(4, spec);
+        buf.render_reserved(Some(4));
+        buf.chan_mut(0).copy_from_slice(&[0.5, 0.5, -1.0, 0.0]);
+        buf.chan_mut(1).copy_from_slice(&[0.5, -0.5, 0.0, 1.0]);
+
+        let mut converter = PcmConverter::default();
+        assert_eq!(converter.convert(&buf), &[0.5, 0.0, -0.5, 0.5]);
+    }
+
+    #[test]
+    fn test_encode_wav() {
+        let samples = [0, 1, -1, i16::MAX, i16::MIN];
+        let wav = encode_wav(samples.into_iter(), SAMPLE_RATE as u32);
+        assert_eq!(wav.len(), 44 + 2 * samples.len());
+
+        let mut reader = WavReader::new(Cursor::new(wav)).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE as u32);
+        let decoded: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn test_resample_pcm16() {
+        let samples: Vec<i16> = (0..16000).map(|i| (i % 100) as i16 * 100).collect();
+        assert_eq!(resample_pcm16(&samples, 16000.0, 16000.0), samples);
+        assert!(resample_pcm16(&[], 16000.0, 8000.0).is_empty());
+
+        let count = |target_rate| resample_pcm16(&samples, 16000.0, target_rate).len() as f32;
+        assert!((count(8000.0) - 8000.0).abs() <= 10.0);
+        assert!((count(24000.0) - 24000.0).abs() <= 10.0);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::util::fmt::ErrorChainDisplay;
+use log::{debug, error, warn};
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+use tokio::{
+    sync::mpsc::{channel, Sender},
+    task::{spawn_blocking, JoinHandle},
+};
+use uuid::Uuid;
+
+/// Number of PCM chunks queued for writing before a capture starts dropping audio.
+const CAPTURE_QUEUE_LEN: usize = 256;
+
+/// Storage of session audio captured for debugging (accessed from blocking tasks).
+pub trait AudioStore: Send + Sync {
+    /// Create an audio item with a given key returning a writer to append PCM to.
+    fn create(&self, key: Uuid) -> io::Result<Box<dyn Write + Send>>;
+
+    /// Change a key of a stored audio item.
+    fn rename(&self, from: Uuid, to: Uuid) -> io::Result<()>;
+
+    /// Delete audio items last modified before a given time,
+    /// returns the number of deleted items.
+    fn delete_older_than(&self, time: SystemTime) -> io::Result<usize>;
+}
+
+/// Audio store keeping raw PCM files in a local directory.
+pub struct DiskAudioStore {
+    dir: PathBuf,
+}
+
+impl DiskAudioStore {
+    /// Create a new DiskAudioStore instance.
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    fn path(&self, key: Uuid) -> PathBuf {
+        self.dir.join(format!("{key}.pcm"))
+    }
+}
+
+impl AudioStore for DiskAudioStore {
+    fn create(&self, key: Uuid) -> io::Result<Box<dyn Write + Send>> {
+        fs::create_dir_all(&self.dir)?;
+        let file = File::create_new(self.path(key))?;
+        Ok(Box::new(BufWriter::new(file)))
+    }
+
+    fn rename(&self, from: Uuid, to: Uuid) -> io::Result<()> {
+        fs::rename(self.path(from), self.path(to))
+    }
+
+    fn delete_older_than(&self, time: SystemTime) -> io::Result<usize> {
+        let entries = match fs::read_dir(&self.dir) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+            result => result?,
+        };
+
+        let mut count = 0;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "pcm") {
+                continue;
+            }
+            if fs::metadata(&path)?.modified()? < time {
+                fs::remove_file(&path)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+}
+
+/// Capture of session PCM (i16 le-encoded samples, 16kHz mono) written
+/// to an audio store in background. The audio is dropped rather than
+/// delaying the session if the writing lags behind.
+pub struct AudioCapture {
+    key: Uuid,
+    store: Arc<dyn AudioStore>,
+    sender: Sender<Vec<u8>>,
+    handle: JoinHandle<bool>,
+    truncated: AtomicBool,
+}
+
+impl AudioCapture {
+    /// Start a new capture with a temporary key.
+    pub fn start(store: Arc<dyn AudioStore>) -> Self {
+        let key = Uuid::new_v4();
+        let (sender, mut receiver) = channel::<Vec<u8>>(CAPTURE_QUEUE_LEN);
+        let cloned_store = store.clone();
+        let handle = spawn_blocking(move || {
+            let result = cloned_store.create(key).and_then(|mut writer| {
+                while let Some(pcm) = receiver.blocking_recv() {
+                    writer.write_all(&pcm)?;
+                }
+                writer.flush()
+            });
+            if let Err(err) = &result {
+                error!("failed to write audio capture: {}", ErrorChainDisplay(err));
+            }
+            result.is_ok()
+        });
+
+        Self {
+            key,
+            store,
+            sender,
+            handle,
+            truncated: AtomicBool::new(false),
+        }
+    }
+
+    /// Queue samples for writing.
+    pub fn push(&self, samples: &[i16]) {
+        let pcm = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        if self.sender.try_send(pcm).is_err() && !self.truncated.swap(true, Ordering::SeqCst) {
+            warn!("dropping audio of lagging capture {}", self.key);
+        }
+    }
+
+    /// Finish writing and store the capture under a given session ID
+    /// (or keep the temporary key until the capture expires).
+    pub async fn finish(self, session: Option<Uuid>) {
+        let Self {
+            key,
+            store,
+            sender,
+            handle,
+            ..
+        } = self;
+        drop(sender);
+        if !handle.await.unwrap_or_default() {
+            return;
+        }
+        let Some(session) = session else {
+            return;
+        };
+
+        match spawn_blocking(move || store.rename(key, session)).await {
+            Ok(Ok(())) => debug!("stored audio capture of session {session}"),
+            Ok(Err(err)) => error!("failed to store audio capture: {}", ErrorChainDisplay(&err)),
+            Err(_) => error!("failed to join audio capture task"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_audio_capture() {
+        let dir = std::env::temp_dir().join(format!("bfsrv-test-{}", Uuid::new_v4()));
+        let store = Arc::new(DiskAudioStore::new(dir.clone()));
+
+        let capture = AudioCapture::start(store.clone());
+        capture.push(&[1, -2]);
+        capture.push(&[3]);
+        let session = Uuid::new_v4();
+        capture.finish(Some(session)).await;
+        let pcm = fs::read(dir.join(format!("{session}.pcm"))).unwrap();
+        assert_eq!(pcm, [1, 0, 254, 255, 3, 0]);
+
+        // Captures of unstored sessions are kept until they expire.
+        AudioCapture::start(store.clone()).finish(None).await;
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        let past = SystemTime::now() - Duration::from_secs(3600);
+        assert_eq!(store.delete_older_than(past).unwrap(), 0);
+        let future = SystemTime::now() + Duration::from_secs(3600);
+        assert_eq!(store.delete_older_than(future).unwrap(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        fs::remove_dir(&dir).unwrap();
+        assert_eq!(store.delete_older_than(future).unwrap(), 0);
+    }
+}
@@ -1,11 +1,60 @@
-use clap::Parser;
+use crate::infsrv_pool::VadSensitivity;
+use clap::{Parser, Subcommand, ValueEnum};
 use lettre::Address as EmailAddress;
-use std::net::SocketAddr;
+use rust_decimal::Decimal;
+use std::{net::SocketAddr, path::PathBuf, str::FromStr};
 use url::Url;
 
 /// Service configuration.
 #[derive(Parser)]
 pub struct Config {
+    #[clap(long, env = "ADMIN_TOKEN_MAX_TTL_SECS", default_value = "604800")]
+    pub admin_token_max_ttl_secs: u64,
+    #[clap(
+        long,
+        env = "ALLOCATION_MODE",
+        value_enum,
+        default_value = "single-node"
+    )]
+    pub allocation_mode: AllocationMode,
+    #[clap(long, env = "AUDIO_CAPTURE_DIR")]
+    pub audio_capture_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        env = "AUDIO_CAPTURE_SWEEP_INTERVAL_SECS",
+        default_value = "3600",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub audio_capture_sweep_interval_secs: u64,
+    #[clap(
+        long,
+        env = "AUDIO_CAPTURE_TTL_SECS",
+        default_value = "604800",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub audio_capture_ttl_secs: u64,
+    #[clap(long, env = "AUTH_CACHE_TTL_SECS", default_value = "0")]
+    pub auth_cache_ttl_secs: u64,
+    #[clap(
+        long,
+        env = "BCRYPT_WORK_FACTOR",
+        default_value = "6",
+        value_parser = clap::value_parser!(u32).range(4..=31)
+    )]
+    pub bcrypt_work_factor: u32,
+    #[clap(
+        long,
+        env = "BILLING_UNIT_SECS",
+        default_value = "1",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub billing_unit_secs: u64,
+    #[clap(long, env = "CALLBACK_ALLOWED_HOSTS", value_delimiter = ',')]
+    pub callback_allowed_hosts: Vec<String>,
+    #[clap(long, env = "CLIENT_DRAIN_TIMEOUT_SECS", default_value = "30")]
+    pub client_drain_timeout_secs: u64,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
     #[clap(long, env = "CURRENCY", default_value = "USD")]
     pub currency: String,
     #[clap(
@@ -14,26 +63,323 @@ pub struct Config {
         default_value = "postgres://127.0.0.1/blobfish"
     )]
     pub database_url: Url,
+    #[clap(long, env = "DEFAULT_TARIFF", default_value = "basic")]
+    pub default_tariff: String,
+    #[clap(long, env = "HTTP_COMPRESSION", default_value = "true")]
+    pub http_compression: bool,
+    #[clap(long, env = "HTTP_COMPRESSION_MIN_SIZE", default_value = "1024")]
+    pub http_compression_min_size: u16,
+    #[clap(long, env = "HTTP_CONNECT_TIMEOUT_SECS", default_value = "10")]
+    pub http_connect_timeout_secs: u64,
+    #[clap(long, env = "HTTP_TIMEOUT_SECS", default_value = "60")]
+    pub http_timeout_secs: u64,
+    #[clap(
+        long,
+        env = "INFSRV_BREAKER_COOLDOWN_SECS",
+        default_value = "30",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub infsrv_breaker_cooldown_secs: u64,
+    #[clap(
+        long,
+        env = "INFSRV_BREAKER_FAILURES",
+        default_value = "5",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub infsrv_breaker_failures: u32,
+    #[clap(
+        long,
+        env = "INFSRV_BREAKER_WINDOW_SECS",
+        default_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub infsrv_breaker_window_secs: u64,
+    #[clap(long, env = "INFSRV_RECONNECT_ATTEMPTS", default_value = "3")]
+    pub infsrv_reconnect_attempts: u32,
+    #[clap(long, env = "INFSRV_RECONNECT_DELAY_SECS", default_value = "1")]
+    pub infsrv_reconnect_delay_secs: u64,
+    #[clap(
+        long,
+        env = "JOB_POLL_INTERVAL_SECS",
+        default_value = "5",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub job_poll_interval_secs: u64,
+    #[clap(long, env = "JOB_STALE_SECS", default_value = "3600")]
+    pub job_stale_secs: u64,
     #[clap(long, env = "LIMIT_AUDIO_RATE", default_value = "true")]
     pub limit_audio_rate: bool,
+    #[clap(long, env = "LOG_FORMAT", value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+    #[clap(
+        long,
+        env = "LOW_BALANCE_POLL_INTERVAL_SECS",
+        default_value = "300",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub low_balance_poll_interval_secs: u64,
+    #[clap(long, env = "LOW_BALANCE_THRESHOLD")]
+    pub low_balance_threshold: Option<Decimal>,
+    #[clap(long, env = "MAX_CONCURRENT_REQUESTS", default_value = "256")]
+    pub max_concurrent_requests: usize,
+    #[clap(long, env = "MAX_PACKET_FRAMES", default_value = "16384")]
+    pub max_packet_frames: usize,
+    #[clap(long, env = "MAX_JOB_FILE_DURATION_SECS", default_value = "14400")]
+    pub max_job_file_duration_secs: u64,
+    #[clap(long, env = "MAX_JOB_FILE_SIZE", default_value = "268435456")]
+    pub max_job_file_size: usize,
+    #[clap(long, env = "MAX_PROMPT_SIZE", default_value = "1024")]
+    pub max_prompt_size: usize,
+    #[clap(long, env = "MAX_REQUEST_BODY_SIZE", default_value = "65536")]
+    pub max_request_body_size: usize,
+    #[clap(long, env = "MAX_SESSION_SEGMENTS", default_value = "100000")]
+    pub max_session_segments: usize,
+    #[clap(long, env = "MAX_TOKEN_BATCH_SIZE", default_value = "100")]
+    pub max_token_batch_size: usize,
+    #[clap(
+        long,
+        env = "MAX_TRANSCRIBE_FILE_DURATION_SECS",
+        default_value = "3600"
+    )]
+    pub max_transcribe_file_duration_secs: u64,
+    #[clap(long, env = "MAX_TRANSCRIBE_FILE_SIZE", default_value = "67108864")]
+    pub max_transcribe_file_size: usize,
+    #[clap(long, env = "MAX_TRANSCRIBE_SESSIONS", default_value = "64")]
+    pub max_transcribe_sessions: usize,
+    #[clap(
+        long,
+        env = "MAX_USAGE_EXPORT_DAYS",
+        default_value = "366",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_usage_export_days: u64,
+    #[clap(long, env = "MAX_WS_MESSAGE_SIZE", default_value = "1048576")]
+    pub max_ws_message_size: usize,
+    #[clap(long, env = "MIGRATE_ON_STARTUP", default_value = "false")]
+    pub migrate_on_startup: bool,
+    #[clap(long, env = "PAYMENT_LIMITS", value_delimiter = ',')]
+    pub payment_limits: Vec<PaymentLimit>,
+    #[clap(long, env = "PAYMENT_NEW_TTL_SECS", default_value = "86400")]
+    pub payment_new_ttl_secs: u64,
+    #[clap(long, env = "PAYMENT_POLL_INTERVAL_SECS", default_value = "60")]
+    pub payment_poll_interval_secs: u64,
+    #[clap(long, env = "PAYMENT_POLL_MIN_AGE_SECS", default_value = "300")]
+    pub payment_poll_min_age_secs: u64,
     #[clap(long, env = "PAYPAL_CANCEL_URL")]
     pub paypal_cancel_url: Url,
     #[clap(long, env = "PAYPAL_CLIENT_ID")]
     pub paypal_client_id: String,
+    #[clap(long, env = "PAYPAL_CURRENCIES", value_delimiter = ',')]
+    pub paypal_currencies: Option<Vec<String>>,
+    #[clap(long, env = "PAYPAL_LOCALES", value_delimiter = ',')]
+    pub paypal_locales: Option<Vec<String>>,
     #[clap(long, env = "PAYPAL_RETURN_URL")]
     pub paypal_return_url: Url,
     #[clap(long, env = "PAYPAL_SANDBOX", default_value = "true")]
     pub paypal_sandbox: bool,
     #[clap(long, env = "PAYPAL_SECRET_KEY")]
     pub paypal_secret_key: String,
-    #[clap(long, env = "SERVER_ADDRESS", default_value = "127.0.0.1:9321")]
-    pub server_address: SocketAddr,
+    #[clap(long, env = "PAYPAL_TOKEN_EXPIRY_SKEW_SECS", default_value = "60")]
+    pub paypal_token_expiry_skew_secs: u64,
+    #[clap(long, env = "PG_POOL_CREATE_TIMEOUT_SECS", default_value = "5")]
+    pub pg_pool_create_timeout_secs: u64,
+    #[clap(long, env = "PG_POOL_MAX_SIZE", default_value = "32")]
+    pub pg_pool_max_size: usize,
+    #[clap(long, env = "PG_POOL_RECYCLE_TIMEOUT_SECS", default_value = "5")]
+    pub pg_pool_recycle_timeout_secs: u64,
+    #[clap(long, env = "PG_POOL_WAIT_TIMEOUT_SECS", default_value = "5")]
+    pub pg_pool_wait_timeout_secs: u64,
+    #[clap(
+        long = "server-address",
+        env = "SERVER_ADDRESS",
+        value_delimiter = ',',
+        default_value = "127.0.0.1:9321"
+    )]
+    pub server_addresses: Vec<SocketAddr>,
+    #[clap(long, env = "SHUTDOWN_GRACE_SECS", default_value = "5")]
+    pub shutdown_grace_secs: u64,
     #[clap(long, env = "SMTP_FROM")]
     pub smtp_from: EmailAddress,
+    #[clap(long, env = "SMTP_FROM_NAME")]
+    pub smtp_from_name: Option<String>,
     #[clap(long, env = "SMTP_USERNAME")]
     pub smtp_username: String,
     #[clap(long, env = "SMTP_PASSWORD")]
     pub smtp_password: String,
     #[clap(long, env = "SMTP_RELAY")]
     pub smtp_relay: String,
+    #[clap(long, env = "SMTP_REPLY_TO")]
+    pub smtp_reply_to: Option<EmailAddress>,
+    #[clap(
+        long,
+        env = "SPEECH_FLUSH_GRACE_MILLIS",
+        default_value = "0",
+        value_parser = clap::value_parser!(u64).range(..=60000)
+    )]
+    pub speech_flush_grace_millis: u64,
+    #[clap(long, env = "TARIFF_CONCURRENCY_LIMITS", value_delimiter = ',')]
+    pub tariff_concurrency_limits: Vec<TariffConcurrencyLimit>,
+    #[clap(
+        long,
+        env = "TCP_KEEPALIVE_IDLE_SECS",
+        default_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub tcp_keepalive_idle_secs: u64,
+    #[clap(
+        long,
+        env = "TCP_KEEPALIVE_INTERVAL_SECS",
+        default_value = "15",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub tcp_keepalive_interval_secs: u64,
+    #[clap(long, env = "TCP_NODELAY", default_value = "true")]
+    pub tcp_nodelay: bool,
+    #[clap(long, env = "TOKEN_DEFAULT_TTL_SECS", default_value = "2592000")]
+    pub token_default_ttl_secs: u64,
+    #[clap(long, env = "TOKEN_INACTIVITY_TTL_SECS", default_value = "0")]
+    pub token_inactivity_ttl_secs: u64,
+    #[clap(long, env = "TOKEN_MAX_TTL_SECS", default_value = "31536000")]
+    pub token_max_ttl_secs: u64,
+    #[clap(long, env = "TRANSCRIPT_URL_SECRET")]
+    pub transcript_url_secret: Option<String>,
+    #[clap(long, env = "TRANSCRIPT_URL_TTL_SECS", default_value = "86400")]
+    pub transcript_url_ttl_secs: u64,
+    #[clap(long, env = "TRIAL_ENABLED", default_value = "false")]
+    pub trial_enabled: bool,
+    #[clap(long, env = "TRIAL_IP_INTERVAL_SECS", default_value = "86400")]
+    pub trial_ip_interval_secs: u64,
+    #[clap(long, env = "TRIAL_PROMO_CODE", default_value = "default")]
+    pub trial_promo_code: String,
+    #[clap(long, env = "TRIAL_TOKEN_TTL_SECS", default_value = "86400")]
+    pub trial_token_ttl_secs: u64,
+    #[clap(long, env = "VAD_SENSITIVITY", value_enum, default_value = "high")]
+    pub vad_sensitivity: VadSensitivity,
+    #[clap(long, env = "WS_ALLOWED_ORIGINS", value_delimiter = ',')]
+    pub ws_allowed_origins: Vec<String>,
+    #[clap(
+        long,
+        env = "WS_IDLE_TIMEOUT_SECS",
+        default_value = "60",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub ws_idle_timeout_secs: u64,
+    #[clap(
+        long,
+        env = "WS_PING_INTERVAL_SECS",
+        default_value = "20",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub ws_ping_interval_secs: u64,
+}
+
+/// How task capabilities are placed on nodes.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum AllocationMode {
+    /// Place a task on nodes having available resources for the capabilities they host.
+    MultiNode,
+    /// Place all task capabilities on a single node.
+    SingleNode,
+}
+
+/// Command to run instead of serving requests.
+#[derive(Subcommand)]
+pub enum Command {
+    /// Create an admin token for a user with a given email
+    /// (registering the user with the default campaign if needed).
+    CreateAdminToken {
+        #[clap(long)]
+        email: EmailAddress,
+    },
+    /// Apply pending database schema migrations.
+    Migrate,
+}
+
+/// Log output format.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Json,
+    Text,
+}
+
+/// Payment amount bounds for a given currency ('*' matches any currency).
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaymentLimit {
+    pub currency: String,
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+impl FromStr for PaymentLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("malformed payment limit '{s}', expected CURRENCY:MIN:MAX");
+        let mut parts = s.split(':');
+        let (Some(currency), Some(min), Some(max), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(err());
+        };
+
+        let min = Decimal::from_str(min).map_err(|_| err())?;
+        let max = Decimal::from_str(max).map_err(|_| err())?;
+        if currency.is_empty() || min <= Decimal::ZERO || min > max {
+            return Err(err());
+        }
+
+        Ok(PaymentLimit {
+            currency: currency.to_owned(),
+            min,
+            max,
+        })
+    }
+}
+
+/// Maximum number of concurrent allocations of a given tariff per task type
+/// (enforced by each server instance separately).
+#[derive(Clone, Debug, PartialEq)]
+pub struct TariffConcurrencyLimit {
+    pub tariff: String,
+    pub max: usize,
+}
+
+impl FromStr for TariffConcurrencyLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || format!("malformed tariff concurrency limit '{s}', expected TARIFF:MAX");
+        let Some((tariff, max)) = s.rsplit_once(':') else {
+            return Err(err());
+        };
+
+        let max = usize::from_str(max).map_err(|_| err())?;
+        if tariff.is_empty() || max == 0 {
+            return Err(err());
+        }
+
+        Ok(TariffConcurrencyLimit {
+            tariff: tariff.to_owned(),
+            max,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tariff_concurrency_limit_from_str() {
+        assert_eq!(
+            TariffConcurrencyLimit::from_str("basic:10"),
+            Ok(TariffConcurrencyLimit {
+                tariff: "basic".to_owned(),
+                max: 10,
+            })
+        );
+        assert!(TariffConcurrencyLimit::from_str("basic").is_err());
+        assert!(TariffConcurrencyLimit::from_str(":10").is_err());
+        assert!(TariffConcurrencyLimit::from_str("basic:0").is_err());
+    }
 }
@@ -1,7 +1,7 @@
-use axum::http::StatusCode;
+use crate::error_kind::{self as kind, ErrorKind};
 use log::debug;
 use reqwest::Client;
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::Deserialize;
 use std::{collections::HashMap, sync::RwLock, time::Duration};
 use time::OffsetDateTime;
@@ -18,19 +18,12 @@ pub enum Error {
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
         match self {
-            Reqwest(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
-
-    /// Kind code.
-    pub fn code(&self) -> &str {
-        use Error::*;
-        match self {
-            Reqwest(_) => "reqwest",
+            Reqwest(err) if err.is_timeout() => &kind::REQWEST_TIMEOUT,
+            Reqwest(_) => &kind::REQWEST,
         }
     }
 }
@@ -51,14 +44,16 @@ struct State {
 /// Currency converter.
 pub struct CurrencyConverter {
     base: String,
+    client: Client,
     state: RwLock<State>,
 }
 
 impl CurrencyConverter {
     /// Create a new CurrencyConverter instance for a given base currency.
-    pub fn new(base: String) -> Self {
+    pub fn new(base: String, client: Client) -> Self {
         Self {
             base,
+            client,
             state: RwLock::new(State {
                 rates: HashMap::new(),
                 updated_at: OffsetDateTime::UNIX_EPOCH,
@@ -66,16 +61,28 @@ impl CurrencyConverter {
         }
     }
 
-    /// Convert amount from one currency to another.
+    /// Convert amount from a given currency to the base one.
     pub async fn convert(&self, currency: &str, amount: Decimal) -> Result<Option<Decimal>> {
+        self.convert_between(currency, &self.base, amount).await
+    }
+
+    /// Convert amount from one currency to another using cross-rates.
+    /// Returns None if any of the currencies is unknown.
+    pub async fn convert_between(
+        &self,
+        from: &str,
+        to: &str,
+        amount: Decimal,
+    ) -> Result<Option<Decimal>> {
         {
             let state = self.state.read().unwrap();
             if OffsetDateTime::now_utc() < state.updated_at + Duration::from_secs(24 * 3600) {
-                return Ok(state.rates.get(currency).map(|r| amount / *r));
+                return Ok(cross_convert(&state.rates, from, to, amount));
             }
         }
 
-        let response = Client::default()
+        let response = self
+            .client
             .get(format!(
                 "https://api.exchangerate-api.com/v4/latest/{}",
                 &self.base
@@ -91,6 +98,100 @@ impl CurrencyConverter {
         state.updated_at = OffsetDateTime::now_utc();
 
         debug!("retrieved currency rates");
-        Ok(state.rates.get(currency).map(|r| amount / *r))
+        Ok(cross_convert(&state.rates, from, to, amount))
+    }
+}
+
+/// Currencies with no minor units (ISO 4217).
+const ZERO_DECIMAL_CURRENCIES: &[&str] = &[
+    "BIF", "CLP", "DJF", "GNF", "ISK", "JPY", "KMF", "KRW", "PYG", "RWF", "UGX", "UYI", "VND",
+    "VUV", "XAF", "XOF", "XPF",
+];
+
+/// Currencies with three-digit minor units (ISO 4217).
+const THREE_DECIMAL_CURRENCIES: &[&str] = &["BHD", "IQD", "JOD", "KWD", "LYD", "OMR", "TND"];
+
+/// Number of minor-unit digits for a given currency.
+pub fn minor_unit_scale(currency: &str) -> u32 {
+    if ZERO_DECIMAL_CURRENCIES.contains(&currency) {
+        0
+    } else if THREE_DECIMAL_CURRENCIES.contains(&currency) {
+        3
+    } else {
+        2
+    }
+}
+
+/// Round amount half-up to minor units of a given currency.
+pub fn round_to_minor_units(currency: &str, amount: Decimal) -> Decimal {
+    amount.round_dp_with_strategy(
+        minor_unit_scale(currency),
+        RoundingStrategy::MidpointAwayFromZero,
+    )
+}
+
+/// Convert amount using rates given as units of currency per unit of base currency.
+/// The result is rounded to minor units of the target currency.
+fn cross_convert(
+    rates: &HashMap<String, Decimal>,
+    from: &str,
+    to: &str,
+    amount: Decimal,
+) -> Option<Decimal> {
+    let rate_from = rates.get(from).filter(|r| !r.is_zero())?;
+    let rate_to = rates.get(to)?;
+    Some(round_to_minor_units(to, amount * rate_to / rate_from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_cross_convert() {
+        let dec = |s| Decimal::from_str(s).unwrap();
+        let rates: HashMap<_, _> = [("USD", "1"), ("EUR", "0.9"), ("JPY", "150")]
+            .into_iter()
+            .map(|(c, r)| (c.to_owned(), dec(r)))
+            .collect();
+
+        assert_eq!(
+            cross_convert(&rates, "USD", "EUR", dec("100")),
+            Some(dec("90"))
+        );
+        assert_eq!(
+            cross_convert(&rates, "EUR", "USD", dec("90")),
+            Some(dec("100"))
+        );
+        assert_eq!(
+            cross_convert(&rates, "EUR", "JPY", dec("90")),
+            Some(dec("15000"))
+        );
+        assert_eq!(
+            cross_convert(&rates, "USD", "USD", dec("1.5")),
+            Some(dec("1.5"))
+        );
+        assert_eq!(cross_convert(&rates, "USD", "XXX", dec("1")), None);
+        assert_eq!(cross_convert(&rates, "XXX", "USD", dec("1")), None);
+        assert_eq!(
+            cross_convert(&rates, "EUR", "USD", dec("1")),
+            Some(dec("1.11"))
+        );
+    }
+
+    #[test]
+    fn test_round_to_minor_units() {
+        let dec = |s| Decimal::from_str(s).unwrap();
+        assert_eq!(minor_unit_scale("JPY"), 0);
+        assert_eq!(minor_unit_scale("USD"), 2);
+        assert_eq!(minor_unit_scale("BHD"), 3);
+
+        assert_eq!(round_to_minor_units("JPY", dec("149.5")), dec("150"));
+        assert_eq!(round_to_minor_units("JPY", dec("149.49")), dec("149"));
+        assert_eq!(round_to_minor_units("USD", dec("1.005")), dec("1.01"));
+        assert_eq!(round_to_minor_units("USD", dec("-1.005")), dec("-1.01"));
+        assert_eq!(round_to_minor_units("BHD", dec("1.2345")), dec("1.235"));
+        assert_eq!(round_to_minor_units("BHD", dec("1.2344")), dec("1.234"));
     }
 }
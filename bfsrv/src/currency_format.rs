@@ -0,0 +1,133 @@
+use crate::currency_converter::{minor_unit_scale, round_to_minor_units};
+use rust_decimal::Decimal;
+
+/// Number formatting conventions of a locale.
+struct Locale {
+    languages: &'static [&'static str],
+    decimal_separator: char,
+    group_separator: char,
+    symbol_first: bool,
+}
+
+const LOCALES: &[Locale] = &[
+    Locale {
+        languages: &["en", "ja", "ko", "zh"],
+        decimal_separator: '.',
+        group_separator: ',',
+        symbol_first: true,
+    },
+    Locale {
+        languages: &["da", "de", "es", "id", "it", "nl", "pt", "tr"],
+        decimal_separator: ',',
+        group_separator: '.',
+        symbol_first: false,
+    },
+    Locale {
+        languages: &["cs", "fi", "fr", "nb", "pl", "ru", "sv", "uk"],
+        decimal_separator: ',',
+        group_separator: '\u{a0}',
+        symbol_first: false,
+    },
+];
+
+/// Locale used if none of the accepted languages is supported.
+const DEFAULT_LOCALE: &Locale = &LOCALES[0];
+
+const CURRENCY_SYMBOLS: &[(&str, &str)] = &[
+    ("EUR", "€"),
+    ("GBP", "£"),
+    ("INR", "₹"),
+    ("JPY", "¥"),
+    ("KRW", "₩"),
+    ("RUB", "₽"),
+    ("UAH", "₴"),
+    ("USD", "$"),
+];
+
+/// Locale-aware formatter of amounts in a given currency.
+pub struct CurrencyFormat<'a> {
+    currency: &'a str,
+    locale: &'static Locale,
+}
+
+impl<'a> CurrencyFormat<'a> {
+    /// Create a new CurrencyFormat instance for language tags ordered
+    /// by preference (see util::http::accept_languages).
+    pub fn new(currency: &'a str, languages: &[String]) -> Self {
+        let locale = languages
+            .iter()
+            .find_map(|tag| {
+                let language = tag.split(['-', '_']).next()?;
+                LOCALES
+                    .iter()
+                    .find(|l| l.languages.iter().any(|l| l.eq_ignore_ascii_case(language)))
+            })
+            .unwrap_or(DEFAULT_LOCALE);
+        Self { currency, locale }
+    }
+
+    /// Format amount rounded to minor units with a currency symbol
+    /// (or code if the symbol is unknown) and grouped thousands.
+    pub fn format(&self, amount: Decimal) -> String {
+        let scale = minor_unit_scale(self.currency) as usize;
+        let rounded = round_to_minor_units(self.currency, amount.abs());
+        let digits = format!("{rounded:.scale$}");
+        let (integer, fraction) = digits.split_once('.').unwrap_or((&digits, ""));
+
+        let mut number = String::new();
+        for (i, c) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                number.push(self.locale.group_separator);
+            }
+            number.push(c);
+        }
+        if !fraction.is_empty() {
+            number.push(self.locale.decimal_separator);
+            number.push_str(fraction);
+        }
+
+        let symbol = CURRENCY_SYMBOLS
+            .iter()
+            .find(|(c, _)| *c == self.currency)
+            .map(|(_, s)| *s);
+        let sign = if rounded.is_zero() || amount.is_sign_positive() {
+            ""
+        } else {
+            "-"
+        };
+
+        match (symbol, self.locale.symbol_first) {
+            (Some(symbol), true) => format!("{sign}{symbol}{number}"),
+            (None, true) => format!("{sign}{}\u{a0}{number}", self.currency),
+            (symbol, false) => {
+                format!("{sign}{number}\u{a0}{}", symbol.unwrap_or(self.currency))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_currency_format() {
+        let dec = |s| Decimal::from_str(s).unwrap();
+        let format = |currency, languages: &[&str], amount| {
+            let languages: Vec<_> = languages.iter().map(|l| l.to_string()).collect();
+            CurrencyFormat::new(currency, &languages).format(dec(amount))
+        };
+
+        assert_eq!(format("USD", &[], "1234567.891"), "$1,234,567.89");
+        assert_eq!(format("USD", &["en-US"], "0.004"), "$0.00");
+        assert_eq!(format("USD", &["en"], "-0.004"), "$0.00");
+        assert_eq!(format("USD", &["en"], "-12.5"), "-$12.50");
+        assert_eq!(format("EUR", &["hu-HU", "de"], "1234.5"), "1.234,50\u{a0}€");
+        assert_eq!(format("EUR", &["xx", "fr-CA"], "999"), "999,00\u{a0}€");
+        assert_eq!(format("RUB", &["ru"], "1000"), "1\u{a0}000,00\u{a0}₽");
+        assert_eq!(format("JPY", &["ja"], "1500.5"), "¥1,501");
+        assert_eq!(format("BHD", &["en"], "1234.5678"), "BHD\u{a0}1,234.568");
+        assert_eq!(format("CHF", &["de_CH"], "-100"), "-100,00\u{a0}CHF");
+    }
+}
@@ -0,0 +1,59 @@
+use crate::data::Result;
+use deadpool_postgres::GenericClient;
+use rust_decimal::Decimal;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Manual user balance adjustment made by admin.
+pub struct BalanceAdjustment {
+    pub id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub user: Uuid,
+    pub amount: Decimal,
+    pub reason: String,
+    pub token: Uuid,
+}
+
+impl BalanceAdjustment {
+    /// Create a new BalanceAdjustment instance.
+    pub fn new(user: Uuid, amount: Decimal, reason: String, token: Uuid) -> Self {
+        Self {
+            id: Uuid::nil(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            user,
+            amount,
+            reason,
+            token,
+        }
+    }
+
+    /// Insert a new BalanceAdjustment row and assign ID and created_at.
+    pub async fn insert(&mut self, client: &impl GenericClient) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                INSERT INTO
+                    balance_adjustment(
+                        "user",
+                        amount,
+                        reason,
+                        token)
+                VALUES ($1, $2, $3, $4)
+             RETURNING id, created_at
+                "#,
+            )
+            .await
+            .unwrap();
+
+        let row = client
+            .query_one(
+                &stmt,
+                &[&self.user, &self.amount, &self.reason, &self.token],
+            )
+            .await?;
+
+        self.id = row.try_get("id")?;
+        self.created_at = row.try_get("created_at")?;
+        Ok(())
+    }
+}
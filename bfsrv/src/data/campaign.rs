@@ -1,6 +1,7 @@
 use crate::data::Result;
 use deadpool_postgres::GenericClient;
 use rust_decimal::Decimal;
+use time::OffsetDateTime;
 use tokio_postgres::Row;
 use uuid::Uuid;
 
@@ -10,9 +11,53 @@ pub struct Campaign {
     #[allow(dead_code)]
     pub hash: String,
     pub initial_balance: Decimal,
+    pub referral_bonus_amount: Decimal,
+    pub referral_bonus_percent: Decimal,
+    pub expires_at: Option<OffsetDateTime>,
+    pub max_redemptions: Option<u32>,
+    pub redemptions: u32,
+    /// Overrides the default low balance notification threshold.
+    pub low_balance_threshold: Option<Decimal>,
 }
 
 impl Campaign {
+    /// Create a new Campaign instance.
+    pub fn new(
+        initial_balance: Decimal,
+        referral_bonus_amount: Decimal,
+        referral_bonus_percent: Decimal,
+        expires_at: Option<OffsetDateTime>,
+        max_redemptions: Option<u32>,
+    ) -> Self {
+        Self {
+            id: Uuid::nil(),
+            hash: String::new(),
+            initial_balance,
+            referral_bonus_amount,
+            referral_bonus_percent,
+            expires_at,
+            max_redemptions,
+            redemptions: 0,
+            low_balance_threshold: None,
+        }
+    }
+
+    /// Get a campaign with a given ID.
+    pub async fn get(client: &impl GenericClient, id: Uuid) -> Result<Option<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM campaign
+                 WHERE id = $1
+                ",
+            )
+            .await
+            .unwrap();
+        let row = client.query_opt(&stmt, &[&id]).await?;
+        row.map(Self::from_row).transpose()
+    }
+
     /// Find campaign by a given promo code.
     pub async fn find_by_promo_code(
         client: &impl GenericClient,
@@ -33,11 +78,151 @@ impl Campaign {
         row.map(Self::from_row).transpose()
     }
 
+    /// Find all campaigns.
+    pub async fn find_all(client: &impl GenericClient) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM campaign
+                ",
+            )
+            .await
+            .unwrap();
+        let rows = client.query(&stmt, &[]).await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    /// Insert a new Campaign row for a given promo code and assign ID and hash.
+    /// The promo code is hashed with bcrypt of a given work factor (4 to 31).
+    pub async fn insert(
+        &mut self,
+        client: &impl GenericClient,
+        promo_code: &str,
+        work_factor: u32,
+    ) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                "
+                INSERT INTO
+                    campaign(
+                        hash,
+                        initial_balance,
+                        referral_bonus_amount,
+                        referral_bonus_percent,
+                        expires_at,
+                        max_redemptions,
+                        low_balance_threshold)
+                VALUES (crypt($1, gen_salt('bf', $8)), $2, $3, $4, $5, $6, $7)
+             RETURNING id, hash
+                ",
+            )
+            .await
+            .unwrap();
+
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &promo_code,
+                    &self.initial_balance,
+                    &self.referral_bonus_amount,
+                    &self.referral_bonus_percent,
+                    &self.expires_at,
+                    &self.max_redemptions.map(|m| m as i32),
+                    &self.low_balance_threshold,
+                    &(work_factor as i32),
+                ],
+            )
+            .await?;
+
+        self.id = row.try_get("id")?;
+        self.hash = row.try_get("hash")?;
+        self.redemptions = 0;
+        Ok(())
+    }
+
+    /// Atomically count a redemption of a campaign with a given ID.
+    /// Returns false if the campaign is expired or fully redeemed.
+    pub async fn redeem(client: &impl GenericClient, id: Uuid) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE campaign
+                   SET redemptions = redemptions + 1
+                 WHERE id = $1
+                       AND (expires_at IS NULL OR expires_at > now())
+                       AND (max_redemptions IS NULL OR redemptions < max_redemptions)
+                ",
+            )
+            .await
+            .unwrap();
+        let updated = client.execute(&stmt, &[&id]).await?;
+        Ok(updated > 0)
+    }
+
+    /// Check if the campaign can still be redeemed at a given time.
+    pub fn is_redeemable(&self, now: OffsetDateTime) -> bool {
+        self.expires_at.is_none_or(|e| e > now)
+            && self.max_redemptions.is_none_or(|m| self.redemptions < m)
+    }
+
+    /// Bonus to credit a referrer for a given top-up amount of a referred user.
+    pub fn referral_bonus(&self, amount: Decimal) -> Decimal {
+        self.referral_bonus_amount + amount * self.referral_bonus_percent / Decimal::ONE_HUNDRED
+    }
+
     fn from_row(row: Row) -> Result<Self> {
         Ok(Self {
             id: row.try_get("id")?,
             hash: row.try_get("hash")?,
             initial_balance: row.try_get("initial_balance")?,
+            referral_bonus_amount: row.try_get("referral_bonus_amount")?,
+            referral_bonus_percent: row.try_get("referral_bonus_percent")?,
+            expires_at: row.try_get("expires_at")?,
+            max_redemptions: row
+                .try_get::<'_, _, Option<i32>>("max_redemptions")?
+                .map(|m| m as u32),
+            redemptions: row.try_get::<'_, _, i32>("redemptions")? as u32,
+            low_balance_threshold: row.try_get("low_balance_threshold")?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{str::FromStr, time::Duration};
+
+    #[test]
+    fn test_campaign_referral_bonus() {
+        let dec = |s| Decimal::from_str(s).unwrap();
+        let mut campaign = Campaign::new(Decimal::ONE, Decimal::ZERO, Decimal::ZERO, None, None);
+        assert_eq!(campaign.referral_bonus(dec("10")), Decimal::ZERO);
+
+        campaign.referral_bonus_amount = dec("0.5");
+        assert_eq!(campaign.referral_bonus(dec("10")), dec("0.5"));
+
+        campaign.referral_bonus_percent = dec("5");
+        assert_eq!(campaign.referral_bonus(dec("10")), dec("1.0"));
+    }
+
+    #[test]
+    fn test_campaign_is_redeemable() {
+        let now = OffsetDateTime::now_utc();
+        let mut campaign = Campaign::new(Decimal::ONE, Decimal::ZERO, Decimal::ZERO, None, None);
+        assert!(campaign.is_redeemable(now));
+
+        campaign.expires_at = Some(now + Duration::from_secs(1));
+        assert!(campaign.is_redeemable(now));
+        campaign.expires_at = Some(now);
+        assert!(!campaign.is_redeemable(now));
+
+        campaign.expires_at = None;
+        campaign.max_redemptions = Some(2);
+        campaign.redemptions = 1;
+        assert!(campaign.is_redeemable(now));
+        campaign.redemptions = 2;
+        assert!(!campaign.is_redeemable(now));
+    }
+}
@@ -1,18 +1,25 @@
-use crate::data::Result;
+use crate::{currency_converter::minor_unit_scale, data::Result};
 use deadpool_postgres::GenericClient;
 use postgres_types::{FromSql, ToSql};
-use rust_decimal::Decimal;
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::collections::HashSet;
 use tokio_postgres::Row;
 use uuid::Uuid;
 
 /// Node task type.
-#[derive(Clone, Copy, Debug, ToSql, FromSql)]
+#[derive(Clone, Copy, Debug, Default, Eq, FromSql, Hash, PartialEq, ToSql)]
 #[postgres(name = "task_type", rename_all = "snake_case")]
 pub enum TaskType {
     Segment,
+    #[default]
     Transcribe,
+    /// Transcription labelling segments with speakers.
+    Diarize,
 }
 
+/// Extra fee digits beyond currency minor units (fees are charged per second).
+const FEE_EXTRA_SCALE: u32 = 6;
+
 /// Node capability.
 pub struct Capability {
     #[allow(dead_code)]
@@ -21,7 +28,22 @@ pub struct Capability {
     pub compute_load: u32,
     pub memory_load: u32,
     pub fee: Decimal,
+    /// Comma-separated ISO-639-1 codes, "*" or "all" for any language.
     pub languages: Option<String>,
+    pub max_segment_duration: Option<f32>,
+    pub segment_window_duration: Option<f32>,
+    /// Preferred input sample rate (in Hz) of a transcription capability.
+    pub sample_rate: Option<u32>,
+    /// RMS energy (relative to full scale) below which leading and trailing
+    /// audio of speech intervals is trimmed before transcription.
+    pub trim_threshold: Option<f32>,
+    /// Number of speech segments of a session transcribed concurrently.
+    pub transcribe_concurrency: Option<u32>,
+    /// Capture session audio for debugging (privacy-sensitive, disabled by default).
+    pub capture_audio: bool,
+    /// Place tasks only on nodes hosting no other capabilities (for cost control),
+    /// otherwise any node hosting a superset of task capabilities fits.
+    pub strict_placement: bool,
 }
 
 impl Capability {
@@ -47,6 +69,43 @@ impl Capability {
         result
     }
 
+    /// Sum capability fees rounded half-up to a fee scale of a given currency.
+    /// Returns None if any fee is negative or the sum overflows.
+    pub fn total_fee(capabilities: &[Self], currency: &str) -> Option<Decimal> {
+        let total = capabilities.iter().try_fold(Decimal::ZERO, |acc, cap| {
+            if cap.fee.is_sign_negative() && !cap.fee.is_zero() {
+                return None;
+            }
+            acc.checked_add(cap.fee)
+        })?;
+        Some(total.round_dp_with_strategy(
+            minor_unit_scale(currency) + FEE_EXTRA_SCALE,
+            RoundingStrategy::MidpointAwayFromZero,
+        ))
+    }
+
+    /// Parse supported languages (lowercased), None means any language.
+    pub fn supported_languages(&self) -> Option<HashSet<String>> {
+        let languages: HashSet<_> = self
+            .languages
+            .as_deref()?
+            .split(',')
+            .map(|l| l.trim().to_lowercase())
+            .filter(|l| !l.is_empty())
+            .collect();
+        if languages.contains("*") || languages.contains("all") {
+            None
+        } else {
+            Some(languages)
+        }
+    }
+
+    /// Check if a given language is supported.
+    pub fn supports_language(&self, lang: &str) -> bool {
+        self.supported_languages()
+            .is_none_or(|l| l.contains(&lang.trim().to_lowercase()))
+    }
+
     fn from_row(row: Row) -> Result<Self> {
         Ok(Self {
             id: row.try_get("id")?,
@@ -55,6 +114,101 @@ impl Capability {
             memory_load: row.try_get::<'_, _, i32>("memory_load")? as u32,
             fee: row.try_get("fee")?,
             languages: row.try_get("languages")?,
+            max_segment_duration: row.try_get("max_segment_duration")?,
+            segment_window_duration: row.try_get("segment_window_duration")?,
+            sample_rate: row
+                .try_get::<'_, _, Option<i32>>("sample_rate")?
+                .map(|r| r as u32),
+            trim_threshold: row.try_get("trim_threshold")?,
+            transcribe_concurrency: row
+                .try_get::<'_, _, Option<i32>>("transcribe_concurrency")?
+                .map(|c| c as u32),
+            capture_audio: row.try_get("capture_audio")?,
+            strict_placement: row.try_get("strict_placement")?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capability(languages: Option<&str>) -> Capability {
+        Capability {
+            id: Uuid::nil(),
+            name: "transcribe-cpu".to_owned(),
+            compute_load: 0,
+            memory_load: 0,
+            fee: Decimal::ZERO,
+            languages: languages.map(str::to_owned),
+            max_segment_duration: None,
+            segment_window_duration: None,
+            sample_rate: None,
+            trim_threshold: None,
+            transcribe_concurrency: None,
+            capture_audio: false,
+            strict_placement: false,
+        }
+    }
+
+    #[test]
+    fn test_total_fee() {
+        let dec = |s: &str| s.parse::<Decimal>().unwrap();
+        let with_fees = |fees: &[Decimal]| -> Vec<_> {
+            fees.iter()
+                .map(|&fee| Capability {
+                    fee,
+                    ..capability(None)
+                })
+                .collect()
+        };
+        let total = |fees: &[&str], currency| {
+            let fees: Vec<_> = fees.iter().map(|f| dec(f)).collect();
+            Capability::total_fee(&with_fees(&fees), currency)
+        };
+
+        assert_eq!(total(&[], "USD"), Some(Decimal::ZERO));
+        assert_eq!(total(&["0.001"], "USD"), Some(dec("0.001")));
+        assert_eq!(total(&["0.001", "0.02", "1"], "USD"), Some(dec("1.021")));
+        assert_eq!(total(&["0", "-0"], "USD"), Some(Decimal::ZERO));
+        assert_eq!(
+            total(&["0.00000001", "0.00000004"], "USD"),
+            Some(dec("0.00000005"))
+        );
+        assert_eq!(total(&["0.000000005"], "USD"), Some(dec("0.00000001")));
+        assert_eq!(total(&["0.000000004"], "USD"), Some(Decimal::ZERO));
+        assert_eq!(total(&["0.0000005"], "JPY"), Some(dec("0.000001")));
+        assert_eq!(total(&["0.0000000005"], "BHD"), Some(dec("0.000000001")));
+        assert_eq!(total(&["1", "-0.5"], "USD"), None);
+
+        let max = with_fees(&[Decimal::MAX, Decimal::ZERO]);
+        assert_eq!(Capability::total_fee(&max, "USD"), Some(Decimal::MAX));
+        let overflow = with_fees(&[Decimal::MAX, Decimal::ONE]);
+        assert_eq!(Capability::total_fee(&overflow, "USD"), None);
+    }
+
+    #[test]
+    fn test_supported_languages() {
+        let set = |langs: &[&str]| Some(langs.iter().map(|l| l.to_string()).collect());
+
+        assert_eq!(capability(None).supported_languages(), None);
+        assert_eq!(capability(Some("")).supported_languages(), set(&[]));
+        assert_eq!(capability(Some("en")).supported_languages(), set(&["en"]));
+        assert_eq!(
+            capability(Some(" en, NO ,,de ")).supported_languages(),
+            set(&["en", "no", "de"])
+        );
+        assert_eq!(capability(Some("*")).supported_languages(), None);
+        assert_eq!(capability(Some("en, ALL")).supported_languages(), None);
+    }
+
+    #[test]
+    fn test_supports_language() {
+        assert!(capability(None).supports_language("en"));
+        assert!(capability(Some("*")).supports_language("en"));
+        assert!(capability(Some("en,no")).supports_language("no"));
+        assert!(capability(Some("en, no")).supports_language("NO"));
+        assert!(!capability(Some("en,no")).supports_language("de"));
+        assert!(!capability(Some("")).supports_language("en"));
+    }
+}
@@ -0,0 +1,101 @@
+use crate::data::Result;
+use deadpool_postgres::GenericClient;
+use lettre::Address as EmailAddress;
+use std::str::FromStr;
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+
+/// Email address which must not be mailed (e.g. due to bounces or complaints).
+pub struct EmailSuppression {
+    pub email: EmailAddress,
+    pub reason: String,
+    pub created_at: OffsetDateTime,
+}
+
+impl EmailSuppression {
+    /// Create a new EmailSuppression instance.
+    pub fn new(email: EmailAddress, reason: String) -> Self {
+        Self {
+            email,
+            reason,
+            created_at: OffsetDateTime::UNIX_EPOCH,
+        }
+    }
+
+    /// Get a suppression of a given email (matched case-insensitively).
+    pub async fn get(client: &impl GenericClient, email: &EmailAddress) -> Result<Option<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM email_suppression
+                 WHERE lower(email) = lower($1)
+                ",
+            )
+            .await
+            .unwrap();
+        let email_str: &str = email.as_ref();
+        let row = client.query_opt(&stmt, &[&email_str]).await?;
+        row.map(Self::from_row).transpose()
+    }
+
+    /// List all suppressions ordered by creation time.
+    pub async fn list(client: &impl GenericClient) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM email_suppression
+                 ORDER BY created_at
+                ",
+            )
+            .await
+            .unwrap();
+        let rows = client.query(&stmt, &[]).await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    /// Insert a new EmailSuppression row (or update the reason of an existing one)
+    /// and assign created_at.
+    pub async fn upsert(&mut self, client: &impl GenericClient) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                "
+                INSERT INTO email_suppression(email, reason)
+                VALUES ($1, $2)
+                    ON CONFLICT (lower(email))
+                    DO UPDATE SET reason = EXCLUDED.reason
+             RETURNING created_at
+                ",
+            )
+            .await
+            .unwrap();
+        let email_str: &str = self.email.as_ref();
+        let row = client.query_one(&stmt, &[&email_str, &self.reason]).await?;
+        self.created_at = row.try_get("created_at")?;
+        Ok(())
+    }
+
+    /// Delete a suppression of a given email. Returns false if there was none.
+    pub async fn delete(client: &impl GenericClient, email: &EmailAddress) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                "
+                DELETE FROM email_suppression
+                 WHERE lower(email) = lower($1)
+                ",
+            )
+            .await
+            .unwrap();
+        let email_str: &str = email.as_ref();
+        Ok(client.execute(&stmt, &[&email_str]).await? > 0)
+    }
+
+    fn from_row(row: Row) -> Result<Self> {
+        Ok(Self {
+            email: EmailAddress::from_str(row.try_get("email")?)?,
+            reason: row.try_get("reason")?,
+            created_at: row.try_get("created_at")?,
+        })
+    }
+}
@@ -0,0 +1,300 @@
+use crate::{data::Result, util::text::TextNormalization};
+use deadpool_postgres::GenericClient;
+use postgres_types::{FromSql, ToSql};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSql, FromSql)]
+#[postgres(name = "job_status", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// Columns of job rows except audio (which is only read to process a job).
+const COLUMNS: &str = r#"
+    id, created_at, updated_at, "user", tariff, lang, diarize, punctuation, casing, status,
+    items, total_seconds, total_cost, transcript, error
+"#;
+
+/// Batch transcription job.
+pub struct Job {
+    pub id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub updated_at: OffsetDateTime,
+    pub user: Uuid,
+    pub tariff: String,
+    pub lang: Option<String>,
+    pub diarize: bool,
+    pub normalization: TextNormalization,
+    pub status: JobStatus,
+    /// Items transcribed so far.
+    pub items: serde_json::Value,
+    pub total_seconds: Option<f32>,
+    pub total_cost: Option<Decimal>,
+    pub transcript: Option<Uuid>,
+    /// Error of a failed job (code and message as in HTTP responses).
+    pub error: Option<serde_json::Value>,
+}
+
+impl Job {
+    /// Create a new Job instance.
+    pub fn new(
+        user: Uuid,
+        tariff: String,
+        lang: Option<String>,
+        diarize: bool,
+        normalization: TextNormalization,
+    ) -> Self {
+        Self {
+            id: Uuid::nil(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            updated_at: OffsetDateTime::UNIX_EPOCH,
+            user,
+            tariff,
+            lang,
+            diarize,
+            normalization,
+            status: JobStatus::Queued,
+            items: serde_json::Value::Array(Vec::new()),
+            total_seconds: None,
+            total_cost: None,
+            transcript: None,
+            error: None,
+        }
+    }
+
+    /// Get a job with a given ID.
+    pub async fn get(client: &impl GenericClient, id: Uuid) -> Result<Option<Self>> {
+        let stmt = client
+            .prepare_cached(&format!(
+                "
+                SELECT {COLUMNS}
+                  FROM job
+                 WHERE id = $1
+                "
+            ))
+            .await
+            .unwrap();
+        let row = client.query_opt(&stmt, &[&id]).await?;
+        row.map(Self::from_row).transpose()
+    }
+
+    /// Insert a new queued Job row with given audio and assign ID and timestamps.
+    pub async fn insert(&mut self, client: &impl GenericClient, audio: &[u8]) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                INSERT INTO
+                    job(
+                        "user",
+                        tariff,
+                        lang,
+                        diarize,
+                        punctuation,
+                        casing,
+                        audio)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, created_at, updated_at
+                "#,
+            )
+            .await
+            .unwrap();
+
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &self.user,
+                    &self.tariff,
+                    &self.lang,
+                    &self.diarize,
+                    &self.normalization.punctuation,
+                    &self.normalization.casing,
+                    &audio,
+                ],
+            )
+            .await?;
+
+        self.id = row.try_get("id")?;
+        self.created_at = row.try_get("created_at")?;
+        self.updated_at = row.try_get("updated_at")?;
+        self.status = JobStatus::Queued;
+        Ok(())
+    }
+
+    /// Atomically mark the oldest queued job as running.
+    /// Returns the job along with its audio.
+    pub async fn claim_next(client: &impl GenericClient) -> Result<Option<(Self, Vec<u8>)>> {
+        let stmt = client
+            .prepare_cached(&format!(
+                "
+                UPDATE job
+                   SET status = 'running',
+                       updated_at = now()
+                 WHERE id = (
+                           SELECT id
+                             FROM job
+                            WHERE status = 'queued'
+                         ORDER BY created_at
+                              FOR UPDATE SKIP LOCKED
+                            LIMIT 1)
+             RETURNING {COLUMNS}, audio
+                "
+            ))
+            .await
+            .unwrap();
+        let Some(row) = client.query_opt(&stmt, &[]).await? else {
+            return Ok(None);
+        };
+        let audio: Option<Vec<u8>> = row.try_get("audio")?;
+        Ok(Some((Self::from_row(row)?, audio.unwrap_or_default())))
+    }
+
+    /// Append a transcribed item to a running job with a given ID,
+    /// returns false if the job is not running anymore.
+    pub async fn append_item(
+        client: &impl GenericClient,
+        id: Uuid,
+        item: &serde_json::Value,
+    ) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE job
+                   SET items = items || jsonb_build_array($2::jsonb),
+                       updated_at = now()
+                 WHERE id = $1
+                       AND status = 'running'
+                ",
+            )
+            .await
+            .unwrap();
+        Ok(client.execute(&stmt, &[&id, item]).await? > 0)
+    }
+
+    /// Touch a running job with a given ID (so it isn't considered stale),
+    /// returns false if the job is not running anymore.
+    pub async fn heartbeat(client: &impl GenericClient, id: Uuid) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE job
+                   SET updated_at = now()
+                 WHERE id = $1
+                       AND status = 'running'
+                ",
+            )
+            .await
+            .unwrap();
+        Ok(client.execute(&stmt, &[&id]).await? > 0)
+    }
+
+    /// Mark a running job with a given ID as completed releasing its audio,
+    /// returns false if the job is not running anymore.
+    pub async fn complete(
+        client: &impl GenericClient,
+        id: Uuid,
+        total_seconds: f32,
+        total_cost: Decimal,
+        transcript: Uuid,
+    ) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE job
+                   SET status = 'completed',
+                       updated_at = now(),
+                       audio = NULL,
+                       total_seconds = $2,
+                       total_cost = $3,
+                       transcript = $4
+                 WHERE id = $1
+                       AND status = 'running'
+                ",
+            )
+            .await
+            .unwrap();
+        let updated = client
+            .execute(&stmt, &[&id, &total_seconds, &total_cost, &transcript])
+            .await?;
+        Ok(updated > 0)
+    }
+
+    /// Mark a running job with a given ID as failed releasing its audio.
+    pub async fn fail(
+        client: &impl GenericClient,
+        id: Uuid,
+        error: &serde_json::Value,
+    ) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE job
+                   SET status = 'failed',
+                       updated_at = now(),
+                       audio = NULL,
+                       error = $2
+                 WHERE id = $1
+                       AND status = 'running'
+                ",
+            )
+            .await
+            .unwrap();
+        client.execute(&stmt, &[&id, error]).await?;
+        Ok(())
+    }
+
+    /// Fail running jobs not updated since a given time (e.g. after a crash).
+    /// They are not requeued as their transcribed segments are already charged.
+    /// Returns the number of failed jobs.
+    pub async fn fail_stale(
+        client: &impl GenericClient,
+        updated_before: OffsetDateTime,
+        error: &serde_json::Value,
+    ) -> Result<u64> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE job
+                   SET status = 'failed',
+                       updated_at = now(),
+                       audio = NULL,
+                       error = $2
+                 WHERE status = 'running'
+                       AND updated_at < $1
+                ",
+            )
+            .await
+            .unwrap();
+        Ok(client.execute(&stmt, &[&updated_before, error]).await?)
+    }
+
+    fn from_row(row: Row) -> Result<Self> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            created_at: row.try_get("created_at")?,
+            updated_at: row.try_get("updated_at")?,
+            user: row.try_get("user")?,
+            tariff: row.try_get("tariff")?,
+            lang: row.try_get("lang")?,
+            diarize: row.try_get("diarize")?,
+            normalization: TextNormalization {
+                punctuation: row.try_get("punctuation")?,
+                casing: row.try_get("casing")?,
+            },
+            status: row.try_get("status")?,
+            items: row.try_get("items")?,
+            total_seconds: row.try_get("total_seconds")?,
+            total_cost: row.try_get("total_cost")?,
+            transcript: row.try_get("transcript")?,
+            error: row.try_get("error")?,
+        })
+    }
+}
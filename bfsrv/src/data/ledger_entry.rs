@@ -0,0 +1,59 @@
+use crate::data::Result;
+use deadpool_postgres::GenericClient;
+use rust_decimal::Decimal;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+/// Balance credit of a completed payment.
+pub struct LedgerEntry {
+    pub id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub user: Uuid,
+    pub payment: Uuid,
+    pub amount: Decimal,
+}
+
+impl LedgerEntry {
+    /// Create a new LedgerEntry instance.
+    pub fn new(user: Uuid, payment: Uuid, amount: Decimal) -> Self {
+        Self {
+            id: Uuid::nil(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            user,
+            payment,
+            amount,
+        }
+    }
+
+    /// Insert a new LedgerEntry row and assign ID and created_at.
+    /// Returns false if the payment has been already credited
+    /// (a concurrent insertion waits for the other transaction to finish).
+    pub async fn insert(&mut self, client: &impl GenericClient) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                INSERT INTO
+                    ledger_entry(
+                        "user",
+                        payment,
+                        amount)
+                VALUES ($1, $2, $3)
+                    ON CONFLICT (payment) DO NOTHING
+             RETURNING id, created_at
+                "#,
+            )
+            .await
+            .unwrap();
+
+        let Some(row) = client
+            .query_opt(&stmt, &[&self.user, &self.payment, &self.amount])
+            .await?
+        else {
+            return Ok(false);
+        };
+
+        self.id = row.try_get("id")?;
+        self.created_at = row.try_get("created_at")?;
+        Ok(true)
+    }
+}
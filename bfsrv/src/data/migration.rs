@@ -0,0 +1,200 @@
+use crate::data::Result;
+use deadpool_postgres::Client;
+use log::info;
+
+/// Versioned schema migration.
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Schema migrations in order of their versions.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "billing_and_transcripts",
+        sql: include_str!("../../migrations/0002_billing_and_transcripts.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "email_suppression",
+        sql: include_str!("../../migrations/0003_email_suppression.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "low_balance_notification",
+        sql: include_str!("../../migrations/0004_low_balance_notification.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "capability_sample_rate",
+        sql: include_str!("../../migrations/0005_capability_sample_rate.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "trial_users",
+        sql: include_str!("../../migrations/0006_trial_users.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "user_credit_limit",
+        sql: include_str!("../../migrations/0007_user_credit_limit.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "transcription_jobs",
+        sql: include_str!("../../migrations/0008_transcription_jobs.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "diarization",
+        sql: include_str!("../../migrations/0009_diarization.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "text_normalization",
+        sql: include_str!("../../migrations/0010_text_normalization.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "sessions",
+        sql: include_str!("../../migrations/0011_sessions.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "ledger_entries",
+        sql: include_str!("../../migrations/0012_ledger_entries.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "capability_trim_threshold",
+        sql: include_str!("../../migrations/0013_capability_trim_threshold.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "session_started_at_idx",
+        sql: include_str!("../../migrations/0014_session_started_at_idx.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "capability_transcribe_concurrency",
+        sql: include_str!("../../migrations/0015_capability_transcribe_concurrency.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "node_capability_weight",
+        sql: include_str!("../../migrations/0016_node_capability_weight.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "capability_capture_audio",
+        sql: include_str!("../../migrations/0017_capability_capture_audio.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "token_last_used_at",
+        sql: include_str!("../../migrations/0018_token_last_used_at.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "session_segment_stats",
+        sql: include_str!("../../migrations/0019_session_segment_stats.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "capability_strict_placement",
+        sql: include_str!("../../migrations/0020_capability_strict_placement.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "drop_user_allocated_fee",
+        sql: include_str!("../../migrations/0021_drop_user_allocated_fee.sql"),
+    },
+    Migration {
+        version: 22,
+        name: "capability_segment_ranges",
+        sql: include_str!("../../migrations/0022_capability_segment_ranges.sql"),
+    },
+];
+
+/// Advisory lock key which serializes concurrently running migrations.
+const MIGRATION_LOCK_KEY: i64 = 0x626c_6f62_6669_7368;
+
+/// Apply pending migrations each in its own transaction.
+/// Returns versions of the applied migrations.
+pub async fn migrate(client: &mut Client) -> Result<Vec<i32>> {
+    client
+        .batch_execute(
+            "
+            CREATE TABLE IF NOT EXISTS schema_migration(
+              version integer PRIMARY KEY,
+              name text NOT NULL,
+              applied_at timestamp with time zone NOT NULL DEFAULT now()
+            )
+            ",
+        )
+        .await?;
+
+    let mut applied = Vec::new();
+    for migration in MIGRATIONS {
+        let tx = client.transaction().await?;
+        tx.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK_KEY])
+            .await?;
+
+        let row = tx
+            .query_one(
+                "SELECT count(*) > 0 FROM schema_migration WHERE version = $1",
+                &[&migration.version],
+            )
+            .await?;
+        if row.try_get(0)? {
+            continue;
+        }
+
+        // Databases created from schema.sql before migrations were introduced
+        // already have the initial schema, so it is only recorded.
+        let baseline = migration.version == 1
+            && tx
+                .query_one("SELECT to_regclass('\"user\"') IS NOT NULL", &[])
+                .await?
+                .try_get(0)?;
+        if !baseline {
+            tx.batch_execute(migration.sql).await?;
+        }
+
+        tx.execute(
+            "INSERT INTO schema_migration(version, name) VALUES ($1, $2)",
+            &[&migration.version, &migration.name],
+        )
+        .await?;
+        tx.commit().await?;
+
+        info!(
+            "applied migration {} ({})",
+            migration.version, migration.name
+        );
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrations_ordered() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1);
+            assert!(!migration.name.is_empty());
+            assert!(!migration.sql.trim().is_empty());
+        }
+    }
+}
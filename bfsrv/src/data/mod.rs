@@ -1,11 +1,21 @@
+pub mod balance_adjustment;
 pub mod campaign;
 pub mod capability;
+pub mod email_suppression;
+pub mod job;
+pub mod ledger_entry;
+pub mod migration;
 pub mod node;
 pub mod payment;
+pub mod session;
 pub mod token;
+pub mod transcript;
 pub mod user;
 
-use axum::http::StatusCode;
+use crate::error_kind::{self as kind, ErrorKind};
+use deadpool_postgres::{PoolConfig, Timeouts};
+use std::time::Duration;
+use tokio_postgres::error::SqlState;
 
 /// Data error.
 #[derive(Debug, thiserror::Error)]
@@ -31,24 +41,40 @@ pub enum Error {
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
         match self {
-            Lettre(_) | LettreAddress(_) | Postgres(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Lettre(_) => &kind::LETTRE,
+            LettreAddress(_) => &kind::LETTRE_ADDRESS,
+            Postgres(_) => &kind::POSTGRES,
         }
     }
 
-    /// Kind code.
-    pub fn code(&self) -> &str {
-        use Error::*;
-        match self {
-            Lettre(_) => "lettre",
-            LettreAddress(_) => "lettre_address",
-            Postgres(_) => "postgres",
-        }
+    /// Check if the error is caused by a unique constraint violation.
+    pub fn is_unique_violation(&self) -> bool {
+        matches!(self, Self::Postgres(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION))
     }
 }
 
 /// Data result.
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// Postgres pool config with a given size and timeouts to wait for a connection,
+/// create a new one and recycle an idle one.
+pub fn pool_config(
+    max_size: usize,
+    wait_timeout: Duration,
+    create_timeout: Duration,
+    recycle_timeout: Duration,
+) -> PoolConfig {
+    PoolConfig {
+        max_size,
+        timeouts: Timeouts {
+            wait: Some(wait_timeout),
+            create: Some(create_timeout),
+            recycle: Some(recycle_timeout),
+        },
+        ..Default::default()
+    }
+}
@@ -2,6 +2,7 @@ use std::net::IpAddr;
 
 use crate::data::Result;
 use deadpool_postgres::GenericClient;
+use log::warn;
 use tokio_postgres::Row;
 use uuid::Uuid;
 
@@ -17,18 +18,27 @@ pub struct Node {
 }
 
 impl Node {
-    /// Find a random node with specified resources available.
+    /// Find a random node with specified resources available, favouring
+    /// the preferred nodes and then the highest total weight of the capabilities.
+    /// Nodes hosting a superset of the capabilities fit unless allowed
+    /// capabilities are given (then the node may host only them).
+    /// Nodes with excluded IP addresses are skipped.
     pub async fn find_one_with_available_resources(
         client: &impl GenericClient,
         capabilities: &[Uuid],
         compute: u32,
         memory: u32,
+        excluded: &[IpAddr],
+        allowed: Option<&[Uuid]>,
+        preferred: &[Uuid],
     ) -> Result<Option<Node>> {
         let stmt = client
             .prepare_cached(
                 "
                 WITH capable AS (
-                    SELECT node, COUNT(DISTINCT capability) AS matched
+                    SELECT node,
+                           COUNT(DISTINCT capability) AS matched,
+                           SUM(weight) AS weight
                       FROM node_capability
                      WHERE capability = ANY($1)
                      GROUP BY node
@@ -39,14 +49,33 @@ impl Node {
                  WHERE matched = cardinality($1)
                        AND compute_capacity - compute_load >= $2
                        AND memory_capacity - memory_load >= $3
-                 ORDER BY random() -- Too few nodes to worry about inefficiency.
+                       AND NOT ip_address = ANY($4)
+                       AND ($5::uuid[] IS NULL OR NOT EXISTS (
+                           SELECT 1
+                             FROM node_capability
+                            WHERE node_capability.node = node.id
+                                  AND NOT node_capability.capability = ANY($5)
+                       ))
+                 ORDER BY id = ANY($6) DESC,
+                          weight DESC,
+                          random() -- Too few nodes to worry about inefficiency.
                  LIMIT 1
                 ",
             )
             .await
             .unwrap();
         let row = client
-            .query_opt(&stmt, &[&capabilities, &(compute as i32), &(memory as i32)])
+            .query_opt(
+                &stmt,
+                &[
+                    &capabilities,
+                    &(compute as i32),
+                    &(memory as i32),
+                    &excluded,
+                    &allowed,
+                    &preferred,
+                ],
+            )
             .await?;
         row.map(Self::from_row).transpose()
     }
@@ -67,6 +96,22 @@ impl Node {
         row.map(Self::from_row).transpose()
     }
 
+    /// List all nodes ordered by label.
+    pub async fn list(client: &impl GenericClient) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM node
+                 ORDER BY label
+                ",
+            )
+            .await
+            .unwrap();
+        let rows = client.query(&stmt, &[]).await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
     /// Update node row columns with the current field values.
     pub async fn update(&self, client: &impl GenericClient) -> Result<()> {
         let stmt = client
@@ -101,6 +146,19 @@ impl Node {
         Ok(())
     }
 
+    /// Subtract released resources from the node loads. The loads saturate
+    /// at zero since they can be cleared while allocations are outstanding.
+    pub fn release_load(&mut self, compute: u32, memory: u32) {
+        if compute > self.compute_load || memory > self.memory_load {
+            warn!(
+                "node {} load underflow on release of compute {compute} (load {}), memory {memory} (load {})",
+                self.id, self.compute_load, self.memory_load
+            );
+        }
+        self.compute_load = self.compute_load.saturating_sub(compute);
+        self.memory_load = self.memory_load.saturating_sub(memory);
+    }
+
     /// Clear compute_load and memory_load for every node.
     pub async fn clear_loads(client: &impl GenericClient) -> Result<()> {
         let stmt = client
@@ -129,3 +187,34 @@ impl Node {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(compute_load: u32, memory_load: u32) -> Node {
+        Node {
+            id: Uuid::nil(),
+            label: "test".to_owned(),
+            ip_address: [127, 0, 0, 1].into(),
+            compute_capacity: 10,
+            memory_capacity: 10,
+            compute_load,
+            memory_load,
+        }
+    }
+
+    #[test]
+    fn test_node_release_load() {
+        let mut node = node(5, 3);
+        node.release_load(2, 3);
+        assert_eq!((node.compute_load, node.memory_load), (3, 0));
+    }
+
+    #[test]
+    fn test_node_release_load_underflow() {
+        let mut node = node(0, 1);
+        node.release_load(2, 4);
+        assert_eq!((node.compute_load, node.memory_load), (0, 0));
+    }
+}
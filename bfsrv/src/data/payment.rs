@@ -3,6 +3,7 @@ use deadpool_postgres::GenericClient;
 use postgres_types::{FromSql, ToSql};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr, time::Duration};
 use time::OffsetDateTime;
 use tokio_postgres::Row;
 use uuid::Uuid;
@@ -24,6 +25,42 @@ pub enum PaymentProcessor {
     Paypal,
 }
 
+/// Position in a payment list (created_at and ID of a payment to continue from).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaymentCursor {
+    pub created_at: OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl PaymentCursor {
+    /// Cursor pointing to a given payment.
+    pub fn of(payment: &Payment) -> Self {
+        Self {
+            created_at: payment.created_at,
+            id: payment.id,
+        }
+    }
+}
+
+impl fmt::Display for PaymentCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}", self.created_at.unix_timestamp_nanos(), self.id)
+    }
+}
+
+impl FromStr for PaymentCursor {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (nanos, id) = s.split_once('_').ok_or(())?;
+        let nanos = nanos.parse().map_err(|_| ())?;
+        Ok(Self {
+            created_at: OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|_| ())?,
+            id: id.parse().map_err(|_| ())?,
+        })
+    }
+}
+
 /// Balance top-up payment.
 pub struct Payment {
     pub id: Uuid,
@@ -144,23 +181,127 @@ impl Payment {
         Ok(())
     }
 
-    /// Find payments from a given user. The payments are sorted by created_at in descending order.
-    pub async fn find_from_user(client: &impl GenericClient, user: Uuid) -> Result<Vec<Self>> {
+    /// Find up to a limit of payments from a given user located strictly before a cursor.
+    /// The payments are sorted by created_at (then by ID) in descending order.
+    pub async fn find_from_user(
+        client: &impl GenericClient,
+        user: Uuid,
+        before: Option<PaymentCursor>,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM payment
+                 WHERE from_user = $1 -- use payment_from_user_idx
+                       AND ($2::timestamptz IS NULL OR (created_at, id) < ($2, $3))
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT $4
+                ",
+            )
+            .await
+            .unwrap();
+        let rows = client
+            .query(
+                &stmt,
+                &[
+                    &user,
+                    &before.map(|c| c.created_at),
+                    &before.map(|c| c.id),
+                    &limit,
+                ],
+            )
+            .await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    /// Find the latest not canceled payment from a given user.
+    pub async fn find_last_active_from_user(
+        client: &impl GenericClient,
+        user: Uuid,
+    ) -> Result<Option<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM payment
+                 WHERE from_user = $1 -- use payment_from_user_idx
+                       AND status <> 'canceled'
+                 ORDER BY created_at DESC, id DESC
+                 LIMIT 1
+                ",
+            )
+            .await
+            .unwrap();
+        let row = client.query_opt(&stmt, &[&user]).await?;
+        row.map(Self::from_row).transpose()
+    }
+
+    /// Find new or approved payments created before a given time.
+    pub async fn find_pending_created_before(
+        client: &impl GenericClient,
+        before: OffsetDateTime,
+    ) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM payment
+                 WHERE status IN ('new', 'approved')
+                       AND created_at < $1 -- use payment_pending_created_at_idx
+                 ORDER BY created_at
+                ",
+            )
+            .await
+            .unwrap();
+        let rows = client.query(&stmt, &[&before]).await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    /// Find new payments older than a given age.
+    pub async fn find_stale(
+        client: &impl GenericClient,
+        older_than: Duration,
+    ) -> Result<Vec<Self>> {
         let stmt = client
             .prepare_cached(
                 "
                 SELECT *
                   FROM payment
-                 WHERE from_user = $1
-                 ORDER BY created_at DESC
+                 WHERE status = 'new'
+                       AND created_at < $1 -- use payment_pending_created_at_idx
                 ",
             )
             .await
             .unwrap();
-        let rows = client.query(&stmt, &[&user]).await?;
+        let before = OffsetDateTime::now_utc() - older_than;
+        let rows = client.query(&stmt, &[&before]).await?;
         rows.into_iter().map(Self::from_row).collect()
     }
 
+    /// Change status of payments with given IDs which still have a given status.
+    /// Returns the number of updated payments.
+    pub async fn update_statuses(
+        client: &impl GenericClient,
+        ids: &[Uuid],
+        from: PaymentStatus,
+        to: PaymentStatus,
+    ) -> Result<u64> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE payment
+                   SET status = $3
+                 WHERE id = ANY($1)
+                       AND status = $2
+                ",
+            )
+            .await
+            .unwrap();
+        Ok(client.execute(&stmt, &[&ids, &from, &to]).await?)
+    }
+
     /// Update payment row columns with the current field values.
     pub async fn update(&self, client: &impl GenericClient) -> Result<()> {
         let stmt = client
@@ -219,3 +360,30 @@ impl Payment {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payment_cursor() {
+        let cursor = PaymentCursor {
+            created_at: OffsetDateTime::from_unix_timestamp_nanos(1_717_000_000_123_456_000)
+                .unwrap(),
+            id: Uuid::from_u128(42),
+        };
+        let s = cursor.to_string();
+        assert_eq!(
+            s,
+            "1717000000123456000_00000000-0000-0000-0000-00000000002a"
+        );
+        assert_eq!(s.parse(), Ok(cursor));
+
+        assert!("".parse::<PaymentCursor>().is_err());
+        assert!("1717000000".parse::<PaymentCursor>().is_err());
+        assert!("abc_00000000-0000-0000-0000-00000000002a"
+            .parse::<PaymentCursor>()
+            .is_err());
+        assert!("1717000000_not-a-uuid".parse::<PaymentCursor>().is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::data::{capability::TaskType, Result};
+use deadpool_postgres::GenericClient;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+/// Position in a session list (started_at and ID of a session to continue from).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SessionCursor {
+    pub started_at: OffsetDateTime,
+    pub id: Uuid,
+}
+
+impl SessionCursor {
+    /// Cursor pointing to a given session.
+    pub fn of(session: &Session) -> Self {
+        Self {
+            started_at: session.started_at,
+            id: session.id,
+        }
+    }
+}
+
+/// Statistics of segments produced by VAD during a session.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SegmentStats {
+    pub speech_segments: u32,
+    pub void_segments: u32,
+    pub speech_seconds: f32,
+    pub mean_speech_seconds: f32,
+    pub median_speech_seconds: f32,
+    pub longest_speech_seconds: f32,
+}
+
+/// Recorded outcome of a transcription session (streamed or of a whole file).
+pub struct Session {
+    pub id: Uuid,
+    pub user: Uuid,
+    pub tariff: String,
+    pub task: TaskType,
+    pub started_at: OffsetDateTime,
+    pub ended_at: OffsetDateTime,
+    pub total_seconds: f32,
+    pub total_cost: Decimal,
+    pub close_reason: String,
+    /// Nodes which served the session.
+    pub nodes: Vec<IpAddr>,
+    pub transcript: Option<Uuid>,
+    pub segment_stats: Option<SegmentStats>,
+}
+
+impl Session {
+    /// Insert a new Session row and assign ID.
+    pub async fn insert(&mut self, client: &impl GenericClient) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                INSERT INTO
+                    session(
+                        "user",
+                        tariff,
+                        task,
+                        started_at,
+                        ended_at,
+                        total_seconds,
+                        total_cost,
+                        close_reason,
+                        nodes,
+                        transcript,
+                        segment_stats)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+             RETURNING id
+                "#,
+            )
+            .await
+            .unwrap();
+
+        let segment_stats = self
+            .segment_stats
+            .as_ref()
+            .map(|s| serde_json::to_value(s).unwrap());
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &self.user,
+                    &self.tariff,
+                    &self.task,
+                    &self.started_at,
+                    &self.ended_at,
+                    &self.total_seconds,
+                    &self.total_cost,
+                    &self.close_reason,
+                    &self.nodes,
+                    &self.transcript,
+                    &segment_stats,
+                ],
+            )
+            .await?;
+
+        self.id = row.try_get("id")?;
+        Ok(())
+    }
+
+    /// Find up to a limit of sessions (of a given user if any) started within
+    /// a time range [from, to) and located strictly after a cursor.
+    /// The sessions are sorted by started_at (then by ID) in ascending order.
+    pub async fn find_started_between(
+        client: &impl GenericClient,
+        user: Option<Uuid>,
+        from: OffsetDateTime,
+        to: OffsetDateTime,
+        after: Option<SessionCursor>,
+        limit: i64,
+    ) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                SELECT *
+                  FROM session
+                 WHERE ($1::uuid IS NULL OR "user" = $1)
+                       AND started_at >= $2 AND started_at < $3
+                       AND ($4::timestamptz IS NULL OR (started_at, id) > ($4, $5))
+                 ORDER BY started_at, id
+                 LIMIT $6
+                "#,
+            )
+            .await
+            .unwrap();
+        let rows = client
+            .query(
+                &stmt,
+                &[
+                    &user,
+                    &from,
+                    &to,
+                    &after.map(|c| c.started_at),
+                    &after.map(|c| c.id),
+                    &limit,
+                ],
+            )
+            .await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    fn from_row(row: Row) -> Result<Self> {
+        let segment_stats: Option<serde_json::Value> = row.try_get("segment_stats")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            user: row.try_get("user")?,
+            tariff: row.try_get("tariff")?,
+            task: row.try_get("task")?,
+            started_at: row.try_get("started_at")?,
+            ended_at: row.try_get("ended_at")?,
+            total_seconds: row.try_get("total_seconds")?,
+            total_cost: row.try_get("total_cost")?,
+            close_reason: row.try_get("close_reason")?,
+            nodes: row.try_get("nodes")?,
+            transcript: row.try_get("transcript")?,
+            segment_stats: segment_stats.and_then(|s| serde_json::from_value(s).ok()),
+        })
+    }
+}
@@ -1,7 +1,7 @@
 use crate::data::Result;
 use deadpool_postgres::GenericClient;
 use lettre::Address as EmailAddress;
-use std::{net::IpAddr, str::FromStr};
+use std::{net::IpAddr, str::FromStr, time::Duration};
 use time::OffsetDateTime;
 use tokio_postgres::Row;
 use uuid::Uuid;
@@ -13,6 +13,7 @@ pub const TOKEN_KEY_LEN: usize = 32;
 pub type TokenKey = [u8; TOKEN_KEY_LEN];
 
 /// User authentication token.
+#[derive(Clone)]
 pub struct Token {
     pub id: Uuid,
     pub created_at: OffsetDateTime,
@@ -23,6 +24,7 @@ pub struct Token {
     pub is_admin: bool,
     pub ip_address: IpAddr,
     pub email: Option<EmailAddress>,
+    pub last_used_at: OffsetDateTime,
 }
 
 impl Token {
@@ -45,9 +47,19 @@ impl Token {
             is_admin,
             ip_address,
             email,
+            last_used_at: OffsetDateTime::UNIX_EPOCH,
         }
     }
 
+    /// Time the token expires at considering its inactivity
+    /// (a zero inactivity TTL disables the inactivity expiry).
+    pub fn effective_expires_at(&self, inactivity_ttl: Duration) -> OffsetDateTime {
+        if inactivity_ttl.is_zero() {
+            return self.expires_at;
+        }
+        self.expires_at.min(self.last_used_at + inactivity_ttl)
+    }
+
     /// Get and authenticate a token with a given ID.
     pub async fn get_and_authenticate(
         client: &impl GenericClient,
@@ -90,14 +102,21 @@ impl Token {
     }
 
     /// Insert a new Token row and assign ID, created_at and hash.
-    pub async fn insert(&mut self, client: &impl GenericClient) -> Result<TokenKey> {
+    /// The key is hashed with bcrypt of a given work factor (4 to 31):
+    /// each increment doubles the hashing time, which is paid on every
+    /// uncached authentication as well.
+    pub async fn insert(
+        &mut self,
+        client: &impl GenericClient,
+        work_factor: u32,
+    ) -> Result<TokenKey> {
         let stmt = client
             .prepare_cached(
                 r#"
                 WITH key AS (
                     SELECT gen_random_bytes($1)
                 ), hash AS (
-                    SELECT crypt((SELECT * FROM key)::text, gen_salt('bf'))
+                    SELECT crypt((SELECT * FROM key)::text, gen_salt('bf', $8))
                 )
                 INSERT INTO token(
                     expires_at,
@@ -108,7 +127,7 @@ impl Token {
                     ip_address,
                     email)
                 VALUES ($2, (SELECT * FROM hash), $3, $4, $5, $6, $7)
-             RETURNING id, created_at, hash, (SELECT * FROM key) AS key
+             RETURNING id, created_at, hash, last_used_at, (SELECT * FROM key) AS key
                 "#,
             )
             .await
@@ -128,6 +147,7 @@ impl Token {
                         .email
                         .as_ref()
                         .map(<EmailAddress as AsRef<str>>::as_ref),
+                    &(work_factor as i32),
                 ],
             )
             .await?;
@@ -135,6 +155,7 @@ impl Token {
         self.id = row.try_get("id")?;
         self.created_at = row.try_get("created_at")?;
         self.hash = row.try_get("hash")?;
+        self.last_used_at = row.try_get("last_used_at")?;
 
         let token: Vec<u8> = row.try_get("key")?;
         Ok(token.try_into().unwrap())
@@ -153,7 +174,8 @@ impl Token {
                        "user" = $6,
                        is_admin = $7,
                        ip_address = $8,
-                       email = $9
+                       email = $9,
+                       last_used_at = $10
                  WHERE id = $1
                 "#,
             )
@@ -175,12 +197,34 @@ impl Token {
                         .email
                         .as_ref()
                         .map(<EmailAddress as AsRef<str>>::as_ref),
+                    &self.last_used_at,
                 ],
             )
             .await?;
         Ok(())
     }
 
+    /// Set last_used_at of a token with a given ID unless
+    /// it was used more recently than a given time.
+    pub async fn touch(
+        client: &impl GenericClient,
+        id: Uuid,
+        used_at: OffsetDateTime,
+    ) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                "
+                UPDATE token
+                   SET last_used_at = $2
+                 WHERE id = $1 AND last_used_at < $2
+                ",
+            )
+            .await
+            .unwrap();
+        client.execute(&stmt, &[&id, &used_at]).await?;
+        Ok(())
+    }
+
     fn from_row(row: Row) -> Result<Self> {
         let email: Option<&str> = row.try_get("email")?;
         Ok(Self {
@@ -193,6 +237,33 @@ impl Token {
             is_admin: row.try_get("is_admin")?,
             ip_address: row.try_get("ip_address")?,
             email: email.map(EmailAddress::from_str).transpose()?,
+            last_used_at: row.try_get("last_used_at")?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_effective_expires_at() {
+        let day = Duration::from_secs(24 * 3600);
+        let mut token = Token::new(
+            OffsetDateTime::UNIX_EPOCH + day * 30,
+            None,
+            None,
+            false,
+            [127, 0, 0, 1].into(),
+            None,
+        );
+        token.last_used_at = OffsetDateTime::UNIX_EPOCH + day;
+
+        assert_eq!(token.effective_expires_at(Duration::ZERO), token.expires_at);
+        assert_eq!(
+            token.effective_expires_at(day * 7),
+            OffsetDateTime::UNIX_EPOCH + day * 8
+        );
+        assert_eq!(token.effective_expires_at(day * 60), token.expires_at);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::data::Result;
+use deadpool_postgres::GenericClient;
+use rust_decimal::Decimal;
+use time::OffsetDateTime;
+use tokio_postgres::Row;
+use uuid::Uuid;
+
+/// Stored transcript of a completed transcription.
+pub struct Transcript {
+    pub id: Uuid,
+    pub created_at: OffsetDateTime,
+    pub user: Uuid,
+    pub tariff: String,
+    pub items: serde_json::Value,
+    pub total_seconds: f32,
+    pub total_cost: Decimal,
+}
+
+impl Transcript {
+    /// Create a new Transcript instance.
+    pub fn new(
+        user: Uuid,
+        tariff: String,
+        items: serde_json::Value,
+        total_seconds: f32,
+        total_cost: Decimal,
+    ) -> Self {
+        Self {
+            id: Uuid::nil(),
+            created_at: OffsetDateTime::UNIX_EPOCH,
+            user,
+            tariff,
+            items,
+            total_seconds,
+            total_cost,
+        }
+    }
+
+    /// Get a transcript with a given ID.
+    pub async fn get(client: &impl GenericClient, id: Uuid) -> Result<Option<Self>> {
+        let stmt = client
+            .prepare_cached(
+                "
+                SELECT *
+                  FROM transcript
+                 WHERE id = $1
+                ",
+            )
+            .await
+            .unwrap();
+        let row = client.query_opt(&stmt, &[&id]).await?;
+        row.map(Self::from_row).transpose()
+    }
+
+    /// Insert a new Transcript row and assign ID and created_at.
+    pub async fn insert(&mut self, client: &impl GenericClient) -> Result<()> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                INSERT INTO
+                    transcript(
+                        "user",
+                        tariff,
+                        items,
+                        total_seconds,
+                        total_cost)
+                VALUES ($1, $2, $3, $4, $5)
+             RETURNING id, created_at
+                "#,
+            )
+            .await
+            .unwrap();
+
+        let row = client
+            .query_one(
+                &stmt,
+                &[
+                    &self.user,
+                    &self.tariff,
+                    &self.items,
+                    &self.total_seconds,
+                    &self.total_cost,
+                ],
+            )
+            .await?;
+
+        self.id = row.try_get("id")?;
+        self.created_at = row.try_get("created_at")?;
+        Ok(())
+    }
+
+    fn from_row(row: Row) -> Result<Self> {
+        Ok(Self {
+            id: row.try_get("id")?,
+            created_at: row.try_get("created_at")?,
+            user: row.try_get("user")?,
+            tariff: row.try_get("tariff")?,
+            items: row.try_get("items")?,
+            total_seconds: row.try_get("total_seconds")?,
+            total_cost: row.try_get("total_cost")?,
+        })
+    }
+}
@@ -16,7 +16,13 @@ pub struct User {
     pub referrer: Option<Uuid>,
     pub campaign: Uuid,
     pub balance: Decimal,
-    pub allocated_fee: Decimal,
+    pub referral_bonus_paid: bool,
+    pub is_admin: bool,
+    pub callback_secret: String,
+    /// Anonymous trial user (with a placeholder email) who cannot make payments.
+    pub is_trial: bool,
+    /// Amount the balance is allowed to go below zero.
+    pub credit_limit: Decimal,
 }
 
 impl User {
@@ -34,10 +40,19 @@ impl User {
             referrer,
             campaign,
             balance,
-            allocated_fee: Decimal::ZERO,
+            referral_bonus_paid: false,
+            is_admin: false,
+            callback_secret: String::new(),
+            is_trial: false,
+            credit_limit: Decimal::ZERO,
         }
     }
 
+    /// Check if the balance (extended with the credit limit) allows spending.
+    pub fn has_spendable_balance(&self) -> bool {
+        self.balance + self.credit_limit > Decimal::ZERO
+    }
+
     /// Get a user with a given ID.
     pub async fn get(client: &impl GenericClient, id: Uuid) -> Result<Option<Self>> {
         let stmt = client
@@ -74,7 +89,7 @@ impl User {
         row.map(Self::from_row).transpose()
     }
 
-    /// Insert a new User row and assign ID and created_at.
+    /// Insert a new User row and assign ID, created_at and callback_secret.
     pub async fn insert(&mut self, client: &impl GenericClient) -> Result<()> {
         let stmt = client
             .prepare_cached(
@@ -83,9 +98,10 @@ impl User {
                     email,
                     referrer,
                     campaign,
-                    balance)
-                VALUES ($1, $2, $3, $4)
-             RETURNING id, created_at
+                    balance,
+                    is_trial)
+                VALUES ($1, $2, $3, $4, $5)
+             RETURNING id, created_at, callback_secret
                 "#,
             )
             .await
@@ -95,16 +111,45 @@ impl User {
         let row = client
             .query_one(
                 &stmt,
-                &[&email_str, &self.referrer, &self.campaign, &self.balance],
+                &[
+                    &email_str,
+                    &self.referrer,
+                    &self.campaign,
+                    &self.balance,
+                    &self.is_trial,
+                ],
             )
             .await?;
 
         self.id = row.try_get("id")?;
         self.created_at = row.try_get("created_at")?;
-        self.allocated_fee = Decimal::ZERO;
+        self.callback_secret = row.try_get("callback_secret")?;
         Ok(())
     }
 
+    /// Atomically turn a trial user with a given ID into a full one with a given email.
+    /// Returns false if the user is not a trial one.
+    pub async fn upgrade_trial(
+        client: &impl GenericClient,
+        id: Uuid,
+        email: &EmailAddress,
+    ) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                UPDATE "user"
+                   SET email = $2,
+                       is_trial = false
+                 WHERE id = $1
+                       AND is_trial
+                "#,
+            )
+            .await
+            .unwrap();
+        let email_str: &str = email.as_ref();
+        Ok(client.execute(&stmt, &[&id, &email_str]).await? > 0)
+    }
+
     /// Update user row columns with the current field values.
     pub async fn update(&self, client: &impl GenericClient) -> Result<()> {
         let stmt = client
@@ -113,7 +158,8 @@ impl User {
                 UPDATE "user"
                    SET created_at = $2,
                        balance = $3,
-                       allocated_fee = $4
+                       referral_bonus_paid = $4,
+                       is_admin = $5
                  WHERE id = $1
                 "#,
             )
@@ -126,44 +172,112 @@ impl User {
                     &self.id,
                     &self.created_at,
                     &self.balance,
-                    &self.allocated_fee,
+                    &self.referral_bonus_paid,
+                    &self.is_admin,
                 ],
             )
             .await?;
         Ok(())
     }
 
-    /// Decrement user balances with corresponding allocated fees.
-    pub async fn update_balances(client: &impl GenericClient) -> Result<()> {
+    /// Set a credit limit of a user with a given ID.
+    /// Returns false if the user is not found.
+    pub async fn set_credit_limit(
+        client: &impl GenericClient,
+        id: Uuid,
+        credit_limit: Decimal,
+    ) -> Result<bool> {
         let stmt = client
             .prepare_cached(
                 r#"
                 UPDATE "user"
-                   SET balance = balance - allocated_fee
-                 WHERE allocated_fee > 0 -- use user_allocated_fee_idx
+                   SET credit_limit = $2
+                 WHERE id = $1
                 "#,
             )
             .await
             .unwrap();
-        client.execute(&stmt, &[]).await?;
-        Ok(())
+        Ok(client.execute(&stmt, &[&id, &credit_limit]).await? > 0)
     }
 
-    /// Clear allocated_fee for every user.
-    pub async fn clear_allocated_fees(client: &impl GenericClient) -> Result<()> {
+    /// Decrement a user balance with a given amount.
+    pub async fn charge(client: &impl GenericClient, id: Uuid, amount: Decimal) -> Result<()> {
         let stmt = client
             .prepare_cached(
                 r#"
                 UPDATE "user"
-                   SET allocated_fee = 0
+                   SET balance = balance - $2
+                 WHERE id = $1
                 "#,
             )
             .await
             .unwrap();
-        client.execute(&stmt, &[]).await?;
+        client.execute(&stmt, &[&id, &amount]).await?;
         Ok(())
     }
 
+    /// Find users not notified yet whose balance is below a low balance threshold
+    /// of their campaign or a given default one.
+    pub async fn find_newly_low_balance(
+        client: &impl GenericClient,
+        default_threshold: Option<Decimal>,
+    ) -> Result<Vec<Self>> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                SELECT "user".*
+                  FROM "user"
+                  JOIN campaign ON campaign.id = campaign
+                 WHERE low_balance_notified_at IS NULL
+                       AND NOT is_trial
+                       AND balance < COALESCE(low_balance_threshold, $1)
+                "#,
+            )
+            .await
+            .unwrap();
+        let rows = client.query(&stmt, &[&default_threshold]).await?;
+        rows.into_iter().map(Self::from_row).collect()
+    }
+
+    /// Atomically mark a user with a given ID as notified of low balance.
+    /// Returns false if the user has already been notified.
+    pub async fn mark_low_balance_notified(client: &impl GenericClient, id: Uuid) -> Result<bool> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                UPDATE "user"
+                   SET low_balance_notified_at = now()
+                 WHERE id = $1
+                       AND low_balance_notified_at IS NULL
+                "#,
+            )
+            .await
+            .unwrap();
+        Ok(client.execute(&stmt, &[&id]).await? > 0)
+    }
+
+    /// Clear low balance notification marks of users whose balance is not low anymore
+    /// (so they are notified again next time). Returns the number of cleared users.
+    pub async fn reset_low_balance_notified(
+        client: &impl GenericClient,
+        default_threshold: Option<Decimal>,
+    ) -> Result<u64> {
+        let stmt = client
+            .prepare_cached(
+                r#"
+                UPDATE "user"
+                   SET low_balance_notified_at = NULL
+                  FROM campaign
+                 WHERE campaign.id = campaign
+                       AND low_balance_notified_at IS NOT NULL
+                       AND balance >= COALESCE(low_balance_threshold, $1, balance)
+                "#,
+            )
+            .await
+            .unwrap();
+        Ok(client.execute(&stmt, &[&default_threshold]).await?)
+    }
+
     fn from_row(row: Row) -> Result<Self> {
         let email: &str = row.try_get("email")?;
         Ok(Self {
@@ -173,7 +287,37 @@ impl User {
             referrer: row.try_get("referrer")?,
             campaign: row.try_get("campaign")?,
             balance: row.try_get("balance")?,
-            allocated_fee: row.try_get("allocated_fee")?,
+            referral_bonus_paid: row.try_get("referral_bonus_paid")?,
+            is_admin: row.try_get("is_admin")?,
+            callback_secret: row.try_get("callback_secret")?,
+            is_trial: row.try_get("is_trial")?,
+            credit_limit: row.try_get("credit_limit")?,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_user_has_spendable_balance() {
+        let dec = |s| Decimal::from_str(s).unwrap();
+        let email = EmailAddress::from_str("john@example.com").unwrap();
+        let mut user = User::new(email, None, Uuid::nil(), dec("0.01"));
+        assert!(user.has_spendable_balance());
+
+        user.balance = Decimal::ZERO;
+        assert!(!user.has_spendable_balance());
+
+        user.credit_limit = dec("10");
+        assert!(user.has_spendable_balance());
+        user.balance = dec("-9.99");
+        assert!(user.has_spendable_balance());
+        user.balance = dec("-10");
+        assert!(!user.has_spendable_balance());
+        user.balance = dec("-10.01");
+        assert!(!user.has_spendable_balance());
+    }
+}
@@ -0,0 +1,97 @@
+use axum::http::StatusCode;
+
+/// Kind of error reported to clients.
+#[derive(Debug, PartialEq)]
+pub struct ErrorKind {
+    pub code: &'static str,
+    pub status: StatusCode,
+    pub description: &'static str,
+}
+
+macro_rules! error_kinds {
+    ($($name:ident: $code:literal, $status:ident, $description:literal;)*) => {
+        $(
+            pub const $name: ErrorKind = ErrorKind {
+                code: $code,
+                status: StatusCode::$status,
+                description: $description,
+            };
+        )*
+
+        /// All error kinds ordered by code.
+        pub const ERROR_KINDS: &[ErrorKind] = &[$($name),*];
+    };
+}
+
+// Every error kind must be defined here to be listed for clients.
+error_kinds! {
+    AXUM: "axum", INTERNAL_SERVER_ERROR, "Web server failed to process request.";
+    AXUM_JSON_REJECTION: "axum_json_rejection", BAD_REQUEST, "Malformed JSON payload.";
+    AXUM_MULTIPART: "axum_multipart", BAD_REQUEST, "Malformed multipart payload.";
+    AXUM_MULTIPART_REJECTION: "axum_multipart_rejection", BAD_REQUEST, "Malformed multipart request.";
+    AXUM_PATH_REJECTION: "axum_path_rejection", BAD_REQUEST, "Malformed URL path.";
+    AXUM_QUERY_REJECTION: "axum_query_rejection", BAD_REQUEST, "Malformed URL query.";
+    BAD_PAYMENT_STATUS: "bad_payment_status", UNPROCESSABLE_ENTITY, "Payment is in a status which does not allow the operation.";
+    BAD_REQUEST: "bad_request", BAD_REQUEST, "Request is invalid, the message tells why.";
+    CAMPAIGN_EXPIRED: "campaign_expired", BAD_REQUEST, "Promotion campaign is expired or fully redeemed.";
+    CAMPAIGN_NOT_FOUND: "campaign_not_found", BAD_REQUEST, "No promotion campaign with a given promo code.";
+    DEADPOOL_POOL: "deadpool_pool", INTERNAL_SERVER_ERROR, "Failed to connect to database.";
+    DEADPOOL_POOL_TIMEOUT: "deadpool_pool_timeout", SERVICE_UNAVAILABLE, "Timed out waiting for a database connection, retry later.";
+    EMAIL_ALREADY_REGISTERED: "email_already_registered", BAD_REQUEST, "User with a given email is already registered.";
+    EMAIL_SUPPRESSED: "email_suppressed", UNPROCESSABLE_ENTITY, "Emails to a given address are suppressed due to bounces or complaints.";
+    EMAIL_SUPPRESSION_NOT_FOUND: "email_suppression_not_found", NOT_FOUND, "No suppression of a given email.";
+    FORBIDDEN: "forbidden", FORBIDDEN, "Access is forbidden, the message tells why.";
+    HANDLER_NOT_FOUND: "handler_not_found", NOT_FOUND, "No endpoint for a given method and path.";
+    INFSRV_DISCONNECTED: "infsrv_disconnected", SERVICE_UNAVAILABLE, "Transcription service disconnected, retry later.";
+    INFSRV_OVERLOADED: "infsrv_overloaded", SERVICE_UNAVAILABLE, "Transcription service is overloaded, retry later.";
+    INFSRV_REJECTED_AUDIO: "infsrv_rejected_audio", UNPROCESSABLE_ENTITY, "Transcription service rejected the audio.";
+    INFSRV_UNEXPECTED_RESPONSE: "infsrv_unexpected_response", BAD_GATEWAY, "Transcription service responded unexpectedly.";
+    INSTRUMENT_DECLINED: "instrument_declined", PAYMENT_REQUIRED, "Payment processor declined the payment instrument.";
+    INTERNAL: "internal", INTERNAL_SERVER_ERROR, "Internal server error.";
+    INVALID_FEES: "invalid_fees", INTERNAL_SERVER_ERROR, "Tariff fees are misconfigured.";
+    IO: "io", INTERNAL_SERVER_ERROR, "Input/output failed.";
+    JOB_NOT_FOUND: "job_not_found", NOT_FOUND, "No transcription job with a given ID.";
+    LETTRE: "lettre", INTERNAL_SERVER_ERROR, "Failed to compose email.";
+    LETTRE_ADDRESS: "lettre_address", INTERNAL_SERVER_ERROR, "Stored email address is malformed.";
+    LETTRE_SMTP: "lettre_smtp", INTERNAL_SERVER_ERROR, "Failed to send email.";
+    MIXED_CAPTURE_CURRENCIES: "mixed_capture_currencies", INTERNAL_SERVER_ERROR, "Payment processor captured amounts in different currencies.";
+    NODE_NOT_FOUND: "node_not_found", INTERNAL_SERVER_ERROR, "Worker node disappeared.";
+    NOT_ENOUGH_BALANCE: "not_enough_balance", PAYMENT_REQUIRED, "User balance is not enough, top it up.";
+    NOT_ENOUGH_RESOURCES: "not_enough_resources", TOO_MANY_REQUESTS, "No worker nodes are free, retry later.";
+    ORDER_ALREADY_CAPTURED: "order_already_captured", CONFLICT, "Payment is already captured.";
+    ORDER_NOT_APPROVED: "order_not_approved", UNPROCESSABLE_ENTITY, "Payment is not approved by payer yet.";
+    PAYLOAD_TOO_LARGE: "payload_too_large", PAYLOAD_TOO_LARGE, "Request body is too large.";
+    PAYMENT_NOT_FOUND: "payment_not_found", NOT_FOUND, "No payment with a given ID or reference.";
+    POSTGRES: "postgres", INTERNAL_SERVER_ERROR, "Database query failed.";
+    PROMO_CODE_ALREADY_EXISTS: "promo_code_already_exists", BAD_REQUEST, "Promotion campaign with a given promo code already exists.";
+    REQWEST: "reqwest", INTERNAL_SERVER_ERROR, "Request to an external service failed.";
+    REQWEST_TIMEOUT: "reqwest_timeout", GATEWAY_TIMEOUT, "Request to an external service timed out.";
+    SERDE_JSON: "serde_json", INTERNAL_SERVER_ERROR, "External service responded with malformed JSON.";
+    SERVER_OVERLOADED: "server_overloaded", SERVICE_UNAVAILABLE, "Server has too many requests or sessions, retry later.";
+    SERVER_SHUTTING_DOWN: "server_shutting_down", SERVICE_UNAVAILABLE, "Server is shutting down, retry on another one.";
+    TARIFF_CONCURRENCY_EXCEEDED: "tariff_concurrency_exceeded", TOO_MANY_REQUESTS, "Tariff has too many active tasks, retry later.";
+    TARIFF_NOT_FOUND: "tariff_not_found", BAD_REQUEST, "No capabilities for a given tariff and task type.";
+    TRANSCRIPT_NOT_FOUND: "transcript_not_found", NOT_FOUND, "No transcript with a given ID.";
+    TRIAL_ALREADY_UPGRADED: "trial_already_upgraded", BAD_REQUEST, "Trial user is already upgraded to a registered one.";
+    TUNGSTENITE: "tungstenite", INTERNAL_SERVER_ERROR, "Transcription service WebSocket failed.";
+    UNAUTHORIZED: "unauthorized", UNAUTHORIZED, "Access token is missing, malformed or invalid.";
+    UNSUPPORTED_CURRENCY: "unsupported_currency", BAD_REQUEST, "Payment processor does not support a given currency.";
+    UNSUPPORTED_LOCALE: "unsupported_locale", BAD_REQUEST, "Payment processor does not support a given locale.";
+    USER_NOT_FOUND: "user_not_found", NOT_FOUND, "No user with a given ID.";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_kinds_ordered() {
+        for pair in ERROR_KINDS.windows(2) {
+            assert!(pair[0].code < pair[1].code, "{}", pair[1].code);
+        }
+        for kind in ERROR_KINDS {
+            assert!(kind.status.is_client_error() || kind.status.is_server_error());
+            assert!(kind.description.ends_with('.'));
+        }
+    }
+}
@@ -1,24 +1,36 @@
 use crate::{
-    data::capability::TaskType,
-    ledger::Ledger,
-    util::fmt::{ErrorChainDisplay, TruncateDebug},
+    data::capability::{Capability, TaskType},
+    error_kind::{self as kind, ErrorKind},
+    ledger::{Allocation, Ledger},
+    util::{
+        circuit_breaker::{CircuitBreaker, CircuitBreakerParams},
+        fmt::{ErrorChainDisplay, TruncateDebug},
+    },
 };
 use axum::http::{header::CONTENT_TYPE, StatusCode};
+use clap::ValueEnum;
 use futures::{SinkExt, StreamExt};
-use log::{debug, error};
+use hound::WavReader;
+use log::{debug, error, info};
 use reqwest::{
     multipart::{Form, Part},
     Client,
 };
+use rust_decimal::Decimal;
 use serde::Deserialize;
-use std::time::Duration;
+use std::{collections::VecDeque, net::IpAddr, ops::RangeInclusive, sync::Arc, time::Duration};
 use tokio::{
-    sync::mpsc::{channel, Receiver, Sender},
-    time::interval,
+    net::TcpStream,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        watch,
+    },
+    time::{interval, sleep},
 };
 use tokio_tungstenite::{
     connect_async,
-    tungstenite::{client::IntoClientRequest, Message},
+    tungstenite::{client::IntoClientRequest, Error as TungsteniteError, Message},
+    MaybeTlsStream, WebSocketStream,
 };
 use url::Url;
 use uuid::Uuid;
@@ -32,18 +44,32 @@ pub const TERMINATOR_HEADER: &str = "X-Blobfish-Terminator";
 /// Minimum speech segment duration (in seconds).
 pub const MIN_SPEECH_DURATION: f32 = 15.0;
 
-/// Maximum segment duration (in seconds).
-pub const MAX_SEGMENT_DURATION: f32 = 30.0;
+/// Default maximum segment duration (in seconds).
+pub const DEFAULT_MAX_SEGMENT_DURATION: f32 = 30.0;
+
+/// Range of maximum segment durations (in seconds) supported by infsrv.
+const MAX_SEGMENT_DURATION_RANGE: RangeInclusive<f32> = 5.0..=300.0;
 
 /// Economical sample rate that is enough for speech recognition.
 pub const SAMPLE_RATE: f32 = 16000.0;
 
-/// Segmenting window duration (in seconds).
-const SEGMENT_WINDOW_DURATION: f32 = 5.0;
+/// Default segmenting window duration (in seconds).
+pub const DEFAULT_SEGMENT_WINDOW_DURATION: f32 = 5.0;
+
+/// Range of segmenting window durations (in seconds) supported by infsrv.
+const SEGMENT_WINDOW_DURATION_RANGE: RangeInclusive<f32> = 1.0..=10.0;
+
+/// Size of PCM sample (i16 le-encoded).
+const BYTES_PER_SAMPLE: usize = 2;
+
+/// Maximum size of PCM message re-sent after reconnection.
+const RESEND_CHUNK_SIZE: usize = 64 * 1024;
 
 /// InfsrvPool error.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("infsrv disconnected")]
+    Disconnected,
     #[error("internal")]
     Internal,
     #[error("ledger")]
@@ -65,41 +91,173 @@ pub enum Error {
         serde_json::Error,
     ),
     #[error("tungstanite")]
-    Tungstanite(
-        #[from]
-        #[source]
-        tokio_tungstenite::tungstenite::Error,
-    ),
+    Tungstanite(#[source] Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("unexpected infsrv response")]
+    UnexpectedResponse,
+    #[error("unexpected infsrv response status ({0})")]
+    UnexpectedStatus(StatusCode),
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(err: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::Tungstanite(Box::new(err))
+    }
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
         match self {
-            Internal | Reqwest(_) | SerdeJson(_) | Tungstanite(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-            Ledger(err) => err.status(),
+            Disconnected => &kind::INFSRV_DISCONNECTED,
+            Internal => &kind::INTERNAL,
+            Ledger(err) => err.kind(),
+            Reqwest(err) if err.is_timeout() => &kind::REQWEST_TIMEOUT,
+            Reqwest(_) => &kind::REQWEST,
+            SerdeJson(_) => &kind::SERDE_JSON,
+            Tungstanite(_) => &kind::TUNGSTENITE,
+            UnexpectedResponse => &kind::INFSRV_UNEXPECTED_RESPONSE,
+            UnexpectedStatus(status) if is_overload_status(*status) => &kind::INFSRV_OVERLOADED,
+            UnexpectedStatus(status) if status.is_client_error() => &kind::INFSRV_REJECTED_AUDIO,
+            UnexpectedStatus(_) => &kind::INFSRV_UNEXPECTED_RESPONSE,
         }
     }
 
-    /// Kind code.
-    pub fn code(&self) -> &str {
+    /// Check if this error tells that a node is failing (rather than rejecting a request).
+    fn is_node_failure(&self) -> bool {
         use Error::*;
         match self {
-            Internal => "internal",
-            Ledger(err) => err.code(),
-            Reqwest(_) => "reqwest",
-            SerdeJson(_) => "serde_json",
-            Tungstanite(_) => "tungstanite",
+            Disconnected | Reqwest(_) | SerdeJson(_) | Tungstanite(_) | UnexpectedResponse => true,
+            UnexpectedStatus(status) => status.is_server_error(),
+            Internal | Ledger(_) => false,
         }
     }
+
+    /// Check if a node rejected a request (e.g. for lacking capabilities to serve it),
+    /// so another node of a multi-node allocation may serve it instead.
+    fn is_rejection(&self) -> bool {
+        use Error::*;
+        match self {
+            Tungstanite(err) => matches!(
+                &**err,
+                TungsteniteError::Http(response) if response.status() == StatusCode::BAD_REQUEST
+            ),
+            UnexpectedStatus(status) => *status == StatusCode::BAD_REQUEST,
+            _ => false,
+        }
+    }
+
+    /// Check if a request failed with this error may succeed on another node.
+    fn is_retriable(&self) -> bool {
+        use Error::*;
+        match self {
+            UnexpectedResponse => true,
+            UnexpectedStatus(status) => !status.is_client_error() || is_overload_status(*status),
+            _ => false,
+        }
+    }
+}
+
+/// Check if an infsrv response status tells that a node is overloaded.
+pub fn is_overload_status(status: StatusCode) -> bool {
+    status == StatusCode::SERVICE_UNAVAILABLE || status == StatusCode::TOO_MANY_REQUESTS
 }
 
 /// InfsrvPool result.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Voice activity detection sensitivity.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum VadSensitivity {
+    /// Detect only confident speech (e.g. in noisy environments).
+    Low,
+    /// Drop short noise bursts only.
+    Medium,
+    /// Keep all detected speech including quiet one (e.g. in dictation),
+    /// which matches segmentation without a sensitivity level.
+    #[default]
+    High,
+}
+
+impl VadSensitivity {
+    /// Minimum duration (in seconds) of detected speech passed to infsrv,
+    /// shorter speech (e.g. noise bursts) is treated as void.
+    pub fn min_duration_on(self) -> f32 {
+        match self {
+            VadSensitivity::Low => 0.5,
+            VadSensitivity::Medium => 0.25,
+            VadSensitivity::High => 0.0,
+        }
+    }
+}
+
+/// Speech segmentation parameters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentParams {
+    /// Maximum segment duration (in seconds).
+    pub max_segment_duration: f32,
+    /// Segmenting window duration (in seconds).
+    pub window_duration: f32,
+    /// Requested (rather than tariff-defined) voice activity detection sensitivity.
+    pub vad_sensitivity: VadSensitivity,
+    /// Void duration (in seconds) after which short speech is segmented
+    /// at once rather than merged with the following speech (zero disables).
+    pub speech_flush_grace: f32,
+}
+
+impl Default for SegmentParams {
+    fn default() -> Self {
+        Self {
+            max_segment_duration: DEFAULT_MAX_SEGMENT_DURATION,
+            window_duration: DEFAULT_SEGMENT_WINDOW_DURATION,
+            vad_sensitivity: VadSensitivity::default(),
+            speech_flush_grace: 0.0,
+        }
+    }
+}
+
+impl SegmentParams {
+    /// Get effective parameters for given segmentation capabilities.
+    /// The most restrictive value wins if several capabilities specify it.
+    /// Values are clamped to the ranges supported by infsrv.
+    pub fn from_capabilities(capabilities: &[Capability]) -> Self {
+        let min_of =
+            |field: fn(&Capability) -> Option<f32>, default, range: RangeInclusive<f32>| {
+                capabilities
+                    .iter()
+                    .filter_map(field)
+                    .reduce(f32::min)
+                    .unwrap_or(default)
+                    .clamp(*range.start(), *range.end())
+            };
+        Self {
+            max_segment_duration: min_of(
+                |c| c.max_segment_duration,
+                DEFAULT_MAX_SEGMENT_DURATION,
+                MAX_SEGMENT_DURATION_RANGE,
+            ),
+            window_duration: min_of(
+                |c| c.segment_window_duration,
+                DEFAULT_SEGMENT_WINDOW_DURATION,
+                SEGMENT_WINDOW_DURATION_RANGE,
+            ),
+            vad_sensitivity: VadSensitivity::default(),
+            speech_flush_grace: 0.0,
+        }
+    }
+
+    /// Minimum speech segment duration (in seconds).
+    pub fn min_speech_duration(&self) -> f32 {
+        MIN_SPEECH_DURATION.min(self.max_segment_duration)
+    }
+
+    /// Ring buffer capacity (in frames) enough to keep the last segment.
+    pub fn ring_buffer_capacity(&self) -> usize {
+        (2.0 * self.max_segment_duration * SAMPLE_RATE) as usize
+    }
+}
+
 /// An item returned from speech segmentation stream.
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase", tag = "kind")]
@@ -108,175 +266,803 @@ pub enum SegmentItem {
     Void { begin: f32, end: f32 },
 }
 
+impl SegmentItem {
+    /// Segment end (in seconds).
+    pub fn end(&self) -> f32 {
+        use SegmentItem::*;
+        match self {
+            Speech { end, .. } | Void { end, .. } => *end,
+        }
+    }
+
+    /// Shift segment by a given time (in seconds).
+    fn shift(&mut self, secs: f32) {
+        use SegmentItem::*;
+        match self {
+            Speech { begin, end } | Void { begin, end } => {
+                *begin += secs;
+                *end += secs;
+            }
+        }
+    }
+}
+
 /// An item returned from speech transcription.
 #[derive(Deserialize)]
 pub struct TranscribeItem {
     pub text: String,
+    /// Speaker label (if diarization is requested and supported).
+    #[serde(default)]
+    pub speaker: Option<String>,
+    /// Node which transcribed the speech.
+    #[serde(skip)]
+    pub node: Option<IpAddr>,
+    /// Amount charged for the transcription.
+    #[serde(skip)]
+    pub charged: Decimal,
+}
+
+/// Speech transcription options.
+#[derive(Clone, Debug, Default)]
+pub struct TranscribeOptions {
+    pub language: Option<String>,
+    /// Text preceding the transcribed speech.
+    pub prompt: Option<String>,
+    /// Label speech with speakers (requires diarization capabilities of a tariff).
+    pub diarize: bool,
+}
+
+/// Reconnection parameters for infsrv segmentation streams.
+#[derive(Clone, Copy, Debug)]
+pub struct ReconnectParams {
+    /// Maximum number of reconnection attempts.
+    pub attempts: u32,
+    /// Delay before the first attempt (doubled for every next one).
+    pub delay: Duration,
 }
 
 /// Pool of infsrv instances.
 pub struct InfsrvPool {
     ledger: Ledger,
+    reconnect: ReconnectParams,
+    /// Node failures tracked to skip failing nodes on allocation.
+    breaker: Arc<CircuitBreaker<IpAddr>>,
+    client: Client,
 }
 
 impl InfsrvPool {
     /// Create a new InfsrvPool instance.
-    pub fn new(ledger: Ledger) -> Self {
-        Self { ledger }
+    pub fn new(
+        ledger: Ledger,
+        reconnect: ReconnectParams,
+        breaker: CircuitBreakerParams,
+        client: Client,
+    ) -> Self {
+        Self {
+            ledger,
+            reconnect,
+            breaker: Arc::new(CircuitBreaker::new(breaker)),
+            client,
+        }
     }
 
     /// Initiate a speech segmentation session.
-    /// Returns a sender for raw PCM data (i16 le-encoded samples, 16kHz mono)
-    /// and a receiver to receive time intervals (in milliseconds).
+    /// Returns a sender for raw PCM data (i16 le-encoded samples, 16kHz mono),
+    /// a receiver to receive time intervals (in milliseconds), a receiver
+    /// of the amount charged so far and a node address. The amount is final
+    /// once the segment receiver is exhausted.
     pub async fn segment(
         &self,
         user: Uuid,
         tariff: &str,
+        params: SegmentParams,
         terminator: Option<&[u8]>,
-    ) -> Result<(Sender<Vec<u8>>, Receiver<Result<SegmentItem>>)> {
+    ) -> Result<(
+        Sender<Vec<u8>>,
+        Receiver<Result<SegmentItem>>,
+        watch::Receiver<Decimal>,
+        IpAddr,
+    )> {
         let allocation = self
             .ledger
-            .allocate(user, tariff, TaskType::Segment)
+            .allocate(user, tariff, TaskType::Segment, &self.breaker.excluded())
             .await?;
 
         let mut url = Url::parse("ws://127.0.0.1:9322/segment").unwrap();
-        url.set_ip_host(allocation.ip_address()).unwrap();
         url.query_pairs_mut()
-            .append_pair("minsd", &MIN_SPEECH_DURATION.to_string())
-            .append_pair("maxsd", &MAX_SEGMENT_DURATION.to_string())
+            .append_pair("minsd", &params.min_speech_duration().to_string())
+            .append_pair("maxsd", &params.max_segment_duration.to_string())
+            .append_pair(
+                "mdon",
+                &params.vad_sensitivity.min_duration_on().to_string(),
+            )
+            .append_pair("fvd", &params.speech_flush_grace.to_string())
             .append_pair("nc", "1")
             .append_pair("sr", &SAMPLE_RATE.to_string())
             .append_pair("st", "i16")
-            .append_pair("wd", &SEGMENT_WINDOW_DURATION.to_string());
+            .append_pair("wd", &params.window_duration.to_string());
+
+        let (charged_sender, charged_receiver) = watch::channel(Decimal::ZERO);
+        let mut stream = SegmentStream {
+            url,
+            allocation,
+            charged: charged_sender,
+            terminator: terminator.map(<[u8]>::to_vec),
+            reconnect: self.reconnect,
+            breaker: self.breaker.clone(),
+            pending: PendingPcm::with_capacity(params.ring_buffer_capacity() * BYTES_PER_SAMPLE),
+            offset: 0.0,
+        };
+        let ws = loop {
+            let result = stream.connect(false).await;
+            record_node_outcome(&self.breaker, stream.allocation.ip_address(), &result);
+            if !result.as_ref().is_err_and(Error::is_rejection) || !stream.allocation.reroute() {
+                break result?;
+            }
+        };
+        let node = stream.allocation.ip_address();
+
+        let (sender, infsrv_receiver) = channel(32);
+        let (infsrv_sender, receiver) = channel(32);
+        tokio::spawn(stream.run(ws, receiver, sender));
+
+        Ok((infsrv_sender, infsrv_receiver, charged_receiver, node))
+    }
+
+    /// Transcribe a given wav-blob.
+    /// Unexpected node responses (except for rejected requests)
+    /// are retried with a new allocation.
+    pub async fn transcribe(
+        &self,
+        user: Uuid,
+        tariff: &str,
+        wav_blob: Vec<u8>,
+        options: TranscribeOptions,
+    ) -> Result<TranscribeItem> {
+        let duration = wav_duration(&wav_blob).ok_or(Error::Internal)?;
+
+        let mut delay = self.reconnect.delay;
+        let mut attempt = 0;
+        loop {
+            let result = self
+                .try_transcribe(user, tariff, &wav_blob, &options, duration)
+                .await;
+            if !result.as_ref().is_err_and(Error::is_retriable)
+                || attempt == self.reconnect.attempts
+            {
+                break result;
+            }
+
+            attempt += 1;
+            info!("retrying infsrv transcription (attempt {attempt})");
+            sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    async fn try_transcribe(
+        &self,
+        user: Uuid,
+        tariff: &str,
+        wav_blob: &[u8],
+        options: &TranscribeOptions,
+        duration: f32,
+    ) -> Result<TranscribeItem> {
+        let task_type = if options.diarize {
+            TaskType::Diarize
+        } else {
+            TaskType::Transcribe
+        };
+        let mut allocation = self
+            .ledger
+            .allocate(user, tariff, task_type, &self.breaker.excluded())
+            .await?;
+
+        let form = || {
+            let mut form =
+                Form::new().part("file", Part::bytes(wav_blob.to_vec()).file_name("file.wav"));
+
+            if let Some(language) = options.language.clone() {
+                form = form.text("language", language);
+            }
+
+            if let Some(prompt) = options.prompt.clone() {
+                form = form.text("prompt", prompt);
+            }
+
+            if options.diarize {
+                form = form.text("diarize", "true");
+            }
+            form
+        };
+
+        // Nodes of a multi-node allocation are tried until one serves the request.
+        let (node, result) = loop {
+            let node = allocation.ip_address();
+            let mut url = Url::parse("http://127.0.0.1:9322/transcribe").unwrap();
+            url.set_ip_host(node).unwrap();
+            let result = match self
+                .client
+                .post(url)
+                .header(CAPABILITIES_HEADER, allocation.capabilities().join(","))
+                .multipart(form())
+                .send()
+                .await
+            {
+                Ok(response) => read_transcribe_response(response).await,
+                Err(err) => Err(err.into()),
+            };
+            record_node_outcome(&self.breaker, node, &result);
+            if !result.as_ref().is_err_and(Error::is_rejection) || !allocation.reroute() {
+                break (node, result);
+            }
+        };
+
+        let mut item = result?;
+        item.node = Some(node);
 
-        let mut request = url.into_client_request().unwrap();
+        match allocation.consume(duration).await {
+            Ok(amount) => item.charged = amount,
+            Err(err) => error!(
+                "failed to charge for transcribed audio: {}",
+                ErrorChainDisplay(&err)
+            ),
+        }
+        Ok(item)
+    }
+}
+
+/// Record a result of a request to a node in a circuit breaker.
+fn record_node_outcome<T>(breaker: &CircuitBreaker<IpAddr>, node: IpAddr, result: &Result<T>) {
+    match result {
+        Ok(_) => breaker.succeed(node),
+        Err(err) if err.is_node_failure() => breaker.fail(node),
+        Err(_) => {}
+    }
+}
+
+/// Read a transcription from an infsrv response preserving its failed status.
+async fn read_transcribe_response(response: reqwest::Response) -> Result<TranscribeItem> {
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(is_json_content_type);
+    let text = response.text().await?;
+    if !status.is_success() || !is_json {
+        debug!(
+            "unexpected infsrv response (status = {status}): {:?}",
+            TruncateDebug::new(&text)
+        );
+        return Err(if status.is_success() {
+            Error::UnexpectedResponse
+        } else {
+            Error::UnexpectedStatus(status)
+        });
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+/// Check if a given Content-Type header value denotes JSON.
+fn is_json_content_type(content_type: &str) -> bool {
+    content_type
+        .split(';')
+        .next()
+        .is_some_and(|t| t.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Duration (in seconds) of a given wav-blob.
+fn wav_duration(wav_blob: &[u8]) -> Option<f32> {
+    let reader = WavReader::new(wav_blob).ok()?;
+    Some(reader.duration() as f32 / reader.spec().sample_rate as f32)
+}
+
+type InfsrvWebSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Infsrv segmentation session which survives transient connection failures
+/// by reconnecting to the same node and re-sending not yet segmented PCM.
+struct SegmentStream {
+    url: Url,
+    allocation: Allocation,
+    /// Amount charged for the segmented audio so far.
+    charged: watch::Sender<Decimal>,
+    terminator: Option<Vec<u8>>,
+    reconnect: ReconnectParams,
+    breaker: Arc<CircuitBreaker<IpAddr>>,
+    pending: PendingPcm,
+    /// Stream time (in seconds) the current connection started at.
+    offset: f32,
+}
+
+impl SegmentStream {
+    /// Connect to infsrv and re-send pending PCM (if any)
+    /// followed by the terminator if the stream has been terminated.
+    async fn connect(&self, terminated: bool) -> Result<InfsrvWebSocket> {
+        let mut url = self.url.clone();
+        url.set_ip_host(self.allocation.ip_address()).unwrap();
+        let mut request = url.as_str().into_client_request().unwrap();
         let headers = request.headers_mut();
         headers.append(
             CAPABILITIES_HEADER,
-            allocation.capabilities().join(",").try_into().unwrap(),
+            self.allocation.capabilities().join(",").try_into().unwrap(),
         );
         headers.append(CONTENT_TYPE, "audio/lpcm".try_into().unwrap());
-        if let Some(delim) = terminator {
-            headers.append(TERMINATOR_HEADER, delim.try_into().unwrap());
+        if let Some(delim) = &self.terminator {
+            headers.append(TERMINATOR_HEADER, delim.as_slice().try_into().unwrap());
+        }
+
+        let (mut ws, _) = connect_async(request).await?;
+        for chunk in self.pending.bytes().chunks(RESEND_CHUNK_SIZE) {
+            ws.send(Message::binary(chunk)).await?;
+        }
+        if let Some(delim) = self.terminator.as_ref().filter(|_| terminated) {
+            ws.send(Message::binary(delim.clone())).await?;
         }
+        Ok(ws)
+    }
+
+    /// Reconnect with exponential backoff, returns None if attempts are exhausted.
+    async fn reconnect(&mut self, terminated: bool) -> Option<InfsrvWebSocket> {
+        let mut delay = self.reconnect.delay;
+        for attempt in 1..=self.reconnect.attempts {
+            sleep(delay).await;
+            delay *= 2;
 
-        let (ws, _) = connect_async(request).await?;
+            match self.connect(terminated).await {
+                Ok(ws) => {
+                    info!("reconnected to infsrv ws (attempt {attempt})");
+                    self.offset = self.pending.resume_offset();
+                    return Some(ws);
+                }
+                Err(err) => debug!(
+                    "failed to reconnect to infsrv ws (attempt {attempt}): {}",
+                    ErrorChainDisplay(&err)
+                ),
+            }
+        }
+        self.breaker.fail(self.allocation.ip_address());
+        None
+    }
+
+    /// Forward PCM to infsrv and segments back until either side finishes.
+    async fn run(
+        mut self,
+        ws: InfsrvWebSocket,
+        mut receiver: Receiver<Vec<u8>>,
+        sender: Sender<Result<SegmentItem>>,
+    ) {
         let (mut ws_sender, mut ws_receiver) = ws.split();
+        let mut closed_interval = interval(Duration::from_secs(5));
+        closed_interval.tick().await;
+        let mut closing = false;
+        let mut terminated = false;
 
-        let (sender, infsrv_receiver) = channel(32);
-        let (infsrv_sender, mut receiver) = channel(32);
-
-        tokio::spawn(async move {
-            let mut closed_interval = interval(Duration::from_secs(5));
-            closed_interval.tick().await;
-            loop {
-                tokio::select! {
-                    maybe_pcm = receiver.recv() => {
-                        let Some(pcm) = maybe_pcm else {
-                            break;
-                        };
-                        if let Err(err) = ws_sender.send(Message::binary(pcm)).await {
-                            debug!("failed to send pcm to infsrv ws: {}", ErrorChainDisplay(&err));
-                            break;
+        use Error::*;
+        loop {
+            let failed = tokio::select! {
+                maybe_pcm = receiver.recv(), if !closing => {
+                    match maybe_pcm {
+                        Some(pcm) => {
+                            if Some(&pcm) == self.terminator.as_ref() {
+                                terminated = true;
+                            } else {
+                                self.pending.push(&pcm);
+                            }
+                            match ws_sender.send(Message::binary(pcm)).await {
+                                Ok(_) => false,
+                                Err(err) => {
+                                    debug!("failed to send pcm to infsrv ws: {}", ErrorChainDisplay(&err));
+                                    true
+                                }
+                            }
                         }
-                    },
-                    _ = closed_interval.tick() => {
-                        match allocation.check_invalidated().await {
-                            Ok(true) => {
-                                debug!("detected allocation closed");
+                        None => {
+                            closing = true;
+                            let _ = ws_sender.close().await;
+                            false
+                        }
+                    }
+                },
+                _ = closed_interval.tick(), if !closing => {
+                    match self.allocation.check_invalidated().await {
+                        Ok(true) => debug!("detected allocation closed"),
+                        Err(err) => error!("failed to check if allocation closed: {}", ErrorChainDisplay(&err)),
+                        _ => continue,
+                    }
+                    closing = true;
+                    receiver.close();
+                    let _ = ws_sender.close().await;
+                    false
+                },
+                maybe_msg = ws_receiver.next() => {
+                    match maybe_msg {
+                        Some(Ok(Message::Text(json))) => {
+                            let Ok(mut item) = serde_json::from_str::<'_, SegmentItem>(&json) else {
+                                debug!("failed to parse infsrv segment json '{json}'");
+                                let _ = sender.send(Err(Internal)).await;
                                 break;
-                            }
-                            Err(err) => {
-                                error!("failed to check if allocation closed: {}", ErrorChainDisplay(&err));
+                            };
+                            item.shift(self.offset);
+                            self.pending.consume_until(item.end());
+                            self.consume(item.end()).await;
+                            if sender.send(Ok(item)).await.is_err() {
                                 break;
                             }
-                            _ => {},
+                            false
                         }
-                    }
-                }
-            }
-
-            let _ = ws_sender.close().await;
-            debug!("finished sending pcm to infsrv ws");
-        });
-
-        use Error::*;
-        tokio::spawn(async move {
-            while let Some(result) = ws_receiver.next().await {
-                match result {
-                    Ok(Message::Text(json)) => {
-                        let Ok(item) = serde_json::from_str::<'_, SegmentItem>(&json) else {
-                            debug!("failed to parse infsrv segment json '{json}'");
-                            let _ = sender.send(Err(Internal)).await;
+                        Some(Ok(Message::Close(maybe_reason))) => {
+                            if let Some(reason) = maybe_reason {
+                                debug!("received close msg (reason = {reason}) from infsrv ws");
+                            } else {
+                                debug!("received close msg from infsrv ws");
+                            }
                             break;
-                        };
-                        if sender.send(Ok(item)).await.is_err() {
+                        }
+                        Some(Ok(msg)) => {
+                            debug!("ignoring infsrv ws msg {:?}", TruncateDebug::new(&msg));
+                            false
+                        }
+                        Some(Err(err)) if closing => {
+                            debug!("failed to receive from infsrv ws: {}", ErrorChainDisplay(&err));
+                            let _ = sender.send(Err(Tungstanite(Box::new(err)))).await;
                             break;
                         }
-                    }
-                    Ok(Message::Close(maybe_reason)) => {
-                        if let Some(reason) = maybe_reason {
-                            debug!("received close msg (reason = {reason}) from infsrv ws");
-                        } else {
-                            debug!("received close msg from infsrv ws");
+                        Some(Err(err)) => {
+                            debug!("failed to receive from infsrv ws: {}", ErrorChainDisplay(&err));
+                            true
                         }
-                        break;
-                    }
-                    Ok(msg) => {
-                        debug!("ignoring infsrv ws msg {:?}", TruncateDebug::new(&msg));
-                        continue;
+                        None if closing => break,
+                        None => true,
                     }
-                    Err(err) => {
-                        debug!(
-                            "failed to receive from infsrv ws: {}",
-                            ErrorChainDisplay(&err)
-                        );
-                        let _ = sender.send(Err(Tungstanite(err))).await;
-                        break;
-                    }
-                }
+                },
+            };
+
+            if failed {
+                let Some(ws) = self.reconnect(terminated).await else {
+                    let _ = sender.send(Err(Disconnected)).await;
+                    break;
+                };
+                (ws_sender, ws_receiver) = ws.split();
             }
-            debug!("finished receiving segments from infsrv ws");
-        });
+        }
+
+        let _ = ws_sender.close().await;
+        debug!("finished segmenting infsrv stream");
 
-        Ok((infsrv_sender, infsrv_receiver))
+        // Audio past the last segment (e.g. trailing silence) was processed as well.
+        self.consume(self.pending.end()).await;
     }
 
-    /// Transcribe a given wav-blob.
-    pub async fn transcribe(
-        &self,
-        user: Uuid,
-        tariff: &str,
-        wav_blob: Vec<u8>,
-        language: Option<String>,
-        prompt: Option<String>,
-    ) -> Result<TranscribeItem> {
-        let allocation = self
-            .ledger
-            .allocate(user, tariff, TaskType::Transcribe)
-            .await?;
+    /// Charge for audio segmented up to a given time (in seconds).
+    async fn consume(&mut self, time_consumed: f32) {
+        match self.allocation.consume(time_consumed).await {
+            Ok(amount) => self.charged.send_modify(|charged| *charged += amount),
+            Err(err) => error!(
+                "failed to charge for segmented audio: {}",
+                ErrorChainDisplay(&err)
+            ),
+        }
+    }
+}
+
+/// PCM sent to infsrv which is not covered by received segments yet.
+/// When the capacity is exceeded, the oldest PCM is discarded.
+struct PendingPcm {
+    buf: VecDeque<u8>,
+    capacity: usize,
+    /// Stream index of the first pending sample.
+    begin_sample: usize,
+    /// Stream time (in seconds) of the end of the last consumed segment.
+    consumed: f32,
+}
+
+impl PendingPcm {
+    /// Create a new PendingPcm instance with a given capacity (in bytes).
+    fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buf: VecDeque::with_capacity(capacity),
+            capacity,
+            begin_sample: 0,
+            consumed: 0.0,
+        }
+    }
+
+    /// Stream time (in seconds) of the first pending sample.
+    fn begin(&self) -> f32 {
+        self.begin_sample as f32 / SAMPLE_RATE
+    }
+
+    /// Stream time (in seconds) of the end of sent PCM.
+    fn end(&self) -> f32 {
+        (self.begin_sample + self.buf.len() / BYTES_PER_SAMPLE) as f32 / SAMPLE_RATE
+    }
+
+    /// Stream time (in seconds) segments of a resent pending PCM start at.
+    /// A segment end is rounded to a sample, so the pending PCM can start
+    /// slightly before it, while new segments must not overlap delivered ones.
+    fn resume_offset(&self) -> f32 {
+        self.begin().max(self.consumed)
+    }
 
-        let mut form = Form::new().part("file", Part::bytes(wav_blob).file_name("file.wav"));
+    /// Pending PCM bytes.
+    fn bytes(&self) -> Vec<u8> {
+        self.buf.iter().copied().collect()
+    }
 
-        if let Some(language) = language {
-            form = form.text("language", language);
+    /// Append sent PCM.
+    fn push(&mut self, pcm: &[u8]) {
+        self.buf.extend(pcm);
+        if self.buf.len() > self.capacity {
+            self.discard(self.buf.len() - self.capacity);
         }
+    }
+
+    /// Discard PCM preceding a given stream time (in seconds).
+    fn consume_until(&mut self, end: f32) {
+        self.consumed = self.consumed.max(end);
+        let end_sample = (end * SAMPLE_RATE).round().max(0.0) as usize;
+        let samples = end_sample.saturating_sub(self.begin_sample);
+        self.discard((samples * BYTES_PER_SAMPLE).min(self.buf.len()));
+    }
 
-        if let Some(prompt) = prompt {
-            form = form.text("prompt", prompt);
+    fn discard(&mut self, len: usize) {
+        let len = len - len % BYTES_PER_SAMPLE;
+        self.buf.drain(..len);
+        self.begin_sample += len / BYTES_PER_SAMPLE;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capability(
+        max_segment_duration: Option<f32>,
+        segment_window_duration: Option<f32>,
+    ) -> Capability {
+        Capability {
+            id: Uuid::nil(),
+            name: "segment-cpu".to_owned(),
+            compute_load: 0,
+            memory_load: 0,
+            fee: Decimal::ZERO,
+            languages: None,
+            max_segment_duration,
+            segment_window_duration,
+            sample_rate: None,
+            trim_threshold: None,
+            transcribe_concurrency: None,
+            capture_audio: false,
+            strict_placement: false,
         }
+    }
 
-        let mut url = Url::parse("http://127.0.0.1:9322/transcribe").unwrap();
-        url.set_ip_host(allocation.ip_address()).unwrap();
-        let response = Client::default()
-            .post(url)
-            .header(CAPABILITIES_HEADER, allocation.capabilities().join(","))
-            .multipart(form)
-            .send()
-            .await?;
+    #[test]
+    fn test_segment_item_shift() {
+        let mut item = SegmentItem::Speech {
+            begin: 1.0,
+            end: 2.5,
+        };
+        item.shift(10.0);
+        assert!(matches!(item, SegmentItem::Speech { begin, end } if begin == 11.0 && end == 12.5));
+        assert_eq!(item.end(), 12.5);
+    }
 
-        let text = response.text().await?;
-        let item = serde_json::from_str(&text)?;
-        Ok(item)
+    #[test]
+    fn test_pending_pcm() {
+        let second = SAMPLE_RATE as usize * BYTES_PER_SAMPLE;
+        let mut pending = PendingPcm::with_capacity(4 * second);
+        pending.push(&vec![1; 2 * second]);
+        pending.push(&vec![2; second]);
+        assert_eq!(pending.begin(), 0.0);
+        assert_eq!(pending.end(), 3.0);
+        assert_eq!(pending.bytes().len(), 3 * second);
+
+        pending.consume_until(1.5);
+        assert_eq!(pending.begin(), 1.5);
+        assert_eq!(pending.bytes().len(), 3 * second / 2);
+        assert_eq!(pending.bytes()[0], 1);
+
+        // Segments ending before pending PCM have no effect.
+        pending.consume_until(1.0);
+        assert_eq!(pending.begin(), 1.5);
+
+        // The oldest PCM is discarded once the capacity is exceeded.
+        pending.push(&vec![3; 3 * second]);
+        assert_eq!(pending.begin(), 2.0);
+        assert_eq!(pending.bytes().len(), 4 * second);
+        assert_eq!(pending.bytes()[0], 2);
+
+        assert_eq!(pending.end(), 6.0);
+
+        pending.consume_until(100.0);
+        assert_eq!(pending.begin(), 6.0);
+        assert_eq!(pending.end(), 6.0);
+        assert!(pending.bytes().is_empty());
+    }
+
+    #[test]
+    fn test_pending_pcm_resume_offset() {
+        let second = SAMPLE_RATE as usize * BYTES_PER_SAMPLE;
+        let mut pending = PendingPcm::with_capacity(4 * second);
+        pending.push(&vec![1; 3 * second]);
+        assert_eq!(pending.resume_offset(), 0.0);
+
+        // A segment end which is not sample-aligned is rounded down to a sample.
+        let end = 2.0 + 0.3 / SAMPLE_RATE;
+        pending.consume_until(end);
+        assert!(pending.begin() < end);
+        assert_eq!(pending.resume_offset(), end);
+
+        // Discarded PCM takes precedence over consumed segments.
+        pending.push(&vec![2; 4 * second]);
+        assert_eq!(pending.begin(), 3.0);
+        assert_eq!(pending.resume_offset(), 3.0);
+    }
+
+    #[test]
+    fn test_transcribe_item_speaker() {
+        let item: TranscribeItem = serde_json::from_str(r#"{"text": "hello"}"#).unwrap();
+        assert_eq!(item.speaker, None);
+
+        let item: TranscribeItem =
+            serde_json::from_str(r#"{"text": "hello", "speaker": "SPEAKER_01"}"#).unwrap();
+        assert_eq!(item.speaker.as_deref(), Some("SPEAKER_01"));
+    }
+
+    #[tokio::test]
+    async fn test_read_transcribe_response() {
+        let router = axum::Router::new()
+            .route(
+                "/overloaded",
+                axum::routing::post(|| async { (StatusCode::SERVICE_UNAVAILABLE, "busy") }),
+            )
+            .route(
+                "/rejected",
+                axum::routing::post(|| async { (StatusCode::BAD_REQUEST, "bad audio") }),
+            )
+            .route(
+                "/html",
+                axum::routing::post(|| async { axum::response::Html("<html></html>") }),
+            )
+            .route(
+                "/ok",
+                axum::routing::post(|| async { axum::Json(serde_json::json!({"text": "hi"})) }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let client = Client::new();
+        let read = |path: &'static str| {
+            let request = client.post(format!("http://{address}{path}"));
+            async move { read_transcribe_response(request.send().await.unwrap()).await }
+        };
+
+        let err = read("/overloaded").await.err().unwrap();
+        assert!(matches!(
+            err,
+            Error::UnexpectedStatus(StatusCode::SERVICE_UNAVAILABLE)
+        ));
+        assert_eq!(err.kind(), &kind::INFSRV_OVERLOADED);
+        assert!(err.is_retriable());
+        assert!(err.is_node_failure());
+        assert!(!err.is_rejection());
+
+        let err = read("/rejected").await.err().unwrap();
+        assert!(matches!(
+            err,
+            Error::UnexpectedStatus(StatusCode::BAD_REQUEST)
+        ));
+        assert_eq!(err.kind(), &kind::INFSRV_REJECTED_AUDIO);
+        assert!(!err.is_retriable());
+        assert!(!err.is_node_failure());
+        assert!(err.is_rejection());
+
+        let err = read("/html").await.err().unwrap();
+        assert!(matches!(err, Error::UnexpectedResponse));
+        assert!(err.is_retriable());
+
+        assert_eq!(read("/ok").await.unwrap().text, "hi");
+    }
+
+    #[test]
+    fn test_is_json_content_type() {
+        assert!(is_json_content_type("application/json"));
+        assert!(is_json_content_type("application/json; charset=utf-8"));
+        assert!(is_json_content_type("Application/JSON"));
+        assert!(!is_json_content_type("text/html; charset=utf-8"));
+        assert!(!is_json_content_type("text/plain"));
+        assert!(!is_json_content_type(""));
+    }
+
+    #[test]
+    fn test_wav_duration() {
+        let wav = crate::audio::encode_wav(vec![0; 24000].into_iter(), SAMPLE_RATE as u32);
+        assert_eq!(wav_duration(&wav), Some(1.5));
+        assert_eq!(
+            wav_duration(&crate::audio::encode_wav(
+                [].into_iter(),
+                SAMPLE_RATE as u32
+            )),
+            Some(0.0)
+        );
+        assert_eq!(wav_duration(b"not a wav"), None);
+    }
+
+    #[test]
+    fn test_vad_sensitivity_min_duration_on() {
+        // Range of minimum in-window speech durations (in seconds) supported by infsrv.
+        const MIN_DURATION_ON_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+
+        let durations = [
+            VadSensitivity::Low,
+            VadSensitivity::Medium,
+            VadSensitivity::High,
+        ]
+        .map(VadSensitivity::min_duration_on);
+
+        // Higher sensitivity keeps shorter speech.
+        assert!(durations.is_sorted_by(|a, b| a > b));
+        assert!(durations.iter().all(|d| MIN_DURATION_ON_RANGE.contains(d)));
+
+        // The default keeps the infsrv default of no minimum duration.
+        assert_eq!(VadSensitivity::default().min_duration_on(), 0.0);
+        assert_eq!(
+            SegmentParams::default().vad_sensitivity,
+            VadSensitivity::default()
+        );
+    }
+
+    #[test]
+    fn test_segment_params_from_capabilities() {
+        assert_eq!(
+            SegmentParams::from_capabilities(&[]),
+            SegmentParams::default()
+        );
+        assert_eq!(
+            SegmentParams::from_capabilities(&[capability(None, None)]),
+            SegmentParams::default()
+        );
+
+        let params = SegmentParams::from_capabilities(&[
+            capability(Some(10.0), None),
+            capability(Some(20.0), Some(2.0)),
+        ]);
+        assert_eq!(
+            params,
+            SegmentParams {
+                max_segment_duration: 10.0,
+                window_duration: 2.0,
+                vad_sensitivity: VadSensitivity::High,
+                speech_flush_grace: 0.0,
+            }
+        );
+        assert_eq!(params.min_speech_duration(), 10.0);
+        assert_eq!(params.ring_buffer_capacity(), 320000);
+
+        let params = SegmentParams::from_capabilities(&[capability(Some(0.0), Some(0.001))]);
+        assert_eq!(
+            params,
+            SegmentParams {
+                max_segment_duration: 5.0,
+                window_duration: 1.0,
+                vad_sensitivity: VadSensitivity::High,
+                speech_flush_grace: 0.0,
+            }
+        );
+        assert_eq!(params.min_speech_duration(), 5.0);
+
+        let params = SegmentParams::from_capabilities(&[capability(Some(1000.0), Some(60.0))]);
+        assert_eq!(
+            params,
+            SegmentParams {
+                max_segment_duration: 300.0,
+                window_duration: 10.0,
+                vad_sensitivity: VadSensitivity::High,
+                speech_flush_grace: 0.0,
+            }
+        );
     }
 }
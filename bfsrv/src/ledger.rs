@@ -1,21 +1,26 @@
 use crate::{
+    config::{AllocationMode, TariffConcurrencyLimit},
     data::{
         capability::{Capability, TaskType},
         node::Node,
         user::User,
     },
-    util::fmt::ErrorChainDisplay,
+    error_kind::{self as kind, ErrorKind},
+    util::{
+        fmt::ErrorChainDisplay,
+        retry::{retry_on_serialization_failure, retry_while, RetryPolicy, SerializationFailure},
+    },
 };
-use axum::http::StatusCode;
-use deadpool_postgres::{Client, Pool as PgPool};
+use deadpool_postgres::{Client, GenericClient, Pool as PgPool, PoolError};
 use log::{debug, error};
 use rust_decimal::Decimal;
-use std::{net::IpAddr, time::Duration};
-use tokio::{
-    sync::oneshot::{channel, Sender},
-    time::interval,
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
-use tokio_postgres::{error::SqlState, IsolationLevel};
+use tokio_postgres::IsolationLevel;
 use uuid::Uuid;
 
 /// Ledger error.
@@ -33,6 +38,8 @@ pub enum Error {
         #[source]
         deadpool_postgres::PoolError,
     ),
+    #[error("invalid capability fees")]
+    InvalidFees,
     #[error("node {0} not found")]
     NodeNotFound(Uuid),
     #[error("not enough balance")]
@@ -45,35 +52,41 @@ pub enum Error {
     ),
     #[error("not enough resources")]
     NotEnoughResources,
+    #[error("tariff {0} concurrency exceeded")]
+    TariffConcurrencyExceeded(String),
+    #[error("tariff {0} not found")]
+    TariffNotFound(String),
     #[error("user {0} not found")]
     UserNotFound(Uuid),
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
         match self {
-            Data(_) | DeadpoolPool(_) | Postgres(_) | NodeNotFound(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
-            }
-            UserNotFound(_) => StatusCode::NOT_FOUND,
-            NotEnoughBalance => StatusCode::PAYMENT_REQUIRED,
-            NotEnoughResources => StatusCode::TOO_MANY_REQUESTS,
+            Data(err) => err.kind(),
+            DeadpoolPool(PoolError::Timeout(_)) => &kind::DEADPOOL_POOL_TIMEOUT,
+            DeadpoolPool(_) => &kind::DEADPOOL_POOL,
+            InvalidFees => &kind::INVALID_FEES,
+            NodeNotFound(_) => &kind::NODE_NOT_FOUND,
+            NotEnoughBalance => &kind::NOT_ENOUGH_BALANCE,
+            NotEnoughResources => &kind::NOT_ENOUGH_RESOURCES,
+            Postgres(_) => &kind::POSTGRES,
+            TariffConcurrencyExceeded(_) => &kind::TARIFF_CONCURRENCY_EXCEEDED,
+            TariffNotFound(_) => &kind::TARIFF_NOT_FOUND,
+            UserNotFound(_) => &kind::USER_NOT_FOUND,
         }
     }
+}
 
-    /// Kind code.
-    pub fn code(&self) -> &str {
+impl SerializationFailure for Error {
+    fn is_serialization_failure(&self) -> bool {
         use Error::*;
         match self {
-            Data(err) => err.code(),
-            DeadpoolPool(_) => "deadpool_pool",
-            NodeNotFound(_) => "node_not_found",
-            NotEnoughBalance => "not_enough_balance",
-            NotEnoughResources => "not_enough_resources",
-            Postgres(_) => "postgres",
-            UserNotFound(_) => "user_not_found",
+            Data(err) => err.is_serialization_failure(),
+            Postgres(err) => err.is_serialization_failure(),
+            _ => false,
         }
     }
 }
@@ -81,120 +94,107 @@ impl Error {
 /// Ledger result.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Retry policy for resource allocation (which also waits for free resources).
+const ALLOCATE_RETRY_POLICY: RetryPolicy = RetryPolicy::new(100, Duration::from_millis(10));
+
+/// Retry policy for resource deallocation (which must eventually succeed).
+const DEALLOCATE_RETRY_POLICY: RetryPolicy = RetryPolicy::new(10000, Duration::from_millis(10));
+
 /// Node usage ledger.
 pub struct Ledger {
     pg_pool: PgPool,
-    stop_sender: Option<Sender<()>>,
+    currency: String,
+    billing_unit_secs: u64,
+    mode: AllocationMode,
+    tariff_slots: Arc<TariffSlots>,
 }
 
 impl Ledger {
-    /// Create a new Ledger instance.
-    pub fn new(pg_pool: PgPool) -> Self {
-        let (stop_sender, mut stop_receiver) = channel::<()>();
-
-        let pool_cloned = pg_pool.clone();
-        let mut interval = interval(Duration::from_secs(1));
-        tokio::spawn(async move {
-            interval.tick().await;
-            loop {
-                tokio::select! {
-                    _ = interval.tick() => {
-                        if let Err(err) = update_balances(&pool_cloned).await {
-                            error!("failed to update user balances: {}", ErrorChainDisplay(&err));
-                        }
-                    },
-                    _ = &mut stop_receiver => {
-                        debug!("stopped updating user balances");
-                        break;
-                    }
-                }
-            }
-        });
-
+    /// Create a new Ledger instance charging in a given currency
+    /// for consumed audio time rounded up to whole billing units
+    /// and placing tasks on nodes according to a given mode.
+    /// Concurrent allocations of tariffs are capped by given limits.
+    pub fn new(
+        pg_pool: PgPool,
+        currency: String,
+        billing_unit_secs: u64,
+        mode: AllocationMode,
+        tariff_limits: &[TariffConcurrencyLimit],
+    ) -> Self {
         Self {
             pg_pool,
-            stop_sender: Some(stop_sender),
+            currency,
+            billing_unit_secs,
+            mode,
+            tariff_slots: Arc::new(TariffSlots::new(tariff_limits)),
         }
     }
 
-    /// Allocate a node resource.
+    /// Allocate node resources for a task skipping nodes with excluded IP addresses.
     pub async fn allocate(
         &self,
         user: Uuid,
         tariff: &str,
         task_type: TaskType,
+        excluded: &[IpAddr],
     ) -> Result<Allocation> {
         let mut client = self.pg_pool.get().await?;
 
         let capabilities =
             Capability::find_with_task_type_and_tariff(&client, task_type, tariff).await?;
-
-        let (compute, memory, fee) = capabilities.iter().fold((0, 0, Decimal::ZERO), |acc, cap| {
-            (
-                acc.0 + cap.compute_load,
-                acc.1 + cap.memory_load,
-                acc.2 + cap.fee,
-            )
-        });
-
-        let capability_ids: Vec<_> = capabilities.iter().map(|c| c.id).collect();
-
-        let mut interval = interval(Duration::from_millis(10));
-        let mut remains = 100;
-
-        use Error::*;
-        let node = loop {
-            interval.tick().await;
-
-            let result = Self::try_allocate_atomically(
-                &mut client,
-                user,
-                &capability_ids,
-                compute,
-                memory,
-                fee,
-            )
-            .await;
-            if !matches!(&result, Err(NotEnoughResources)) && !is_serialization_failure(&result) {
-                break result;
-            }
-
-            remains -= 1;
-            if remains == 0 {
-                break result;
-            }
-        }?;
+        let fee = tariff_fee(&capabilities, tariff, &self.currency)?;
+
+        // A capped tariff is rejected even if nodes have free resources for it.
+        let tariff_slot = TariffSlots::acquire(&self.tariff_slots, tariff, task_type)?;
+
+        let capabilities = &capabilities;
+        let mode = self.mode;
+        let loads = retry_while(
+            ALLOCATE_RETRY_POLICY,
+            &mut client,
+            |err| matches!(err, Error::NotEnoughResources) || err.is_serialization_failure(),
+            |client| async move {
+                let result =
+                    Self::try_allocate_atomically(client, user, capabilities, excluded, mode).await;
+                (client, result)
+            },
+        )
+        .await?;
 
         let allocation_id = Uuid::new_v4();
-        let capability_names: Vec<_> = capabilities.into_iter().map(|n| n.name).collect();
+        let placements: Vec<_> = loads
+            .iter()
+            .map(|load| format!("{} on {}", load.capabilities.join(","), load.label))
+            .collect();
         log::debug!(
-            "allocated {allocation_id} ({} on {} for {})",
-            capability_names.join(","),
-            node.label,
+            "allocated {allocation_id} ({} for {})",
+            placements.join("; "),
             user
         );
 
         Ok(Allocation {
             id: allocation_id,
-            ip_address: node.ip_address,
-            capabilities: capability_names,
+            routes: loads.iter().map(Route::from).collect(),
+            route: 0,
             pool: self.pg_pool.clone(),
-            user,
-            node: node.id,
-            compute,
-            memory,
-            fee,
+            billing_unit_secs: self.billing_unit_secs,
+            resources: Some(AllocatedResources {
+                user,
+                loads,
+                fee,
+                billed_units: 0,
+            }),
+            _tariff_slot: tariff_slot,
         })
     }
 
     async fn try_allocate_atomically(
         client: &mut Client,
         user: Uuid,
-        capabilities: &[Uuid],
-        compute: u32,
-        memory: u32,
-        fee: Decimal,
-    ) -> Result<Node> {
+        capabilities: &[Capability],
+        excluded: &[IpAddr],
+        mode: AllocationMode,
+    ) -> Result<Vec<NodeLoad>> {
         let tx = client
             .build_transaction()
             .isolation_level(IsolationLevel::RepeatableRead)
@@ -202,104 +202,292 @@ impl Ledger {
             .await?;
 
         use Error::*;
-        let Some(mut user) = User::get(&tx, user).await? else {
+        let Some(user) = User::get(&tx, user).await? else {
             return Err(UserNotFound(user));
         };
 
-        if !user.balance.is_sign_positive() {
+        if !user.has_spendable_balance() {
             return Err(Error::NotEnoughBalance);
         }
 
-        let Some(mut node) =
-            Node::find_one_with_available_resources(&tx, capabilities, compute, memory).await?
+        // Co-location is favoured even if a task may span multiple nodes.
+        let loads = match Self::place_on_single_node(&tx, capabilities, excluded).await {
+            Err(NotEnoughResources) if mode == AllocationMode::MultiNode => {
+                Self::place_on_multiple_nodes(&tx, capabilities, excluded).await?
+            }
+            result => result?,
+        };
+
+        tx.commit().await?;
+        Ok(loads)
+    }
+
+    async fn place_on_single_node(
+        client: &impl GenericClient,
+        capabilities: &[Capability],
+        excluded: &[IpAddr],
+    ) -> Result<Vec<NodeLoad>> {
+        let (compute, memory) = total_loads(capabilities);
+        let capability_ids: Vec<_> = capabilities.iter().map(|c| c.id).collect();
+        let allowed = allowed_capabilities(capabilities);
+
+        let Some(mut node) = Node::find_one_with_available_resources(
+            client,
+            &capability_ids,
+            compute,
+            memory,
+            excluded,
+            allowed.as_deref(),
+            &[],
+        )
+        .await?
         else {
-            return Err(NotEnoughResources);
+            return Err(Error::NotEnoughResources);
         };
 
         node.compute_load += compute;
         node.memory_load += memory;
-        node.update(&tx).await?;
+        node.update(client).await?;
 
-        user.allocated_fee += fee;
-        user.update(&tx).await?;
+        let mut loads = Vec::new();
+        for cap in capabilities {
+            add_node_load(&mut loads, &node, cap);
+        }
+        Ok(loads)
+    }
 
-        tx.commit().await?;
-        Ok(node)
+    async fn place_on_multiple_nodes(
+        client: &impl GenericClient,
+        capabilities: &[Capability],
+        excluded: &[IpAddr],
+    ) -> Result<Vec<NodeLoad>> {
+        let allowed = allowed_capabilities(capabilities);
+
+        let mut loads: Vec<NodeLoad> = Vec::new();
+        for cap in capabilities {
+            // Node loads updated earlier in the transaction are taken into account.
+            let preferred: Vec<_> = loads.iter().map(|load| load.node).collect();
+            let Some(mut node) = Node::find_one_with_available_resources(
+                client,
+                &[cap.id],
+                cap.compute_load,
+                cap.memory_load,
+                excluded,
+                allowed.as_deref(),
+                &preferred,
+            )
+            .await?
+            else {
+                return Err(Error::NotEnoughResources);
+            };
+
+            node.compute_load += cap.compute_load;
+            node.memory_load += cap.memory_load;
+            node.update(client).await?;
+
+            add_node_load(&mut loads, &node, cap);
+        }
+        Ok(loads)
     }
 }
 
 /// Infsrv node resource allocation.
 pub struct Allocation {
     id: Uuid,
-    ip_address: IpAddr,
-    capabilities: Vec<String>,
+    /// Nodes of the allocation in the order requests are routed to them.
+    routes: Vec<Route>,
+    /// Index of the node requests are currently routed to.
+    route: usize,
     pool: PgPool,
+    billing_unit_secs: u64,
+    resources: Option<AllocatedResources>,
+    _tariff_slot: Option<TariffSlot>,
+}
+
+/// In-memory counters (per server instance) of active allocations of tariffs
+/// with concurrency limits. Allocations of different task types are counted
+/// separately, so e.g. segmentation held by open sessions doesn't block transcription.
+struct TariffSlots {
+    limits: HashMap<String, usize>,
+    active: Mutex<HashMap<(String, TaskType), usize>>,
+}
+
+impl TariffSlots {
+    fn new(limits: &[TariffConcurrencyLimit]) -> Self {
+        Self {
+            limits: limits.iter().map(|l| (l.tariff.clone(), l.max)).collect(),
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Occupy a slot of a given tariff and task type
+    /// (returns None if the tariff is not limited).
+    fn acquire(slots: &Arc<Self>, tariff: &str, task_type: TaskType) -> Result<Option<TariffSlot>> {
+        let Some(&max) = slots.limits.get(tariff) else {
+            return Ok(None);
+        };
+
+        let key = (tariff.to_owned(), task_type);
+        let mut active = slots.active.lock().unwrap();
+        let count = active.entry(key.clone()).or_default();
+        if *count >= max {
+            return Err(Error::TariffConcurrencyExceeded(tariff.to_owned()));
+        }
+        *count += 1;
+
+        Ok(Some(TariffSlot {
+            slots: slots.clone(),
+            key,
+        }))
+    }
+}
+
+/// Occupied tariff slot which is released when dropped.
+struct TariffSlot {
+    slots: Arc<TariffSlots>,
+    key: (String, TaskType),
+}
+
+impl Drop for TariffSlot {
+    fn drop(&mut self) {
+        let mut active = self.slots.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.key) {
+            *count -= 1;
+        }
+    }
+}
+
+/// Resources held by an allocation until it is deallocated.
+struct AllocatedResources {
     user: Uuid,
+    loads: Vec<NodeLoad>,
+    fee: Decimal,
+    billed_units: u64,
+}
+
+/// Resources allocated on a single node.
+struct NodeLoad {
     node: Uuid,
+    label: String,
+    ip_address: IpAddr,
+    capabilities: Vec<String>,
     compute: u32,
     memory: u32,
-    fee: Decimal,
+}
+
+/// Add a capability load to the node entry (creating it if missing).
+fn add_node_load(loads: &mut Vec<NodeLoad>, node: &Node, capability: &Capability) {
+    let index = match loads.iter().position(|load| load.node == node.id) {
+        Some(index) => index,
+        None => {
+            loads.push(NodeLoad {
+                node: node.id,
+                label: node.label.clone(),
+                ip_address: node.ip_address,
+                capabilities: Vec::new(),
+                compute: 0,
+                memory: 0,
+            });
+            loads.len() - 1
+        }
+    };
+
+    let load = &mut loads[index];
+    load.capabilities.push(capability.name.clone());
+    load.compute += capability.compute_load;
+    load.memory += capability.memory_load;
+}
+
+/// Node serving requests with the capabilities loaded on it.
+struct Route {
+    ip_address: IpAddr,
+    capabilities: Vec<String>,
+}
+
+impl From<&NodeLoad> for Route {
+    fn from(load: &NodeLoad) -> Self {
+        Self {
+            ip_address: load.ip_address,
+            capabilities: load.capabilities.clone(),
+        }
+    }
 }
 
 impl Allocation {
-    /// Allocated resource capabilities.
+    /// Capabilities allocated on a node requests are currently routed to
+    /// (a node may serve a request only with capabilities loaded on it).
     pub fn capabilities(&self) -> &[String] {
-        &self.capabilities
+        &self.routes[self.route].capabilities
     }
 
-    /// IP address of a node where the resource is allocated.
+    /// IP address of a node requests are currently routed to.
     pub fn ip_address(&self) -> IpAddr {
-        self.ip_address
+        self.routes[self.route].ip_address
+    }
+
+    /// Route requests to the next node of the allocation (e.g. once the current one
+    /// rejects a request it has no capabilities for). Returns false if no nodes left.
+    pub fn reroute(&mut self) -> bool {
+        if self.route + 1 == self.routes.len() {
+            return false;
+        }
+        self.route += 1;
+        debug!("rerouted {} to {}", self.id, self.ip_address());
+        true
+    }
+
+    /// Charge the user for audio time (in seconds) consumed since allocation.
+    /// Consumption is cumulative, so repeated reports are only charged once.
+    /// Returns the amount charged by this call.
+    pub async fn consume(&mut self, time_consumed: f32) -> Result<Decimal> {
+        let Some(resources) = &mut self.resources else {
+            return Ok(Decimal::ZERO);
+        };
+
+        let units = billable_units(time_consumed, self.billing_unit_secs);
+        if units <= resources.billed_units {
+            return Ok(Decimal::ZERO);
+        }
+
+        let secs = Decimal::from((units - resources.billed_units) * self.billing_unit_secs);
+        let amount = resources.fee * secs;
+
+        let client = self.pool.get().await?;
+        User::charge(&client, resources.user, amount).await?;
+        resources.billed_units = units;
+
+        debug!("charged {amount} for {secs}s of {}", self.id);
+        Ok(amount)
     }
 
     /// Check if the resource must be deallocated.
     pub async fn check_invalidated(&self) -> Result<bool> {
+        let Some(resources) = &self.resources else {
+            return Ok(true);
+        };
+
         let client = self.pool.get().await?;
 
-        let Some(user) = User::get(&client, self.user).await? else {
-            return Err(Error::UserNotFound(self.user));
+        let Some(user) = User::get(&client, resources.user).await? else {
+            return Err(Error::UserNotFound(resources.user));
         };
 
-        Ok(!user.balance.is_sign_positive())
+        Ok(!user.has_spendable_balance())
     }
 
-    async fn deallocate(
-        pool: PgPool,
-        user: Uuid,
-        node: Uuid,
-        compute: u32,
-        memory: u32,
-        fee: Decimal,
-    ) -> Result<()> {
+    async fn deallocate(pool: &PgPool, resources: &AllocatedResources) -> Result<()> {
         let mut client = pool.get().await?;
 
-        let mut interval = interval(Duration::from_millis(10));
-        let mut remains = 10000;
-
-        loop {
-            interval.tick().await;
-
-            let result =
-                Self::try_deallocate_atomically(&mut client, user, node, compute, memory, fee)
-                    .await;
-            if !is_serialization_failure(&result) {
-                break result;
-            }
-
-            remains -= 1;
-            if remains == 0 {
-                break result;
-            }
-        }
+        retry_on_serialization_failure(DEALLOCATE_RETRY_POLICY, &mut client, |client| async move {
+            let result = Self::try_deallocate_atomically(client, resources).await;
+            (client, result)
+        })
+        .await
     }
 
     async fn try_deallocate_atomically(
         client: &mut Client,
-        user: Uuid,
-        node: Uuid,
-        compute: u32,
-        memory: u32,
-        fee: Decimal,
+        resources: &AllocatedResources,
     ) -> Result<()> {
         let tx = client
             .build_transaction()
@@ -308,20 +496,14 @@ impl Allocation {
             .await?;
 
         use Error::*;
-        let Some(mut node) = Node::get(&tx, node).await? else {
-            return Err(NodeNotFound(node));
-        };
+        for load in &resources.loads {
+            let Some(mut node) = Node::get(&tx, load.node).await? else {
+                return Err(NodeNotFound(load.node));
+            };
 
-        node.compute_load -= compute;
-        node.memory_load -= memory;
-        node.update(&tx).await?;
-
-        let Some(mut user) = User::get(&tx, user).await? else {
-            return Err(UserNotFound(user));
-        };
-
-        user.allocated_fee -= fee;
-        user.update(&tx).await?;
+            node.release_load(load.compute, load.memory);
+            node.update(&tx).await?;
+        }
 
         tx.commit().await?;
         Ok(())
@@ -330,18 +512,19 @@ impl Allocation {
 
 impl Drop for Allocation {
     fn drop(&mut self) {
+        // Taking the resources guarantees they are released only once.
+        let Some(resources) = self.resources.take() else {
+            return;
+        };
+
         debug!("deallocating {}", self.id);
 
         let id = self.id;
         let pool = self.pool.clone();
-        let user = self.user;
-        let node = self.node;
-        let compute = self.compute;
-        let memory = self.memory;
-        let fee = self.fee;
 
         tokio::spawn(async move {
-            if let Err(err) = Self::deallocate(pool, user, node, compute, memory, fee).await {
+            // Only node loads are left after a failure, they are cleared on the next startup.
+            if let Err(err) = Self::deallocate(&pool, &resources).await {
                 error!("failed to deallocate {id}: {}", ErrorChainDisplay(&err));
             } else {
                 debug!("deallocated {id}");
@@ -350,22 +533,211 @@ impl Drop for Allocation {
     }
 }
 
-impl Drop for Ledger {
-    fn drop(&mut self) {
-        let stop_sender = self.stop_sender.take().unwrap();
-        stop_sender.send(()).unwrap()
+/// Fee of a tariff with given capabilities, a tariff without capabilities
+/// is rejected rather than being allocated for free.
+fn tariff_fee(capabilities: &[Capability], tariff: &str, currency: &str) -> Result<Decimal> {
+    if capabilities.is_empty() {
+        return Err(Error::TariffNotFound(tariff.to_owned()));
     }
+    Capability::total_fee(capabilities, currency).ok_or(Error::InvalidFees)
+}
+
+/// Total compute and memory loads of given capabilities.
+fn total_loads(capabilities: &[Capability]) -> (u32, u32) {
+    capabilities.iter().fold((0, 0), |acc, cap| {
+        (acc.0 + cap.compute_load, acc.1 + cap.memory_load)
+    })
 }
 
-async fn update_balances(pool: &PgPool) -> Result<()> {
-    let client = pool.get().await?;
-    User::update_balances(&client).await.map_err(Into::into)
+/// Capabilities nodes may host if any of given capabilities requires strict placement
+/// (None means nodes hosting a superset of the capabilities fit as well).
+fn allowed_capabilities(capabilities: &[Capability]) -> Option<Vec<Uuid>> {
+    capabilities
+        .iter()
+        .any(|c| c.strict_placement)
+        .then(|| capabilities.iter().map(|c| c.id).collect())
 }
 
-#[inline]
-fn is_serialization_failure<T>(error: &Result<T>) -> bool {
-    const SQL_STATE: Option<&SqlState> = Some(&SqlState::T_R_SERIALIZATION_FAILURE);
-    use Error::*;
-    matches!(error, Err(Data(crate::data::Error::Postgres(e))) if e.code() == SQL_STATE)
-        || matches!(error, Err(Postgres(e)) if e.code() == SQL_STATE)
+/// Number of billing units covering a given consumed time (any started unit counts).
+fn billable_units(time_consumed: f32, billing_unit_secs: u64) -> u64 {
+    let unit = billing_unit_secs.max(1) as f64;
+    (f64::from(time_consumed.max(0.0)) / unit).ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_billable_units() {
+        assert_eq!(billable_units(0.0, 1), 0);
+        assert_eq!(billable_units(-1.0, 1), 0);
+        assert_eq!(billable_units(0.01, 1), 1);
+        assert_eq!(billable_units(1.0, 1), 1);
+        assert_eq!(billable_units(1.5, 1), 2);
+        assert_eq!(billable_units(0.5, 10), 1);
+        assert_eq!(billable_units(10.0, 10), 1);
+        assert_eq!(billable_units(10.1, 10), 2);
+        assert_eq!(billable_units(3.0, 0), 3);
+    }
+
+    #[test]
+    fn test_tariff_slots() {
+        let limits = [TariffConcurrencyLimit {
+            tariff: "basic".to_owned(),
+            max: 2,
+        }];
+        let slots = Arc::new(TariffSlots::new(&limits));
+        let acquire = |tariff| TariffSlots::acquire(&slots, tariff, TaskType::Transcribe);
+        let active = |tariff: &str| {
+            let key = (tariff.to_owned(), TaskType::Transcribe);
+            slots.active.lock().unwrap().get(&key).copied()
+        };
+
+        let first = acquire("basic").unwrap();
+        let second = acquire("basic").unwrap();
+        assert!(first.is_some() && second.is_some());
+        assert!(matches!(
+            acquire("basic"),
+            Err(Error::TariffConcurrencyExceeded(t)) if t == "basic"
+        ));
+        assert_eq!(active("basic"), Some(2));
+
+        // Unlimited tariffs don't occupy slots.
+        assert!(acquire("premium").unwrap().is_none());
+        assert_eq!(active("premium"), None);
+
+        // Deallocation releases the slot.
+        drop(first);
+        assert_eq!(active("basic"), Some(1));
+        let third = acquire("basic").unwrap();
+        assert!(third.is_some());
+        assert!(acquire("basic").is_err());
+
+        drop((second, third));
+        assert_eq!(active("basic"), Some(0));
+    }
+
+    #[test]
+    fn test_tariff_slots_task_types() {
+        let limits = [TariffConcurrencyLimit {
+            tariff: "basic".to_owned(),
+            max: 1,
+        }];
+        let slots = Arc::new(TariffSlots::new(&limits));
+
+        // Segmentation held by an open session doesn't block its transcription.
+        let segment = TariffSlots::acquire(&slots, "basic", TaskType::Segment).unwrap();
+        assert!(segment.is_some());
+        assert!(TariffSlots::acquire(&slots, "basic", TaskType::Segment).is_err());
+        let transcribe = TariffSlots::acquire(&slots, "basic", TaskType::Transcribe).unwrap();
+        assert!(transcribe.is_some());
+        assert!(TariffSlots::acquire(&slots, "basic", TaskType::Transcribe).is_err());
+
+        drop(transcribe);
+        assert!(TariffSlots::acquire(&slots, "basic", TaskType::Transcribe).is_ok());
+    }
+
+    fn capability(id: u128, fee: Decimal, strict_placement: bool) -> Capability {
+        Capability {
+            id: Uuid::from_u128(id),
+            name: format!("cap{id}"),
+            compute_load: 1,
+            memory_load: 1,
+            fee,
+            languages: None,
+            max_segment_duration: None,
+            segment_window_duration: None,
+            sample_rate: None,
+            trim_threshold: None,
+            transcribe_concurrency: None,
+            capture_audio: false,
+            strict_placement,
+        }
+    }
+
+    #[test]
+    fn test_tariff_fee() {
+        assert!(matches!(
+            tariff_fee(&[], "bogus", "USD"),
+            Err(Error::TariffNotFound(t)) if t == "bogus"
+        ));
+
+        let fee = Decimal::new(5, 4);
+        let capabilities = [capability(1, fee, false), capability(2, fee, false)];
+        assert_eq!(
+            tariff_fee(&capabilities, "basic", "USD").unwrap(),
+            Decimal::new(1, 3)
+        );
+
+        let capabilities = [capability(1, -fee, false)];
+        assert!(matches!(
+            tariff_fee(&capabilities, "basic", "USD"),
+            Err(Error::InvalidFees)
+        ));
+    }
+
+    #[test]
+    fn test_allowed_capabilities() {
+        let capability = |id, strict_placement| capability(id, Decimal::ZERO, strict_placement);
+
+        // Loose placement lets nodes hosting a superset of capabilities fit.
+        assert_eq!(allowed_capabilities(&[]), None);
+        assert_eq!(
+            allowed_capabilities(&[capability(1, false), capability(2, false)]),
+            None
+        );
+
+        // Strict placement allows only nodes hosting no other capabilities.
+        assert_eq!(
+            allowed_capabilities(&[capability(1, false), capability(2, true)]),
+            Some(vec![Uuid::from_u128(1), Uuid::from_u128(2)])
+        );
+    }
+
+    #[test]
+    fn test_add_node_load() {
+        let node = |id| Node {
+            id: Uuid::from_u128(id),
+            label: format!("node{id}"),
+            ip_address: [127, 0, 0, id as u8].into(),
+            compute_capacity: 10,
+            memory_capacity: 10,
+            compute_load: 0,
+            memory_load: 0,
+        };
+        let capability = |name: &str, compute_load, memory_load| Capability {
+            id: Uuid::new_v4(),
+            name: name.to_owned(),
+            compute_load,
+            memory_load,
+            fee: Decimal::ZERO,
+            languages: None,
+            max_segment_duration: None,
+            segment_window_duration: None,
+            sample_rate: None,
+            trim_threshold: None,
+            transcribe_concurrency: None,
+            capture_audio: false,
+            strict_placement: false,
+        };
+
+        let mut loads = Vec::new();
+        add_node_load(&mut loads, &node(1), &capability("vad", 1, 2));
+        add_node_load(&mut loads, &node(2), &capability("whisper", 4, 5));
+        add_node_load(&mut loads, &node(1), &capability("punct", 1, 1));
+
+        assert_eq!(loads.len(), 2);
+        assert_eq!(loads[0].node, Uuid::from_u128(1));
+        assert_eq!(loads[0].capabilities, ["vad", "punct"]);
+        assert_eq!((loads[0].compute, loads[0].memory), (2, 3));
+        assert_eq!(loads[1].ip_address, IpAddr::from([127, 0, 0, 2]));
+        assert_eq!(loads[1].capabilities, ["whisper"]);
+        assert_eq!((loads[1].compute, loads[1].memory), (4, 5));
+
+        // Requests are routed to nodes in the order of placement.
+        let routes: Vec<_> = loads.iter().map(Route::from).collect();
+        assert_eq!(routes[0].ip_address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(routes[1].capabilities, ["whisper"]);
+    }
 }
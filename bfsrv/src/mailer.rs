@@ -1,34 +1,43 @@
-use crate::config::Config;
-use lettre::message::header::ContentType;
+use crate::{
+    config::Config,
+    data::email_suppression::EmailSuppression,
+    error_kind::{self as kind, ErrorKind},
+};
+use deadpool_postgres::GenericClient;
+use lettre::message::{header::ContentType, Mailbox};
 use lettre::{transport::smtp::authentication::Credentials, AsyncSmtpTransport, Tokio1Executor};
 use lettre::{Address as EmailAddress, AsyncTransport, Message};
-use reqwest::StatusCode;
+use log::{debug, warn};
+use rust_decimal::Decimal;
+use std::{collections::VecDeque, sync::Mutex};
 
 /// Mailer error.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("data")]
+    Data(
+        #[from]
+        #[source]
+        crate::data::Error,
+    ),
     #[error("lettre_smtp")]
     LettreSmtp(
         #[from]
         #[source]
         lettre::transport::smtp::Error,
     ),
+    #[error("emails to {0} are suppressed")]
+    Suppressed(EmailAddress),
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
         match self {
-            LettreSmtp(_) => StatusCode::INTERNAL_SERVER_ERROR,
-        }
-    }
-
-    /// Kind code.
-    pub fn code(&self) -> &str {
-        use Error::*;
-        match self {
-            LettreSmtp(_) => "lettre_smtp",
+            Data(err) => err.kind(),
+            LettreSmtp(_) => &kind::LETTRE_SMTP,
+            Suppressed(_) => &kind::EMAIL_SUPPRESSED,
         }
     }
 }
@@ -36,53 +45,315 @@ impl Error {
 /// Mailer result.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// SMTP relay value which selects the capture transport.
+pub const STUB_RELAY: &str = "stub";
+
+/// Maximum number of emails kept by the capture transport.
+const MAX_CAPTURED_EMAILS: usize = 100;
+
+/// Email captured instead of being sent.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedEmail {
+    pub to: EmailAddress,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Email transport.
+enum Transport {
+    /// Keep the latest emails in memory (for tests and staging).
+    Capture(Mutex<VecDeque<CapturedEmail>>),
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+}
+
 /// Email sender.
 pub struct Mailer {
-    from: EmailAddress,
-    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    reply_to: Option<Mailbox>,
+    transport: Transport,
 }
 
 impl Mailer {
-    /// Create a new Mailer instance.
+    /// Create a new Mailer instance (capturing emails if the SMTP relay is "stub").
     pub fn new(config: &Config) -> Self {
-        let credentials = Credentials::new(
-            config.smtp_username.to_owned(),
-            config.smtp_password.to_owned(),
-        );
-
-        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_relay)
-            .unwrap()
-            .credentials(credentials)
-            .build();
+        let transport = if config.smtp_relay == STUB_RELAY {
+            warn!("emails are captured instead of being sent");
+            Transport::Capture(Mutex::new(VecDeque::new()))
+        } else {
+            let credentials = Credentials::new(
+                config.smtp_username.to_owned(),
+                config.smtp_password.to_owned(),
+            );
+            let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_relay)
+                .unwrap()
+                .credentials(credentials)
+                .build();
+            Transport::Smtp(transport)
+        };
 
         Self {
-            from: config.smtp_from.clone(),
+            from: Mailbox::new(config.smtp_from_name.clone(), config.smtp_from.clone()),
+            reply_to: config.smtp_reply_to.clone().map(Mailbox::from),
             transport,
         }
     }
 
-    pub async fn send_token(&self, email: EmailAddress, access_token: &str) -> Result<()> {
+    /// Send an email confirmation token localized to the first supported
+    /// of preferred languages (English if none is supported).
+    /// Suppressed addresses are not mailed.
+    pub async fn send_token(
+        &self,
+        client: &impl GenericClient,
+        email: EmailAddress,
+        access_token: &str,
+        languages: &[String],
+    ) -> Result<()> {
+        Self::check_not_suppressed(client, &email).await?;
+
+        let strings = TokenEmailStrings::localized(languages);
+
+        self.send(
+            email,
+            strings.subject,
+            strings.body.replace("{access_token}", access_token),
+        )
+        .await
+    }
+
+    /// Send a notification of a low balance (in a given currency).
+    /// Suppressed addresses are not mailed.
+    pub async fn send_low_balance(
+        &self,
+        client: &impl GenericClient,
+        email: EmailAddress,
+        balance: Decimal,
+        currency: &str,
+    ) -> Result<()> {
+        Self::check_not_suppressed(client, &email).await?;
+
         let body = format!(
             "Hi,
 
+Your balance is running low: {balance} {currency}.
+
+Please top it up to keep transcription sessions from being interrupted.
+"
+        );
+        self.send(email, "Low balance", body).await
+    }
+
+    /// Emails captured instead of being sent (oldest first),
+    /// or None if emails are sent for real.
+    pub fn captured(&self) -> Option<Vec<CapturedEmail>> {
+        match &self.transport {
+            Transport::Capture(emails) => Some(emails.lock().unwrap().iter().cloned().collect()),
+            Transport::Smtp(_) => None,
+        }
+    }
+
+    async fn send(&self, to: EmailAddress, subject: &str, body: String) -> Result<()> {
+        match &self.transport {
+            Transport::Capture(emails) => {
+                let mut emails = emails.lock().unwrap();
+                if emails.len() == MAX_CAPTURED_EMAILS {
+                    emails.pop_front();
+                }
+                debug!("captured email to {to}");
+                emails.push_back(CapturedEmail {
+                    to,
+                    subject: subject.to_owned(),
+                    body,
+                });
+            }
+            Transport::Smtp(transport) => {
+                let message = self.new_message(to, subject, body);
+                transport.send(message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn new_message(&self, to: EmailAddress, subject: &str, body: String) -> Message {
+        let mut builder = Message::builder().from(self.from.clone()).to(to.into());
+        if let Some(reply_to) = &self.reply_to {
+            builder = builder.reply_to(reply_to.clone());
+        }
+        builder
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .unwrap()
+    }
+
+    async fn check_not_suppressed(client: &impl GenericClient, email: &EmailAddress) -> Result<()> {
+        if let Some(suppression) = EmailSuppression::get(client, email).await? {
+            debug!("skipped email to {email} ({})", suppression.reason);
+            return Err(Error::Suppressed(email.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// Localized strings of an email confirmation message.
+struct TokenEmailStrings {
+    language: &'static str,
+    subject: &'static str,
+    body: &'static str,
+}
+
+/// Email confirmation strings per language (English goes first as a fallback).
+const TOKEN_EMAIL_STRINGS: &[TokenEmailStrings] = &[
+    TokenEmailStrings {
+        language: "en",
+        subject: "Email confirmation",
+        body: "Hi,
+
 Here is the access token to confirm your email:
 
 {access_token}
 
 Please ignore this email if you did not initiate this action.
-"
+",
+    },
+    TokenEmailStrings {
+        language: "es",
+        subject: "Confirmación de correo electrónico",
+        body: "Hola:
+
+Este es el token de acceso para confirmar tu correo electrónico:
+
+{access_token}
+
+Ignora este mensaje si no has iniciado esta acción.
+",
+    },
+    TokenEmailStrings {
+        language: "ru",
+        subject: "Подтверждение электронной почты",
+        body: "Здравствуйте!
+
+Вот токен доступа для подтверждения вашей электронной почты:
+
+{access_token}
+
+Если вы не выполняли это действие, просто проигнорируйте это письмо.
+",
+    },
+];
+
+impl TokenEmailStrings {
+    /// Select strings for the first supported of given languages
+    /// (primary subtags are matched, so "es-MX" selects "es").
+    fn localized(languages: &[String]) -> &'static Self {
+        languages
+            .iter()
+            .filter_map(|lang| {
+                let primary = lang.split('-').next().unwrap_or_default();
+                TOKEN_EMAIL_STRINGS
+                    .iter()
+                    .find(|s| s.language.eq_ignore_ascii_case(primary))
+            })
+            .next()
+            .unwrap_or(&TOKEN_EMAIL_STRINGS[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn new_mailer(args: &[&str]) -> Mailer {
+        let relay = (!args.iter().any(|a| a.starts_with("--smtp-relay=")))
+            .then_some("--smtp-relay=localhost");
+        let args = [
+            "bfsrv",
+            "--database-url=postgres://localhost/blobfish",
+            "--paypal-cancel-url=https://example.com/cancel",
+            "--paypal-client-id=id",
+            "--paypal-return-url=https://example.com/return",
+            "--paypal-secret-key=key",
+            "--smtp-from=noreply@example.com",
+            "--smtp-username=user",
+            "--smtp-password=password",
+        ]
+        .into_iter()
+        .chain(relay)
+        .chain(args.iter().copied());
+        Mailer::new(&Config::parse_from(args))
+    }
+
+    fn format_message(mailer: &Mailer) -> String {
+        let message = mailer.new_message("john@example.com".parse().unwrap(), "Hi", String::new());
+        String::from_utf8(message.formatted()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mailer_new_message_headers() {
+        let message = format_message(&new_mailer(&[]));
+        assert!(message.contains("From: noreply@example.com\r\n"));
+        assert!(!message.contains("Reply-To:"));
+
+        let message = format_message(&new_mailer(&[
+            "--smtp-from-name=Blobfish",
+            "--smtp-reply-to=support@example.com",
+        ]));
+        assert!(message.contains("From: Blobfish <noreply@example.com>\r\n"));
+        assert!(message.contains("Reply-To: support@example.com\r\n"));
+
+        let message = format_message(&new_mailer(&["--smtp-from-name=Блобфиш"]));
+        assert!(message.contains("From: =?utf-8?b?0JHQu9C+0LHRhNC40Yg=?= <noreply@example.com>"));
+    }
+
+    #[tokio::test]
+    async fn test_mailer_capture() {
+        assert!(new_mailer(&[]).captured().is_none());
+
+        let mailer = new_mailer(&["--smtp-relay=stub"]);
+        assert_eq!(mailer.captured(), Some(vec![]));
+
+        for i in 0..=MAX_CAPTURED_EMAILS {
+            let to = format!("john{i}@example.com").parse().unwrap();
+            mailer.send(to, "Hi", format!("body {i}")).await.unwrap();
+        }
+
+        let captured = mailer.captured().unwrap();
+        assert_eq!(captured.len(), MAX_CAPTURED_EMAILS);
+        assert_eq!(captured[0].to.to_string(), "john1@example.com");
+        assert_eq!(captured[0].subject, "Hi");
+        assert_eq!(
+            captured.last().unwrap().body,
+            format!("body {MAX_CAPTURED_EMAILS}")
         );
+    }
 
-        let message = Message::builder()
-            .from(self.from.clone().into())
-            .to(email.into())
-            .subject("Email confirmation")
-            .header(ContentType::TEXT_PLAIN)
-            .body(body)
-            .unwrap();
+    #[test]
+    fn test_config_invalid_reply_to() {
+        let args = ["bfsrv", "--smtp-reply-to=not an address"];
+        let err = Config::try_parse_from(args).err().unwrap();
+        assert_eq!(err.kind(), clap::error::ErrorKind::ValueValidation);
+    }
 
-        self.transport.send(message).await?;
+    #[test]
+    fn test_token_email_strings_localized() {
+        let localized = |languages: &[&str]| {
+            let languages: Vec<_> = languages.iter().map(|l| l.to_string()).collect();
+            TokenEmailStrings::localized(&languages).language
+        };
 
-        Ok(())
+        assert_eq!(localized(&[]), "en");
+        assert_eq!(localized(&["fr"]), "en");
+        assert_eq!(localized(&["es"]), "es");
+        assert_eq!(localized(&["ES-mx"]), "es");
+        assert_eq!(localized(&["fr", "ru-RU", "es"]), "ru");
+        assert_eq!(localized(&["*"]), "en");
+    }
+
+    #[test]
+    fn test_token_email_strings_placeholder() {
+        for strings in TOKEN_EMAIL_STRINGS {
+            assert_eq!(strings.body.matches("{access_token}").count(), 1);
+            assert!(!strings.subject.is_empty());
+        }
     }
 }
@@ -1,6 +1,10 @@
+mod audio;
+mod audio_store;
 mod config;
 mod currency_converter;
+mod currency_format;
 mod data;
+mod error_kind;
 mod infsrv_pool;
 mod ledger;
 mod mailer;
@@ -8,21 +12,43 @@ mod paypal;
 mod server;
 mod util;
 
-use crate::{config::Config, ledger::Ledger};
+use crate::{
+    config::{Command, Config, LogFormat},
+    ledger::Ledger,
+};
 use clap::Parser;
 use currency_converter::CurrencyConverter;
-use data::{node::Node, user::User};
+use data::{
+    campaign::Campaign,
+    capability::{Capability, TaskType},
+    migration::migrate,
+    node::Node,
+    pool_config,
+    token::Token,
+    user::User,
+};
 use deadpool_postgres::{Config as DeadpoolClient, ManagerConfig, Pool, RecyclingMethod, Runtime};
-use infsrv_pool::InfsrvPool;
+use infsrv_pool::{InfsrvPool, ReconnectParams};
+use lettre::Address as EmailAddress;
+use log::info;
 use mailer::Mailer;
 use paypal::PaypalProcessor;
-use server::Server;
-use std::{future::Future, sync::Arc};
+use server::{Auth, Server};
+use std::{
+    future::Future,
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use time::OffsetDateTime;
 use tokio_postgres::NoTls;
-use util::fmt::ErrorChainDisplay;
+use tracing_subscriber::EnvFilter;
+use util::{circuit_breaker::CircuitBreakerParams, fmt::ErrorChainDisplay, http::client_builder};
 
 #[derive(Debug, thiserror::Error)]
 enum Error {
+    #[error("config: {0}")]
+    Config(String),
     #[error("data")]
     Data(
         #[from]
@@ -35,26 +61,53 @@ enum Error {
         #[source]
         deadpool_postgres::PoolError,
     ),
+    #[error("postgres")]
+    Postgres(
+        #[from]
+        #[source]
+        tokio_postgres::Error,
+    ),
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
 #[tokio::main]
 async fn main() {
-    let config = Config::parse();
-    if let Err(err) = run(config).await {
+    let mut config = Config::parse();
+    let result = match config.command.take() {
+        Some(Command::CreateAdminToken { email }) => create_admin_token(&config, email).await,
+        Some(Command::Migrate) => {
+            init_logging(config.log_format);
+            migrate_schema(&create_pg_pool(&config)).await
+        }
+        None => run(config).await,
+    };
+    if let Err(err) = result {
         eprintln!("exited with error: {}", ErrorChainDisplay(&err));
+        std::process::exit(1);
     }
 }
 
 async fn run(config: Config) -> Result<()> {
-    env_logger::builder().format_timestamp_millis().init();
+    init_logging(config.log_format);
 
-    let pg_pool = create_pg_pool(&config).await?;
-    let ledger = Ledger::new(pg_pool.clone());
-    let infsrv_pool = InfsrvPool::new(ledger);
-    let currency_converter = CurrencyConverter::new(config.currency.clone());
-    let paypal = new_paypal(&config);
+    let pg_pool = create_pg_pool(&config);
+    if config.migrate_on_startup {
+        migrate_schema(&pg_pool).await?;
+    }
+    reset_loads(&pg_pool).await?;
+    check_default_tariff(&config, &pg_pool).await?;
+    let ledger = Ledger::new(
+        pg_pool.clone(),
+        config.currency.clone(),
+        config.billing_unit_secs,
+        config.allocation_mode,
+        &config.tariff_concurrency_limits,
+    );
+    let http_client = new_http_client(&config);
+    let infsrv_pool = new_infsrv_pool(&config, ledger, http_client.clone());
+    let currency_converter = CurrencyConverter::new(config.currency.clone(), http_client.clone());
+    let paypal = new_paypal(&config, http_client)?;
     let mailer = Mailer::new(&config);
 
     let server = Arc::new(Server::new(
@@ -78,31 +131,150 @@ async fn run(config: Config) -> Result<()> {
     Ok(())
 }
 
-async fn create_pg_pool(config: &Config) -> Result<Pool> {
+fn init_logging(format: LogFormat) {
+    // Records of the log crate are forwarded to tracing subscriber.
+    let builder = tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env());
+    match format {
+        LogFormat::Json => builder.json().flatten_event(true).init(),
+        LogFormat::Text => builder.init(),
+    }
+}
+
+fn create_pg_pool(config: &Config) -> Pool {
     let mut deadpool_config = DeadpoolClient::new();
     deadpool_config.url = Some(config.database_url.to_string());
     deadpool_config.manager = Some(ManagerConfig {
         recycling_method: RecyclingMethod::Fast,
     });
-    let pool = deadpool_config
+    deadpool_config.pool = Some(pool_config(
+        config.pg_pool_max_size,
+        Duration::from_secs(config.pg_pool_wait_timeout_secs),
+        Duration::from_secs(config.pg_pool_create_timeout_secs),
+        Duration::from_secs(config.pg_pool_recycle_timeout_secs),
+    ));
+    deadpool_config
         .create_pool(Some(Runtime::Tokio1), NoTls)
-        .unwrap();
+        .unwrap()
+}
+
+async fn migrate_schema(pool: &Pool) -> Result<()> {
+    let mut client = pool.get().await?;
+    let applied = migrate(&mut client).await?;
+    if applied.is_empty() {
+        info!("database schema is up to date");
+    }
+    Ok(())
+}
 
+/// Clear node loads left by a previous server run,
+/// including ones whose deallocation failed or never happened.
+async fn reset_loads(pool: &Pool) -> Result<()> {
     let client = pool.get().await?;
     Node::clear_loads(&client).await?;
-    User::clear_allocated_fees(&client).await?;
+    Ok(())
+}
+
+/// Create an admin token printing it to stdout, registers the user if needed.
+async fn create_admin_token(config: &Config, email: EmailAddress) -> Result<()> {
+    let pool = create_pg_pool(config);
+    let mut client = pool.get().await?;
+    let tx = client.build_transaction().start().await?;
+
+    let mut user = match User::get_by_email(&tx, &email).await? {
+        Some(user) => user,
+        None => {
+            let Some(campaign) = Campaign::find_by_promo_code(&tx, "default").await? else {
+                return Err(Error::Config("default campaign not found".to_owned()));
+            };
+            if !Campaign::redeem(&tx, campaign.id).await? {
+                return Err(Error::Config("default campaign expired".to_owned()));
+            }
+            let mut user = User::new(email, None, campaign.id, campaign.initial_balance);
+            user.insert(&tx).await?;
+            user
+        }
+    };
+    user.is_admin = true;
+    user.update(&tx).await?;
+
+    let expires_at =
+        OffsetDateTime::now_utc() + Duration::from_secs(config.admin_token_max_ttl_secs);
+    let mut token = Token::new(
+        expires_at,
+        Some("admin".to_owned()),
+        Some(user.id),
+        true,
+        IpAddr::V4(Ipv4Addr::LOCALHOST),
+        None,
+    );
+    let key = token.insert(&tx, config.bcrypt_work_factor).await?;
+
+    tx.commit().await?;
+
+    println!("{}", Auth::compose_access_token(token.id, key));
+    Ok(())
+}
+
+async fn check_default_tariff(config: &Config, pool: &Pool) -> Result<()> {
+    let client = pool.get().await?;
+    let capabilities = Capability::find_with_task_type_and_tariff(
+        &client,
+        TaskType::default(),
+        &config.default_tariff,
+    )
+    .await?;
+    if capabilities.is_empty() {
+        return Err(Error::Config(format!(
+            "unknown default tariff {}",
+            config.default_tariff
+        )));
+    }
+    Ok(())
+}
 
-    Ok(pool)
+fn new_http_client(config: &Config) -> reqwest::Client {
+    client_builder(
+        Duration::from_secs(config.http_connect_timeout_secs),
+        Duration::from_secs(config.http_timeout_secs),
+    )
+    .build()
+    .unwrap()
 }
 
-fn new_paypal(config: &Config) -> PaypalProcessor {
-    PaypalProcessor::new(
+fn new_infsrv_pool(config: &Config, ledger: Ledger, http_client: reqwest::Client) -> InfsrvPool {
+    let reconnect = ReconnectParams {
+        attempts: config.infsrv_reconnect_attempts,
+        delay: Duration::from_secs(config.infsrv_reconnect_delay_secs),
+    };
+    let breaker = CircuitBreakerParams {
+        failures: config.infsrv_breaker_failures,
+        window: Duration::from_secs(config.infsrv_breaker_window_secs),
+        cooldown: Duration::from_secs(config.infsrv_breaker_cooldown_secs),
+    };
+    InfsrvPool::new(ledger, reconnect, breaker, http_client)
+}
+
+fn new_paypal(config: &Config, http_client: reqwest::Client) -> Result<PaypalProcessor> {
+    let mut paypal = PaypalProcessor::new(
         config.paypal_sandbox,
         config.paypal_client_id.clone(),
         config.paypal_secret_key.clone(),
         config.paypal_return_url.clone(),
         config.paypal_cancel_url.clone(),
-    )
+        Duration::from_secs(config.paypal_token_expiry_skew_secs),
+        http_client,
+    );
+    if let Some(currencies) = &config.paypal_currencies {
+        paypal
+            .restrict_currencies(currencies)
+            .map_err(|err| Error::Config(format!("bad paypal currencies ({err})")))?;
+    }
+    if let Some(locales) = &config.paypal_locales {
+        paypal
+            .restrict_locales(locales)
+            .map_err(|err| Error::Config(format!("bad paypal locales ({err})")))?;
+    }
+    Ok(paypal)
 }
 
 fn shutdown_signal() -> impl Future<Output = ()> + Unpin {
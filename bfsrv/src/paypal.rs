@@ -1,21 +1,34 @@
-use axum::http::StatusCode;
 use log::debug;
-use reqwest::Client;
+use rand::Rng;
+use reqwest::{Client, Response, StatusCode};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde_json::json;
 use std::{sync::RwLock, time::Duration};
 use time::OffsetDateTime;
+use tokio::{sync::Mutex, time::sleep};
 use url::Url;
 use uuid::Uuid;
 
-use crate::data::payment::{Payment, PaymentProcessor, PaymentStatus};
+use crate::{
+    data::payment::{Payment, PaymentProcessor, PaymentStatus},
+    error_kind::{self as kind, ErrorKind},
+    util::http::accept_languages,
+};
 
 /// Server error.
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("bad payment status")]
     BadPaymentStatus,
+    #[error("payment instrument declined")]
+    InstrumentDeclined,
+    #[error("order captures span multiple currencies")]
+    MixedCaptureCurrencies,
+    #[error("order already captured")]
+    OrderAlreadyCaptured,
+    #[error("order not approved by payer")]
+    OrderNotApproved,
     #[error("reqwest")]
     Reqwest(
         #[from]
@@ -35,25 +48,20 @@ pub enum Error {
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
         match self {
-            BadPaymentStatus => StatusCode::UNPROCESSABLE_ENTITY,
-            Reqwest(_) | SerdeJson(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            UnsupportedCurrency | UnsupportedLocale => StatusCode::BAD_REQUEST,
-        }
-    }
-
-    /// Kind code.
-    pub fn code(&self) -> &str {
-        use Error::*;
-        match self {
-            BadPaymentStatus => "bad_payment_status",
-            Reqwest(_) => "reqwest",
-            SerdeJson(_) => "serde_json",
-            UnsupportedCurrency => "unsupported_currency",
-            UnsupportedLocale => "unsupported_locale",
+            BadPaymentStatus => &kind::BAD_PAYMENT_STATUS,
+            InstrumentDeclined => &kind::INSTRUMENT_DECLINED,
+            MixedCaptureCurrencies => &kind::MIXED_CAPTURE_CURRENCIES,
+            OrderAlreadyCaptured => &kind::ORDER_ALREADY_CAPTURED,
+            OrderNotApproved => &kind::ORDER_NOT_APPROVED,
+            Reqwest(err) if err.is_timeout() => &kind::REQWEST_TIMEOUT,
+            Reqwest(_) => &kind::REQWEST,
+            SerdeJson(_) => &kind::SERDE_JSON,
+            UnsupportedCurrency => &kind::UNSUPPORTED_CURRENCY,
+            UnsupportedLocale => &kind::UNSUPPORTED_LOCALE,
         }
     }
 }
@@ -61,6 +69,40 @@ impl Error {
 /// Server result.
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Failure to restrict supported codes (e.g. currencies).
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum RestrictError {
+    #[error("empty list")]
+    EmptyList,
+    #[error("unsupported code '{0}'")]
+    Unsupported(String),
+}
+
+#[derive(Deserialize)]
+struct ErrorResponsePayload {
+    name: String,
+    #[serde(default)]
+    details: Vec<ErrorDetail>,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    issue: String,
+}
+
+impl ErrorResponsePayload {
+    /// Map known error issues to specific errors.
+    fn known_error(&self) -> Option<Error> {
+        use Error::*;
+        self.details.iter().find_map(|d| match d.issue.as_str() {
+            "INSTRUMENT_DECLINED" => Some(InstrumentDeclined),
+            "ORDER_ALREADY_CAPTURED" => Some(OrderAlreadyCaptured),
+            "ORDER_NOT_APPROVED" => Some(OrderNotApproved),
+            _ => None,
+        })
+    }
+}
+
 #[derive(Deserialize)]
 struct TokenResponsePayload {
     access_token: String,
@@ -76,11 +118,25 @@ struct OrderResponsePayload {
 }
 
 impl OrderResponsePayload {
-    fn net_amount(&self) -> Option<Decimal> {
-        self.purchase_units
-            .first()
-            .and_then(|u| u.payments.as_ref().and_then(|p| p.captures.first()))
-            .map(|c| c.seller_receivable_breakdown.net_amount.value)
+    /// Sum net amounts of all captures across all purchase units.
+    fn net_amount(&self) -> Result<Option<Decimal>> {
+        let mut total: Option<&Amount> = None;
+        let mut value = Decimal::ZERO;
+        for amount in self
+            .purchase_units
+            .iter()
+            .filter_map(|u| u.payments.as_ref())
+            .flat_map(|p| &p.captures)
+            .filter_map(|c| c.seller_receivable_breakdown.as_ref())
+            .map(|b| &b.net_amount)
+        {
+            if total.is_some_and(|t| t.currency_code != amount.currency_code) {
+                return Err(Error::MixedCaptureCurrencies);
+            }
+            total = Some(amount);
+            value += amount.value;
+        }
+        Ok(total.map(|_| value))
     }
 }
 
@@ -91,12 +147,14 @@ struct PurchaseUnits {
 
 #[derive(Deserialize)]
 struct Payments {
+    #[serde(default)]
     captures: Vec<Capture>,
 }
 
 #[derive(Deserialize)]
 struct Capture {
-    seller_receivable_breakdown: SellerReceivableBreakdown,
+    // Missing for declined or pending captures.
+    seller_receivable_breakdown: Option<SellerReceivableBreakdown>,
 }
 
 #[derive(Deserialize)]
@@ -106,6 +164,7 @@ struct SellerReceivableBreakdown {
 
 #[derive(Deserialize)]
 struct Amount {
+    currency_code: String,
     value: Decimal,
 }
 
@@ -114,6 +173,9 @@ struct State {
     token_expires_at: OffsetDateTime,
 }
 
+const TOKEN_FETCH_ATTEMPTS: u32 = 3;
+const TOKEN_FETCH_RETRY_DELAY: Duration = Duration::from_millis(500);
+
 /// Paypal payment processor.
 pub struct PaypalProcessor {
     sandbox: bool,
@@ -121,17 +183,25 @@ pub struct PaypalProcessor {
     secret_key: String,
     return_url: Url,
     cancel_url: Url,
+    token_expiry_skew: Duration,
+    client: Client,
     state: RwLock<State>,
+    token_refresh: Mutex<()>,
+    currencies: Vec<&'static str>,
+    locales: Vec<&'static str>,
 }
 
 impl PaypalProcessor {
     /// Create a new PaypalProcessor instance.
+    /// Tokens are refreshed a given skew time before they expire.
     pub fn new(
         sandbox: bool,
         client_id: String,
         secret_key: String,
         return_url: Url,
         cancel_url: Url,
+        token_expiry_skew: Duration,
+        client: Client,
     ) -> Self {
         Self {
             sandbox,
@@ -139,13 +209,36 @@ impl PaypalProcessor {
             secret_key,
             return_url,
             cancel_url,
+            token_expiry_skew,
+            client,
             state: RwLock::new(State {
                 token: String::new(),
                 token_expires_at: OffsetDateTime::UNIX_EPOCH,
             }),
+            token_refresh: Mutex::new(()),
+            currencies: Self::CURRENCIES.to_vec(),
+            locales: Self::LOCALES.to_vec(),
         }
     }
 
+    /// Accept only given currencies, which must be supported by PayPal.
+    pub fn restrict_currencies(
+        &mut self,
+        currencies: &[String],
+    ) -> std::result::Result<(), RestrictError> {
+        self.currencies = restrict(currencies, Self::CURRENCIES)?;
+        Ok(())
+    }
+
+    /// Accept only given locales, which must be supported by PayPal.
+    pub fn restrict_locales(
+        &mut self,
+        locales: &[String],
+    ) -> std::result::Result<(), RestrictError> {
+        self.locales = restrict(locales, Self::LOCALES)?;
+        Ok(())
+    }
+
     const CURRENCIES: &'static [&'static str] = &[
         "AUD", "BRL", "CAD", "CNY", "CZK", "DKK", "EUR", "HKD", "HUF", "ILS", "JPY", "MYR", "MXN",
         "TWD", "NZD", "NOK", "PHP", "PLN", "GBP", "RUB", "SGD", "SEK", "CHF", "THB", "USD",
@@ -157,6 +250,58 @@ impl PaypalProcessor {
         "zh-TW", "zh-XC",
     ];
 
+    /// Locales to use for language tags without a supported region.
+    const LANGUAGE_LOCALES: &'static [(&'static str, &'static str)] = &[
+        ("ar", "ar-EG"),
+        ("cs", "cs-CZ"),
+        ("da", "da-DK"),
+        ("de", "de-DE"),
+        ("en", "en-US"),
+        ("es", "es-ES"),
+        ("fr", "fr-FR"),
+        ("it", "it-IT"),
+        ("ja", "ja-JP"),
+        ("ko", "ko-KR"),
+        ("nl", "nl-NL"),
+        ("pl", "pl-PL"),
+        ("pt", "pt-BR"),
+        ("ru", "ru-RU"),
+        ("sv", "sv-SE"),
+        ("zh", "zh-CN"),
+    ];
+
+    /// Check if a given (uppercase) currency code is accepted.
+    pub fn is_supported_currency(&self, currency: &str) -> bool {
+        self.currencies.contains(&currency)
+    }
+
+    /// Pick the best accepted locale for a given Accept-Language header value.
+    /// A tag matching an accepted locale with its region wins over language fallbacks.
+    pub fn locale_from_accept_language(&self, header: &str) -> Option<&'static str> {
+        accept_languages(header).into_iter().find_map(|tag| {
+            // Some clients separate regions with underscores (e.g. zh_TW).
+            let tag = tag.replace('_', "-");
+            let exact = self.locales.iter().find(|l| l.eq_ignore_ascii_case(&tag));
+            exact.copied().or_else(|| {
+                let language = tag.split('-').next()?;
+                let preferred = Self::LANGUAGE_LOCALES
+                    .iter()
+                    .find(|(l, _)| l.eq_ignore_ascii_case(language))
+                    .map(|(_, locale)| *locale)
+                    .filter(|locale| self.locales.contains(locale));
+                // Otherwise any accepted region of the language will do.
+                preferred.or_else(|| {
+                    self.locales.iter().copied().find(|locale| {
+                        locale
+                            .split('-')
+                            .next()
+                            .is_some_and(|l| l.eq_ignore_ascii_case(language))
+                    })
+                })
+            })
+        })
+    }
+
     /// Register a new payment.
     pub async fn create_payment(
         &self,
@@ -167,12 +312,12 @@ impl PaypalProcessor {
         locale: Option<&str>,
     ) -> Result<Payment> {
         use Error::*;
-        if !Self::CURRENCIES.iter().any(|c| *c == currency) {
+        if !self.is_supported_currency(&currency) {
             return Err(UnsupportedCurrency);
         }
 
         if let Some(code) = locale {
-            if !Self::LOCALES.iter().any(|c| *c == code) {
+            if !self.locales.contains(&code) {
                 return Err(UnsupportedLocale);
             }
         }
@@ -202,7 +347,8 @@ impl PaypalProcessor {
         });
 
         let token = self.get_token().await?;
-        let response = Client::default()
+        let response = self
+            .client
             .post(if self.sandbox {
                 "https://api.sandbox.paypal.com/v2/checkout/orders"
             } else {
@@ -230,14 +376,14 @@ impl PaypalProcessor {
     /// Update status for a given payment.
     pub async fn update_payment(&self, payment: &mut Payment) -> Result<()> {
         let token = self.get_token().await?;
-        let response = Client::default()
+        let response = self
+            .client
             .get(self.get_order_link(&payment.reference, false))
             .bearer_auth(token)
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
 
-        let json = response.text().await?;
+        let json = Self::read_order_response(response, payment).await?;
         let payload: OrderResponsePayload = serde_json::from_str(&json)?;
 
         use PaymentStatus::*;
@@ -249,11 +395,34 @@ impl PaypalProcessor {
         };
 
         payment.status = status;
-        payment.net_amount = payload.net_amount();
+        payment.net_amount = payload.net_amount()?;
         payment.details = Some(json);
         Ok(())
     }
 
+    /// Read an order response body. For a failed response the body is stored
+    /// in payment details and known errors are mapped to specific variants.
+    async fn read_order_response(response: Response, payment: &mut Payment) -> Result<String> {
+        let status_error = response.error_for_status_ref().err();
+        let body = response.text().await?;
+        let Some(err) = status_error else {
+            return Ok(body);
+        };
+
+        let payload = serde_json::from_str::<ErrorResponsePayload>(&body).ok();
+        if let Some(payload) = &payload {
+            debug!(
+                "received paypal error {} for payment {}",
+                payload.name, payment.id
+            );
+        }
+        payment.details = Some(body);
+
+        Err(payload
+            .and_then(|p| p.known_error())
+            .unwrap_or(Error::Reqwest(err)))
+    }
+
     fn get_order_link(&self, reference: &str, capture: bool) -> String {
         let mut url = if self.sandbox {
             format!("https://api.sandbox.paypal.com/v2/checkout/orders/{reference}")
@@ -274,19 +443,19 @@ impl PaypalProcessor {
         }
 
         let token = self.get_token().await?;
-        let response = Client::default()
+        let response = self
+            .client
             .post(self.get_order_link(&payment.reference, true))
             .bearer_auth(token)
             .json(&())
             .send()
-            .await?
-            .error_for_status()?;
+            .await?;
 
-        let json = response.text().await?;
+        let json = Self::read_order_response(response, payment).await?;
         let payload: OrderResponsePayload = serde_json::from_str(&json)?;
 
         payment.status = Completed;
-        payment.net_amount = payload.net_amount();
+        payment.net_amount = payload.net_amount()?;
         payment.details = Some(json);
         Ok(())
     }
@@ -310,15 +479,56 @@ impl PaypalProcessor {
         Some(url)
     }
 
+    fn valid_token(&self) -> Option<String> {
+        let state = self.state.read().unwrap();
+        (OffsetDateTime::now_utc() < state.token_expires_at).then(|| state.token.clone())
+    }
+
     async fn get_token(&self) -> Result<String> {
-        {
-            let state = self.state.read().unwrap();
-            if OffsetDateTime::now_utc() < state.token_expires_at {
-                return Ok(state.token.clone());
-            }
+        if let Some(token) = self.valid_token() {
+            return Ok(token);
+        }
+
+        // Only one task refreshes the token, others wait and reuse its result.
+        let _guard = self.token_refresh.lock().await;
+        if let Some(token) = self.valid_token() {
+            return Ok(token);
         }
 
-        let response = Client::default()
+        let mut delay = TOKEN_FETCH_RETRY_DELAY;
+        let mut attempt = 1;
+        let payload = loop {
+            match self.fetch_token().await {
+                Err(Error::Reqwest(err))
+                    if attempt < TOKEN_FETCH_ATTEMPTS && is_transient(&err) =>
+                {
+                    debug!("paypal token fetch attempt {attempt} failed: {err}");
+                    sleep(jittered(delay)).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                result => break result?,
+            }
+        };
+
+        let mut state = self.state.write().unwrap();
+        state.token = payload.access_token;
+        state.token_expires_at = token_expires_at(
+            OffsetDateTime::now_utc(),
+            Duration::from_secs(payload.expires_in),
+            self.token_expiry_skew,
+        );
+
+        debug!(
+            "retrieved paypal token (expires at {})",
+            state.token_expires_at
+        );
+        Ok(state.token.clone())
+    }
+
+    async fn fetch_token(&self) -> Result<TokenResponsePayload> {
+        let response = self
+            .client
             .post(if self.sandbox {
                 "https://api.sandbox.paypal.com/v1/oauth2/token"
             } else {
@@ -330,17 +540,241 @@ impl PaypalProcessor {
             .await?
             .error_for_status()?;
 
-        let payload: TokenResponsePayload = response.json().await?;
+        Ok(response.json().await?)
+    }
+}
 
-        let mut state = self.state.write().unwrap();
-        state.token = payload.access_token;
-        state.token_expires_at =
-            OffsetDateTime::now_utc() + Duration::from_secs(payload.expires_in);
+/// Compute when a token should stop being used, which is a given skew time
+/// before its actual expiration (but no more than half of its lifetime).
+fn token_expires_at(now: OffsetDateTime, expires_in: Duration, skew: Duration) -> OffsetDateTime {
+    now + expires_in - skew.min(expires_in / 2)
+}
 
-        debug!(
-            "retrieved paypal token (expires at {})",
-            state.token_expires_at
+/// Check if a request error is worth retrying.
+fn is_transient(err: &reqwest::Error) -> bool {
+    err.is_timeout()
+        || err.is_connect()
+        || err
+            .status()
+            .is_some_and(|s| s.is_server_error() || s == StatusCode::TOO_MANY_REQUESTS)
+}
+
+/// Randomize a delay within ±50% to spread out retries of concurrent instances.
+fn jittered(delay: Duration) -> Duration {
+    delay.mul_f64(rand::thread_rng().gen_range(0.5..1.5))
+}
+
+/// Map given codes to supported ones (ignoring case) keeping the order.
+/// Fails on an empty list or with the first unsupported code.
+fn restrict(
+    codes: &[String],
+    supported: &[&'static str],
+) -> std::result::Result<Vec<&'static str>, RestrictError> {
+    if codes.is_empty() {
+        return Err(RestrictError::EmptyList);
+    }
+    codes
+        .iter()
+        .map(|code| {
+            let code = code.trim();
+            supported
+                .iter()
+                .find(|s| s.eq_ignore_ascii_case(code))
+                .copied()
+                .ok_or_else(|| RestrictError::Unsupported(code.to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_test_processor() -> PaypalProcessor {
+        PaypalProcessor::new(
+            true,
+            String::new(),
+            String::new(),
+            "https://example.com/return".parse().unwrap(),
+            "https://example.com/cancel".parse().unwrap(),
+            Duration::from_secs(60),
+            Client::new(),
+        )
+    }
+
+    #[test]
+    fn test_locale_from_accept_language() {
+        let processor = new_test_processor();
+        let locale = |header| processor.locale_from_accept_language(header);
+        assert_eq!(locale("de-DE"), Some("de-DE"));
+        assert_eq!(locale("en-gb"), Some("en-GB"));
+        assert_eq!(locale("fr-CA,fr;q=0.9,en;q=0.8"), Some("fr-FR"));
+        assert_eq!(locale("nb-NO,en-GB;q=0.7,ja;q=0.9"), Some("ja-JP"));
+        assert_eq!(locale("zh_TW"), Some("zh-TW"));
+        assert_eq!(locale("zh-tw"), Some("zh-TW"));
+        assert_eq!(locale("zh_HK"), Some("zh-CN"));
+        assert_eq!(locale("en;q=0.5, pt-BR"), Some("pt-BR"));
+        assert_eq!(locale("it;q=0,es"), Some("es-ES"));
+        assert_eq!(locale("nb-NO, *;q=0.5"), None);
+        assert_eq!(locale("en;q=bad"), None);
+        assert_eq!(locale(""), None);
+    }
+
+    #[test]
+    fn test_restrict() {
+        let mut processor = new_test_processor();
+        let codes = |codes: &[&str]| codes.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+
+        processor
+            .restrict_currencies(&codes(&["usd", " EUR"]))
+            .unwrap();
+        assert!(processor.is_supported_currency("USD"));
+        assert!(processor.is_supported_currency("EUR"));
+        assert!(!processor.is_supported_currency("GBP"));
+        assert_eq!(
+            processor.restrict_currencies(&codes(&["USD", "XYZ"])),
+            Err(RestrictError::Unsupported("XYZ".to_owned()))
         );
-        Ok(state.token.clone())
+        assert_eq!(
+            processor.restrict_currencies(&[]),
+            Err(RestrictError::EmptyList)
+        );
+        assert!(!processor.is_supported_currency("GBP"));
+
+        processor
+            .restrict_locales(&codes(&["en-gb", "fr-XC"]))
+            .unwrap();
+        let locale = |header| processor.locale_from_accept_language(header);
+        assert_eq!(locale("en-US"), Some("en-GB"));
+        assert_eq!(locale("fr"), Some("fr-XC"));
+        assert_eq!(locale("de-DE"), None);
+        assert_eq!(
+            processor.restrict_locales(&codes(&["en-XX"])),
+            Err(RestrictError::Unsupported("en-XX".to_owned()))
+        );
+        assert_eq!(
+            processor.restrict_locales(&[]),
+            Err(RestrictError::EmptyList)
+        );
+    }
+
+    #[test]
+    fn test_jittered() {
+        let delay = Duration::from_millis(100);
+        for _ in 0..100 {
+            let jittered = jittered(delay);
+            assert!(jittered >= Duration::from_millis(50));
+            assert!(jittered < Duration::from_millis(150));
+        }
+    }
+
+    #[test]
+    fn test_token_expires_at() {
+        let now = OffsetDateTime::UNIX_EPOCH;
+        let secs = Duration::from_secs;
+        assert_eq!(
+            token_expires_at(now, secs(32400), secs(60)),
+            now + secs(32340)
+        );
+        assert_eq!(
+            token_expires_at(now, secs(32400), secs(0)),
+            now + secs(32400)
+        );
+        assert_eq!(token_expires_at(now, secs(100), secs(60)), now + secs(50));
+        assert_eq!(token_expires_at(now, secs(0), secs(60)), now);
+    }
+
+    #[tokio::test]
+    async fn test_get_token_valid() {
+        let processor = new_test_processor();
+        {
+            let mut state = processor.state.write().unwrap();
+            state.token = "token".to_owned();
+            state.token_expires_at = OffsetDateTime::now_utc() + Duration::from_secs(60);
+        }
+
+        // Concurrent requests reuse the valid token without fetching it.
+        let tokens = futures::future::join_all((0..10).map(|_| processor.get_token())).await;
+        assert!(tokens.into_iter().all(|t| t.unwrap() == "token"));
+    }
+
+    #[test]
+    fn test_error_response_payload_known_error() {
+        let payload: ErrorResponsePayload = serde_json::from_str(
+            r#"{
+                "name": "UNPROCESSABLE_ENTITY",
+                "details": [{
+                    "issue": "INSTRUMENT_DECLINED",
+                    "description": "The instrument presented was either declined by the processor or bank."
+                }],
+                "message": "The requested action could not be performed.",
+                "debug_id": "90957fca61718"
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            payload.known_error(),
+            Some(Error::InstrumentDeclined)
+        ));
+
+        let payload: ErrorResponsePayload = serde_json::from_str(
+            r#"{"name": "UNPROCESSABLE_ENTITY", "details": [{"issue": "ORDER_ALREADY_CAPTURED"}]}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            payload.known_error(),
+            Some(Error::OrderAlreadyCaptured)
+        ));
+
+        let payload: ErrorResponsePayload =
+            serde_json::from_str(r#"{"name": "INTERNAL_SERVER_ERROR"}"#).unwrap();
+        assert!(payload.known_error().is_none());
+    }
+
+    fn capture(currency_code: &str, value: &str) -> serde_json::Value {
+        json!({
+            "id": "3C679366HH908993F",
+            "status": "COMPLETED",
+            "seller_receivable_breakdown": {
+                "gross_amount": {"currency_code": currency_code, "value": "100.00"},
+                "paypal_fee": {"currency_code": currency_code, "value": "3.00"},
+                "net_amount": {"currency_code": currency_code, "value": value}
+            }
+        })
+    }
+
+    #[test]
+    fn test_order_response_payload_net_amount() {
+        let order = json!({
+            "id": "5O190127TN364715T",
+            "status": "COMPLETED",
+            "purchase_units": [
+                {"payments": {"captures": [capture("USD", "97.00"), capture("USD", "48.25")]}},
+                {"payments": {"captures": [
+                    capture("USD", "10.10"),
+                    {"id": "2GG279541U471931P", "status": "DECLINED"}
+                ]}},
+                {}
+            ]
+        });
+        let payload: OrderResponsePayload = serde_json::from_value(order).unwrap();
+        assert_eq!(payload.net_amount().unwrap(), Some(Decimal::new(15535, 2)));
+
+        let payload: OrderResponsePayload =
+            serde_json::from_value(json!({"id": "1", "status": "APPROVED"})).unwrap();
+        assert_eq!(payload.net_amount().unwrap(), None);
+
+        let order = json!({
+            "id": "1",
+            "status": "COMPLETED",
+            "purchase_units": [
+                {"payments": {"captures": [capture("USD", "1.00"), capture("EUR", "1.00")]}}
+            ]
+        });
+        let payload: OrderResponsePayload = serde_json::from_value(order).unwrap();
+        assert!(matches!(
+            payload.net_amount(),
+            Err(Error::MixedCaptureCurrencies)
+        ));
     }
 }
@@ -0,0 +1,257 @@
+use crate::{
+    data::{email_suppression::EmailSuppression, node::Node, user::User},
+    server::{middleware::Auth, Error, Result, Server},
+};
+use axum::{
+    extract::{Path, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::WithRejection;
+use lettre::Address as EmailAddress;
+use log::info;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use uuid::Uuid;
+
+/// Handle admin email suppressions GET requests.
+pub async fn handle_admin_email_suppressions_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    let suppressions: Vec<_> = EmailSuppression::list(&client)
+        .await?
+        .iter()
+        .map(get_email_suppression_item)
+        .collect();
+
+    Ok(Json(json!({ "suppressions": suppressions })).into_response())
+}
+
+fn get_email_suppression_item(suppression: &EmailSuppression) -> serde_json::Value {
+    json!({
+        "email": suppression.email,
+        "reason": suppression.reason,
+        "createdAt": suppression.created_at.format(&Rfc3339).unwrap(),
+    })
+}
+
+/// Body payload for email suppressions POST-request.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailSuppressionsPostRequestPayload {
+    email: EmailAddress,
+    reason: String,
+}
+
+/// Handle admin email suppressions POST requests (adding or updating a suppression).
+pub async fn handle_admin_email_suppressions_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Json(payload), _): WithRejection<
+        Json<EmailSuppressionsPostRequestPayload>,
+        Error,
+    >,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    if payload.reason.is_empty() {
+        return Err(Error::BadRequest("empty suppression reason".to_owned()));
+    }
+
+    let mut suppression = EmailSuppression::new(payload.email, payload.reason);
+    suppression.upsert(&client).await?;
+
+    info!("suppressed emails to {}", suppression.email);
+    Ok(Json(get_email_suppression_item(&suppression)).into_response())
+}
+
+/// Handle admin email suppressions DELETE requests.
+pub async fn handle_admin_email_suppressions_delete(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Path(email), _): WithRejection<Path<EmailAddress>, Error>,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    if !EmailSuppression::delete(&client, &email).await? {
+        return Err(Error::EmailSuppressionNotFound);
+    }
+
+    info!("unsuppressed emails to {email}");
+    Ok(Json(json!({})).into_response())
+}
+
+/// Body payload for user credit limit POST-request.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserCreditLimitPostRequestPayload {
+    credit_limit: Decimal,
+}
+
+/// Handle admin user credit limit POST requests.
+pub async fn handle_admin_user_credit_limit_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Path(user_id), _): WithRejection<Path<Uuid>, Error>,
+    WithRejection(Json(payload), _): WithRejection<Json<UserCreditLimitPostRequestPayload>, Error>,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    if payload.credit_limit.is_sign_negative() {
+        return Err(Error::BadRequest("negative credit limit".to_owned()));
+    }
+
+    if !User::set_credit_limit(&client, user_id, payload.credit_limit).await? {
+        return Err(Error::UserNotFound);
+    }
+
+    info!(
+        "set credit limit of user {user_id} to {}",
+        payload.credit_limit
+    );
+    Ok(Json(json!({})).into_response())
+}
+
+/// Handle admin nodes GET requests.
+pub async fn handle_admin_nodes_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    let nodes = Node::list(&client).await?;
+    let items: Vec<_> = nodes.iter().map(get_node_item).collect();
+
+    Ok(Json(json!({
+        "nodes": items,
+        "totals": get_node_totals(&nodes),
+        "server": get_server_usage(&server),
+    }))
+    .into_response())
+}
+
+fn get_server_usage(server: &Server) -> serde_json::Value {
+    let config = &server.config;
+    json!({
+        "activeRequests": config.max_concurrent_requests - server.request_semaphore.available_permits(),
+        "maxConcurrentRequests": config.max_concurrent_requests,
+        "activeTranscribeSessions": config.max_transcribe_sessions - server.transcribe_semaphore.available_permits(),
+        "maxTranscribeSessions": config.max_transcribe_sessions,
+    })
+}
+
+fn get_node_item(node: &Node) -> serde_json::Value {
+    json!({
+        "id": node.id,
+        "label": node.label,
+        "ipAddress": node.ip_address,
+        "computeCapacity": node.compute_capacity,
+        "memoryCapacity": node.memory_capacity,
+        "computeLoad": node.compute_load,
+        "memoryLoad": node.memory_load,
+        "computeFree": node.compute_capacity.saturating_sub(node.compute_load),
+        "memoryFree": node.memory_capacity.saturating_sub(node.memory_load),
+    })
+}
+
+fn get_node_totals(nodes: &[Node]) -> serde_json::Value {
+    let sum = |f: fn(&Node) -> u32| nodes.iter().map(|n| f(n) as u64).sum::<u64>();
+    let compute_capacity = sum(|n| n.compute_capacity);
+    let memory_capacity = sum(|n| n.memory_capacity);
+    let compute_load = sum(|n| n.compute_load);
+    let memory_load = sum(|n| n.memory_load);
+
+    json!({
+        "nodes": nodes.len(),
+        "computeCapacity": compute_capacity,
+        "memoryCapacity": memory_capacity,
+        "computeLoad": compute_load,
+        "memoryLoad": memory_load,
+        "computeFree": compute_capacity.saturating_sub(compute_load),
+        "memoryFree": memory_capacity.saturating_sub(memory_load),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::{new_test_server, send_request};
+    use axum::{body::Body, http::Request, http::StatusCode};
+
+    fn node(label: &str, capacity: u32, load: u32) -> Node {
+        Node {
+            id: Uuid::nil(),
+            label: label.to_owned(),
+            ip_address: [127, 0, 0, 1].into(),
+            compute_capacity: capacity,
+            memory_capacity: capacity * 2,
+            compute_load: load,
+            memory_load: load * 2,
+        }
+    }
+
+    #[test]
+    fn test_get_node_totals() {
+        let nodes = [node("a", 10, 4), node("b", 20, 25)];
+        let totals = get_node_totals(&nodes);
+        assert_eq!(totals["nodes"], 2);
+        assert_eq!(totals["computeCapacity"], 30);
+        assert_eq!(totals["computeLoad"], 29);
+        assert_eq!(totals["computeFree"], 1);
+        assert_eq!(totals["memoryCapacity"], 60);
+        assert_eq!(totals["memoryFree"], 2);
+
+        let item = get_node_item(&nodes[1]);
+        assert_eq!(item["computeFree"], 0);
+    }
+
+    #[test]
+    fn test_get_email_suppression_item() {
+        let suppression =
+            EmailSuppression::new("john@example.com".parse().unwrap(), "bounce".to_owned());
+        let item = get_email_suppression_item(&suppression);
+        assert_eq!(item["email"], "john@example.com");
+        assert_eq!(item["reason"], "bounce");
+        assert_eq!(item["createdAt"], "1970-01-01T00:00:00Z");
+    }
+
+    #[tokio::test]
+    async fn test_handle_admin_email_suppressions_unauthorized() {
+        let request = Request::delete("/admin/email-suppressions/john@example.com")
+            .body(Body::empty())
+            .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn test_handle_admin_user_credit_limit_post_unauthorized() {
+        let request = Request::post(format!("/admin/users/{}/credit-limit", Uuid::nil()))
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"creditLimit":"100"}"#))
+            .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn test_handle_admin_nodes_get_unauthorized() {
+        let request = Request::get("/admin/nodes").body(Body::empty()).unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+}
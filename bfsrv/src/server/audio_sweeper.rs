@@ -0,0 +1,42 @@
+use crate::{server::Server, util::periodic::PeriodicTask};
+use log::info;
+use std::{
+    io,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{task::spawn_blocking, time::Instant};
+
+/// Background sweep which deletes captured session audio once it expires.
+pub struct AudioSweeper {
+    _task: PeriodicTask,
+}
+
+impl AudioSweeper {
+    /// Create a new AudioSweeper instance (which sweeps right away).
+    pub fn new(server: Arc<Server>) -> Self {
+        let period = Duration::from_secs(server.config.audio_capture_sweep_interval_secs);
+        let action = "delete expired audio captures";
+        let task = PeriodicTask::spawn(action, Instant::now(), period, move || {
+            let server = server.clone();
+            async move { sweep_audio(&server).await }
+        });
+        Self { _task: task }
+    }
+}
+
+async fn sweep_audio(server: &Server) -> io::Result<()> {
+    let Some(store) = server.audio_store.clone() else {
+        return Ok(());
+    };
+    let ttl = Duration::from_secs(server.config.audio_capture_ttl_secs);
+    let expired = SystemTime::now() - ttl;
+
+    let count = spawn_blocking(move || store.delete_older_than(expired))
+        .await
+        .map_err(io::Error::other)??;
+    if count > 0 {
+        info!("deleted {count} expired audio captures");
+    }
+    Ok(())
+}
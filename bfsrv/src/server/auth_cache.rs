@@ -0,0 +1,169 @@
+use crate::data::token::Token;
+use log::debug;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+/// Maximum number of cached tokens (stale entries are evicted when reached).
+const MAX_ENTRIES: usize = 10000;
+
+/// Hash of an access token (the token itself is never kept in memory).
+type Key = [u8; 32];
+
+struct Entry {
+    token: Token,
+    cached_until: Instant,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<Key, Entry>,
+    hits: u64,
+    misses: u64,
+    miss_duration: Duration,
+}
+
+impl State {
+    /// Estimated authentication time saved by cache hits so far.
+    fn saved(&self) -> Duration {
+        self.miss_duration
+            .checked_div(self.misses as u32)
+            .unwrap_or_default()
+            * self.hits as u32
+    }
+}
+
+/// Short-lived cache of authenticated tokens which saves
+/// database and password hash lookups for request bursts.
+pub struct AuthCache {
+    ttl: Duration,
+    state: Mutex<State>,
+}
+
+impl AuthCache {
+    /// Create a new AuthCache instance, a zero TTL disables caching.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Get a cached token authenticated by a given access token.
+    pub fn get(&self, access_token: &str) -> Option<Token> {
+        if self.ttl.is_zero() {
+            return None;
+        }
+
+        let key = Self::key(access_token);
+        let mut state = self.state.lock().unwrap();
+
+        let entry = state.entries.get(&key)?;
+        if entry.cached_until <= Instant::now() {
+            state.entries.remove(&key);
+            return None;
+        }
+        let token = entry.token.clone();
+
+        state.hits += 1;
+        debug!(
+            "authenticated token {} from cache (saved {:?} so far)",
+            token.id,
+            state.saved()
+        );
+        Some(token)
+    }
+
+    /// Cache a token authenticated by a given access token, it took
+    /// a given time to authenticate it without the cache.
+    pub fn insert(&self, access_token: &str, token: &Token, duration: Duration) {
+        if self.ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.misses += 1;
+        state.miss_duration += duration;
+
+        if state.entries.len() >= MAX_ENTRIES {
+            state.entries.retain(|_, e| e.cached_until > now);
+            if state.entries.len() >= MAX_ENTRIES {
+                return;
+            }
+        }
+
+        state.entries.insert(
+            Self::key(access_token),
+            Entry {
+                token: token.clone(),
+                cached_until: now + self.ttl,
+            },
+        );
+    }
+
+    /// Drop cached entries for a given token (e.g. once it is revoked).
+    pub fn invalidate(&self, token: Uuid) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.retain(|_, e| e.token.id != token);
+    }
+
+    fn key(access_token: &str) -> Key {
+        Sha256::digest(access_token.as_bytes()).into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::OffsetDateTime;
+
+    fn new_token(id: u128) -> Token {
+        let mut token = Token::new(
+            OffsetDateTime::now_utc(),
+            None,
+            None,
+            false,
+            [127, 0, 0, 1].into(),
+            None,
+        );
+        token.id = Uuid::from_u128(id);
+        token
+    }
+
+    #[test]
+    fn test_auth_cache() {
+        let cache = AuthCache::new(Duration::from_secs(60));
+        assert!(cache.get("a").is_none());
+
+        cache.insert("a", &new_token(1), Duration::from_millis(10));
+        cache.insert("b", &new_token(2), Duration::from_millis(30));
+        assert_eq!(cache.get("a").unwrap().id, Uuid::from_u128(1));
+        assert_eq!(cache.get("b").unwrap().id, Uuid::from_u128(2));
+        assert!(cache.get("c").is_none());
+        assert_eq!(
+            cache.state.lock().unwrap().saved(),
+            Duration::from_millis(40)
+        );
+
+        cache.invalidate(Uuid::from_u128(1));
+        assert!(cache.get("a").is_none());
+        assert!(cache.get("b").is_some());
+    }
+
+    #[test]
+    fn test_auth_cache_expiry() {
+        let cache = AuthCache::new(Duration::from_millis(1));
+        cache.insert("a", &new_token(1), Duration::ZERO);
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(cache.get("a").is_none());
+
+        let cache = AuthCache::new(Duration::ZERO);
+        cache.insert("a", &new_token(1), Duration::ZERO);
+        assert!(cache.get("a").is_none());
+    }
+}
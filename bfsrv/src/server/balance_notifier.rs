@@ -0,0 +1,97 @@
+use crate::{
+    currency_converter::round_to_minor_units,
+    data::user::User,
+    mailer,
+    server::{Result, Server},
+    util::{fmt::ErrorChainDisplay, periodic::PeriodicTask},
+};
+use log::{debug, error, info};
+use std::{sync::Arc, time::Duration};
+use tokio::time::Instant;
+
+/// Background sweep which emails users once their balance drops below
+/// a low balance threshold (notifying again only after a top-up).
+pub struct BalanceNotifier {
+    _task: PeriodicTask,
+}
+
+impl BalanceNotifier {
+    /// Create a new BalanceNotifier instance.
+    pub fn new(server: Arc<Server>) -> Self {
+        let period = Duration::from_secs(server.config.low_balance_poll_interval_secs);
+        let start = Instant::now() + period;
+        let task = PeriodicTask::spawn("notify low balances", start, period, move || {
+            let server = server.clone();
+            async move { notify_low_balances(&server).await }
+        });
+        Self { _task: task }
+    }
+}
+
+async fn notify_low_balances(server: &Server) -> Result<()> {
+    let mut client = server.pg_pool.get().await?;
+    let threshold = server.config.low_balance_threshold;
+
+    let reset = User::reset_low_balance_notified(&client, threshold).await?;
+    if reset > 0 {
+        debug!("reset low balance notifications of {reset} users");
+    }
+
+    let currency = &server.config.currency;
+    for user in User::find_newly_low_balance(&client, threshold).await? {
+        // The mark locks the user row until the email is sent,
+        // which prevents duplicates from concurrently running servers.
+        let tx = client.transaction().await?;
+        if !User::mark_low_balance_notified(&tx, user.id).await? {
+            continue;
+        }
+
+        let balance = round_to_minor_units(currency, user.balance);
+        let result = server
+            .mailer
+            .send_low_balance(&tx, user.email, balance, currency)
+            .await;
+        match &result {
+            Ok(()) => info!("notified user {} of low balance", user.id),
+            Err(mailer::Error::Suppressed(_)) => {
+                debug!("skipped low balance notification of user {}", user.id);
+            }
+            Err(err) => error!(
+                "failed to notify user {} of low balance: {}",
+                user.id,
+                ErrorChainDisplay(err)
+            ),
+        }
+
+        // A failed notification is rolled back to be retried on the next run.
+        if is_notification_settled(&result) {
+            tx.commit().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check if a user doesn't need to be notified again after a given send result.
+fn is_notification_settled(result: &mailer::Result<()>) -> bool {
+    matches!(result, Ok(()) | Err(mailer::Error::Suppressed(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lettre::Address as EmailAddress;
+
+    #[test]
+    fn test_is_notification_settled() {
+        assert!(is_notification_settled(&Ok(())));
+
+        let email: EmailAddress = "user@example.com".parse().unwrap();
+        let suppressed = mailer::Error::Suppressed(email);
+        assert!(is_notification_settled(&Err(suppressed)));
+
+        let err = "invalid".parse::<EmailAddress>().unwrap_err();
+        let failed = mailer::Error::Data(err.into());
+        assert!(!is_notification_settled(&Err(failed)));
+    }
+}
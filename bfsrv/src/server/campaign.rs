@@ -0,0 +1,124 @@
+use crate::{
+    data::campaign::Campaign,
+    server::{middleware::Auth, Error, Result, Server, TX_RETRY_POLICY},
+    util::retry::retry_on_serialization_failure,
+};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::WithRejection;
+use deadpool_postgres::Client;
+use log::info;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde_json::json;
+use std::sync::Arc;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio_postgres::IsolationLevel;
+
+/// Handle campaign GET requests.
+pub async fn handle_campaign_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    let campaigns: Vec<_> = Campaign::find_all(&client)
+        .await?
+        .iter()
+        .map(get_campaign_item)
+        .collect();
+
+    Ok(Json(json!({ "campaigns": campaigns })).into_response())
+}
+
+fn get_campaign_item(campaign: &Campaign) -> serde_json::Value {
+    json!({
+        "id": campaign.id,
+        "initialBalance": campaign.initial_balance,
+        "referralBonusAmount": campaign.referral_bonus_amount,
+        "referralBonusPercent": campaign.referral_bonus_percent,
+        "expiresAt": campaign.expires_at.map(|e| e.format(&Rfc3339).unwrap()),
+        "maxRedemptions": campaign.max_redemptions,
+        "redemptions": campaign.redemptions,
+        "lowBalanceThreshold": campaign.low_balance_threshold,
+    })
+}
+
+/// Body payload for POST-request.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostRequestPayload {
+    promo_code: String,
+    initial_balance: Decimal,
+    referral_bonus_amount: Option<Decimal>,
+    referral_bonus_percent: Option<Decimal>,
+    #[serde(default, with = "time::serde::rfc3339::option")]
+    expires_at: Option<OffsetDateTime>,
+    max_redemptions: Option<u32>,
+    low_balance_threshold: Option<Decimal>,
+}
+
+/// Handle campaign POST requests.
+pub async fn handle_campaign_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Json(payload), _): WithRejection<Json<PostRequestPayload>, Error>,
+) -> Result<Response> {
+    let mut client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    if payload.promo_code.is_empty() {
+        return Err(Error::BadRequest("empty promo code".to_owned()));
+    }
+
+    let (work_factor, payload) = (server.config.bcrypt_work_factor, &payload);
+    let campaign =
+        retry_on_serialization_failure(TX_RETRY_POLICY, &mut client, |client| async move {
+            let result = try_create_campaign_atomically(client, payload, work_factor).await;
+            (client, result)
+        })
+        .await?;
+
+    info!("created campaign {}", campaign.id);
+    Ok(Json(json!({ "id": campaign.id })).into_response())
+}
+
+async fn try_create_campaign_atomically(
+    client: &mut Client,
+    payload: &PostRequestPayload,
+    bcrypt_work_factor: u32,
+) -> Result<Campaign> {
+    // Promo codes are stored as salted hashes, so uniqueness can only be
+    // checked by lookup, which is made race-free by serializable isolation.
+    let tx = client
+        .build_transaction()
+        .isolation_level(IsolationLevel::Serializable)
+        .start()
+        .await?;
+
+    if Campaign::find_by_promo_code(&tx, &payload.promo_code)
+        .await?
+        .is_some()
+    {
+        return Err(Error::PromoCodeAlreadyExists);
+    }
+
+    let mut campaign = Campaign::new(
+        payload.initial_balance,
+        payload.referral_bonus_amount.unwrap_or_default(),
+        payload.referral_bonus_percent.unwrap_or_default(),
+        payload.expires_at,
+        payload.max_redemptions,
+    );
+    campaign.low_balance_threshold = payload.low_balance_threshold;
+    campaign
+        .insert(&tx, &payload.promo_code, bcrypt_work_factor)
+        .await?;
+
+    tx.commit().await?;
+    Ok(campaign)
+}
@@ -0,0 +1,63 @@
+use crate::{
+    mailer::CapturedEmail,
+    server::{middleware::Auth, Error, Result, Server},
+};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Handle dev emails GET requests (emails captured by the stub mailer).
+pub async fn handle_dev_emails_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+) -> Result<Response> {
+    let client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    let Some(captured) = server.mailer.captured() else {
+        return Err(Error::HandlerNotFound);
+    };
+    let emails: Vec<_> = captured.iter().map(get_email_item).collect();
+
+    Ok(Json(json!({ "emails": emails })).into_response())
+}
+
+fn get_email_item(email: &CapturedEmail) -> serde_json::Value {
+    json!({
+        "to": email.to,
+        "subject": email.subject,
+        "body": email.body,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::tests::{new_test_server, new_test_server_with_args, send_request};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    fn get_dev_emails() -> Request<Body> {
+        Request::get("/dev/emails").body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_handle_dev_emails_get_not_captured() {
+        let (status, json) = send_request(new_test_server(), get_dev_emails()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json["error"]["code"], "handler_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_handle_dev_emails_get_unauthorized() {
+        let server = new_test_server_with_args(&["--smtp-relay=stub"]);
+        let (status, json) = send_request(server, get_dev_emails()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+}
@@ -0,0 +1,45 @@
+use crate::error_kind::ERROR_KINDS;
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+/// Handle errors GET requests (a list of error kinds clients can receive).
+pub async fn handle_errors_get() -> Response {
+    let errors: Vec<_> = ERROR_KINDS
+        .iter()
+        .map(|kind| {
+            json!({
+                "code": kind.code,
+                "status": kind.status.as_u16(),
+                "description": kind.description,
+            })
+        })
+        .collect();
+    Json(json!({ "errors": errors })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::tests::{new_test_server, send_request};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    #[tokio::test]
+    async fn test_handle_errors_get() {
+        let request = Request::get("/errors").body(Body::empty()).unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let errors = json["errors"].as_array().unwrap();
+        let error = errors
+            .iter()
+            .find(|e| e["code"] == "not_enough_balance")
+            .unwrap();
+        assert_eq!(error["status"], 402);
+        assert!(error["description"].as_str().is_some_and(|d| !d.is_empty()));
+    }
+}
@@ -0,0 +1,17 @@
+use crate::server::{Error, Result, Server};
+use axum::{
+    extract::State,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::sync::{atomic::Ordering, Arc};
+
+/// Handle readiness GET requests.
+/// The server stops being ready once it starts draining before shutdown.
+pub async fn handle_readyz_get(State(server): State<Arc<Server>>) -> Result<Response> {
+    if !server.ready.load(Ordering::SeqCst) {
+        return Err(Error::ServerShuttingDown);
+    }
+    Ok(Json(json!({ "ready": true })).into_response())
+}
@@ -0,0 +1,150 @@
+use crate::{
+    data::job::Job,
+    server::{
+        middleware::Auth,
+        transcribe::{read_audio_file, Tariff, TranscribeQuery},
+        Error, Result, Server,
+    },
+};
+use axum::{
+    extract::{Multipart, Path, Query, State},
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::WithRejection;
+use log::info;
+use serde_json::json;
+use std::sync::Arc;
+use time::format_description::well_known::Rfc3339;
+use uuid::Uuid;
+
+/// Handle jobs POST requests (queueing an audio file for batch transcription).
+pub async fn handle_jobs_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Query(query), _): WithRejection<Query<TranscribeQuery>, Error>,
+    WithRejection(mut multipart, _): WithRejection<Multipart, Error>,
+) -> Result<Response> {
+    let user = auth.user()?;
+
+    if query.callback_url.is_some() {
+        return Err(Error::BadRequest(
+            "callback is not supported for jobs".to_owned(),
+        ));
+    }
+    if query.vad.is_some() {
+        return Err(Error::BadRequest(
+            "vad sensitivity is not supported for jobs".to_owned(),
+        ));
+    }
+
+    // The tariff is resolved early to reject unknown tariffs and languages.
+    let tariff = Tariff::resolve(&server, &query).await?;
+    let file = read_audio_file(&mut multipart).await?;
+
+    let normalization = query.normalization();
+    let mut job = Job::new(user, tariff.name, query.lang, query.diarize, normalization);
+    let client = server.pg_pool.get().await?;
+    job.insert(&client, &file).await?;
+
+    info!("queued job {} ({} bytes)", job.id, file.len());
+    Ok(Json(get_job_item(&job)).into_response())
+}
+
+/// Handle job GET requests.
+pub async fn handle_job_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Path(id), _): WithRejection<Path<Uuid>, Error>,
+) -> Result<Response> {
+    let user = auth.user()?;
+
+    let client = server.pg_pool.get().await?;
+    let Some(job) = Job::get(&client, id).await?.filter(|j| j.user == user) else {
+        return Err(Error::JobNotFound);
+    };
+
+    Ok(Json(json!({ "job": get_job_item(&job) })).into_response())
+}
+
+fn get_job_item(job: &Job) -> serde_json::Value {
+    let mut json = json!({
+        "id": job.id,
+        "createdAt": job.created_at.format(&Rfc3339).unwrap(),
+        "updatedAt": job.updated_at.format(&Rfc3339).unwrap(),
+        "tariff": job.tariff,
+        "status": job.status,
+        "diarize": job.diarize,
+        "punctuation": job.normalization.punctuation,
+        "casing": job.normalization.casing,
+        "items": job.items,
+    });
+    if let Some(lang) = &job.lang {
+        json["lang"] = json!(lang);
+    }
+    if let Some(total_seconds) = job.total_seconds {
+        json["totalSeconds"] = json!(total_seconds);
+    }
+    if let Some(total_cost) = job.total_cost {
+        json["totalCost"] = json!(total_cost);
+    }
+    if let Some(transcript) = job.transcript {
+        json["transcriptId"] = json!(transcript);
+    }
+    if let Some(error) = &job.error {
+        json["error"] = error.clone();
+    }
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        server::tests::{new_test_server, send_request},
+        util::text::TextNormalization,
+    };
+    use axum::{body::Body, http::Request, http::StatusCode};
+
+    #[test]
+    fn test_get_job_item() {
+        let mut job = Job::new(
+            Uuid::nil(),
+            "basic".to_owned(),
+            None,
+            false,
+            TextNormalization::default(),
+        );
+        assert_eq!(
+            get_job_item(&job),
+            json!({
+                "id": Uuid::nil(),
+                "createdAt": "1970-01-01T00:00:00Z",
+                "updatedAt": "1970-01-01T00:00:00Z",
+                "tariff": "basic",
+                "status": "queued",
+                "diarize": false,
+                "punctuation": true,
+                "casing": "original",
+                "items": [],
+            })
+        );
+
+        job.lang = Some("en".to_owned());
+        job.error =
+            Some(json!({"code": "bad_request", "message": "bad request (no audio in file)"}));
+        let item = get_job_item(&job);
+        assert_eq!(item["lang"], "en");
+        assert_eq!(item["error"]["code"], "bad_request");
+    }
+
+    #[tokio::test]
+    async fn test_handle_job_get_unauthorized() {
+        let request = Request::get(format!("/jobs/{}", Uuid::nil()))
+            .body(Body::empty())
+            .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+}
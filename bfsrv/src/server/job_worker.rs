@@ -0,0 +1,267 @@
+use crate::{
+    data::{job::Job, transcript::Transcript},
+    infsrv_pool::SAMPLE_RATE,
+    server::{
+        transcribe::{
+            decode_ogg_vorbis, segment_samples, store_file_session, transcribe_interval, Tariff,
+            TranscribeItem, TranscribeQuery,
+        },
+        Error, Result, Server,
+    },
+    util::{fmt::ErrorChainDisplay, periodic::PeriodicTask},
+};
+use axum::http::StatusCode;
+use log::{debug, error, info, warn};
+use rust_decimal::Decimal;
+use serde_json::json;
+use std::{future::Future, pin::pin, sync::Arc, time::Duration};
+use time::OffsetDateTime;
+use tokio::{
+    task::spawn_blocking,
+    time::{interval_at, Instant},
+};
+use uuid::Uuid;
+
+/// Background worker which transcribes queued jobs one by one,
+/// storing transcribed items as soon as they are ready.
+pub struct JobWorker {
+    _task: PeriodicTask,
+}
+
+impl JobWorker {
+    /// Create a new JobWorker instance.
+    pub fn new(server: Arc<Server>) -> Self {
+        let period = Duration::from_secs(server.config.job_poll_interval_secs);
+        let task = PeriodicTask::spawn("process jobs", Instant::now(), period, move || {
+            let server = server.clone();
+            async move { process_jobs(&server).await }
+        });
+        Self { _task: task }
+    }
+}
+
+async fn process_jobs(server: &Server) -> Result<()> {
+    {
+        let client = server.pg_pool.get().await?;
+        let stale_before =
+            OffsetDateTime::now_utc() - Duration::from_secs(server.config.job_stale_secs);
+        let interrupted = Error::Internal("job interrupted".to_owned());
+        let failed = Job::fail_stale(&client, stale_before, &job_error(&interrupted)).await?;
+        if failed > 0 {
+            info!("failed {failed} stale jobs");
+        }
+    }
+
+    loop {
+        // Connections are not held while processing potentially long jobs.
+        let claimed = Job::claim_next(&server.pg_pool.get().await?).await?;
+        let Some((job, audio)) = claimed else {
+            return Ok(());
+        };
+
+        info!("processing job {}", job.id);
+        let processing = process_job(server, &job, audio);
+        match with_heartbeat(server, job.id, processing).await {
+            Ok(()) => info!("completed job {}", job.id),
+            Err(err) => {
+                if err.status() == StatusCode::INTERNAL_SERVER_ERROR {
+                    error!(
+                        "failed to process job {}: {}",
+                        job.id,
+                        ErrorChainDisplay(&err)
+                    );
+                } else {
+                    debug!(
+                        "failed to process job {}: {}",
+                        job.id,
+                        ErrorChainDisplay(&err)
+                    );
+                }
+                let client = server.pg_pool.get().await?;
+                Job::fail(&client, job.id, &job_error(&err)).await?;
+            }
+        }
+    }
+}
+
+async fn process_job(server: &Server, job: &Job, audio: Vec<u8>) -> Result<()> {
+    let query = TranscribeQuery {
+        tariff: Some(job.tariff.clone()),
+        lang: job.lang.clone(),
+        callback_url: None,
+        codec: None,
+        sample_rate: None,
+        channels: None,
+        sample_format: None,
+        diarize: job.diarize,
+        punctuation: Some(job.normalization.punctuation),
+        casing: Some(job.normalization.casing),
+        vad: None,
+    };
+    let tariff = Tariff::resolve(server, &query).await?;
+
+    // Jobs are transcribed as long as streams, so they share the limit
+    // (but wait for a free slot rather than get rejected).
+    let _permit = server
+        .transcribe_semaphore
+        .clone()
+        .acquire_owned()
+        .await
+        .map_err(|_| Error::Internal("transcribe semaphore closed".to_owned()))?;
+
+    let max_packet_frames = server.config.max_packet_frames;
+    let max_duration = server.config.max_job_file_duration_secs;
+    let samples =
+        spawn_blocking(move || decode_ogg_vorbis(&audio, max_packet_frames, max_duration))
+            .await
+            .map_err(|_| Error::Internal("failed to join decoding task".to_owned()))??;
+
+    let started_at = OffsetDateTime::now_utc();
+    let total_seconds = samples.len() as f32 / SAMPLE_RATE;
+    let mut total_cost = Decimal::ZERO;
+    let result = transcribe_job(server, job, &tariff, &query, &samples, &mut total_cost).await;
+    store_file_session(
+        server,
+        job.user,
+        &tariff,
+        started_at,
+        total_seconds,
+        total_cost,
+        result.as_ref().ok().copied(),
+    )
+    .await;
+    result.map(|_| ())
+}
+
+/// Transcribe decoded samples of a job completing it, returns its transcript ID.
+/// Amounts charged for the job are added to a given total cost.
+async fn transcribe_job(
+    server: &Server,
+    job: &Job,
+    tariff: &Tariff,
+    query: &TranscribeQuery,
+    samples: &[i16],
+    total_cost: &mut Decimal,
+) -> Result<Uuid> {
+    let speech = segment_samples(server, job.user, tariff, samples, total_cost, |_| ()).await?;
+
+    // Every transcribed segment is charged on its own, so billing
+    // keeps up with the progress even if the job fails later.
+    let mut items: Vec<TranscribeItem> = Vec::with_capacity(speech.len());
+    let mut prompt = None;
+    for (begin, end) in speech {
+        let (item, charged) = transcribe_interval(
+            server,
+            job.user,
+            tariff,
+            query,
+            samples,
+            (begin, end),
+            &mut prompt,
+        )
+        .await?;
+        *total_cost += charged;
+        let client = server.pg_pool.get().await?;
+        if !Job::append_item(&client, job.id, &json!(item)).await? {
+            return Err(job_not_running());
+        }
+        items.push(item);
+    }
+
+    let total_seconds = samples.len() as f32 / SAMPLE_RATE;
+    let mut transcript = Transcript::new(
+        job.user,
+        tariff.name.clone(),
+        serde_json::to_value(&items).unwrap(),
+        total_seconds,
+        *total_cost,
+    );
+    let mut client = server.pg_pool.get().await?;
+    let tx = client.transaction().await?;
+    transcript.insert(&tx).await?;
+    // The transcript is rolled back if the job was failed meanwhile.
+    if !Job::complete(&tx, job.id, total_seconds, *total_cost, transcript.id).await? {
+        return Err(job_not_running());
+    }
+    tx.commit().await?;
+    Ok(transcript.id)
+}
+
+/// Run processing of a job touching it periodically, so other instances
+/// don't fail it as stale. Processing is aborted once the job isn't running.
+async fn with_heartbeat(
+    server: &Server,
+    id: Uuid,
+    processing: impl Future<Output = Result<()>>,
+) -> Result<()> {
+    let period = heartbeat_period(server.config.job_stale_secs);
+    let mut heartbeat = interval_at(Instant::now() + period, period);
+    let mut processing = pin!(processing);
+    loop {
+        tokio::select! {
+            result = &mut processing => return result,
+            _ = heartbeat.tick() => {
+                let result = match server.pg_pool.get().await {
+                    Ok(client) => Job::heartbeat(&client, id).await.map_err(Error::from),
+                    Err(err) => Err(err.into()),
+                };
+                match result {
+                    Ok(true) => {}
+                    Ok(false) => return Err(job_not_running()),
+                    Err(err) => warn!("failed to touch job {id}: {}", ErrorChainDisplay(&err)),
+                }
+            }
+        }
+    }
+}
+
+/// Period of touching running jobs, several heartbeats fit into the stale timeout.
+fn heartbeat_period(stale_secs: u64) -> Duration {
+    Duration::from_secs(stale_secs / 4).max(Duration::from_secs(1))
+}
+
+fn job_not_running() -> Error {
+    Error::Internal("job is no longer running".to_owned())
+}
+
+/// Error of a failed job as reported to clients.
+fn job_error(err: &Error) -> serde_json::Value {
+    json!({
+        "code": err.code(),
+        "message": err.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_period() {
+        assert_eq!(heartbeat_period(3600), Duration::from_secs(900));
+        assert_eq!(heartbeat_period(2), Duration::from_secs(1));
+        assert_eq!(heartbeat_period(0), Duration::from_secs(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_with_heartbeat_completes() {
+        // Processing finishing before the first heartbeat doesn't touch the database.
+        let server = crate::server::tests::new_test_server_with_args(&["--job-stale-secs=40"]);
+        let processing = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            Ok(())
+        };
+        assert!(with_heartbeat(&server, Uuid::nil(), processing)
+            .await
+            .is_ok());
+    }
+
+    #[test]
+    fn test_job_error() {
+        let error = job_error(&Error::BadRequest("no audio in file".to_owned()));
+        assert_eq!(
+            error,
+            json!({"code": "bad_request", "message": "bad request (no audio in file)"})
+        );
+    }
+}
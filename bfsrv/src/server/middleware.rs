@@ -1,18 +1,37 @@
 use crate::{
-    data::token::{Token, TokenKey, TOKEN_KEY_LEN},
+    data::{
+        token::{Token, TokenKey, TOKEN_KEY_LEN},
+        user::User,
+    },
     server::{Error, Result, Server},
 };
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, HeaderMap},
+    extract::{FromRequestParts, Request, State},
+    http::{header::ORIGIN, request::Parts, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
 };
 use base64::prelude::{Engine as _, BASE64_STANDARD};
-use deadpool_postgres::Pool;
-use std::{net::IpAddr, str::FromStr, sync::Arc};
+use deadpool_postgres::GenericClient;
+use std::{
+    net::IpAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use time::OffsetDateTime;
+use tokio::sync::Semaphore;
 use uuid::Uuid;
 
+/// Header to pass request ID in.
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Maximum precision of tracking token usage times.
+const MAX_LAST_USED_RESOLUTION: Duration = Duration::from_secs(3600);
+
 /// Authentication middleware.
 pub struct Auth {
     pub token: Token,
@@ -42,7 +61,7 @@ impl Auth {
     }
 
     /// Authenticate request and create an Auth instance.
-    pub async fn create(pool: &Pool, headers: &HeaderMap) -> Result<Self> {
+    pub async fn create(server: &Server, headers: &HeaderMap) -> Result<Self> {
         use Error::*;
         let Some(authorization) = headers.get("Authorization") else {
             return Err(Unauthorized("missing Authorization header".to_owned()));
@@ -58,20 +77,43 @@ impl Auth {
             return Err(Unauthorized("unsupported authorization scheme".to_owned()));
         };
 
-        const ACCESS_DENIED: &str = "access denied";
-        let Some((id, key)) = Self::parse_access_token(token) else {
-            return Err(Unauthorized(ACCESS_DENIED.to_owned()));
-        };
+        let access_token = token;
+        let inactivity_ttl = Duration::from_secs(server.config.token_inactivity_ttl_secs);
+        let now = OffsetDateTime::now_utc();
+        // Cached tokens due for a usage record are authenticated again to record it.
+        let cached = server
+            .auth_cache
+            .get(access_token)
+            .filter(|token| !is_last_used_outdated(token, now, inactivity_ttl));
+        let token = match cached {
+            Some(token) => token,
+            None => {
+                const ACCESS_DENIED: &str = "access denied";
+                let Some((id, key)) = Self::parse_access_token(access_token) else {
+                    return Err(Unauthorized(ACCESS_DENIED.to_owned()));
+                };
 
-        let client = pool.get().await?;
-        let Some(token) = Token::get_and_authenticate(&client, id, key).await? else {
-            return Err(Unauthorized(ACCESS_DENIED.to_owned()));
-        };
+                let started_at = Instant::now();
+                let client = server.pg_pool.get().await?;
+                let Some(mut token) = Token::get_and_authenticate(&client, id, key).await? else {
+                    return Err(Unauthorized(ACCESS_DENIED.to_owned()));
+                };
+                check_expiry(server, &token)?;
 
-        if token.expires_at < OffsetDateTime::now_utc() {
-            return Err(Unauthorized("token expired".to_owned()));
-        }
+                // Usage is recorded at most once per resolution,
+                // so it adds no writes to bursts of requests.
+                if is_last_used_outdated(&token, now, inactivity_ttl) {
+                    Token::touch(&client, token.id, now).await?;
+                    token.last_used_at = now;
+                }
 
+                let duration = started_at.elapsed();
+                server.auth_cache.insert(access_token, &token, duration);
+                token
+            }
+        };
+
+        check_expiry(server, &token)?;
         Ok(Self { token })
     }
 
@@ -81,6 +123,50 @@ impl Auth {
             "token not associated with user".to_owned(),
         ))
     }
+
+    /// Get associated user ensuring it is not an anonymous trial one.
+    pub async fn non_trial(&self, client: &impl GenericClient) -> Result<Uuid> {
+        let user_id = self.user()?;
+        match User::get(client, user_id).await? {
+            Some(user) if user.is_trial => {
+                Err(Error::Forbidden("not allowed for trial users".to_owned()))
+            }
+            _ => Ok(user_id),
+        }
+    }
+
+    /// Get associated user ensuring it has admin privileges.
+    pub async fn admin(&self, client: &impl GenericClient) -> Result<Uuid> {
+        let user_id = self.user()?;
+        if self.token.is_admin {
+            if let Some(user) = User::get(client, user_id).await? {
+                if user.is_admin {
+                    return Ok(user_id);
+                }
+            }
+        }
+        Err(Error::Forbidden("admin privileges required".to_owned()))
+    }
+}
+
+/// Precision of tracking token usage times which keeps continuously
+/// used tokens from expiring due to inactivity.
+fn last_used_resolution(inactivity_ttl: Duration) -> Duration {
+    MAX_LAST_USED_RESOLUTION.min(inactivity_ttl / 4)
+}
+
+/// Check if token usage must be recorded, which is needed
+/// only if tokens expire due to inactivity (a non-zero TTL).
+fn is_last_used_outdated(token: &Token, now: OffsetDateTime, inactivity_ttl: Duration) -> bool {
+    !inactivity_ttl.is_zero() && token.last_used_at + last_used_resolution(inactivity_ttl) < now
+}
+
+fn check_expiry(server: &Server, token: &Token) -> Result<()> {
+    let inactivity_ttl = Duration::from_secs(server.config.token_inactivity_ttl_secs);
+    if token.effective_expires_at(inactivity_ttl) < OffsetDateTime::now_utc() {
+        return Err(Error::Unauthorized("token expired".to_owned()));
+    }
+    Ok(())
 }
 
 #[async_trait]
@@ -88,10 +174,79 @@ impl FromRequestParts<Arc<Server>> for Auth {
     type Rejection = Error;
 
     async fn from_request_parts(parts: &mut Parts, server: &Arc<Server>) -> Result<Self> {
-        Self::create(&server.pg_pool, &parts.headers).await
+        let auth = Self::create(server, &parts.headers).await?;
+        if let Some(context) = parts.extensions.get::<AccessLogContext>() {
+            *context.user.lock().unwrap() = auth.token.user;
+        }
+        Ok(auth)
     }
 }
 
+/// Concurrency limiting middleware which rejects requests beyond the permits.
+pub async fn limit_concurrency(
+    State(semaphore): State<Arc<Semaphore>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Ok(_permit) = semaphore.try_acquire_owned() else {
+        return Error::ServerOverloaded("too many concurrent requests".to_owned()).into_response();
+    };
+    next.run(request).await
+}
+
+/// Request state collected for access logging.
+#[derive(Clone, Default)]
+pub struct AccessLogContext {
+    user: Arc<Mutex<Option<Uuid>>>,
+}
+
+/// Error code of a failed request (attached to response extensions).
+#[derive(Clone, Debug, PartialEq)]
+pub struct ErrorCode(pub String);
+
+/// Access logging middleware which emits a structured line per request.
+pub async fn log_access(mut request: Request, next: Next) -> Response {
+    let started_at = Instant::now();
+
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty() && v.len() <= MAX_REQUEST_ID_LEN)
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let context = AccessLogContext::default();
+    request.extensions_mut().insert(context.clone());
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+
+    let mut response = next.run(request).await;
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    let user = context.user.lock().unwrap().map(|u| u.to_string());
+    let error_code = response
+        .extensions()
+        .get::<ErrorCode>()
+        .map(|c| c.0.clone());
+    tracing::info!(
+        target: "access",
+        method = %method,
+        path = %path,
+        status = response.status().as_u16(),
+        latency_ms = started_at.elapsed().as_secs_f64() * 1000.0,
+        user = user.as_deref(),
+        request_id = %request_id,
+        error_code = error_code.as_deref(),
+        "served request"
+    );
+
+    response
+}
+
 /// Request IP address extractor (taken from X-Real-IP header).
 pub struct RealIpAddress(pub IpAddr);
 
@@ -113,6 +268,38 @@ impl<T> FromRequestParts<T> for RealIpAddress {
     }
 }
 
+/// Extractor which rejects requests from origins not allowed for WebSockets.
+/// Requests without Origin header come from non-browser clients, so they are allowed.
+pub struct AllowedWsOrigin;
+
+#[async_trait]
+impl FromRequestParts<Arc<Server>> for AllowedWsOrigin {
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, server: &Arc<Server>) -> Result<Self> {
+        let origin = parts
+            .headers
+            .get(ORIGIN)
+            .map(|v| v.to_str().unwrap_or_default());
+        if is_origin_allowed(origin, &server.config.ws_allowed_origins) {
+            Ok(Self)
+        } else {
+            Err(Error::Forbidden("origin not allowed".to_owned()))
+        }
+    }
+}
+
+/// Check if an origin is allowed, an empty allow-list allows every origin.
+fn is_origin_allowed(origin: Option<&str>, allowed_origins: &[String]) -> bool {
+    let Some(origin) = origin else {
+        return true;
+    };
+    allowed_origins.is_empty()
+        || allowed_origins
+            .iter()
+            .any(|allowed| allowed.trim_end_matches('/').eq_ignore_ascii_case(origin))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -133,4 +320,96 @@ mod tests {
             ))
         )
     }
+
+    #[test]
+    fn test_last_used_resolution() {
+        assert_eq!(
+            last_used_resolution(Duration::from_secs(60)),
+            Duration::from_secs(15)
+        );
+        assert_eq!(
+            last_used_resolution(Duration::from_secs(30 * 24 * 3600)),
+            MAX_LAST_USED_RESOLUTION
+        );
+    }
+
+    #[test]
+    fn test_continuous_use_under_short_inactivity_ttl() {
+        let inactivity_ttl = Duration::from_secs(60);
+        let mut now = OffsetDateTime::UNIX_EPOCH;
+        let mut token = Token::new(
+            now + Duration::from_secs(24 * 3600),
+            None,
+            None,
+            false,
+            [127, 0, 0, 1].into(),
+            None,
+        );
+        token.last_used_at = now;
+
+        // A request every 10 seconds for 10 minutes.
+        for _ in 0..60 {
+            now += Duration::from_secs(10);
+            assert!(token.effective_expires_at(inactivity_ttl) >= now);
+            if is_last_used_outdated(&token, now, inactivity_ttl) {
+                token.last_used_at = now;
+            }
+        }
+
+        now += inactivity_ttl * 2;
+        assert!(token.effective_expires_at(inactivity_ttl) < now);
+    }
+
+    #[test]
+    fn test_is_last_used_outdated_without_inactivity_ttl() {
+        let token = Token::new(
+            OffsetDateTime::UNIX_EPOCH + Duration::from_secs(365 * 24 * 3600),
+            None,
+            None,
+            false,
+            [127, 0, 0, 1].into(),
+            None,
+        );
+        let now = OffsetDateTime::UNIX_EPOCH + Duration::from_secs(30 * 24 * 3600);
+        assert!(is_last_used_outdated(&token, now, Duration::from_secs(60)));
+        // Usage is not recorded if tokens never expire due to inactivity.
+        assert!(!is_last_used_outdated(&token, now, Duration::ZERO));
+    }
+
+    #[test]
+    fn test_is_origin_allowed() {
+        let allowed_origins = [
+            "https://app.example.com".to_owned(),
+            "http://localhost:8080/".to_owned(),
+        ];
+
+        assert!(is_origin_allowed(None, &allowed_origins));
+        assert!(is_origin_allowed(
+            Some("https://app.example.com"),
+            &allowed_origins
+        ));
+        assert!(is_origin_allowed(
+            Some("https://APP.example.com"),
+            &allowed_origins
+        ));
+        assert!(is_origin_allowed(
+            Some("http://localhost:8080"),
+            &allowed_origins
+        ));
+
+        assert!(!is_origin_allowed(
+            Some("https://evil.com"),
+            &allowed_origins
+        ));
+        assert!(!is_origin_allowed(
+            Some("http://app.example.com"),
+            &allowed_origins
+        ));
+        assert!(!is_origin_allowed(
+            Some("http://localhost:8081"),
+            &allowed_origins
+        ));
+        assert!(!is_origin_allowed(Some("null"), &allowed_origins));
+        assert!(is_origin_allowed(Some("https://evil.com"), &[]));
+    }
 }
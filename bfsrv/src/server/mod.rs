@@ -1,30 +1,88 @@
+mod admin;
+mod audio_sweeper;
+mod auth_cache;
+mod balance_notifier;
+mod callback;
+mod campaign;
+mod dev;
+mod errors;
+mod health;
+mod job;
+mod job_worker;
 mod middleware;
 mod payment;
+mod payment_poller;
 mod token;
 mod transcribe;
+mod transcript;
+mod usage;
 mod user;
+mod version;
+mod whoami;
 
 use crate::{
+    audio_store::{AudioStore, DiskAudioStore},
     config::Config,
     currency_converter::CurrencyConverter,
+    error_kind::{self as kind, ErrorKind},
     infsrv_pool::{self, InfsrvPool},
     mailer::Mailer,
     paypal::PaypalProcessor,
-    util::fmt::ErrorChainDisplay,
+    util::{
+        fmt::ErrorChainDisplay,
+        http::client_builder,
+        retry::{RetryPolicy, SerializationFailure},
+    },
 };
+use audio_sweeper::AudioSweeper;
+use auth_cache::AuthCache;
 use axum::{
-    extract::rejection,
-    http::StatusCode,
+    extract::{multipart, rejection, DefaultBodyLimit},
+    http::{HeaderValue, StatusCode},
+    middleware::{from_fn, from_fn_with_state, map_response},
     response::{IntoResponse, Response},
-    routing::{get, patch, post},
+    routing::{delete, get, patch, post},
     Json, Router,
 };
-use deadpool_postgres::Pool as PgPool;
+use balance_notifier::BalanceNotifier;
+use deadpool_postgres::{Pool as PgPool, PoolError};
+use futures::future::{try_join_all, FutureExt};
+use job_worker::JobWorker;
 use log::{debug, error, info};
-use reqwest::{header::CONTENT_TYPE, Method};
+pub use middleware::Auth;
+use middleware::{limit_concurrency, log_access, ErrorCode};
+use payment_poller::PaymentPoller;
+use reqwest::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    Method,
+};
 use serde_json::json;
-use std::{future::Future, sync::Arc};
-use tower_http::cors::{Any, CorsLayer};
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
+use std::{
+    future::{Future, IntoFuture},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{net::TcpListener, sync::Semaphore, time::sleep};
+use tower_http::{
+    compression::{
+        predicate::{NotForContentType, Predicate, SizeAbove},
+        CompressionLayer,
+    },
+    cors::{Any, CorsLayer},
+    decompression::RequestDecompressionLayer,
+    limit::RequestBodyLimitLayer,
+};
+
+/// Retry-After header value (in seconds) for temporarily unavailable service.
+const RETRY_AFTER_SECS: &str = "1";
+
+/// Maximum number of pending connections (same as tokio uses by default).
+const LISTEN_BACKLOG: i32 = 1024;
 
 /// Server error.
 #[derive(Debug, thiserror::Error)]
@@ -35,12 +93,30 @@ pub enum Error {
         #[source]
         axum::Error,
     ),
+    #[error("malformed URL path")]
+    AxumPathRejection(
+        #[from]
+        #[source]
+        rejection::PathRejection,
+    ),
     #[error("malformed JSON payload")]
     AxumJsonRejection(
         #[from]
         #[source]
         rejection::JsonRejection,
     ),
+    #[error("malformed multipart payload")]
+    AxumMultipart(
+        #[from]
+        #[source]
+        multipart::MultipartError,
+    ),
+    #[error("malformed multipart payload")]
+    AxumMultipartRejection(
+        #[from]
+        #[source]
+        multipart::MultipartRejection,
+    ),
     #[error("malformed URL query")]
     AxumQueryRejection(
         #[from]
@@ -51,6 +127,8 @@ pub enum Error {
     BadPaymentStatus,
     #[error("bad request ({0})")]
     BadRequest(String),
+    #[error("promotion campaign expired")]
+    CampaignExpired,
     #[error("promotion campaign not found")]
     CampaignNotFound,
     #[error("failed to convert currency")]
@@ -73,6 +151,10 @@ pub enum Error {
     ),
     #[error("user with email already registered")]
     EmailAlreadyRegistered,
+    #[error("email suppression not found")]
+    EmailSuppressionNotFound,
+    #[error("access forbidden ({0})")]
+    Forbidden(String),
     #[error("endpoint not found")]
     HandlerNotFound,
     #[error("worker node error")]
@@ -89,12 +171,16 @@ pub enum Error {
         #[source]
         std::io::Error,
     ),
+    #[error("job not found")]
+    JobNotFound,
     #[error("mailing error")]
     Mailer(
         #[from]
         #[source]
         crate::mailer::Error,
     ),
+    #[error("payload too large")]
+    PayloadTooLarge,
     #[error("payment not found")]
     PaymentNotFound,
     #[error("paypal error")]
@@ -103,65 +189,94 @@ pub enum Error {
         #[source]
         crate::paypal::Error,
     ),
+    #[error("promo code already exists")]
+    PromoCodeAlreadyExists,
     #[error("database query error")]
     Postgres(
         #[from]
         #[source]
         tokio_postgres::Error,
     ),
+    #[error("server overloaded ({0})")]
+    ServerOverloaded(String),
+    #[error("server shutting down")]
+    ServerShuttingDown,
+    #[error("transcript not found")]
+    TranscriptNotFound,
+    #[error("trial user already upgraded")]
+    TrialAlreadyUpgraded,
     #[error("unauthorized access ({0})")]
     Unauthorized(String),
+    #[error("user not found")]
+    UserNotFound,
 }
 
 impl Error {
-    /// HTTP status code.
-    pub fn status(&self) -> StatusCode {
+    /// Error kind.
+    pub fn kind(&self) -> &'static ErrorKind {
         use Error::*;
-        match &self {
-            Axum(_) | DeadpoolPool(_) | Internal(_) | Postgres(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR
+        match self {
+            Axum(_) => &kind::AXUM,
+            AxumJsonRejection(err) if err.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                &kind::PAYLOAD_TOO_LARGE
+            }
+            AxumJsonRejection(_) => &kind::AXUM_JSON_REJECTION,
+            AxumMultipart(err) if err.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                &kind::PAYLOAD_TOO_LARGE
             }
-            AxumJsonRejection(_)
-            | AxumQueryRejection(_)
-            | BadRequest(_)
-            | CampaignNotFound
-            | EmailAlreadyRegistered => StatusCode::BAD_REQUEST,
-            BadPaymentStatus => StatusCode::UNPROCESSABLE_ENTITY,
-            CurrencyConverter(err) => err.status(),
-            Data(err) => err.status(),
-            HandlerNotFound | PaymentNotFound => StatusCode::NOT_FOUND,
-            InfsrvPool(err) => err.status(),
-            Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            Mailer(err) => err.status(),
-            Paypal(err) => err.status(),
-            Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AxumMultipart(_) => &kind::AXUM_MULTIPART,
+            AxumMultipartRejection(_) => &kind::AXUM_MULTIPART_REJECTION,
+            AxumPathRejection(_) => &kind::AXUM_PATH_REJECTION,
+            AxumQueryRejection(_) => &kind::AXUM_QUERY_REJECTION,
+            BadPaymentStatus => &kind::BAD_PAYMENT_STATUS,
+            BadRequest(_) => &kind::BAD_REQUEST,
+            CampaignExpired => &kind::CAMPAIGN_EXPIRED,
+            CampaignNotFound => &kind::CAMPAIGN_NOT_FOUND,
+            CurrencyConverter(err) => err.kind(),
+            Data(err) => err.kind(),
+            DeadpoolPool(PoolError::Timeout(_)) => &kind::DEADPOOL_POOL_TIMEOUT,
+            DeadpoolPool(_) => &kind::DEADPOOL_POOL,
+            EmailAlreadyRegistered => &kind::EMAIL_ALREADY_REGISTERED,
+            EmailSuppressionNotFound => &kind::EMAIL_SUPPRESSION_NOT_FOUND,
+            Forbidden(_) => &kind::FORBIDDEN,
+            HandlerNotFound => &kind::HANDLER_NOT_FOUND,
+            InfsrvPool(err) => err.kind(),
+            Internal(_) => &kind::INTERNAL,
+            Io(_) => &kind::IO,
+            JobNotFound => &kind::JOB_NOT_FOUND,
+            Mailer(err) => err.kind(),
+            PayloadTooLarge => &kind::PAYLOAD_TOO_LARGE,
+            PaymentNotFound => &kind::PAYMENT_NOT_FOUND,
+            Paypal(err) => err.kind(),
+            Postgres(_) => &kind::POSTGRES,
+            PromoCodeAlreadyExists => &kind::PROMO_CODE_ALREADY_EXISTS,
+            ServerOverloaded(_) => &kind::SERVER_OVERLOADED,
+            ServerShuttingDown => &kind::SERVER_SHUTTING_DOWN,
+            TranscriptNotFound => &kind::TRANSCRIPT_NOT_FOUND,
+            TrialAlreadyUpgraded => &kind::TRIAL_ALREADY_UPGRADED,
+            Unauthorized(_) => &kind::UNAUTHORIZED,
+            UserNotFound => &kind::USER_NOT_FOUND,
         }
     }
 
+    /// HTTP status code.
+    pub fn status(&self) -> StatusCode {
+        self.kind().status
+    }
+
     /// Kind code.
     pub fn code(&self) -> &str {
-        use Error::*;
-        match &self {
-            Axum(_) => "axum",
-            AxumJsonRejection(_) => "axum_json_rejection",
-            AxumQueryRejection(_) => "axum_query_rejection",
-            BadPaymentStatus => "bad_payment_status",
-            BadRequest(_) => "bad_request",
-            CampaignNotFound => "campaign_not_found",
-            CurrencyConverter(err) => err.code(),
-            Data(err) => err.code(),
-            DeadpoolPool(_) => "deadpool_pool",
-            EmailAlreadyRegistered => "email_already_registered",
-            HandlerNotFound => "handler_not_found",
-            InfsrvPool(err) => err.code(),
-            Internal(_) => "internal",
-            Io(_) => "io",
-            Mailer(err) => err.code(),
-            PaymentNotFound => "payment_not_found",
-            Paypal(err) => err.code(),
-            Postgres(_) => "postgres",
-            Unauthorized(_) => "unauthorized",
-        }
+        self.kind().code
+    }
+
+    /// JSON body of an error response.
+    pub fn json(&self) -> serde_json::Value {
+        json!({
+            "error": {
+                "code": self.code(),
+                "message": self.to_string()
+            }
+        })
     }
 }
 
@@ -177,27 +292,50 @@ impl IntoResponse for Error {
             }
         }
 
-        let response = json!({
-            "error": {
-                "code": self.code(),
-                "message": self.to_string()
-            }
-        });
-        (status, Json(response)).into_response()
+        let code = self.code().to_owned();
+        let mut response = (status, Json(self.json())).into_response();
+        response.extensions_mut().insert(ErrorCode(code));
+        if status == StatusCode::SERVICE_UNAVAILABLE {
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+        }
+        response
     }
 }
 
 /// Server result.
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl SerializationFailure for Error {
+    fn is_serialization_failure(&self) -> bool {
+        use Error::*;
+        match self {
+            Data(err) => err.is_serialization_failure(),
+            Postgres(err) => err.is_serialization_failure(),
+            _ => false,
+        }
+    }
+}
+
+/// Retry policy for serializable transactions of request handlers.
+const TX_RETRY_POLICY: RetryPolicy = RetryPolicy::new(100, Duration::from_millis(10));
+
 /// HTTP/WS server for Handler.
 pub struct Server {
     config: Config,
     pg_pool: PgPool,
+    auth_cache: AuthCache,
     infsrv_pool: InfsrvPool,
     currency_converter: CurrencyConverter,
     paypal: PaypalProcessor,
     mailer: Mailer,
+    /// Store of captured session audio (if capturing is enabled).
+    audio_store: Option<Arc<dyn AudioStore>>,
+    http_client: reqwest::Client,
+    request_semaphore: Arc<Semaphore>,
+    transcribe_semaphore: Arc<Semaphore>,
+    ready: AtomicBool,
 }
 
 impl Server {
@@ -210,52 +348,641 @@ impl Server {
         paypal: PaypalProcessor,
         mailer: Mailer,
     ) -> Self {
+        let request_semaphore = Arc::new(Semaphore::new(config.max_concurrent_requests));
+        let transcribe_semaphore = Arc::new(Semaphore::new(config.max_transcribe_sessions));
+        // Redirects are not followed to keep callback URL validation effective.
+        let http_client = client_builder(
+            Duration::from_secs(config.http_connect_timeout_secs),
+            Duration::from_secs(config.http_timeout_secs),
+        )
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+        let auth_cache = AuthCache::new(Duration::from_secs(config.auth_cache_ttl_secs));
+        let audio_store = config
+            .audio_capture_dir
+            .clone()
+            .map(|dir| Arc::new(DiskAudioStore::new(dir)) as Arc<dyn AudioStore>);
         Self {
             config,
             pg_pool,
+            auth_cache,
             infsrv_pool,
             currency_converter,
             paypal,
             mailer,
+            audio_store,
+            http_client,
+            request_semaphore,
+            transcribe_semaphore,
+            ready: AtomicBool::new(true),
         }
     }
 
-    /// Serve HTTP/WS requests with graceful shutdown on a given signal.
+    /// Serve HTTP/WS requests on every configured address
+    /// with graceful shutdown on a given signal (after draining).
     pub async fn serve<F>(self: Arc<Self>, shutdown_signal: F) -> Result<()>
     where
         F: Future<Output = ()> + Send + 'static,
     {
+        let mut listeners = Vec::with_capacity(self.config.server_addresses.len());
+        for address in &self.config.server_addresses {
+            listeners.push(self.bind(address)?);
+            info!("listening on {address}");
+        }
+
+        let _payment_poller = PaymentPoller::new(self.clone());
+        let _balance_notifier = BalanceNotifier::new(self.clone());
+        let _job_worker = JobWorker::new(self.clone());
+        let _audio_sweeper = self
+            .audio_store
+            .is_some()
+            .then(|| AudioSweeper::new(self.clone()));
+        let app = self.clone().router();
+        let shutdown_signal = self.drain_after(shutdown_signal).shared();
+
+        info!("started HTTP/WS server");
+
+        try_join_all(listeners.into_iter().map(|listener| {
+            axum::serve(listener, app.clone())
+                .with_graceful_shutdown(shutdown_signal.clone())
+                .into_future()
+        }))
+        .await?;
+        Ok(())
+    }
+
+    /// Bind a listener with TCP keepalive and nodelay options configured.
+    /// Sockets of accepted connections inherit these options.
+    fn bind(&self, address: &SocketAddr) -> std::io::Result<TcpListener> {
+        let socket = Socket::new(
+            Domain::for_address(*address),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        socket.set_reuse_address(true)?;
+        socket.set_nodelay(self.config.tcp_nodelay)?;
+        let keepalive = TcpKeepalive::new()
+            .with_time(Duration::from_secs(self.config.tcp_keepalive_idle_secs))
+            .with_interval(Duration::from_secs(self.config.tcp_keepalive_interval_secs));
+        socket.set_tcp_keepalive(&keepalive)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&(*address).into())?;
+        socket.listen(LISTEN_BACKLOG)?;
+        TcpListener::from_std(socket.into())
+    }
+
+    /// Wait for a given signal and report not being ready while
+    /// the shutdown grace period lets load balancers stop routing requests.
+    async fn drain_after<F>(self: Arc<Self>, signal: F)
+    where
+        F: Future<Output = ()>,
+    {
+        signal.await;
+        self.ready.store(false, Ordering::SeqCst);
+        let grace = Duration::from_secs(self.config.shutdown_grace_secs);
+        info!(
+            "draining connections for {}s before shutdown",
+            grace.as_secs()
+        );
+        sleep(grace).await;
+    }
+
+    /// Create a router for all HTTP/WS endpoints.
+    fn router(self: Arc<Self>) -> Router {
         async fn handle_fallback() -> Result<Response> {
             Err(Error::HandlerNotFound)
         }
 
-        let address = self.config.server_address;
-
         // Enable page-status.html to call /payment PATCH.
         let cors = CorsLayer::new()
             .allow_headers([CONTENT_TYPE])
             .allow_methods([Method::GET, Method::PATCH, Method::POST])
             .allow_origin(Any);
 
-        let app = Router::<Arc<Server>>::new()
+        let mut router = Router::<Arc<Server>>::new()
+            .route(
+                "/admin/email-suppressions",
+                get(admin::handle_admin_email_suppressions_get),
+            )
+            .route(
+                "/admin/email-suppressions",
+                post(admin::handle_admin_email_suppressions_post),
+            )
+            .route(
+                "/admin/email-suppressions/:email",
+                delete(admin::handle_admin_email_suppressions_delete),
+            )
+            .route("/admin/nodes", get(admin::handle_admin_nodes_get))
+            .route(
+                "/admin/users/:id/credit-limit",
+                post(admin::handle_admin_user_credit_limit_post),
+            )
+            .route("/campaign", get(campaign::handle_campaign_get))
+            .route("/campaign", post(campaign::handle_campaign_post))
+            .route("/errors", get(errors::handle_errors_get))
+            .route("/jobs/:id", get(job::handle_job_get))
             .route("/payment", get(payment::handle_payment_get))
             .route("/payment", patch(payment::handle_payment_patch))
             .route("/payment", post(payment::handle_payment_post))
             .route("/token", post(token::handle_token_post))
-            .route("/transcribe", get(transcribe::handle_transcribe))
+            .route("/tokens", post(token::handle_tokens_post))
+            .route("/transcript/:id", get(transcript::handle_transcript_get))
+            .route(
+                "/transcript/:id/url",
+                post(transcript::handle_transcript_url_post),
+            )
+            .route("/usage/export", get(usage::handle_usage_export_get))
             .route("/user", get(user::handle_user_get))
             .route("/user", post(user::handle_user_post))
+            .route("/user/:id/adjust", post(user::handle_user_adjust_post))
+            .route("/version", get(version::handle_version_get))
+            .route("/whoami", get(whoami::handle_whoami_get));
+        if self.config.trial_enabled {
+            router = router.route("/user/trial", post(user::handle_user_trial_post));
+        }
+        if self.mailer.captured().is_some() {
+            router = router.route("/dev/emails", get(dev::handle_dev_emails_get));
+        }
+        router = with_body_limit(router, self.config.max_request_body_size);
+        router = router.layer(from_fn_with_state(
+            self.request_semaphore.clone(),
+            limit_concurrency,
+        ));
+        if self.config.http_compression {
+            router = with_compression(router, self.config.http_compression_min_size);
+        }
+
+        // Transcribe and job upload routes are added after REST layers to bypass them,
+        // files are limited by their own size (and transcribe ones by the session limit).
+        let transcribe_file_limit = DefaultBodyLimit::max(self.config.max_transcribe_file_size);
+        let job_file_limit = DefaultBodyLimit::max(self.config.max_job_file_size);
+        // Probes are not limited to keep reporting under load.
+        router
+            .route("/jobs", post(job::handle_jobs_post).layer(job_file_limit))
+            .route("/readyz", get(health::handle_readyz_get))
+            .route(
+                "/transcribe",
+                get(transcribe::handle_transcribe)
+                    .post(transcribe::handle_transcribe_post)
+                    .layer(transcribe_file_limit),
+            )
             .fallback(handle_fallback)
             .with_state(self)
-            .layer(cors);
+            .layer(cors)
+            .layer(from_fn(log_access))
+    }
+}
 
-        info!("started HTTP/WS server");
+/// Reject requests with bodies larger than a given size.
+fn with_body_limit<S>(router: Router<S>, limit: usize) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    // Replace non-JSON responses of RequestBodyLimitLayer with a regular error.
+    async fn map_payload_too_large(response: Response) -> Response {
+        if response.status() == StatusCode::PAYLOAD_TOO_LARGE
+            && response.headers().get(CONTENT_TYPE)
+                != Some(&HeaderValue::from_static("application/json"))
+        {
+            return Error::PayloadTooLarge.into_response();
+        }
+        response
+    }
+
+    router
+        .layer(DefaultBodyLimit::disable())
+        .layer(RequestBodyLimitLayer::new(limit))
+        .layer(map_response(map_payload_too_large))
+}
+
+/// Compress responses above a given size and decompress request bodies.
+fn with_compression<S>(router: Router<S>, min_size: u16) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    let predicate = SizeAbove::new(min_size)
+        .and(NotForContentType::GRPC)
+        .and(NotForContentType::IMAGES)
+        .and(NotForContentType::SSE);
+    router
+        .layer(CompressionLayer::new().compress_when(predicate))
+        .layer(RequestDecompressionLayer::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::{pool_config, token::Token},
+        infsrv_pool::ReconnectParams,
+        ledger::Ledger,
+        util::circuit_breaker::CircuitBreakerParams,
+    };
+    use axum::body::{to_bytes, Body};
+    use axum::http::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH};
+    use clap::Parser;
+    use deadpool_postgres::{Config as DeadpoolConfig, Runtime};
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+    use middleware::REQUEST_ID_HEADER;
+    use std::{
+        io::{Read, Write},
+        time::Duration,
+    };
+    use time::OffsetDateTime;
+    use tokio_postgres::NoTls;
+    use tower::ServiceExt;
+    use uuid::Uuid;
 
-        let listener = tokio::net::TcpListener::bind(address).await?;
+    /// Create a server with lazily connected dependencies.
+    pub fn new_test_server() -> Arc<Server> {
+        new_test_server_with_args(&[])
+    }
+
+    /// Create a server with lazily connected dependencies and extra arguments
+    /// (which replace the default ones with the same names).
+    pub fn new_test_server_with_args(args: &[&str]) -> Arc<Server> {
+        let defaults = [
+            "--paypal-cancel-url=http://localhost/cancel",
+            "--paypal-client-id=client",
+            "--paypal-return-url=http://localhost/return",
+            "--paypal-secret-key=secret",
+            "--smtp-from=noreply@localhost",
+            "--smtp-username=user",
+            "--smtp-password=password",
+            "--smtp-relay=localhost",
+        ];
+        let name = |arg: &str| arg.split('=').next().unwrap().to_owned();
+        let defaults = defaults
+            .into_iter()
+            .filter(|d| !args.iter().any(|a| name(a) == name(d)));
+        let config = Config::parse_from(
+            ["bfsrv"]
+                .into_iter()
+                .chain(defaults)
+                .chain(args.iter().copied()),
+        );
+
+        let mut deadpool_config = DeadpoolConfig::new();
+        deadpool_config.url = Some(config.database_url.to_string());
+        deadpool_config.pool = Some(pool_config(
+            config.pg_pool_max_size,
+            Duration::from_secs(config.pg_pool_wait_timeout_secs),
+            Duration::from_secs(config.pg_pool_create_timeout_secs),
+            Duration::from_secs(config.pg_pool_recycle_timeout_secs),
+        ));
+        let pg_pool = deadpool_config
+            .create_pool(Some(Runtime::Tokio1), NoTls)
+            .unwrap();
+
+        let reconnect = ReconnectParams {
+            attempts: config.infsrv_reconnect_attempts,
+            delay: Duration::from_secs(config.infsrv_reconnect_delay_secs),
+        };
+        let http_client = client_builder(
+            Duration::from_secs(config.http_connect_timeout_secs),
+            Duration::from_secs(config.http_timeout_secs),
+        )
+        .build()
+        .unwrap();
+        let infsrv_pool = InfsrvPool::new(
+            Ledger::new(
+                pg_pool.clone(),
+                config.currency.clone(),
+                config.billing_unit_secs,
+                config.allocation_mode,
+                &config.tariff_concurrency_limits,
+            ),
+            reconnect,
+            CircuitBreakerParams {
+                failures: config.infsrv_breaker_failures,
+                window: Duration::from_secs(config.infsrv_breaker_window_secs),
+                cooldown: Duration::from_secs(config.infsrv_breaker_cooldown_secs),
+            },
+            http_client.clone(),
+        );
+        let currency_converter =
+            CurrencyConverter::new(config.currency.clone(), http_client.clone());
+        let paypal = PaypalProcessor::new(
+            config.paypal_sandbox,
+            config.paypal_client_id.clone(),
+            config.paypal_secret_key.clone(),
+            config.paypal_return_url.clone(),
+            config.paypal_cancel_url.clone(),
+            Duration::from_secs(config.paypal_token_expiry_skew_secs),
+            http_client,
+        );
+        let mailer = Mailer::new(&config);
+
+        Arc::new(Server::new(
+            config,
+            pg_pool,
+            infsrv_pool,
+            currency_converter,
+            paypal,
+            mailer,
+        ))
+    }
+
+    /// Cache an authenticated token of a given user, returns its access token
+    /// (the server must be created with a non-zero auth cache TTL).
+    pub fn cache_test_token(server: &Server, user: Uuid, is_admin: bool) -> String {
+        let now = OffsetDateTime::now_utc();
+        let mut token = Token::new(
+            now + Duration::from_secs(3600),
+            None,
+            Some(user),
+            is_admin,
+            [127, 0, 0, 1].into(),
+            None,
+        );
+        token.last_used_at = now;
+        let access_token = Uuid::new_v4().to_string();
+        server
+            .auth_cache
+            .insert(&access_token, &token, Duration::ZERO);
+        access_token
+    }
+
+    /// Send a request to a given server and return status and JSON body.
+    pub async fn send_request(
+        server: Arc<Server>,
+        request: axum::http::Request<Body>,
+    ) -> (StatusCode, serde_json::Value) {
+        let response = server.router().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn get(uri: &str) -> axum::http::Request<Body> {
+        axum::http::Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_router_payment_get() {
+        let id = Uuid::new_v4();
+        for uri in [
+            "/payment".to_owned(),
+            format!("/payment?id={id}"),
+            "/payment?reference=ref".to_owned(),
+        ] {
+            let (status, json) = send_request(new_test_server(), get(&uri)).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+            assert_eq!(json["error"]["code"], "unauthorized");
+        }
+
+        // Fetching payments needs a database, so only queries rejected upfront are checked.
+        let server = new_test_server_with_args(&["--auth-cache-ttl-secs=60"]);
+        let access_token = cache_test_token(&server, Uuid::new_v4(), false);
+        for uri in [
+            format!("/payment?id={id}&reference=ref"),
+            "/payment?limit=0".to_owned(),
+            "/payment?before=bad".to_owned(),
+        ] {
+            let request = axum::http::Request::get(uri)
+                .header("Authorization", format!("Bearer {access_token}"))
+                .body(Body::empty())
+                .unwrap();
+            let (status, json) = send_request(server.clone(), request).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            assert_eq!(json["error"]["code"], "bad_request");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_router_fallback() {
+        let (status, json) = send_request(new_test_server(), get("/unknown")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json["error"]["code"], "handler_not_found");
+    }
+
+    #[tokio::test]
+    async fn test_with_compression() {
+        let router = with_compression(
+            Router::new()
+                .route("/echo", post(|body: String| async move { body }))
+                .route("/small", axum::routing::get(|| async { "small" })),
+            64,
+        );
+
+        let request = axum::http::Request::post("/echo")
+            .header(ACCEPT_ENCODING, "gzip")
+            .header(CONTENT_ENCODING, "gzip")
+            .body(Body::from(gzip(&"a".repeat(1000))))
+            .unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_ENCODING], "gzip");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.len() < 1000);
+        assert_eq!(gunzip(&body), "a".repeat(1000));
+
+        let request = axum::http::Request::get("/small")
+            .header(ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(CONTENT_ENCODING).is_none());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(&body[..], b"small");
+    }
+
+    fn gzip(data: &str) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn gunzip(data: &[u8]) -> String {
+        let mut decoded = String::new();
+        GzDecoder::new(data).read_to_string(&mut decoded).unwrap();
+        decoded
+    }
+
+    #[tokio::test]
+    async fn test_with_body_limit() {
+        let router = with_body_limit(
+            Router::new().route("/echo", post(|body: String| async move { body })),
+            16,
+        );
+        let post = |body: Body| {
+            axum::http::Request::post("/echo")
+                .header(CONTENT_TYPE, "text/plain")
+                .body(body)
+                .unwrap()
+        };
 
-        axum::serve(listener, app)
-            .with_graceful_shutdown(shutdown_signal)
+        let response = router
+            .clone()
+            .oneshot(post(Body::from("a".repeat(16))))
             .await
-            .map_err(Into::into)
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(post(Body::from("a".repeat(17))))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "payload_too_large");
+
+        // A streamed body has no content length to reject it upfront.
+        let chunks = futures::stream::iter(["a".repeat(10), "a".repeat(7)].map(Ok::<_, Error>));
+        let response = router
+            .oneshot(post(Body::from_stream(chunks)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn test_router_body_limit() {
+        let server = new_test_server();
+        let limit = server.config.max_request_body_size;
+        let json = |len| {
+            let body = format!(r#"{{"email":"{}"}}"#, "a".repeat(len - 12));
+            assert_eq!(body.len(), len);
+            axum::http::Request::post("/user")
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_LENGTH, len)
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        let (status, json_body) = send_request(server.clone(), json(limit)).await;
+        assert_ne!(status, StatusCode::PAYLOAD_TOO_LARGE, "{json_body}");
+
+        let (status, json_body) = send_request(server, json(limit + 1)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(json_body["error"]["code"], "payload_too_large");
+    }
+
+    #[tokio::test]
+    async fn test_router_request_id() {
+        let server = new_test_server();
+
+        let response = server
+            .clone()
+            .router()
+            .oneshot(get("/payment"))
+            .await
+            .unwrap();
+        let request_id = response.headers()[REQUEST_ID_HEADER].to_str().unwrap();
+        assert!(Uuid::parse_str(request_id).is_ok());
+        assert_eq!(
+            response.extensions().get::<ErrorCode>(),
+            Some(&ErrorCode("unauthorized".to_owned()))
+        );
+
+        let request = axum::http::Request::get("/payment")
+            .header(REQUEST_ID_HEADER, "abc")
+            .body(Body::empty())
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc");
+    }
+
+    #[tokio::test]
+    async fn test_serve_multiple_addresses() {
+        let server = new_test_server_with_args(&[
+            "--server-address=127.0.0.1:0,127.0.0.1:0",
+            "--shutdown-grace-secs=0",
+        ]);
+        assert_eq!(server.config.server_addresses.len(), 2);
+
+        let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel::<()>();
+        let handle = tokio::spawn(server.serve(async move {
+            let _ = stop_receiver.await;
+        }));
+
+        stop_sender.send(()).unwrap();
+        let result = tokio::time::timeout(std::time::Duration::from_secs(5), handle).await;
+        assert!(result.unwrap().unwrap().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_after() {
+        let server = new_test_server_with_args(&["--shutdown-grace-secs=5"]);
+        let (status, json) = send_request(server.clone(), get("/readyz")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["ready"], true);
+
+        let (stop_sender, stop_receiver) = tokio::sync::oneshot::channel::<()>();
+        let start = tokio::time::Instant::now();
+        let handle = tokio::spawn(server.clone().drain_after(async move {
+            let _ = stop_receiver.await;
+        }));
+        stop_sender.send(()).unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        // Requests are still served, but readiness is reported as lost.
+        let (status, json) = send_request(server.clone(), get("/readyz")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json["error"]["code"], "server_shutting_down");
+        assert!(!handle.is_finished());
+
+        handle.await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_bind_socket_options() {
+        let server = new_test_server_with_args(&["--tcp-keepalive-idle-secs=30"]);
+        let listener = server.bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let _client = tokio::net::TcpStream::connect(address).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(stream.nodelay().unwrap());
+
+        let socket = socket2::SockRef::from(&stream);
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(
+            socket.keepalive_interval().unwrap(),
+            Duration::from_secs(15)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_router_concurrency_limit() {
+        let server = new_test_server_with_args(&["--max-concurrent-requests=0"]);
+        let (status, json) = send_request(server, get("/payment")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json["error"]["code"], "server_overloaded");
+    }
+
+    #[tokio::test]
+    async fn test_router_pg_pool_exhausted() {
+        let server =
+            new_test_server_with_args(&["--pg-pool-max-size=0", "--pg-pool-wait-timeout-secs=0"]);
+        let request = axum::http::Request::post("/token")
+            .header(CONTENT_TYPE, "application/json")
+            .header("X-Real-IP", "127.0.0.1")
+            .body(Body::from(r#"{"email":"user@example.com"}"#))
+            .unwrap();
+        let response = server.router().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], RETRY_AFTER_SECS);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "deadpool_pool_timeout");
+    }
+
+    #[tokio::test]
+    async fn test_tariff_not_found() {
+        let err = crate::ledger::Error::TariffNotFound("bogus".to_owned());
+        let response = Error::InfsrvPool(infsrv_pool::Error::Ledger(err)).into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["error"]["code"], "tariff_not_found");
     }
 }
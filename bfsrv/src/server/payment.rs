@@ -1,58 +1,139 @@
 use crate::{
+    config::PaymentLimit,
+    currency_converter::minor_unit_scale,
+    currency_format::CurrencyFormat,
     data::{
-        payment::{Payment, PaymentProcessor, PaymentStatus},
+        campaign::Campaign,
+        ledger_entry::LedgerEntry,
+        payment::{Payment, PaymentCursor, PaymentProcessor, PaymentStatus},
         user::User,
     },
-    server::{middleware::Auth, Error, Result, Server},
+    server::{middleware::Auth, Error, Result, Server, TX_RETRY_POLICY},
+    util::{http::accept_languages, retry::retry_on_serialization_failure},
 };
 use axum::{
     extract::{Json, Query, State},
+    http::{header::ACCEPT_LANGUAGE, HeaderMap},
     response::{IntoResponse, Response},
 };
 use axum_extra::extract::WithRejection;
-use deadpool_postgres::Client;
-use log::info;
+use deadpool_postgres::{Client, GenericClient};
+use log::{debug, info};
 use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde_json::json;
 use std::{sync::Arc, time::Duration};
 use time::{format_description::well_known::Rfc3339, OffsetDateTime};
-use tokio::time::interval;
-use tokio_postgres::{error::SqlState, IsolationLevel};
+use tokio_postgres::IsolationLevel;
 use uuid::Uuid;
 
+/// Default number of payments per page.
+const DEFAULT_PAGE_LIMIT: usize = 50;
+
+/// Maximum number of payments per page.
+const MAX_PAGE_LIMIT: usize = 500;
+
 /// Payment GET request query.
 #[derive(Deserialize)]
 pub struct PaymentQuery {
     id: Option<Uuid>,
+    reference: Option<String>,
+    limit: Option<usize>,
+    before: Option<String>,
+    formatted: Option<bool>,
 }
 
+/// Handle payment GET requests.
 pub async fn handle_payment_get(
     State(server): State<Arc<Server>>,
     auth: Auth,
+    headers: HeaderMap,
     WithRejection(Query(query), _): WithRejection<Query<PaymentQuery>, Error>,
 ) -> Result<Response> {
     let user = auth.user()?;
-    let client = server.pg_pool.get().await?;
 
-    let payments = if let Some(id) = query.id {
-        use Error::*;
-        let Some(payment) = Payment::get(&client, id).await? else {
-            return Err(PaymentNotFound);
-        };
-        if payment.from_user != user {
-            return Err(PaymentNotFound);
+    // Queries are validated before taking a connection.
+    use Error::*;
+    let (payments, next) = if let Some(id) = query.id {
+        if query.reference.is_some() {
+            return Err(BadRequest("both id and reference specified".to_owned()));
         }
-        vec![get_payment_item(server.as_ref(), &payment)]
+        let client = server.pg_pool.get().await?;
+        let payment = Payment::get(&client, id).await?;
+        (vec![check_payment_visible(payment, user, false)?], None)
+    } else if let Some(reference) = &query.reference {
+        let client = server.pg_pool.get().await?;
+        let payment = Payment::get_by_reference(&client, reference).await?;
+        // Admins can look up payments of other users for reconciliation.
+        let is_admin = match payment.as_ref() {
+            Some(p) if p.from_user != user => match auth.admin(&client).await {
+                Ok(_) => true,
+                Err(Forbidden(_)) => false,
+                Err(err) => return Err(err),
+            },
+            _ => false,
+        };
+        (vec![check_payment_visible(payment, user, is_admin)?], None)
     } else {
-        Payment::find_from_user(&client, user)
-            .await?
-            .iter()
-            .map(|p| get_payment_item(server.as_ref(), p))
-            .collect()
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        if limit == 0 || limit > MAX_PAGE_LIMIT {
+            return Err(BadRequest(format!(
+                "limit must be between 1 and {MAX_PAGE_LIMIT}"
+            )));
+        }
+        let before = query
+            .before
+            .map(|b| b.parse())
+            .transpose()
+            .map_err(|_| BadRequest("malformed cursor".to_owned()))?;
+
+        // Fetching one extra payment tells whether there is a next page.
+        let client = server.pg_pool.get().await?;
+        let mut payments = Payment::find_from_user(&client, user, before, limit as i64 + 1).await?;
+        let next = next_page_cursor(&mut payments, limit);
+        (payments, next)
     };
 
-    Ok(Json(json!({ "payments": payments })).into_response())
+    let languages = query.formatted.unwrap_or_default().then(|| {
+        headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map(accept_languages)
+            .unwrap_or_default()
+    });
+    let payments: Vec<_> = payments
+        .iter()
+        .map(|p| {
+            let mut item = get_payment_item(server.as_ref(), p);
+            if let Some(languages) = &languages {
+                item["formatted"] = get_formatted_amounts(p, languages);
+            }
+            item
+        })
+        .collect();
+    Ok(Json(json!({
+        "payments": payments,
+        "next": next.map(|c| c.to_string()),
+    }))
+    .into_response())
+}
+
+/// Check that a found payment can be seen by a given user.
+/// Payments of other users are reported as not found to avoid leaking them.
+fn check_payment_visible(payment: Option<Payment>, user: Uuid, is_admin: bool) -> Result<Payment> {
+    match payment {
+        Some(payment) if is_admin || payment.from_user == user => Ok(payment),
+        _ => Err(Error::PaymentNotFound),
+    }
+}
+
+/// Truncate payments to a page limit, returns a cursor of the next page if any.
+fn next_page_cursor(payments: &mut Vec<Payment>, limit: usize) -> Option<PaymentCursor> {
+    if payments.len() <= limit {
+        return None;
+    }
+    payments.truncate(limit);
+    payments.last().map(PaymentCursor::of)
 }
 
 fn get_payment_item(server: &Server, payment: &Payment) -> serde_json::Value {
@@ -75,6 +156,16 @@ fn get_payment_item(server: &Server, payment: &Payment) -> serde_json::Value {
     })
 }
 
+/// Amounts formatted for display, the raw ones are kept intact.
+fn get_formatted_amounts(payment: &Payment, languages: &[String]) -> serde_json::Value {
+    let format = CurrencyFormat::new(&payment.currency, languages);
+    let mut json = json!({ "grossAmount": format.format(payment.gross_amount) });
+    if let Some(net_amount) = payment.net_amount {
+        json["netAmount"] = json!(format.format(net_amount));
+    }
+    json
+}
+
 /// Body payload for PATCH-request.
 #[derive(Deserialize)]
 pub struct PatchRequestPayload {
@@ -106,52 +197,73 @@ pub async fn handle_payment_patch(
     let complete = payload.complete.unwrap_or_default();
     match payment.processor {
         PaymentProcessor::Paypal => {
-            server.paypal.update_payment(&mut payment).await?;
-            payment.update(&client).await?;
-            if complete {
-                server.paypal.complete_payment(&mut payment).await?;
+            let result = server.paypal.update_payment(&mut payment).await;
+            persist_on_error(&client, &payment, result).await?;
+            update_payment_status(&client, &payment).await?;
+            if complete && !matches!(payment.status, PaymentStatus::Completed) {
+                let result = server.paypal.complete_payment(&mut payment).await;
+                persist_on_error(&client, &payment, result).await?;
             }
         }
     }
 
     if complete {
-        let net_amount = payment.net_amount.ok_or_else(|| {
-            Internal(format!(
-                "failed to get net_amount for payment {}",
-                payment.id
-            ))
-        })?;
-
-        let Some(amount) = server
-            .currency_converter
-            .convert(&payment.currency, net_amount)
-            .await?
-        else {
-            return Err(Internal(format!(
-                "failed to convert currency for payment {}",
-                payment.id
-            )));
-        };
-
-        let mut interval = interval(Duration::from_millis(10));
-        let mut remains = 100;
+        top_up_balance(&server, &mut client, &payment).await?;
+    }
 
-        loop {
-            interval.tick().await;
+    Ok(Json(json!({})).into_response())
+}
 
-            let result = try_top_up_balance_atomically(&mut client, &payment, amount).await;
-            if !is_serialization_failure(&result) {
-                break result;
-            }
+/// Persist a payment status reported by processor. A completed status is
+/// only persisted along with the balance top-up to avoid losing the credit.
+pub async fn update_payment_status(client: &impl GenericClient, payment: &Payment) -> Result<()> {
+    if !matches!(payment.status, PaymentStatus::Completed) {
+        payment.update(client).await?;
+    }
+    Ok(())
+}
 
-            remains -= 1;
-            if remains == 0 {
-                break result;
-            }
-        }?;
+/// Persist payment details reported by processor along with its error.
+pub async fn persist_on_error<T>(
+    client: &impl GenericClient,
+    payment: &Payment,
+    result: crate::paypal::Result<T>,
+) -> Result<T> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            update_payment_status(client, payment).await?;
+            Err(err.into())
+        }
     }
+}
 
-    Ok(Json(json!({})).into_response())
+/// Credit the net amount of a completed payment to the recipient balance.
+pub async fn top_up_balance(server: &Server, client: &mut Client, payment: &Payment) -> Result<()> {
+    use Error::*;
+    let net_amount = payment.net_amount.ok_or_else(|| {
+        Internal(format!(
+            "failed to get net_amount for payment {}",
+            payment.id
+        ))
+    })?;
+
+    let Some(amount) = server
+        .currency_converter
+        .convert(&payment.currency, net_amount)
+        .await?
+    else {
+        return Err(Internal(format!(
+            "failed to convert currency for payment {}",
+            payment.id
+        )));
+    };
+
+    retry_on_serialization_failure(TX_RETRY_POLICY, client, |client| async move {
+        let result = try_top_up_balance_atomically(client, payment, amount).await;
+        (client, result)
+    })
+    .await
 }
 
 async fn try_top_up_balance_atomically(
@@ -170,7 +282,8 @@ async fn try_top_up_balance_atomically(
         .await?
         .ok_or_else(|| Internal(format!("failed to get payment {}", payment.id)))?
         .status;
-    if !matches!(status, PaymentStatus::Approved) {
+    // A new payment can be already captured if its approval wasn't persisted.
+    if !matches!(status, PaymentStatus::New | PaymentStatus::Approved) {
         return Err(BadPaymentStatus);
     }
 
@@ -181,8 +294,18 @@ async fn try_top_up_balance_atomically(
         )));
     };
 
+    // A unique entry per payment makes the credit once-only regardless of status.
+    let mut entry = LedgerEntry::new(user.id, payment.id, amount);
+    if !entry.insert(&tx).await? {
+        debug!("payment {} is already credited", payment.id);
+        return Err(BadPaymentStatus);
+    }
+
     payment.update(&tx).await?;
     user.balance += amount;
+    if !user.referral_bonus_paid {
+        user.referral_bonus_paid = credit_referral_bonus(&tx, &user, amount).await?;
+    }
     user.update(&tx).await?;
     tx.commit().await?;
 
@@ -190,12 +313,38 @@ async fn try_top_up_balance_atomically(
     Ok(())
 }
 
-#[inline]
-fn is_serialization_failure<T>(error: &Result<T>) -> bool {
-    const SQL_STATE: Option<&SqlState> = Some(&SqlState::T_R_SERIALIZATION_FAILURE);
+/// Credit a referrer of a given user with the campaign referral bonus.
+/// Returns false if there is no referrer to credit.
+async fn credit_referral_bonus(
+    client: &impl GenericClient,
+    user: &User,
+    amount: Decimal,
+) -> Result<bool> {
+    let Some(referrer_id) = user.referrer else {
+        return Ok(false);
+    };
+
+    let Some(mut referrer) = User::get(client, referrer_id).await? else {
+        debug!("referrer {referrer_id} of user {} not found", user.id);
+        return Ok(false);
+    };
+
     use Error::*;
-    matches!(error, Err(Data(crate::data::Error::Postgres(e))) if e.code() == SQL_STATE)
-        || matches!(error, Err(Postgres(e)) if e.code() == SQL_STATE)
+    let Some(campaign) = Campaign::get(client, user.campaign).await? else {
+        return Err(Internal(format!(
+            "failed to get campaign for user {}",
+            user.id
+        )));
+    };
+
+    let bonus = campaign.referral_bonus(amount);
+    if bonus > Decimal::ZERO {
+        referrer.balance += bonus;
+        referrer.update(client).await?;
+        info!("credited referrer {referrer_id} with bonus {bonus}");
+    }
+
+    Ok(true)
 }
 
 /// Body payload for POST-request.
@@ -213,13 +362,24 @@ pub struct PostRequestPayload {
 pub async fn handle_payment_post(
     State(server): State<Arc<Server>>,
     auth: Auth,
+    headers: HeaderMap,
     WithRejection(Json(payload), _): WithRejection<Json<PostRequestPayload>, Error>,
 ) -> Result<Response> {
     let user = auth.user()?;
+    let currency = normalize_currency(&server, payload.processor, &payload.currency)?;
+    validate_payment_amount(
+        &server.config.payment_limits,
+        &currency,
+        payload.gross_amount,
+    )?;
+
     let client = server.pg_pool.get().await?;
+    auth.non_trial(&client).await?;
 
-    let payments = Payment::find_from_user(&client, user).await?;
-    if let Some(created_at) = payments.first().map(|p| p.created_at) {
+    if let Some(created_at) = Payment::find_last_active_from_user(&client, user)
+        .await?
+        .map(|p| p.created_at)
+    {
         if created_at > OffsetDateTime::now_utc() - Duration::from_secs(3600) {
             return Err(Error::BadRequest(
                 "too frequent payment requests".to_owned(),
@@ -229,14 +389,21 @@ pub async fn handle_payment_post(
 
     let mut payment = match payload.processor {
         PaymentProcessor::Paypal => {
+            // Unsupported Accept-Language values fall back to the default locale.
+            let locale = payload.locale.as_deref().or_else(|| {
+                headers
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| server.paypal.locale_from_accept_language(v))
+            });
             server
                 .paypal
                 .create_payment(
-                    payload.currency,
+                    currency,
                     payload.gross_amount,
                     user,
                     payload.to_user.unwrap_or(user),
-                    payload.locale.as_deref(),
+                    locale,
                 )
                 .await?
         }
@@ -251,3 +418,280 @@ pub async fn handle_payment_post(
     let item = get_payment_item(server.as_ref(), &payment);
     Ok(Json(json!({ "payment": item })).into_response())
 }
+
+/// Uppercase a currency code checking it's accepted by a given processor.
+fn normalize_currency(
+    server: &Server,
+    processor: PaymentProcessor,
+    currency: &str,
+) -> Result<String> {
+    let currency = currency.trim().to_ascii_uppercase();
+    let supported = match processor {
+        PaymentProcessor::Paypal => server.paypal.is_supported_currency(&currency),
+    };
+    if !supported {
+        return Err(Error::BadRequest(format!(
+            "unsupported payment currency {currency}"
+        )));
+    }
+    Ok(currency)
+}
+
+/// Check that a payment amount is positive, has no more decimal places
+/// than the currency minor units and lies within the configured limits
+/// (if any are configured).
+fn validate_payment_amount(limits: &[PaymentLimit], currency: &str, amount: Decimal) -> Result<()> {
+    use Error::*;
+    if amount <= Decimal::ZERO {
+        return Err(BadRequest("payment amount must be positive".to_owned()));
+    }
+
+    let scale = minor_unit_scale(currency);
+    if amount.normalize().scale() > scale {
+        return Err(BadRequest(format!(
+            "payment amount must have at most {scale} decimal places for {currency}"
+        )));
+    }
+
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let Some(limit) = limits
+        .iter()
+        .find(|l| l.currency == currency)
+        .or_else(|| limits.iter().find(|l| l.currency == "*"))
+    else {
+        return Err(BadRequest(format!(
+            "unsupported payment currency {currency}"
+        )));
+    };
+
+    if amount < limit.min {
+        return Err(BadRequest(format!(
+            "payment amount must be at least {} {currency}",
+            limit.min
+        )));
+    }
+    if amount > limit.max {
+        return Err(BadRequest(format!(
+            "payment amount must be at most {} {currency}",
+            limit.max
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::new_test_server;
+    use std::str::FromStr;
+
+    fn limits() -> Vec<PaymentLimit> {
+        ["*:1:1000", "JPY:100:150000"]
+            .into_iter()
+            .map(|s| PaymentLimit::from_str(s).unwrap())
+            .collect()
+    }
+
+    fn validate(currency: &str, amount: &str) -> Result<()> {
+        validate_payment_amount(&limits(), currency, Decimal::from_str(amount).unwrap())
+    }
+
+    #[test]
+    fn test_validate_payment_amount() {
+        assert!(validate("USD", "1").is_ok());
+        assert!(validate("USD", "1000.00").is_ok());
+        assert!(validate("USD", "9.99").is_ok());
+        assert!(validate("JPY", "100").is_ok());
+        assert!(validate("JPY", "150000").is_ok());
+
+        let message = |r: Result<()>| match r {
+            Err(Error::BadRequest(m)) => m,
+            _ => panic!("expected bad request"),
+        };
+        assert_eq!(
+            message(validate("USD", "0.99")),
+            "payment amount must be at least 1 USD"
+        );
+        assert_eq!(
+            message(validate("USD", "1000.01")),
+            "payment amount must be at most 1000 USD"
+        );
+        assert_eq!(
+            message(validate("JPY", "99")),
+            "payment amount must be at least 100 JPY"
+        );
+        assert_eq!(
+            message(validate("USD", "-5")),
+            "payment amount must be positive"
+        );
+        assert_eq!(
+            message(validate("USD", "0")),
+            "payment amount must be positive"
+        );
+        assert_eq!(
+            message(validate("USD", "1.005")),
+            "payment amount must have at most 2 decimal places for USD"
+        );
+        assert_eq!(
+            message(validate("JPY", "100.5")),
+            "payment amount must have at most 0 decimal places for JPY"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_normalize_currency() {
+        let server = new_test_server();
+        let normalize = |currency| normalize_currency(&server, PaymentProcessor::Paypal, currency);
+        assert_eq!(normalize("USD").unwrap(), "USD");
+        assert_eq!(normalize("usd").unwrap(), "USD");
+        assert_eq!(normalize(" eUr ").unwrap(), "EUR");
+        assert!(matches!(
+            normalize("xyz"),
+            Err(Error::BadRequest(m)) if m == "unsupported payment currency XYZ"
+        ));
+        assert!(normalize("").is_err());
+        assert!(normalize("US D").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_validate_payment_amount_without_limits() {
+        assert!(new_test_server().config.payment_limits.is_empty());
+        let validate = |currency, amount| {
+            validate_payment_amount(&[], currency, Decimal::from_str(amount).unwrap())
+        };
+        assert!(validate("USD", "0.01").is_ok());
+        assert!(validate("JPY", "1000000").is_ok());
+        assert!(validate("USD", "0").is_err());
+        assert!(validate("JPY", "100.5").is_err());
+    }
+
+    #[test]
+    fn test_validate_payment_amount_without_wildcard() {
+        let limits = vec![PaymentLimit::from_str("USD:1:10").unwrap()];
+        assert!(validate_payment_amount(&limits, "EUR", Decimal::ONE).is_err());
+    }
+
+    #[test]
+    fn test_payment_limit_from_str() {
+        assert_eq!(
+            PaymentLimit::from_str("EUR:0.5:20").unwrap(),
+            PaymentLimit {
+                currency: "EUR".to_owned(),
+                min: Decimal::from_str("0.5").unwrap(),
+                max: Decimal::from(20),
+            }
+        );
+        assert!(PaymentLimit::from_str("EUR:1").is_err());
+        assert!(PaymentLimit::from_str("EUR:1:2:3").is_err());
+        assert!(PaymentLimit::from_str("EUR:2:1").is_err());
+        assert!(PaymentLimit::from_str("EUR:-1:1").is_err());
+        assert!(PaymentLimit::from_str(":1:2").is_err());
+    }
+
+    #[test]
+    fn test_check_payment_visible() {
+        let (owner, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let payment = || {
+            Some(Payment::new(
+                "USD".to_owned(),
+                Decimal::ONE,
+                owner,
+                owner,
+                PaymentProcessor::Paypal,
+                "REF".to_owned(),
+            ))
+        };
+
+        assert!(check_payment_visible(payment(), owner, false).is_ok());
+        assert!(check_payment_visible(payment(), other, true).is_ok());
+        assert!(matches!(
+            check_payment_visible(payment(), other, false),
+            Err(Error::PaymentNotFound)
+        ));
+        assert!(matches!(
+            check_payment_visible(None, owner, true),
+            Err(Error::PaymentNotFound)
+        ));
+    }
+
+    #[test]
+    fn test_get_formatted_amounts() {
+        let mut payment = Payment::new(
+            "EUR".to_owned(),
+            Decimal::new(123456789, 5),
+            Uuid::nil(),
+            Uuid::nil(),
+            PaymentProcessor::Paypal,
+            String::new(),
+        );
+        let languages = ["de-DE".to_owned()];
+        assert_eq!(
+            get_formatted_amounts(&payment, &languages),
+            json!({ "grossAmount": "1.234,57\u{a0}€" })
+        );
+
+        payment.net_amount = Some(Decimal::new(119999, 2));
+        assert_eq!(
+            get_formatted_amounts(&payment, &[]),
+            json!({ "grossAmount": "€1,234.57", "netAmount": "€1,199.99" })
+        );
+        assert_eq!(payment.gross_amount, Decimal::new(123456789, 5));
+    }
+
+    #[test]
+    fn test_next_page_cursor() {
+        // Pairs of payments share the same created_at.
+        let payment = |i: u128| {
+            let mut payment = Payment::new(
+                "USD".to_owned(),
+                Decimal::ONE,
+                Uuid::nil(),
+                Uuid::nil(),
+                PaymentProcessor::Paypal,
+                String::new(),
+            );
+            payment.id = Uuid::from_u128(i);
+            payment.created_at = OffsetDateTime::UNIX_EPOCH + Duration::from_secs((i / 2) as u64);
+            payment
+        };
+        let cursor = |i| PaymentCursor::of(&payment(i));
+
+        // Mimics Payment::find_from_user for payments 0..10.
+        let find = |before: Option<PaymentCursor>, limit| -> Vec<_> {
+            (0..10)
+                .rev()
+                .filter(|&i| {
+                    before
+                        .is_none_or(|b| (cursor(i).created_at, cursor(i).id) < (b.created_at, b.id))
+                })
+                .take(limit)
+                .map(payment)
+                .collect()
+        };
+
+        let mut ids = Vec::new();
+        let mut before = None;
+        let mut pages = 0;
+        loop {
+            let mut page = find(before, 4);
+            let next = next_page_cursor(&mut page, 3);
+            assert!(page.len() <= 3);
+            ids.extend(page.iter().map(|p| p.id.as_u128()));
+            pages += 1;
+
+            let Some(next) = next else {
+                break;
+            };
+            assert_eq!(next, PaymentCursor::of(page.last().unwrap()));
+            before = Some(next.to_string().parse().unwrap());
+        }
+
+        assert_eq!(pages, 4);
+        assert_eq!(ids, (0..10).rev().collect::<Vec<_>>());
+        assert_eq!(next_page_cursor(&mut find(None, 10), 10), None);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{
+    data::payment::{Payment, PaymentProcessor, PaymentStatus},
+    server::{
+        payment::{persist_on_error, top_up_balance, update_payment_status},
+        Result, Server,
+    },
+    util::{fmt::ErrorChainDisplay, periodic::PeriodicTask},
+};
+use deadpool_postgres::Client;
+use log::{error, info};
+use std::{sync::Arc, time::Duration};
+use time::OffsetDateTime;
+use tokio::time::Instant;
+
+/// Background poller which settles payments approved by users
+/// but never completed through payment PATCH requests,
+/// and cancels new payments abandoned by users.
+pub struct PaymentPoller {
+    _task: PeriodicTask,
+}
+
+impl PaymentPoller {
+    /// Create a new PaymentPoller instance.
+    pub fn new(server: Arc<Server>) -> Self {
+        let period = Duration::from_secs(server.config.payment_poll_interval_secs);
+        let task = PeriodicTask::spawn(
+            "poll payments",
+            Instant::now() + period,
+            period,
+            move || {
+                let server = server.clone();
+                async move { poll_payments(&server).await }
+            },
+        );
+        Self { _task: task }
+    }
+}
+
+async fn poll_payments(server: &Server) -> Result<()> {
+    let mut client = server.pg_pool.get().await?;
+
+    let min_age = Duration::from_secs(server.config.payment_poll_min_age_secs);
+    let before = OffsetDateTime::now_utc() - min_age;
+    let payments = Payment::find_pending_created_before(&client, before).await?;
+
+    for mut payment in payments {
+        let id = payment.id;
+        if let Err(err) = settle_payment(server, &mut client, &mut payment).await {
+            error!("failed to settle payment {id}: {}", ErrorChainDisplay(&err));
+        }
+    }
+
+    // Stale payments are canceled after settling, so the ones approved
+    // in the meantime get completed rather than canceled.
+    let ttl = Duration::from_secs(server.config.payment_new_ttl_secs);
+    let ids: Vec<_> = Payment::find_stale(&client, ttl)
+        .await?
+        .iter()
+        .map(|p| p.id)
+        .collect();
+    if !ids.is_empty() {
+        use PaymentStatus::*;
+        let canceled = Payment::update_statuses(&client, &ids, New, Canceled).await?;
+        info!("canceled {canceled} stale payments");
+    }
+
+    Ok(())
+}
+
+async fn settle_payment(server: &Server, client: &mut Client, payment: &mut Payment) -> Result<()> {
+    match payment.processor {
+        PaymentProcessor::Paypal => {
+            let result = server.paypal.update_payment(payment).await;
+            persist_on_error(&*client, payment, result).await?;
+            update_payment_status(&*client, payment).await?;
+            if matches!(payment.status, PaymentStatus::Approved) {
+                let result = server.paypal.complete_payment(payment).await;
+                persist_on_error(&*client, payment, result).await?;
+            }
+        }
+    }
+
+    if matches!(payment.status, PaymentStatus::Completed) {
+        // The top-up fails for payments already completed by PATCH requests.
+        top_up_balance(server, client, payment).await?;
+        info!("settled payment {} by polling", payment.id);
+    }
+
+    Ok(())
+}
@@ -1,15 +1,18 @@
 use crate::{
     data::token::Token,
     server::{middleware::Auth, Error, Result, Server},
+    util::http::accept_languages,
 };
 use axum::{
     extract::State,
-    http::HeaderMap,
+    http::{header::ACCEPT_LANGUAGE, HeaderMap},
     response::{IntoResponse, Response},
     Json,
 };
 use axum_extra::extract::WithRejection;
+use deadpool_postgres::GenericClient;
 use lettre::Address as EmailAddress;
+use log::info;
 use serde::Deserialize;
 use serde_json::{json, Map};
 use std::{sync::Arc, time::Duration};
@@ -22,6 +25,7 @@ use super::middleware::RealIpAddress;
 #[serde(rename_all = "camelCase")]
 pub struct PostRequestPayload {
     expires_at: Option<OffsetDateTime>,
+    never_expires: Option<bool>,
     label: Option<String>,
     is_admin: Option<bool>,
     email: Option<EmailAddress>,
@@ -34,39 +38,92 @@ pub async fn handle_token_post(
     RealIpAddress(ip_address): RealIpAddress,
     WithRejection(Json(payload), _): WithRejection<Json<PostRequestPayload>, Error>,
 ) -> Result<Response> {
+    let auth = match Auth::create(&server, &headers).await {
+        Ok(auth) => Some(auth),
+        Err(Error::Unauthorized(_)) if payload.email.is_some() => None,
+        Err(err) => return Err(err),
+    };
+
+    // Admin tokens are minted only by admin tokens of admin users
+    // (other tokens are rejected without taking a connection).
+    let is_admin = payload.is_admin.unwrap_or_default();
+    if is_admin && !auth.as_ref().is_some_and(|a| a.token.is_admin) {
+        return Err(Error::Forbidden("admin privileges required".to_owned()));
+    }
+
     let mut client = server.pg_pool.get().await?;
-    if let Some(token) = Token::find_last_with_ip_address(&client, ip_address).await? {
-        if token.created_at > OffsetDateTime::now_utc() - Duration::from_secs(3600) {
-            return Err(Error::BadRequest("too frequent token requests".to_owned()));
+    let by_admin = is_admin_auth(auth.as_ref(), &client).await?;
+    if is_admin && !by_admin {
+        return Err(Error::Forbidden("admin privileges required".to_owned()));
+    }
+
+    // Admins provisioning tokens are not throttled.
+    if !by_admin {
+        if let Some(token) = Token::find_last_with_ip_address(&client, ip_address).await? {
+            if token.created_at > OffsetDateTime::now_utc() - Duration::from_secs(3600) {
+                return Err(Error::BadRequest("too frequent token requests".to_owned()));
+            }
+        }
+    }
+    // Trial tokens are short-lived, so they must not mint other tokens
+    // except for email confirmation ones (to upgrade to a full account).
+    if let Some(auth) = auth.as_ref().filter(|a| a.token.user.is_some()) {
+        if payload.email.is_none() || is_admin {
+            auth.non_trial(&client).await?;
         }
     }
 
-    let user = match Auth::create(&server.pg_pool, &headers).await {
-        Ok(auth) => auth.token.user,
-        Err(Error::Unauthorized(_)) if payload.email.is_some() => None,
-        Err(err) => return Err(err),
+    let expires_at = if payload.never_expires.unwrap_or_default() {
+        if payload.expires_at.is_some() {
+            return Err(Error::BadRequest(
+                "both expiry and never-expiring requested".to_owned(),
+            ));
+        }
+        let Some(auth) = &auth else {
+            return Err(Error::Forbidden("admin privileges required".to_owned()));
+        };
+        auth.admin(&client).await?;
+        OffsetDateTime::new_utc(Date::MAX, Time::MIDNIGHT)
+    } else {
+        let config = &server.config;
+        let max_ttl = if is_admin {
+            config.admin_token_max_ttl_secs
+        } else {
+            config.token_max_ttl_secs
+        };
+        resolve_expires_at(
+            OffsetDateTime::now_utc(),
+            payload.expires_at,
+            Duration::from_secs(config.token_default_ttl_secs),
+            Duration::from_secs(max_ttl),
+        )?
     };
 
-    let never = OffsetDateTime::new_utc(Date::MAX, Time::MIDNIGHT);
-    let expires_at = payload.expires_at.unwrap_or(never);
-
     let mut token = Token::new(
         expires_at,
         payload.label,
-        user,
-        payload.is_admin.unwrap_or_default(),
+        auth.and_then(|a| a.token.user),
+        is_admin,
         ip_address,
         payload.email,
     );
 
     let tx = client.build_transaction().start().await?;
 
-    let key = token.insert(&tx).await?;
+    let key = token.insert(&tx, server.config.bcrypt_work_factor).await?;
     let access_token = Auth::compose_access_token(token.id, key);
 
     let mut response = Map::new();
     if let Some(email) = token.email {
-        server.mailer.send_token(email, &access_token).await?;
+        let languages = headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map(accept_languages)
+            .unwrap_or_default();
+        server
+            .mailer
+            .send_token(&tx, email, &access_token, &languages)
+            .await?;
     } else {
         response["id"] = json!(token.id);
         response["token"] = json!(access_token);
@@ -76,3 +133,216 @@ pub async fn handle_token_post(
 
     Ok(Json(response).into_response())
 }
+
+/// Token privilege granted on creation.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    Admin,
+}
+
+/// Item of tokens POST-request body payload.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PostTokensItem {
+    label: Option<String>,
+    expires_at: Option<OffsetDateTime>,
+    #[serde(default)]
+    scopes: Vec<TokenScope>,
+}
+
+/// Handle tokens POST requests creating a batch of tokens for an admin user.
+pub async fn handle_tokens_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    RealIpAddress(ip_address): RealIpAddress,
+    WithRejection(Json(items), _): WithRejection<Json<Vec<PostTokensItem>>, Error>,
+) -> Result<Response> {
+    let mut client = server.pg_pool.get().await?;
+    let user = auth.admin(&client).await?;
+    check_token_batch_size(items.len(), server.config.max_token_batch_size)?;
+
+    // Expiries are resolved upfront not to start a transaction for a malformed batch.
+    let config = &server.config;
+    let now = OffsetDateTime::now_utc();
+    let mut tokens = Vec::with_capacity(items.len());
+    for item in items {
+        let is_admin = item.scopes.contains(&TokenScope::Admin);
+        let max_ttl = if is_admin {
+            config.admin_token_max_ttl_secs
+        } else {
+            config.token_max_ttl_secs
+        };
+        let expires_at = resolve_expires_at(
+            now,
+            item.expires_at,
+            Duration::from_secs(config.token_default_ttl_secs),
+            Duration::from_secs(max_ttl),
+        )?;
+        tokens.push(Token::new(
+            expires_at,
+            item.label,
+            Some(user),
+            is_admin,
+            ip_address,
+            None,
+        ));
+    }
+
+    let tx = client.build_transaction().start().await?;
+
+    let mut response = Vec::with_capacity(tokens.len());
+    for token in &mut tokens {
+        let key = token.insert(&tx, config.bcrypt_work_factor).await?;
+        response.push(json!({
+            "id": token.id,
+            "token": Auth::compose_access_token(token.id, key),
+        }));
+    }
+
+    tx.commit().await?;
+
+    info!("created {} tokens", tokens.len());
+    Ok(Json(json!({ "tokens": response })).into_response())
+}
+
+/// Check if a caller is authenticated with an admin token of an admin user.
+async fn is_admin_auth(auth: Option<&Auth>, client: &impl GenericClient) -> Result<bool> {
+    let Some(auth) = auth.filter(|a| a.token.is_admin) else {
+        return Ok(false);
+    };
+    match auth.admin(client).await {
+        Ok(_) => Ok(true),
+        Err(Error::Forbidden(_) | Error::Unauthorized(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Check that a token batch is neither empty nor exceeds a given size.
+fn check_token_batch_size(size: usize, max_size: usize) -> Result<()> {
+    use Error::*;
+    if size == 0 {
+        return Err(BadRequest("no tokens requested".to_owned()));
+    }
+    if size > max_size {
+        return Err(BadRequest(format!(
+            "too many tokens requested (max {max_size})"
+        )));
+    }
+    Ok(())
+}
+
+/// Resolve a token expiry applying a default TTL and capping it with a maximum one.
+fn resolve_expires_at(
+    now: OffsetDateTime,
+    requested: Option<OffsetDateTime>,
+    default_ttl: Duration,
+    max_ttl: Duration,
+) -> Result<OffsetDateTime> {
+    let max = now.saturating_add(max_ttl.try_into().unwrap_or(time::Duration::MAX));
+    let Some(requested) = requested else {
+        let default = now.saturating_add(default_ttl.try_into().unwrap_or(time::Duration::MAX));
+        return Ok(default.min(max));
+    };
+
+    use Error::*;
+    if requested <= now {
+        return Err(BadRequest("expiry in the past".to_owned()));
+    }
+    if requested > max {
+        return Err(BadRequest("expiry exceeds maximum token TTL".to_owned()));
+    }
+    Ok(requested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::{
+        cache_test_token, new_test_server, new_test_server_with_args, send_request,
+    };
+    use axum::{body::Body, http::StatusCode};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_check_token_batch_size() {
+        assert!(check_token_batch_size(1, 100).is_ok());
+        assert!(check_token_batch_size(100, 100).is_ok());
+        assert!(matches!(
+            check_token_batch_size(0, 100),
+            Err(Error::BadRequest(_))
+        ));
+        assert_eq!(
+            check_token_batch_size(101, 100).unwrap_err().to_string(),
+            "bad request (too many tokens requested (max 100))"
+        );
+    }
+
+    #[test]
+    fn test_post_tokens_item() {
+        let item: PostTokensItem =
+            serde_json::from_str(r#"{"label":"ci","scopes":["admin"]}"#).unwrap();
+        assert_eq!(item.label.as_deref(), Some("ci"));
+        assert_eq!(item.scopes, [TokenScope::Admin]);
+
+        let item: PostTokensItem = serde_json::from_str("{}").unwrap();
+        assert!(item.scopes.is_empty());
+        assert!(serde_json::from_str::<PostTokensItem>(r#"{"scopes":["root"]}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handle_tokens_post_unauthorized() {
+        let request = axum::http::Request::post("/tokens")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"[{"label":"ci"}]"#))
+            .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn test_handle_token_post_admin_forbidden() {
+        let server = new_test_server_with_args(&["--auth-cache-ttl-secs=60"]);
+        let access_token = cache_test_token(&server, Uuid::new_v4(), false);
+
+        let request = axum::http::Request::post("/token")
+            .header("Authorization", format!("Bearer {access_token}"))
+            .header("Content-Type", "application/json")
+            .header("X-Real-IP", "127.0.0.1")
+            .body(Body::from(r#"{"isAdmin":true}"#))
+            .unwrap();
+        let (status, json) = send_request(server, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(json["error"]["code"], "forbidden");
+    }
+
+    #[test]
+    fn test_resolve_expires_at() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+        let day = Duration::from_secs(86400);
+        let resolve = |requested| resolve_expires_at(now, requested, 30 * day, 365 * day);
+
+        assert_eq!(resolve(None).unwrap(), now + 30 * day);
+        assert_eq!(
+            resolve_expires_at(now, None, 30 * day, 7 * day).unwrap(),
+            now + 7 * day
+        );
+        assert_eq!(resolve(Some(now + day)).unwrap(), now + day);
+        assert_eq!(resolve(Some(now + 365 * day)).unwrap(), now + 365 * day);
+        assert!(matches!(
+            resolve(Some(now + 366 * day)),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(resolve(Some(now)), Err(Error::BadRequest(_))));
+        assert!(matches!(
+            resolve(Some(now - day)),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(matches!(
+            resolve(Some(OffsetDateTime::new_utc(Date::MAX, Time::MIDNIGHT))),
+            Err(Error::BadRequest(_))
+        ));
+        assert!(resolve_expires_at(now, None, Duration::MAX, Duration::MAX).is_ok());
+    }
+}
@@ -1,63 +1,205 @@
 use crate::{
-    data::capability::{Capability, TaskType},
+    audio::{
+        decode_lpcm_to_pcm16, decode_ogg_to_pcm16, encode_wav, resample_pcm16, trim_silence,
+        Error as AudioError, PcmChunk,
+    },
+    audio_store::AudioCapture,
+    data::{
+        capability::{Capability, TaskType},
+        session::{SegmentStats, Session as SessionRecord},
+        transcript::Transcript,
+        user::User,
+    },
     infsrv_pool::{
-        Result as InfsrvResult, SegmentItem, MAX_SEGMENT_DURATION, SAMPLE_RATE, TERMINATOR_HEADER,
+        is_overload_status, Error as InfsrvError, Result as InfsrvResult, SegmentItem,
+        SegmentParams, TranscribeOptions, VadSensitivity, SAMPLE_RATE, TERMINATOR_HEADER,
+    },
+    server::{
+        callback::{send_callback, validate_callback_url},
+        middleware::{AllowedWsOrigin, Auth},
+        Error, Result, Server,
+    },
+    util::{
+        fmt::{ErrorChainDisplay, TruncateDebug},
+        text::{sanitize_prompt, Casing, TextNormalization},
     },
-    server::{middleware::Auth, Error, Result, Server},
-    util::fmt::{ErrorChainDisplay, TruncateDebug},
 };
 use axum::{
+    body::{Body, Bytes},
     extract::{
-        ws::{CloseFrame, Message, WebSocket},
-        Query, State, WebSocketUpgrade,
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        Multipart, Query, State, WebSocketUpgrade,
+    },
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, HeaderValue,
     },
-    http::{header::CONTENT_TYPE, HeaderMap, HeaderValue},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
+    Json,
 };
 use axum_extra::extract::WithRejection;
 use futures::{
-    channel::mpsc::channel,
-    stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt, TryStreamExt,
+    channel::mpsc::channel, executor::block_on, future::BoxFuture, stream::FuturesOrdered,
+    AsyncRead, Sink, SinkExt, Stream, StreamExt, TryStreamExt,
 };
-use hound::{SampleFormat, WavSpec, WavWriter};
 use log::{debug, error, info};
-use ogg::reading::async_api::PacketReader;
-use rubato::{FastFixedIn, PolynomialDegree, Resampler};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::{
     collections::VecDeque,
-    io::{Cursor, Error as IoError, ErrorKind as IoErrorKind},
-    mem::swap,
-    sync::{Arc, Mutex},
-    time::Duration,
-};
-use symphonia::{
-    core::{
-        audio::{AudioBuffer, AudioBufferRef, Signal},
-        codecs::{CodecParameters, Decoder, DecoderOptions, CODEC_TYPE_VORBIS},
-        formats::Packet as SymphoniaPacket,
+    convert::Infallible,
+    future::Future,
+    io::Error as IoError,
+    net::IpAddr,
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
     },
-    default::codecs::VorbisDecoder,
+    time::Duration,
 };
+use time::OffsetDateTime;
 use tokio::{
-    io::AsyncRead,
-    sync::mpsc::{unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
-    task::JoinHandle,
-    time::interval,
+    sync::{
+        mpsc::{unbounded_channel, Receiver, Sender, UnboundedReceiver, UnboundedSender},
+        watch, Notify,
+    },
+    task::{spawn_blocking, JoinHandle},
+    time::{interval, interval_at, timeout, timeout_at, Instant, MissedTickBehavior},
 };
+use tokio_tungstenite::tungstenite::Error as TungsteniteError;
+use url::Url;
 use uuid::Uuid;
 
-const VORBIS_CONTENT_TYPE: &str = "audio/ogg; codecs=vorbis";
+/// Content type of transcription progress event streams.
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+/// Share of file transcription progress taken by segmentation
+/// (which is much faster than transcription of speech intervals).
+const SEGMENTATION_PROGRESS_SHARE: f32 = 0.1;
+
+/// Input audio stream codec.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// Raw PCM of a format declared by query parameters.
+    Lpcm,
+    Vorbis,
+}
 
-/// Ring buffer time capacity for keeping last audio segment.
-const RING_BUFFER_CAPACITY: usize = (2.0 * MAX_SEGMENT_DURATION * SAMPLE_RATE) as usize;
+impl Codec {
+    /// Codecs supported for transcribe input.
+    const SUPPORTED: &'static [Codec] = &[Codec::Lpcm, Codec::Vorbis];
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Codec::Lpcm => "audio/lpcm",
+            Codec::Vorbis => "audio/ogg; codecs=vorbis",
+        }
+    }
+
+    fn from_content_type(value: &HeaderValue) -> Option<Self> {
+        Self::SUPPORTED
+            .iter()
+            .copied()
+            .find(|c| value == c.content_type())
+    }
+}
+
+/// Sample format of raw PCM input.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SampleFormat {
+    S16le,
+}
 
 /// Transcribe request query.
 #[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
 pub struct TranscribeQuery {
-    pub tariff: String,
+    pub tariff: Option<String>,
     pub lang: Option<String>,
+    pub callback_url: Option<Url>,
+    pub codec: Option<Codec>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub sample_format: Option<SampleFormat>,
+    #[serde(default)]
+    pub diarize: bool,
+    pub punctuation: Option<bool>,
+    pub casing: Option<Casing>,
+    pub vad: Option<VadSensitivity>,
+}
+
+impl TranscribeQuery {
+    /// Requested post-processing of transcribed text.
+    pub fn normalization(&self) -> TextNormalization {
+        TextNormalization {
+            punctuation: self.punctuation.unwrap_or(true),
+            casing: self.casing.unwrap_or_default(),
+        }
+    }
+}
+
+/// Determine an input codec from a Content-Type header and a codec query parameter
+/// (for clients unable to set headers), which must agree if both are given.
+fn resolve_codec(content_type: Option<&HeaderValue>, codec: Option<Codec>) -> Result<Codec> {
+    use Error::*;
+    match (content_type, codec) {
+        (Some(value), codec) => {
+            let Some(header_codec) = Codec::from_content_type(value) else {
+                return Err(BadRequest("unsupported content type".to_owned()));
+            };
+            if codec.is_some_and(|c| c != header_codec) {
+                return Err(BadRequest("content type conflicts with codec".to_owned()));
+            }
+            Ok(header_codec)
+        }
+        (None, Some(codec)) => Ok(codec),
+        (None, None) => Err(BadRequest("missing content type or codec".to_owned())),
+    }
+}
+
+/// Check that a raw PCM format is declared by query parameters if and only if
+/// the input is raw PCM, and that it matches what is passed to infsrv as is.
+fn check_pcm_format(codec: Codec, query: &TranscribeQuery) -> Result<()> {
+    use Error::*;
+    let declared = (query.sample_rate, query.channels, query.sample_format);
+    if codec != Codec::Lpcm {
+        if declared != (None, None, None) {
+            return Err(BadRequest(
+                "pcm format is only applicable to lpcm input".to_owned(),
+            ));
+        }
+        return Ok(());
+    }
+
+    let (Some(sample_rate), Some(channels), Some(sample_format)) = declared else {
+        return Err(BadRequest(
+            "lpcm input requires sample rate, channels and sample format".to_owned(),
+        ));
+    };
+    if sample_rate != SAMPLE_RATE as u32 {
+        return Err(BadRequest(format!(
+            "unsupported lpcm sample rate (expected {SAMPLE_RATE})"
+        )));
+    }
+    if channels != 1 {
+        return Err(BadRequest(
+            "unsupported lpcm channels (expected 1)".to_owned(),
+        ));
+    }
+    debug!("lpcm format: {sample_rate}Hz, {channels} channel, {sample_format:?}");
+    Ok(())
+}
+
+/// Session control message sent by client as a text WebSocket message.
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum ControlMessage {
+    /// Stop the session discarding audio which is not transcribed yet
+    /// (unlike the terminator which finalizes the session).
+    Cancel,
 }
 
 /// Transcribe request output item.
@@ -66,11 +208,14 @@ pub struct TranscribeItem {
     pub begin: f32,
     pub end: f32,
     pub text: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speaker: Option<String>,
 }
 
 /// Handle transcribe requests.
 pub async fn handle_transcribe(
     State(server): State<Arc<Server>>,
+    _origin: AllowedWsOrigin,
     auth: Auth,
     WithRejection(Query(query), _): WithRejection<Query<TranscribeQuery>, Error>,
     headers: HeaderMap,
@@ -79,116 +224,801 @@ pub async fn handle_transcribe(
     let user = auth.user()?;
     info!("received transcribe request");
 
-    if headers.get(CONTENT_TYPE) != Some(&HeaderValue::from_static(VORBIS_CONTENT_TYPE)) {
-        return Err(Error::BadRequest("unsupported content type".to_owned()));
-    }
+    let codec = resolve_codec(headers.get(CONTENT_TYPE), query.codec)?;
+    debug!("input codec: {codec:?}");
+    check_pcm_format(codec, &query)?;
 
-    let capabilities = {
-        let client = server.pg_pool.get().await?;
-        Capability::find_with_task_type_and_tariff(&client, TaskType::Transcribe, &query.tariff)
-            .await?
-    };
-    if capabilities.is_empty() {
-        return Err(Error::BadRequest("unknown tariff".to_owned()));
+    // Sessions are long-lived, so they are limited apart from REST requests.
+    let Ok(permit) = server.transcribe_semaphore.clone().try_acquire_owned() else {
+        return Err(Error::ServerOverloaded(
+            "too many transcribe sessions".to_owned(),
+        ));
     };
-    if let Some(lang) = &query.lang {
-        if capabilities
-            .iter()
-            .filter_map(|c| {
-                c.languages
-                    .as_ref()
-                    .map(|l| l.split(',').any(|c| c == lang))
-            })
-            .any(|b| !b)
-        {
-            return Err(Error::BadRequest("unsupported language".to_owned()));
+
+    let tariff = Tariff::resolve(&server, &query).await?;
+
+    let callback_secret = match &query.callback_url {
+        Some(url) => {
+            validate_callback_url(url, &server.config.callback_allowed_hosts)?;
+            let client = server.pg_pool.get().await?;
+            let Some(user) = User::get(&client, user).await? else {
+                return Err(Error::Internal("user not found".to_owned()));
+            };
+            Some(user.callback_secret)
         }
-    }
+        None => None,
+    };
 
     let terminator = headers.get(TERMINATOR_HEADER).map(|v| {
         debug!("stream terminator: {}", v.to_str().unwrap_or("?"));
         v.as_bytes().to_vec()
     });
 
-    let (infsrv_sender, infsrv_receiver) = server
+    let (infsrv_sender, infsrv_receiver, segment_charged, node) = server
         .infsrv_pool
-        .segment(user, &query.tariff, terminator.as_deref())
+        .segment(
+            user,
+            &tariff.name,
+            SegmentParams {
+                // Only streaming clients wait for short speech to be finalized.
+                speech_flush_grace: server.config.speech_flush_grace_millis as f32 / 1000.0,
+                ..tariff.segment_params
+            },
+            terminator.as_deref(),
+        )
         .await?;
 
+    // Let WebSocket reading fail early instead of buffering oversized messages,
+    // the exact limit is checked in create_packet_reader to close gracefully.
+    let max_message_size = server.config.max_ws_message_size + 1;
+    let ws = ws
+        .max_message_size(max_message_size)
+        .max_frame_size(max_message_size);
+
     Ok(ws.on_upgrade(move |client_ws| async move {
-        ws_callback(
+        let _permit = permit;
+        let session = Arc::new(Session {
             server,
             user,
+            tariff,
             query,
+            codec,
+            callback_secret,
+            terminator,
+            terminated: AtomicBool::new(false),
+            cancelled: Notify::new(),
+            close_reason: Mutex::new(None),
+            started_at: OffsetDateTime::now_utc(),
+            nodes: Mutex::new(vec![node]),
+        });
+        ws_callback(
+            session,
             infsrv_sender,
             infsrv_receiver,
+            segment_charged,
             client_ws,
-            terminator,
         )
         .await
     }))
 }
 
-async fn ws_callback(
+/// Handle transcribe POST requests with a whole audio file.
+pub async fn handle_transcribe_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    headers: HeaderMap,
+    WithRejection(Query(query), _): WithRejection<Query<TranscribeQuery>, Error>,
+    WithRejection(mut multipart, _): WithRejection<Multipart, Error>,
+) -> Result<Response> {
+    let user = auth.user()?;
+    info!("received transcribe file request");
+
+    use Error::*;
+    if query.callback_url.is_some() {
+        return Err(BadRequest("callback is not supported for files".to_owned()));
+    }
+
+    // Files are transcribed as long as streams, so they share the limit.
+    let Ok(permit) = server.transcribe_semaphore.clone().try_acquire_owned() else {
+        return Err(ServerOverloaded("too many transcribe sessions".to_owned()));
+    };
+
+    let tariff = Tariff::resolve(&server, &query).await?;
+    let file = read_audio_file(&mut multipart).await?;
+
+    let max_packet_frames = server.config.max_packet_frames;
+    let max_duration = server.config.max_transcribe_file_duration_secs;
+    let samples = spawn_blocking(move || decode_ogg_vorbis(&file, max_packet_frames, max_duration))
+        .await
+        .map_err(|_| Internal("failed to join decoding task".to_owned()))??;
+
+    if !accepts_ndjson(&headers) {
+        let transcript =
+            transcribe_samples(&server, user, &tariff, &query, &samples, |_| ()).await?;
+        return Ok(Json(transcript).into_response());
+    }
+
+    // Progress events are streamed while the file is transcribed in background,
+    // the transcription is abandoned once the client disconnects.
+    let (sender, mut receiver) = unbounded_channel::<serde_json::Value>();
+    tokio::spawn(async move {
+        let _permit = permit;
+        let progress_sender = sender.clone();
+        let progress = move |progress: f32| {
+            let _ = progress_sender.send(json!({ "progress": progress }));
+        };
+        let transcribing = transcribe_samples(&server, user, &tariff, &query, &samples, progress);
+        let event = tokio::select! {
+            result = transcribing => match result {
+                Ok(transcript) => json!({ "transcript": transcript }),
+                Err(err) => {
+                    error!("failed to transcribe file: {}", ErrorChainDisplay(&err));
+                    err.json()
+                }
+            },
+            _ = sender.closed() => {
+                debug!("abandoned transcription of disconnected client");
+                return;
+            }
+        };
+        let _ = sender.send(event);
+    });
+
+    let events = futures::stream::poll_fn(move |cx| receiver.poll_recv(cx))
+        .map(|event| Ok::<_, Infallible>(event.to_string() + "\n"));
+    Ok((
+        [(CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        Body::from_stream(events),
+    )
+        .into_response())
+}
+
+/// Overall progress of file transcription for shares of the audio
+/// segmented and transcribed so far.
+fn file_progress(segmented: f32, transcribed: f32) -> f32 {
+    let share = |s: f32| s.clamp(0.0, 1.0);
+    SEGMENTATION_PROGRESS_SHARE * share(segmented)
+        + (1.0 - SEGMENTATION_PROGRESS_SHARE) * share(transcribed)
+}
+
+/// Check if a client accepts a stream of newline-delimited JSON events.
+fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|t| t.split(';').next().unwrap().trim() == NDJSON_CONTENT_TYPE)
+}
+
+/// Segment and transcribe PCM samples at SAMPLE_RATE storing the transcript.
+/// Progress (a share of the audio processed) is reported after each received
+/// segment and each transcribed speech interval.
+async fn transcribe_samples(
+    server: &Server,
+    user: Uuid,
+    tariff: &Tariff,
+    query: &TranscribeQuery,
+    samples: &[i16],
+    mut progress: impl FnMut(f32),
+) -> Result<serde_json::Value> {
+    let started_at = OffsetDateTime::now_utc();
+    let total_seconds = samples.len() as f32 / SAMPLE_RATE;
+    let mut total_cost = Decimal::ZERO;
+    let mut transcript_id = None;
+    let result = async {
+        let speech = segment_samples(server, user, tariff, samples, &mut total_cost, |end| {
+            progress(file_progress(end / total_seconds, 0.0));
+        })
+        .await?;
+
+        let mut items = Vec::with_capacity(speech.len());
+        let mut prompt = None;
+        for (begin, end) in speech {
+            let (item, charged) = transcribe_interval(
+                server,
+                user,
+                tariff,
+                query,
+                samples,
+                (begin, end),
+                &mut prompt,
+            )
+            .await?;
+            total_cost += charged;
+            items.push(item);
+            progress(file_progress(1.0, end / total_seconds));
+        }
+
+        let mut transcript = Transcript::new(
+            user,
+            tariff.name.clone(),
+            serde_json::to_value(&items).unwrap(),
+            total_seconds,
+            total_cost,
+        );
+        let client = server.pg_pool.get().await?;
+        transcript.insert(&client).await?;
+        transcript_id = Some(transcript.id);
+
+        Ok(json!({
+            "id": transcript.id,
+            "items": items,
+            "totalSeconds": total_seconds,
+            "totalCost": total_cost,
+        }))
+    }
+    .await;
+
+    store_file_session(
+        server,
+        user,
+        tariff,
+        started_at,
+        total_seconds,
+        total_cost,
+        transcript_id,
+    )
+    .await;
+    result
+}
+
+/// Read an Ogg audio file from a multipart "file" field.
+pub(super) async fn read_audio_file(multipart: &mut Multipart) -> Result<Bytes> {
+    use Error::*;
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() != Some("file") {
+            continue;
+        }
+        if !field
+            .content_type()
+            .is_none_or(|t| t.starts_with("audio/ogg"))
+        {
+            return Err(BadRequest("unsupported content type".to_owned()));
+        }
+        return Ok(field.bytes().await?);
+    }
+    Err(BadRequest("missing file".to_owned()))
+}
+
+/// Transcribe a speech interval (in seconds) of PCM samples at SAMPLE_RATE
+/// normalizing its text. Silent edges of the interval are trimmed if the
+/// tariff requires so. A given prompt is replaced with the (sanitized) original text.
+/// Returns the transcribed item and the amount charged for it.
+pub(super) async fn transcribe_interval(
+    server: &Server,
+    user: Uuid,
+    tariff: &Tariff,
+    query: &TranscribeQuery,
+    samples: &[i16],
+    (begin, end): (f32, f32),
+    prompt: &mut Option<String>,
+) -> Result<(TranscribeItem, Decimal)> {
+    let range = |t: f32| ((t * SAMPLE_RATE) as usize).min(samples.len());
+    let ((begin, end), samples) = trim_interval(
+        &samples[range(begin)..range(end)],
+        (begin, end),
+        SAMPLE_RATE,
+        tariff.trim_threshold,
+    );
+    let wav_blob = encode_speech_wav(samples, SAMPLE_RATE, tariff.transcribe_sample_rate);
+    let options = TranscribeOptions {
+        language: query.lang.clone(),
+        prompt: prompt.take(),
+        diarize: tariff.diarize,
+    };
+    let item = server
+        .infsrv_pool
+        .transcribe(user, &tariff.name, wav_blob, options)
+        .await?;
+    let text = query.normalization().apply(&item.text);
+    *prompt = Some(sanitize_prompt(&item.text, server.config.max_prompt_size));
+    Ok((
+        TranscribeItem {
+            begin,
+            end,
+            text,
+            speaker: item.speaker,
+        },
+        item.charged,
+    ))
+}
+
+/// Decode a whole Ogg/Vorbis file into mono PCM at SAMPLE_RATE.
+pub(super) fn decode_ogg_vorbis(
+    data: &[u8],
+    max_packet_frames: usize,
+    max_duration_secs: u64,
+) -> Result<Vec<i16>> {
+    use Error::*;
+    let max_samples = max_duration_secs as usize * SAMPLE_RATE as usize;
+
+    let mut pcm_stream = pin!(decode_ogg_to_pcm16(
+        futures::io::Cursor::new(data),
+        max_packet_frames
+    ));
+    let mut samples = Vec::new();
+    while let Some(result) = block_on(pcm_stream.next()) {
+        let Ok(chunk) = result else {
+            return Err(BadRequest("malformed audio file".to_owned()));
+        };
+        samples.extend(chunk.samples);
+        if samples.len() > max_samples {
+            return Err(BadRequest("audio file too long".to_owned()));
+        }
+    }
+
+    if samples.is_empty() {
+        return Err(BadRequest("no audio in file".to_owned()));
+    }
+    Ok(samples)
+}
+
+/// Segment PCM samples with infsrv, returns speech intervals (in seconds).
+/// The amount charged for the segmentation is added to a given total cost
+/// (even if it fails). The end of each received segment (in seconds)
+/// is reported as progress.
+pub(super) async fn segment_samples(
+    server: &Server,
+    user: Uuid,
+    tariff: &Tariff,
+    samples: &[i16],
+    total_cost: &mut Decimal,
+    mut progress: impl FnMut(f32),
+) -> Result<Vec<(f32, f32)>> {
+    // The terminator makes infsrv to flush all segments before closing.
+    let terminator = Uuid::new_v4().as_bytes().to_vec();
+    let (infsrv_sender, mut infsrv_receiver, charged, _) = server
+        .infsrv_pool
+        .segment(user, &tariff.name, tariff.segment_params, Some(&terminator))
+        .await?;
+
+    // Segments are received concurrently not to stall infsrv.
+    let sending = async move {
+        const CHUNK_FRAMES: usize = 16384;
+        for chunk in samples.chunks(CHUNK_FRAMES) {
+            let pcm = chunk.iter().flat_map(|s| s.to_le_bytes()).collect();
+            if infsrv_sender.send(pcm).await.is_err() {
+                return;
+            }
+        }
+        let _ = infsrv_sender.send(terminator).await;
+    };
+    let receiving = async {
+        let mut speech = Vec::new();
+        while let Some(item) = infsrv_receiver.recv().await {
+            let item = item?;
+            progress(item.end());
+            if let SegmentItem::Speech { begin, end } = item {
+                speech.push((begin, end));
+            }
+        }
+        Ok::<_, Error>(speech)
+    };
+
+    let ((), result) = tokio::join!(sending, receiving);
+    *total_cost += *charged.borrow();
+    result
+}
+
+/// Tariff capabilities resolved for a transcribe request.
+pub(super) struct Tariff {
+    pub name: String,
+    segment_params: SegmentParams,
+    transcribe_sample_rate: f32,
+    /// Threshold to trim silent edges of speech intervals with (if any).
+    trim_threshold: Option<f32>,
+    /// Number of speech segments of a session transcribed concurrently.
+    transcribe_concurrency: usize,
+    /// Capture session audio (if the server has audio capturing configured).
+    capture_audio: bool,
+    /// Transcription capabilities label speech with speakers.
+    pub diarize: bool,
+}
+
+impl Tariff {
+    /// Resolve a requested (or default) tariff checking the requested language.
+    pub async fn resolve(server: &Server, query: &TranscribeQuery) -> Result<Self> {
+        let name = query
+            .tariff
+            .clone()
+            .unwrap_or_else(|| server.config.default_tariff.clone());
+
+        let client = server.pg_pool.get().await?;
+        let segment_capabilities =
+            Capability::find_with_task_type_and_tariff(&client, TaskType::Segment, &name).await?;
+        let task_type = if query.diarize {
+            TaskType::Diarize
+        } else {
+            TaskType::Transcribe
+        };
+        let capabilities =
+            Capability::find_with_task_type_and_tariff(&client, task_type, &name).await?;
+
+        if capabilities.is_empty() {
+            let message = if query.diarize {
+                "tariff does not support diarization"
+            } else {
+                "unknown tariff"
+            };
+            return Err(Error::BadRequest(message.to_owned()));
+        };
+        if let Some(lang) = &query.lang {
+            if capabilities.iter().any(|c| !c.supports_language(lang)) {
+                return Err(Error::BadRequest("unsupported language".to_owned()));
+            }
+        }
+
+        Ok(Self {
+            name,
+            segment_params: SegmentParams {
+                vad_sensitivity: query.vad.unwrap_or(server.config.vad_sensitivity),
+                ..SegmentParams::from_capabilities(&segment_capabilities)
+            },
+            transcribe_sample_rate: transcribe_sample_rate(&capabilities),
+            trim_threshold: trim_threshold(&capabilities),
+            transcribe_concurrency: transcribe_concurrency(&capabilities),
+            capture_audio: segment_capabilities
+                .iter()
+                .chain(&capabilities)
+                .any(|c| c.capture_audio),
+            diarize: query.diarize,
+        })
+    }
+
+    /// Type of transcription tasks of the tariff.
+    fn task_type(&self) -> TaskType {
+        if self.diarize {
+            TaskType::Diarize
+        } else {
+            TaskType::Transcribe
+        }
+    }
+}
+
+/// Sample rate of audio sent for transcription (independent of the segmentation one).
+/// The lowest rate wins if several capabilities specify it.
+fn transcribe_sample_rate(capabilities: &[Capability]) -> f32 {
+    capabilities
+        .iter()
+        .filter_map(|c| c.sample_rate)
+        .min()
+        .map_or(SAMPLE_RATE, |r| r as f32)
+}
+
+/// Threshold to trim silent edges of speech intervals with.
+/// The lowest threshold wins if several capabilities specify it.
+fn trim_threshold(capabilities: &[Capability]) -> Option<f32> {
+    capabilities
+        .iter()
+        .filter_map(|c| c.trim_threshold)
+        .min_by(f32::total_cmp)
+}
+
+/// Number of speech segments of a session transcribed concurrently.
+/// The lowest number wins if several capabilities specify it.
+fn transcribe_concurrency(capabilities: &[Capability]) -> usize {
+    capabilities
+        .iter()
+        .filter_map(|c| c.transcribe_concurrency)
+        .min()
+        .map_or(1, |c| c as usize)
+}
+
+/// Trim silent edges of samples of a speech interval (in seconds)
+/// with a given threshold, returns the trimmed interval and samples.
+fn trim_interval(
+    samples: &[i16],
+    (begin, end): (f32, f32),
+    sample_rate: f32,
+    threshold: Option<f32>,
+) -> ((f32, f32), &[i16]) {
+    let Some(threshold) = threshold else {
+        return ((begin, end), samples);
+    };
+
+    let range = trim_silence(samples, sample_rate, threshold);
+    let time = |index: usize| begin + index as f32 / sample_rate;
+    let trimmed_begin = if range.start > 0 {
+        time(range.start)
+    } else {
+        begin
+    };
+    let trimmed_end = if range.end < samples.len() {
+        time(range.end)
+    } else {
+        end
+    };
+    ((trimmed_begin, trimmed_end), &samples[range])
+}
+
+/// Encode speech samples as a WAV blob resampling them to a given target rate.
+fn encode_speech_wav(samples: &[i16], sample_rate: f32, target_rate: f32) -> Vec<u8> {
+    let resampled = resample_pcm16(samples, sample_rate, target_rate);
+    encode_wav(resampled.into_iter(), target_rate as u32)
+}
+
+/// Transcribe session context.
+struct Session {
     server: Arc<Server>,
     user: Uuid,
+    tariff: Tariff,
     query: TranscribeQuery,
+    codec: Codec,
+    callback_secret: Option<String>,
+    terminator: Option<Vec<u8>>,
+    terminated: AtomicBool,
+    /// Notified once client cancels the session.
+    cancelled: Notify,
+    close_reason: Mutex<Option<CloseReason>>,
+    started_at: OffsetDateTime,
+    nodes: Mutex<Vec<IpAddr>>,
+}
+
+impl Session {
+    /// Record a reason to close the session with (the first one wins).
+    fn close(&self, reason: CloseReason) {
+        debug!("closing transcribe session: {}", reason.reason());
+        self.close_reason.lock().unwrap().get_or_insert(reason);
+    }
+
+    /// Cancel the session discarding its audio which is not transcribed yet.
+    fn cancel(&self) {
+        self.close(CloseReason::Cancelled);
+        self.cancelled.notify_one();
+    }
+
+    /// Remember a node which served the session.
+    fn add_node(&self, node: IpAddr) {
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains(&node) {
+            nodes.push(node);
+        }
+    }
+
+    /// Time to wait for client to drain a sent message.
+    fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.config.client_drain_timeout_secs)
+    }
+
+    /// Interval between pings sent to client.
+    fn ping_interval(&self) -> Duration {
+        Duration::from_secs(self.server.config.ws_ping_interval_secs)
+    }
+
+    /// Time to wait for client to respond to pings before disconnecting it.
+    fn pong_timeout(&self) -> Duration {
+        2 * self.ping_interval()
+    }
+
+    /// Time to wait for client audio before disconnecting it.
+    fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(self.server.config.ws_idle_timeout_secs)
+    }
+
+    /// Close frame to send to client.
+    fn close_frame(&self) -> Option<CloseFrame<'static>> {
+        self.close_reason.lock().unwrap().map(|r| CloseFrame {
+            code: r.code(),
+            reason: r.reason().into(),
+        })
+    }
+}
+
+/// Reason to close a transcribe session reported to client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CloseReason {
+    Cancelled,
+    ClientTooSlow,
+    IdleTimeout,
+    InfsrvDisconnected,
+    InfsrvFailed,
+    InfsrvOverloaded,
+    InfsrvRejectedAudio,
+    MessageTooLarge,
+    NoAudio,
+    PacketTooLarge,
+    PongTimeout,
+    TooManySegments,
+}
+
+impl CloseReason {
+    /// Reason to close a session after a failed transcription.
+    fn from_infsrv_error(err: &InfsrvError) -> Self {
+        use CloseReason::*;
+        match err {
+            InfsrvError::UnexpectedStatus(status) if is_overload_status(*status) => {
+                InfsrvOverloaded
+            }
+            InfsrvError::UnexpectedStatus(status) if status.is_client_error() => {
+                InfsrvRejectedAudio
+            }
+            _ => InfsrvFailed,
+        }
+    }
+
+    /// WebSocket close code.
+    fn code(&self) -> u16 {
+        use CloseReason::*;
+        match self {
+            Cancelled => close_code::NORMAL,
+            ClientTooSlow | InfsrvOverloaded => close_code::AGAIN,
+            IdleTimeout | NoAudio | PongTimeout | TooManySegments => close_code::POLICY,
+            InfsrvDisconnected | InfsrvFailed => close_code::ERROR,
+            InfsrvRejectedAudio => close_code::INVALID,
+            MessageTooLarge | PacketTooLarge => close_code::SIZE,
+        }
+    }
+
+    /// Human-readable reason.
+    fn reason(&self) -> &'static str {
+        use CloseReason::*;
+        match self {
+            Cancelled => "cancelled by client",
+            ClientTooSlow => "client too slow",
+            IdleTimeout => "no audio received in time",
+            InfsrvDisconnected => "transcription service disconnected",
+            InfsrvFailed => "transcription service failed",
+            InfsrvOverloaded => "transcription service overloaded, retry later",
+            InfsrvRejectedAudio => "transcription service rejected audio",
+            MessageTooLarge => "message too large",
+            NoAudio => "no audio received",
+            PacketTooLarge => "packet too large",
+            PongTimeout => "client stopped responding to pings",
+            TooManySegments => "too many segments",
+        }
+    }
+}
+
+async fn ws_callback(
+    session: Arc<Session>,
     infsrv_sender: Sender<Vec<u8>>,
     infsrv_receiver: Receiver<InfsrvResult<SegmentItem>>,
+    segment_charged: watch::Receiver<Decimal>,
     client_ws: WebSocket,
-    terminator: Option<Vec<u8>>,
 ) {
     let (client_sender, client_receiver) = client_ws.split();
 
     let ring_buffer = Arc::new(Mutex::new(RingBuffer::with_capacity(
         SAMPLE_RATE,
-        RING_BUFFER_CAPACITY,
+        session.tariff.segment_params.ring_buffer_capacity(),
     )));
 
     let (limit_sender, limit_receiver) = unbounded_channel::<f32>();
 
-    let cloned_server = server.clone();
+    let cloned_session = session.clone();
     let cloned_ring_buffer = ring_buffer.clone();
     let segment_handle = tokio::spawn(async move {
         process_segments(
-            cloned_server,
-            user,
-            query,
+            cloned_session,
             client_sender,
             infsrv_receiver,
+            segment_charged,
             cloned_ring_buffer,
             limit_sender,
         )
-        .await;
+        .await
     });
 
-    let mut processor = AudioStreamProcessor::new(server.config.limit_audio_rate);
+    let capture = session
+        .server
+        .audio_store
+        .clone()
+        .filter(|_| session.tariff.capture_audio)
+        .map(AudioCapture::start);
+    let mut processor = AudioStreamProcessor::new(session.server.config.limit_audio_rate, capture);
     processor
         .process(
+            &session,
             infsrv_sender,
             client_receiver,
-            terminator,
             ring_buffer.clone(),
             limit_receiver,
         )
         .await;
 
-    let _ = segment_handle.await;
+    // A panicked task still leaves a record of the session.
+    let outcome = segment_handle.await.unwrap_or_default();
+    let id = store_session(&session, outcome).await;
+    if let Some(capture) = processor.capture {
+        capture.finish(id).await;
+    }
     info!("disconnected transcribe");
 }
 
-async fn process_segments(
-    server: Arc<Server>,
-    user: Uuid,
-    query: TranscribeQuery,
-    mut client_sender: SplitSink<WebSocket, Message>,
+/// Check if WebSocket reading failed due to exceeded message size.
+fn is_capacity_error(err: &axum::Error) -> bool {
+    use std::error::Error;
+    matches!(
+        err.source()
+            .and_then(|e| e.downcast_ref::<TungsteniteError>()),
+        Some(TungsteniteError::Capacity(_))
+    )
+}
+
+/// Forward transcribed segments to client.
+///
+/// Segments are consumed from infsrv one at a time, so a client which is
+/// slow to read stalls consumption rather than letting results pile up.
+/// A stalled consumption stops advancing limit_sender, so once the ring buffer
+/// (sized to twice the maximum segment duration) fills up, the audio stream
+/// processor stops reading client audio. A client which fails to drain
+/// a message within the configured timeout gets disconnected.
+async fn process_segments<S>(
+    session: Arc<Session>,
+    mut client_sender: S,
     mut infsrv_receiver: Receiver<InfsrvResult<SegmentItem>>,
+    segment_charged: watch::Receiver<Decimal>,
     ring_buffer: Arc<Mutex<RingBuffer>>,
     limit_sender: UnboundedSender<f32>,
-) {
-    let mut item = None;
+) -> SessionOutcome
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error,
+{
     let mut consumed = 0.0;
-    while let Some(Ok(segment_item)) = infsrv_receiver.recv().await {
+    let mut segments = 0;
+    let mut lengths = SegmentLengths::default();
+    let mut exhausted = false;
+    let mut context = SpeechContext {
+        normalization: session.query.normalization(),
+        prompt: None,
+        items: Vec::new(),
+        charged: Decimal::ZERO,
+        queue: TranscribeQueue::new(session.tariff.transcribe_concurrency),
+    };
+
+    // Pings keep idle connections alive, pongs are checked by the audio reader.
+    let ping_interval = session.ping_interval();
+    let mut ping_interval = interval_at(Instant::now() + ping_interval, ping_interval);
+    ping_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        let result = tokio::select! {
+            // Pending and in-flight speech of a cancelled session is discarded.
+            _ = session.cancelled.notified() => {
+                debug!("discarding segments of cancelled session");
+                break;
+            }
+            result = infsrv_receiver.recv() => result,
+            _ = ping_interval.tick() => {
+                if !send_to_client(&session, &mut client_sender, Message::Ping(Vec::new())).await {
+                    break;
+                }
+                continue;
+            }
+            Some(transcribed) = context.queue.next(), if !context.queue.is_empty() => {
+                if !send_transcribed(&session, &mut context, &mut client_sender, transcribed).await {
+                    break;
+                }
+                continue;
+            }
+        };
+        let segment_item = match result {
+            Some(Ok(segment_item)) => segment_item,
+            Some(Err(err)) => {
+                debug!("failed to receive segment: {}", ErrorChainDisplay(&err));
+                if matches!(err, InfsrvError::Disconnected) {
+                    session.close(CloseReason::InfsrvDisconnected);
+                }
+                // Speech being transcribed is still delivered.
+                drain_transcribed(&session, &mut context, &mut client_sender).await;
+                break;
+            }
+            None => {
+                exhausted = drain_transcribed(&session, &mut context, &mut client_sender).await;
+                break;
+            }
+        };
+
+        // Pathologically tiny segments would inflate cost and memory.
+        segments += 1;
+        if segments > session.server.config.max_session_segments {
+            drain_transcribed(&session, &mut context, &mut client_sender).await;
+            session.close(CloseReason::TooManySegments);
+            break;
+        }
+
         use SegmentItem::*;
         let (speech, begin, end) = match segment_item {
             Speech { begin, end } => (true, begin, end),
@@ -200,99 +1030,259 @@ async fn process_segments(
             if speech { "speech" } else { "void" }
         );
 
-        assert!(begin >= consumed);
-        assert!(end > begin);
-        consumed = end;
-
-        if !speech {
-            if limit_sender.send(end).is_err() {
-                debug!("failed to send time consumed for void segment");
-                break;
-            }
+        // Segments resent after an infsrv reconnection can overlap delivered ones.
+        let begin = if begin < consumed {
+            debug!("clamping segment begin {begin}s to consumed {consumed}s");
+            consumed
+        } else {
+            begin
+        };
+        if end <= begin {
+            debug!("ignoring empty segment {begin}s-{end}s");
             continue;
         }
+        consumed = end;
+        lengths.push(speech, end - begin);
 
-        let wav_blob = ring_buffer
-            .lock()
-            .unwrap()
-            .extract_time_interval_wav(begin, end);
-
-        if limit_sender.send(end).is_err() {
-            debug!("failed to send time consumed for speech segment");
+        let flushed = flush_speech(
+            &session,
+            &mut context,
+            &mut client_sender,
+            &ring_buffer,
+            &limit_sender,
+            speech.then_some((begin, end)),
+            end,
+        )
+        .await;
+        if !flushed {
             break;
         }
+    }
 
-        let result = server
-            .infsrv_pool
-            .transcribe(
-                user,
-                query.tariff.as_str(),
-                wav_blob,
-                query.lang.as_ref().cloned(),
-                item.take().map(|s: TranscribeItem| s.text),
-            )
-            .await;
+    let SpeechContext { items, charged, .. } = context;
 
-        let transcribe_item = match result {
-            Ok(item) => item,
-            Err(err) => {
-                if matches!(
-                    err,
-                    crate::infsrv_pool::Error::Ledger(crate::ledger::Error::NotEnoughBalance)
-                ) {
-                    debug!("not enough balance to transcribe segment");
-                } else {
-                    error!("failed to transcribe segment: {}", ErrorChainDisplay(&err));
-                }
-                break;
-            }
-        };
+    // Acknowledge delivery of all segments of a terminated stream.
+    // Segmentation of an exhausted stream is already charged in full.
+    let done = exhausted && session.terminated.load(Ordering::SeqCst);
+    let total_cost = *segment_charged.borrow() + charged;
+    let segment_stats = lengths.stats();
+    let mut transcript_id = None;
+    if done {
+        transcript_id = store_transcript(&session, &items, consumed, total_cost).await;
+        let ack = json!({
+            "done": true,
+            "totalSeconds": consumed,
+            "totalCost": total_cost,
+            "transcriptId": transcript_id,
+            "segmentStats": segment_stats,
+        });
+        send_to_client(
+            &session,
+            &mut client_sender,
+            Message::Text(ack.to_string() + "\n"),
+        )
+        .await;
+    }
 
-        item = Some(TranscribeItem {
-            begin,
-            end,
-            text: transcribe_item.text,
+    let drain_timeout = session.drain_timeout();
+    let closing = async {
+        if let Some(frame) = session.close_frame() {
+            let _ = client_sender.send(Message::Close(Some(frame))).await;
+        }
+        let _ = client_sender.close().await;
+    };
+    if timeout(drain_timeout, closing).await.is_err() {
+        debug!("timed out closing client ws");
+    }
+    debug!("finished processing infsrv segments");
+
+    // Notify integrator in background not to hold the session while retrying.
+    if let (Some(url), Some(secret)) = (&session.query.callback_url, &session.callback_secret) {
+        let payload = json!({
+            "user": session.user,
+            "tariff": session.tariff.name,
+            "transcript": items.iter().map(|i| i.text.trim()).collect::<Vec<_>>().join(" "),
+            "done": done,
+            "totalSeconds": consumed,
+            "totalCost": total_cost,
         });
-        let json = serde_json::to_string(&item).unwrap();
-        if let Err(err) = client_sender.send(Message::Text(json + "\n")).await {
+        let (server, url, secret) = (session.server.clone(), url.clone(), secret.clone());
+        tokio::spawn(async move {
+            send_callback(&server.http_client, &url, &secret, &payload).await;
+        });
+    }
+
+    SessionOutcome {
+        done,
+        total_seconds: consumed,
+        total_cost,
+        transcript: transcript_id,
+        segment_stats,
+    }
+}
+
+/// Outcome of processed session segments.
+#[derive(Default)]
+struct SessionOutcome {
+    done: bool,
+    total_seconds: f32,
+    total_cost: Decimal,
+    transcript: Option<Uuid>,
+    segment_stats: SegmentStats,
+}
+
+/// Record an outcome of a finished session, returns its ID on success.
+async fn store_session(session: &Session, outcome: SessionOutcome) -> Option<Uuid> {
+    let close_reason = match *session.close_reason.lock().unwrap() {
+        Some(reason) => reason.reason(),
+        None if outcome.done => "completed",
+        None => "interrupted",
+    };
+    let record = SessionRecord {
+        id: Uuid::nil(),
+        user: session.user,
+        tariff: session.tariff.name.clone(),
+        task: session.tariff.task_type(),
+        started_at: session.started_at,
+        ended_at: OffsetDateTime::now_utc(),
+        total_seconds: outcome.total_seconds,
+        total_cost: outcome.total_cost,
+        close_reason: close_reason.to_owned(),
+        nodes: session.nodes.lock().unwrap().clone(),
+        transcript: outcome.transcript,
+        segment_stats: Some(outcome.segment_stats),
+    };
+    insert_session(&session.server, record).await
+}
+
+/// Record a transcription of a whole file (which has no streaming session)
+/// as a session, so its usage is exported along with streaming ones.
+/// A file without a stored transcript is recorded as failed.
+pub(super) async fn store_file_session(
+    server: &Server,
+    user: Uuid,
+    tariff: &Tariff,
+    started_at: OffsetDateTime,
+    total_seconds: f32,
+    total_cost: Decimal,
+    transcript: Option<Uuid>,
+) {
+    let record = SessionRecord {
+        id: Uuid::nil(),
+        user,
+        tariff: tariff.name.clone(),
+        task: tariff.task_type(),
+        started_at,
+        ended_at: OffsetDateTime::now_utc(),
+        total_seconds,
+        total_cost,
+        close_reason: if transcript.is_some() {
+            "completed"
+        } else {
+            "failed"
+        }
+        .to_owned(),
+        nodes: Vec::new(),
+        transcript,
+        segment_stats: None,
+    };
+    insert_session(server, record).await;
+}
+
+/// Insert a session record, returns its ID on success.
+async fn insert_session(server: &Server, mut record: SessionRecord) -> Option<Uuid> {
+    let result = match server.pg_pool.get().await {
+        Ok(client) => record.insert(&client).await.map_err(Error::from),
+        Err(err) => Err(err.into()),
+    };
+    match result {
+        Ok(()) => {
+            debug!("stored session {}", record.id);
+            Some(record.id)
+        }
+        Err(err) => {
+            error!("failed to store session: {}", ErrorChainDisplay(&err));
+            None
+        }
+    }
+}
+
+/// Store a transcript of a completed session, returns its ID on success.
+async fn store_transcript(
+    session: &Session,
+    items: &[TranscribeItem],
+    total_seconds: f32,
+    total_cost: Decimal,
+) -> Option<Uuid> {
+    let mut transcript = Transcript::new(
+        session.user,
+        session.tariff.name.clone(),
+        serde_json::to_value(items).unwrap(),
+        total_seconds,
+        total_cost,
+    );
+    let result = match session.server.pg_pool.get().await {
+        Ok(client) => transcript.insert(&client).await.map_err(Error::from),
+        Err(err) => Err(err.into()),
+    };
+    match result {
+        Ok(()) => Some(transcript.id),
+        Err(err) => {
+            error!("failed to store transcript: {}", ErrorChainDisplay(&err));
+            None
+        }
+    }
+}
+
+/// Send a message to client waiting for it to be drained.
+/// Returns false if the sending failed or timed out.
+async fn send_to_client<S>(session: &Session, client_sender: &mut S, msg: Message) -> bool
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error,
+{
+    match timeout(session.drain_timeout(), client_sender.send(msg)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
             debug!("failed to send to client ws: {}", ErrorChainDisplay(&err));
-            break;
+            false
+        }
+        Err(_) => {
+            session.close(CloseReason::ClientTooSlow);
+            false
         }
     }
-    let _ = client_sender.close().await;
-    debug!("finished processing infsrv segments");
 }
 
 struct AudioStreamProcessor {
-    resampler: Option<FastFixedIn<f32>>,
-    merged: Vec<f32>,
-    resampled: Vec<f32>,
     limit_audio_rate: bool,
+    /// Capture of decoded audio (if enabled for the session).
+    capture: Option<AudioCapture>,
 }
 
 impl AudioStreamProcessor {
-    pub fn new(limit_audio_rate: bool) -> Self {
+    pub fn new(limit_audio_rate: bool, capture: Option<AudioCapture>) -> Self {
         Self {
-            resampler: None,
-            merged: Vec::new(),
-            resampled: Vec::new(),
             limit_audio_rate,
+            capture,
         }
     }
 
-    pub async fn process(
+    pub async fn process<S>(
         &mut self,
+        session: &Arc<Session>,
         infsrv_sender: Sender<Vec<u8>>,
-        client_receiver: SplitStream<WebSocket>,
-        terminator: Option<Vec<u8>>,
+        client_receiver: S,
         ring_buffer: Arc<Mutex<RingBuffer>>,
         mut limit_receiver: UnboundedReceiver<f32>,
-    ) {
-        let (mut packet_reader, join_handle) =
-            Self::create_packet_reader(client_receiver, terminator.clone());
+    ) where
+        S: Stream<Item = std::result::Result<Message, axum::Error>> + Send + Unpin + 'static,
+    {
+        let terminator = session.terminator.as_deref();
+        let max_packet_frames = session.server.config.max_packet_frames;
+        let (reader, join_handle) = Self::create_reader(session.clone(), client_receiver);
+        let mut pcm_stream = Self::decode(session.codec, reader, max_packet_frames);
 
-        let mut id_header = Vec::new();
-        let mut decoder = None;
         let mut frames_consumed = 0;
 
         let mut frames_received = 0;
@@ -300,101 +1290,96 @@ impl AudioStreamProcessor {
         let mut interval = interval(Duration::from_secs(1));
         interval.tick().await;
 
-        let mut packet_index = 0;
+        let mut finished = false;
+        let mut last = false;
+        let mut received = false;
         loop {
-            let mut packet = tokio::select! {
+            let chunk = tokio::select! {
                  _ = infsrv_sender.closed() => {
                         debug!("closed infsrv pcm sender");
                         break;
                 }
-                result = packet_reader.next() => {
+                result = pcm_stream.next() => {
                     match result {
-                        Some(Ok(packet)) => packet,
+                        Some(Ok(chunk)) => chunk,
+                        Some(Err(AudioError::PacketTooLarge)) => {
+                            session.close(CloseReason::PacketTooLarge);
+                            break;
+                        }
                         Some(Err(err)) => {
-                            debug!("failed to read ogg packet: {err}");
+                            debug!("failed to decode audio stream: {}", ErrorChainDisplay(&err));
                             break;
                         }
                         None => {
-                            debug!("no more ogg packets");
+                            debug!("no more audio packets");
+                            finished = true;
                             break;
                         }
                     }
                 }
             };
+            last = chunk.last_in_stream;
+            received |= !chunk.samples.is_empty();
+            if let Some(capture) = self.capture.as_ref().filter(|_| !chunk.samples.is_empty()) {
+                capture.push(&chunk.samples);
+            }
 
-            let last = packet.last_in_stream();
-            match packet_index {
-                0 => id_header = packet.data,
-                1 => (), // Skip comment header.
-                2 => {
-                    let mut codec_params = CodecParameters::new();
-                    codec_params.for_codec(CODEC_TYPE_VORBIS);
-                    id_header.append(&mut packet.data);
-                    swap(&mut id_header, &mut packet.data);
-                    codec_params.with_extra_data(packet.data.into_boxed_slice());
-
-                    let decoder_opts = DecoderOptions::default();
-
-                    decoder = match VorbisDecoder::try_new(&codec_params, &decoder_opts) {
-                        Ok(decoder) => Some(decoder),
-                        Err(err) => {
-                            debug!(
-                                "failed to create vorbis decoder: {}",
-                                ErrorChainDisplay(&err)
-                            );
-                            return;
-                        }
-                    };
+            if self.limit_audio_rate {
+                frames_received += chunk.source_frames;
+                while secs_elapsed < frames_received / chunk.source_rate as usize {
+                    interval.tick().await;
+                    secs_elapsed += 1;
                 }
-                _ => {
-                    let packet = SymphoniaPacket::new_from_boxed_slice(
-                        0,
-                        0,
-                        0,
-                        packet.data.into_boxed_slice(),
-                    );
-                    let buf = match decoder.as_mut().unwrap().decode(&packet) {
-                        Ok(buf) => buf,
-                        Err(err) => {
-                            debug!("failed to decode packet: {}", ErrorChainDisplay(&err));
-                            return;
-                        }
-                    };
+            }
 
-                    if self.limit_audio_rate {
-                        frames_received += buf.frames();
-                        while secs_elapsed < frames_received / buf.spec().rate as usize {
-                            interval.tick().await;
-                            secs_elapsed += 1;
-                        }
-                    }
+            if !self
+                .process_samples(
+                    &infsrv_sender,
+                    &ring_buffer,
+                    &mut limit_receiver,
+                    &mut frames_consumed,
+                    &chunk.samples,
+                )
+                .await
+            {
+                break;
+            }
+        }
 
-                    let AudioBufferRef::F32(buf_f32) = buf else {
-                        debug!("unsupported type of decoded samples");
-                        return;
-                    };
-                    if !self
-                        .process_audio_buffer(
-                            &infsrv_sender,
-                            &ring_buffer,
-                            &mut limit_receiver,
-                            &mut frames_consumed,
-                            buf_f32.as_ref(),
-                            terminator.as_deref().filter(|_| last),
-                        )
-                        .await
-                    {
-                        break;
-                    }
-                }
+        // A stream without audio is not terminated, so nothing gets transcribed
+        // or billed and the client learns why the session is closed.
+        if finished && !received {
+            session.close(CloseReason::NoAudio);
+        }
+
+        // A chained stream ends every logical stream but the last one,
+        // so the terminator is only forwarded once the input is exhausted.
+        if let Some(delim) = terminator.filter(|_| finished && last && received) {
+            if let Err(err) = infsrv_sender.send(delim.to_owned()).await {
+                debug!(
+                    "failed to send terminator to infsrv ws: {}",
+                    ErrorChainDisplay(&err)
+                );
+            } else {
+                session.terminated.store(true, Ordering::SeqCst);
             }
-            packet_index += 1;
         }
         debug!("finished processing client audio stream");
 
-        drop(packet_reader);
+        drop(pcm_stream);
         let mut client_receiver = join_handle.await.unwrap();
 
+        // A timed out (or closed without audio) client may be gone and a cancelling
+        // one awaits closing, so the infsrv session is released right away.
+        use CloseReason::*;
+        if matches!(
+            *session.close_reason.lock().unwrap(),
+            Some(Cancelled | IdleTimeout | NoAudio | PongTimeout)
+        ) {
+            debug!("skipping post-audio client ws reading");
+            return;
+        }
+
         while let Some(Ok(msg)) = client_receiver.next().await {
             use Message::*;
             if let Binary(_) | Text(_) = msg {
@@ -412,20 +1397,71 @@ impl AudioStreamProcessor {
         debug!("finished to read post-audio client ws");
     }
 
-    fn create_packet_reader(
-        mut client_receiver: SplitStream<WebSocket>,
-        terminator: Option<Vec<u8>>,
-    ) -> (
-        PacketReader<impl AsyncRead>,
-        JoinHandle<SplitStream<WebSocket>>,
-    ) {
+    /// Decode client audio of a given codec into PCM chunks at SAMPLE_RATE.
+    fn decode<'a, R>(
+        codec: Codec,
+        reader: R,
+        max_packet_frames: usize,
+    ) -> Pin<Box<dyn Stream<Item = std::result::Result<PcmChunk, AudioError>> + Send + 'a>>
+    where
+        R: AsyncRead + Send + Unpin + 'a,
+    {
+        match codec {
+            Codec::Lpcm => Box::pin(decode_lpcm_to_pcm16(reader, max_packet_frames)),
+            Codec::Vorbis => Box::pin(decode_ogg_to_pcm16(reader, max_packet_frames)),
+        }
+    }
+
+    /// Create a reader of client audio which is fed by a background task.
+    /// The task stops if no audio is received within the idle timeout
+    /// or if no messages (including pongs) are received within the pong timeout.
+    fn create_reader<S>(
+        session: Arc<Session>,
+        mut client_receiver: S,
+    ) -> (impl AsyncRead + Unpin, JoinHandle<S>)
+    where
+        S: Stream<Item = std::result::Result<Message, axum::Error>> + Send + Unpin + 'static,
+    {
+        let max_message_size = session.server.config.max_ws_message_size;
         let (mut sender, receiver) = channel(32);
         let join_handle = tokio::spawn(async move {
-            while let Some(result) = client_receiver.next().await {
+            let mut idle_deadline = Instant::now() + session.idle_timeout();
+            let mut pong_deadline = Instant::now() + session.pong_timeout();
+            loop {
+                let deadline = idle_deadline.min(pong_deadline);
+                let result = match timeout_at(deadline, client_receiver.next()).await {
+                    Ok(Some(result)) => result,
+                    Ok(None) => break,
+                    Err(_) => {
+                        let (reason, message) = if idle_deadline <= pong_deadline {
+                            (CloseReason::IdleTimeout, "client audio timed out")
+                        } else {
+                            (CloseReason::PongTimeout, "client pong timed out")
+                        };
+                        session.close(reason);
+                        if sender.send(Err(IoError::other(message))).await.is_err() {
+                            debug!("failed to send error to packet reader");
+                        }
+                        break;
+                    }
+                };
+                if result.is_ok() {
+                    pong_deadline = Instant::now() + session.pong_timeout();
+                }
+
                 match result {
                     Ok(Message::Binary(mut data)) => {
+                        if data.len() > max_message_size {
+                            session.close(CloseReason::MessageTooLarge);
+                            let io_err = IoError::other("message too large");
+                            if sender.send(Err(io_err)).await.is_err() {
+                                debug!("failed to send error to packet reader");
+                            }
+                            break;
+                        }
+
                         let mut last = false;
-                        if let Some(delim) = terminator.as_deref() {
+                        if let Some(delim) = session.terminator.as_deref() {
                             if data.ends_with(delim) {
                                 data.truncate(data.len() - delim.len());
                                 debug!("detected client audio stream terminator");
@@ -439,6 +1475,9 @@ impl AudioStreamProcessor {
                         if last {
                             break;
                         }
+                        // Backpressure may delay the sending, so the timers are reset after it.
+                        idle_deadline = Instant::now() + session.idle_timeout();
+                        pong_deadline = Instant::now() + session.pong_timeout();
                     }
                     Ok(Message::Close(maybe_reason)) => {
                         if let Some(CloseFrame { code, reason }) = maybe_reason {
@@ -448,12 +1487,32 @@ impl AudioStreamProcessor {
                         }
                         break;
                     }
+                    Ok(Message::Text(text)) => match serde_json::from_str(&text) {
+                        Ok(ControlMessage::Cancel) => {
+                            debug!("received cancel msg from client ws");
+                            session.cancel();
+                            let io_err = IoError::other("session cancelled");
+                            if sender.send(Err(io_err)).await.is_err() {
+                                debug!("failed to send error to packet reader");
+                            }
+                            break;
+                        }
+                        Err(_) => {
+                            debug!(
+                                "ignoring client ws text msg {:?}",
+                                TruncateDebug::new(&text)
+                            );
+                        }
+                    },
                     Ok(msg) => {
                         debug!("ignoring client ws msg {:?}", TruncateDebug::new(&msg));
                     }
                     Err(err) => {
+                        if is_capacity_error(&err) {
+                            session.close(CloseReason::MessageTooLarge);
+                        }
                         debug!("failed to read client ws: {}", ErrorChainDisplay(&err));
-                        let io_err = IoError::new(IoErrorKind::Other, err);
+                        let io_err = IoError::other(err);
                         if sender.send(Err(io_err)).await.is_err() {
                             debug!("failed to send error to packet reader");
                         }
@@ -461,32 +1520,27 @@ impl AudioStreamProcessor {
                     }
                 }
             }
-            debug!("finished to feed ogg packet reader");
+            debug!("finished to feed audio packet reader");
             client_receiver
         });
-        (
-            PacketReader::new_compat(receiver.into_async_read()),
-            join_handle,
-        )
+        (receiver.into_async_read(), join_handle)
     }
 
-    async fn process_audio_buffer(
+    async fn process_samples(
         &mut self,
         infsrv_sender: &Sender<Vec<u8>>,
         ring_buffer: &Mutex<RingBuffer>,
         limit_receiver: &mut UnboundedReceiver<f32>,
         frames_consumed: &mut usize,
-        audio_buffer: &AudioBuffer<f32>,
-        terminator: Option<&[u8]>,
+        samples: &[i16],
     ) -> bool {
-        self.merge_channels(audio_buffer);
-        self.resample(audio_buffer.spec().rate as f32);
-
         let mut offset = 0;
-        while offset < self.resampled.len() {
-            let pushed = ring_buffer.lock().unwrap().pushed;
-            let chunk_len = (RING_BUFFER_CAPACITY - (pushed - *frames_consumed))
-                .min(self.resampled.len() - offset);
+        while offset < samples.len() {
+            let (pushed, capacity) = {
+                let guard = ring_buffer.lock().unwrap();
+                (guard.pushed, guard.capacity)
+            };
+            let chunk_len = (capacity - (pushed - *frames_consumed)).min(samples.len() - offset);
 
             if chunk_len == 0 {
                 // Wait until more frames have been consumed before pushing.
@@ -498,13 +1552,12 @@ impl AudioStreamProcessor {
                 continue;
             }
 
-            let mut pcm = Vec::with_capacity(2 * self.resampled.len());
+            let mut pcm = Vec::with_capacity(2 * chunk_len);
             {
                 let mut guard = ring_buffer.lock().unwrap();
-                for f32_sample in &self.resampled[offset..offset + chunk_len] {
-                    let i16_sample = (*f32_sample * i16::MAX as f32) as i16;
-                    pcm.extend_from_slice(&i16_sample.to_le_bytes());
-                    guard.push(i16_sample);
+                for sample in &samples[offset..offset + chunk_len] {
+                    pcm.extend_from_slice(&sample.to_le_bytes());
+                    guard.push(*sample);
                 }
             }
             if let Err(err) = infsrv_sender.send(pcm).await {
@@ -517,86 +1570,239 @@ impl AudioStreamProcessor {
             offset += chunk_len;
         }
 
-        if let Some(delim) = terminator {
-            if let Err(err) = infsrv_sender.send(delim.to_owned()).await {
-                debug!(
-                    "failed to send terminator to infsrv ws: {}",
-                    ErrorChainDisplay(&err)
-                );
+        true
+    }
+}
+
+/// Transcription state of a streaming session.
+struct SpeechContext {
+    normalization: TextNormalization,
+    /// Original text of the last delivered speech.
+    prompt: Option<String>,
+    items: Vec<TranscribeItem>,
+    /// Amount charged for transcribed speech.
+    charged: Decimal,
+    queue: TranscribeQueue<Transcribed>,
+}
+
+/// Outcome of transcribing a speech interval (in seconds).
+type Transcribed = ((f32, f32), InfsrvResult<crate::infsrv_pool::TranscribeItem>);
+
+/// Start transcribing a given speech interval (if any), audio up to a given
+/// time is released from the ring buffer beforehand. Results of previous
+/// intervals are sent to client while the transcription queue is full.
+/// Returns false if the session should be finished.
+async fn flush_speech<S>(
+    session: &Arc<Session>,
+    context: &mut SpeechContext,
+    client_sender: &mut S,
+    ring_buffer: &Mutex<RingBuffer>,
+    limit_sender: &UnboundedSender<f32>,
+    speech: Option<(f32, f32)>,
+    consumed: f32,
+) -> bool
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error,
+{
+    let wav_blobs: Vec<_> = speech
+        .into_iter()
+        .map(|(begin, end)| {
+            ring_buffer.lock().unwrap().extract_time_interval_wav(
+                begin,
+                end,
+                session.tariff.transcribe_sample_rate,
+                session.tariff.trim_threshold,
+            )
+        })
+        .collect();
+
+    if limit_sender.send(consumed).is_err() {
+        debug!("failed to send time consumed for segment");
+        return false;
+    }
+
+    for (interval, wav_blob) in wav_blobs {
+        while context.queue.is_full() {
+            let Some(transcribed) = context.queue.next().await else {
+                break;
+            };
+            if !send_transcribed(session, context, client_sender, transcribed).await {
                 return false;
             }
         }
 
-        true
+        // Concurrently transcribed speech is prompted with the last delivered one.
+        let options = TranscribeOptions {
+            language: session.query.lang.as_ref().cloned(),
+            prompt: context.prompt.clone(),
+            diarize: session.tariff.diarize,
+        };
+        let session = session.clone();
+        context.queue.push(async move {
+            let result = session
+                .server
+                .infsrv_pool
+                .transcribe(
+                    session.user,
+                    session.tariff.name.as_str(),
+                    wav_blob,
+                    options,
+                )
+                .await;
+            (interval, result)
+        });
     }
 
-    fn merge_channels(&mut self, audio_buffer: &AudioBuffer<f32>) {
-        let offset = self.merged.len();
-
-        self.merged.resize(offset + audio_buffer.frames(), 0.0);
-        self.merged[offset..].fill(0.0);
+    true
+}
 
-        for i in 0..audio_buffer.spec().channels.count() {
-            self.merged[offset..]
-                .iter_mut()
-                .zip(audio_buffer.chan(i).iter())
-                .for_each(|(m, s)| *m += (*s - *m) / (i + 1) as f32);
+/// Send results of all queued transcriptions to client.
+/// Returns false if the session should be finished.
+async fn drain_transcribed<S>(
+    session: &Session,
+    context: &mut SpeechContext,
+    client_sender: &mut S,
+) -> bool
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error,
+{
+    while let Some(transcribed) = context.queue.next().await {
+        if !send_transcribed(session, context, client_sender, transcribed).await {
+            return false;
         }
     }
+    true
+}
 
-    fn resample(&mut self, sample_rate: f32) {
-        if sample_rate != SAMPLE_RATE {
-            const CHUNK_SIZE: usize = 1024;
-            if self.resampler.is_none() {
-                self.resampler = Some(
-                    FastFixedIn::<f32>::new(
-                        SAMPLE_RATE as f64 / sample_rate as f64,
-                        1.0,
-                        PolynomialDegree::Linear,
-                        CHUNK_SIZE,
-                        1,
-                    )
-                    .unwrap(),
-                );
+/// Send a transcribed speech interval to client.
+/// Returns false if the session should be finished.
+async fn send_transcribed<S>(
+    session: &Session,
+    context: &mut SpeechContext,
+    client_sender: &mut S,
+    ((begin, end), result): Transcribed,
+) -> bool
+where
+    S: Sink<Message> + Unpin,
+    S::Error: std::error::Error,
+{
+    let transcribe_item = match result {
+        Ok(item) => item,
+        Err(err) => {
+            if matches!(
+                err,
+                InfsrvError::Ledger(crate::ledger::Error::NotEnoughBalance)
+            ) {
+                debug!("not enough balance to transcribe segment");
+            } else {
+                error!("failed to transcribe segment: {}", ErrorChainDisplay(&err));
+                session.close(CloseReason::from_infsrv_error(&err));
             }
+            return false;
+        }
+    };
+    // Only transcribed speech is charged.
+    context.charged += transcribe_item.charged;
 
-            const OUTPUT_MARGIN: usize = 10;
-            let ratio = SAMPLE_RATE / sample_rate;
-            self.resampled.resize(
-                (self.merged.len() as f32 * ratio) as usize + OUTPUT_MARGIN,
-                0.0,
-            );
+    // Prompting with the original text is more faithful to the model.
+    let item = TranscribeItem {
+        begin,
+        end,
+        text: context.normalization.apply(&transcribe_item.text),
+        speaker: transcribe_item.speaker,
+    };
+    let max_prompt_size = session.server.config.max_prompt_size;
+    context.prompt = Some(sanitize_prompt(&transcribe_item.text, max_prompt_size));
+    if let Some(node) = transcribe_item.node {
+        session.add_node(node);
+    }
+    let json = serde_json::to_string(&item).unwrap();
+    context.items.push(item);
+    send_to_client(session, client_sender, Message::Text(json + "\n")).await
+}
 
-            let mut merged_offset = 0;
-            let mut resampled_offset = 0;
-
-            while self.merged.len() - merged_offset >= CHUNK_SIZE {
-                let (in_samples, out_samples) = self
-                    .resampler
-                    .as_mut()
-                    .unwrap()
-                    .process_into_buffer(
-                        &[&self.merged[merged_offset..]],
-                        &mut [&mut self.resampled[resampled_offset..]],
-                        None,
-                    )
-                    .unwrap();
+/// Queue of concurrently running transcriptions (up to a given number)
+/// which yields their outcomes in the order they were pushed.
+struct TranscribeQueue<T> {
+    concurrency: usize,
+    futures: FuturesOrdered<BoxFuture<'static, T>>,
+}
 
-                merged_offset += in_samples;
-                resampled_offset += out_samples;
-            }
+impl<T> TranscribeQueue<T> {
+    fn new(concurrency: usize) -> Self {
+        Self {
+            concurrency,
+            futures: FuturesOrdered::new(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.futures.is_empty()
+    }
+
+    fn is_full(&self) -> bool {
+        self.futures.len() >= self.concurrency
+    }
+
+    fn push<F>(&mut self, future: F)
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        self.futures.push_back(Box::pin(future));
+    }
 
-            self.merged.drain(..merged_offset);
-            self.resampled.truncate(resampled_offset);
+    /// Wait for the earliest pushed transcription to finish.
+    async fn next(&mut self) -> Option<T> {
+        self.futures.next().await
+    }
+}
+
+/// Durations of segments received from VAD (to diagnose segmentation).
+#[derive(Default)]
+struct SegmentLengths {
+    speech: Vec<f32>,
+    voids: u32,
+}
+
+impl SegmentLengths {
+    fn push(&mut self, speech: bool, duration: f32) {
+        if speech {
+            self.speech.push(duration);
         } else {
-            self.resampled.clear();
-            swap(&mut self.merged, &mut self.resampled);
+            self.voids += 1;
+        }
+    }
+
+    fn stats(&self) -> SegmentStats {
+        let mut sorted = self.speech.clone();
+        sorted.sort_by(f32::total_cmp);
+        let len = sorted.len();
+        let speech_seconds = sorted.iter().sum();
+        let median_speech_seconds = match len {
+            0 => 0.0,
+            _ if len.is_multiple_of(2) => (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0,
+            _ => sorted[len / 2],
+        };
+        SegmentStats {
+            speech_segments: len as u32,
+            void_segments: self.voids,
+            speech_seconds,
+            mean_speech_seconds: if len == 0 {
+                0.0
+            } else {
+                speech_seconds / len as f32
+            },
+            median_speech_seconds,
+            longest_speech_seconds: sorted.last().copied().unwrap_or_default(),
         }
     }
 }
 
 struct RingBuffer {
     sample_rate: f32,
+    capacity: usize,
     deque: VecDeque<i16>,
     pushed: usize,
 }
@@ -605,6 +1811,7 @@ impl RingBuffer {
     fn with_capacity(sample_rate: f32, capacity: usize) -> Self {
         Self {
             sample_rate,
+            capacity,
             deque: VecDeque::with_capacity(capacity),
             pushed: 0,
         }
@@ -612,39 +1819,748 @@ impl RingBuffer {
 
     #[inline]
     fn push(&mut self, sample: i16) {
-        if self.deque.len() == self.deque.capacity() {
+        if self.deque.len() == self.capacity {
             self.deque.pop_front();
         }
         self.deque.push_back(sample);
         self.pushed += 1;
     }
 
-    fn extract_time_interval_wav(&self, begin: f32, end: f32) -> Vec<u8> {
+    /// Extract a time interval as a WAV blob at a given target sample rate
+    /// trimming its silent edges with a given threshold (if any).
+    /// Returns the extracted interval along with the blob.
+    fn extract_time_interval_wav(
+        &self,
+        begin: f32,
+        end: f32,
+        target_rate: f32,
+        trim_threshold: Option<f32>,
+    ) -> ((f32, f32), Vec<u8>) {
         let frame_offset = self.pushed - self.deque.len();
         let get_index = |time| {
             (((time * self.sample_rate) as usize).max(frame_offset) - frame_offset)
                 .min(self.deque.len() - 1)
         };
 
-        const WAV_HEADER_SIZE: usize = 44;
         let (begin_index, end_index) = (get_index(begin), get_index(end));
-        let capacity = WAV_HEADER_SIZE + (end_index - begin_index) * 2;
-        let mut data = Vec::with_capacity(capacity);
-
-        let spec = WavSpec {
-            channels: 1,
-            sample_rate: self.sample_rate as u32,
-            bits_per_sample: 16,
-            sample_format: SampleFormat::Int,
+        let samples: Vec<_> = self.deque.range(begin_index..end_index).copied().collect();
+        let (interval, samples) =
+            trim_interval(&samples, (begin, end), self.sample_rate, trim_threshold);
+        (
+            interval,
+            encode_speech_wav(samples, self.sample_rate, target_rate),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::{new_test_server, new_test_server_with_args, send_request};
+    use axum::{body::Body, http::StatusCode};
+    use futures::AsyncReadExt;
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+
+    fn new_test_session() -> Session {
+        Session {
+            server: new_test_server(),
+            user: Uuid::nil(),
+            tariff: Tariff {
+                name: "basic".to_owned(),
+                segment_params: SegmentParams::default(),
+                transcribe_sample_rate: SAMPLE_RATE,
+                trim_threshold: None,
+                transcribe_concurrency: 1,
+                capture_audio: false,
+                diarize: false,
+            },
+            query: TranscribeQuery {
+                tariff: None,
+                lang: None,
+                callback_url: None,
+                codec: None,
+                sample_rate: None,
+                channels: None,
+                sample_format: None,
+                diarize: false,
+                punctuation: None,
+                casing: None,
+                vad: None,
+            },
+            codec: Codec::Vorbis,
+            callback_secret: None,
+            terminator: None,
+            terminated: AtomicBool::new(false),
+            cancelled: Notify::new(),
+            close_reason: Mutex::new(None),
+            started_at: OffsetDateTime::UNIX_EPOCH,
+            nodes: Mutex::new(Vec::new()),
+        }
+    }
+
+    #[test]
+    fn test_transcribe_sample_rate() {
+        let capability = |sample_rate| Capability {
+            id: Uuid::nil(),
+            name: "transcribe-cpu".to_owned(),
+            compute_load: 0,
+            memory_load: 0,
+            fee: Decimal::ZERO,
+            languages: None,
+            max_segment_duration: None,
+            segment_window_duration: None,
+            sample_rate,
+            trim_threshold: None,
+            transcribe_concurrency: None,
+            capture_audio: false,
+            strict_placement: false,
+        };
+
+        assert_eq!(transcribe_sample_rate(&[]), SAMPLE_RATE);
+        assert_eq!(transcribe_sample_rate(&[capability(None)]), SAMPLE_RATE);
+        assert_eq!(
+            transcribe_sample_rate(&[
+                capability(Some(24000)),
+                capability(None),
+                capability(Some(8000))
+            ]),
+            8000.0
+        );
+    }
+
+    #[test]
+    fn test_transcribe_concurrency() {
+        let capability = |transcribe_concurrency| Capability {
+            id: Uuid::nil(),
+            name: "transcribe-cpu".to_owned(),
+            compute_load: 0,
+            memory_load: 0,
+            fee: Decimal::ZERO,
+            languages: None,
+            max_segment_duration: None,
+            segment_window_duration: None,
+            sample_rate: None,
+            trim_threshold: None,
+            transcribe_concurrency,
+            capture_audio: false,
+            strict_placement: false,
+        };
+
+        assert_eq!(transcribe_concurrency(&[]), 1);
+        assert_eq!(transcribe_concurrency(&[capability(None)]), 1);
+        assert_eq!(
+            transcribe_concurrency(&[capability(Some(4)), capability(None), capability(Some(2))]),
+            2
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transcribe_queue_order() {
+        let mut queue = TranscribeQueue::new(3);
+        let start = Instant::now();
+        for (i, millis) in [30, 10, 20].into_iter().enumerate() {
+            assert!(!queue.is_full());
+            queue.push(async move {
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+                i
+            });
+        }
+        assert!(queue.is_full());
+
+        // Later pushed transcriptions finish first, but are yielded in order.
+        let mut order = Vec::new();
+        while let Some(i) = queue.next().await {
+            order.push(i);
+        }
+        assert_eq!(order, [0, 1, 2]);
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_segment_lengths_stats() {
+        let mut lengths = SegmentLengths::default();
+        assert_eq!(lengths.stats(), SegmentStats::default());
+
+        lengths.push(true, 3.0);
+        lengths.push(false, 0.5);
+        lengths.push(true, 1.0);
+        lengths.push(true, 2.0);
+        assert_eq!(
+            lengths.stats(),
+            SegmentStats {
+                speech_segments: 3,
+                void_segments: 1,
+                speech_seconds: 6.0,
+                mean_speech_seconds: 2.0,
+                median_speech_seconds: 2.0,
+                longest_speech_seconds: 3.0,
+            }
+        );
+
+        lengths.push(true, 4.0);
+        assert_eq!(lengths.stats().median_speech_seconds, 2.5);
+    }
+
+    #[test]
+    fn test_ring_buffer_extract_time_interval_wav() {
+        let mut ring_buffer = RingBuffer::with_capacity(SAMPLE_RATE, 32000);
+        for i in 0..32000 {
+            ring_buffer.push(i as i16);
+        }
+
+        let read = |wav: Vec<u8>| {
+            let reader = hound::WavReader::new(std::io::Cursor::new(wav)).unwrap();
+            (reader.spec().sample_rate, reader.len() as f32)
+        };
+
+        let (interval, wav) = ring_buffer.extract_time_interval_wav(0.5, 1.5, SAMPLE_RATE, None);
+        assert_eq!(interval, (0.5, 1.5));
+        assert_eq!(read(wav), (16000, 16000.0));
+
+        let (_, wav) = ring_buffer.extract_time_interval_wav(0.5, 1.5, 8000.0, None);
+        let (rate, len) = read(wav);
+        assert_eq!(rate, 8000);
+        assert!((len - 8000.0).abs() <= 10.0);
+    }
+
+    #[test]
+    fn test_ring_buffer_extract_trimmed_interval() {
+        // 0.25s of silence padding on both sides of 0.5s of noise-like speech.
+        let mut ring_buffer = RingBuffer::with_capacity(SAMPLE_RATE, 16000);
+        for i in 0..16000 {
+            let speech = (4000..12000).contains(&i);
+            ring_buffer.push(if speech { (i % 64 * 512) as i16 } else { 0 });
+        }
+
+        let (interval, wav) =
+            ring_buffer.extract_time_interval_wav(0.0, 1.0, SAMPLE_RATE, Some(0.01));
+        assert_eq!(interval, (0.25, 0.75));
+        let reader = hound::WavReader::new(std::io::Cursor::new(wav)).unwrap();
+        assert_eq!(reader.len(), 8000);
+
+        let (interval, _) =
+            ring_buffer.extract_time_interval_wav(0.3, 0.7, SAMPLE_RATE, Some(0.01));
+        assert_eq!(interval, (0.3, 0.7));
+    }
+
+    #[test]
+    fn test_trim_threshold() {
+        let capability = |trim_threshold| Capability {
+            id: Uuid::nil(),
+            name: "transcribe-cpu".to_owned(),
+            compute_load: 0,
+            memory_load: 0,
+            fee: Decimal::ZERO,
+            languages: None,
+            max_segment_duration: None,
+            segment_window_duration: None,
+            sample_rate: None,
+            trim_threshold,
+            transcribe_concurrency: None,
+            capture_audio: false,
+            strict_placement: false,
+        };
+
+        assert_eq!(trim_threshold(&[]), None);
+        assert_eq!(trim_threshold(&[capability(None)]), None);
+        assert_eq!(
+            trim_threshold(&[
+                capability(Some(0.02)),
+                capability(None),
+                capability(Some(0.01))
+            ]),
+            Some(0.01)
+        );
+    }
+
+    #[test]
+    fn test_close_reason_from_infsrv_error() {
+        use axum::http::StatusCode;
+        let reason =
+            |status| CloseReason::from_infsrv_error(&InfsrvError::UnexpectedStatus(status));
+
+        assert_eq!(
+            reason(StatusCode::SERVICE_UNAVAILABLE),
+            CloseReason::InfsrvOverloaded
+        );
+        assert_eq!(
+            reason(StatusCode::SERVICE_UNAVAILABLE).code(),
+            close_code::AGAIN
+        );
+        assert_eq!(
+            reason(StatusCode::TOO_MANY_REQUESTS),
+            CloseReason::InfsrvOverloaded
+        );
+        assert_eq!(
+            reason(StatusCode::UNPROCESSABLE_ENTITY),
+            CloseReason::InfsrvRejectedAudio
+        );
+        assert_eq!(
+            reason(StatusCode::INTERNAL_SERVER_ERROR),
+            CloseReason::InfsrvFailed
+        );
+        assert_eq!(
+            CloseReason::from_infsrv_error(&InfsrvError::UnexpectedResponse),
+            CloseReason::InfsrvFailed
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_transcribed_billing() {
+        let session = new_test_session();
+        let mut context = SpeechContext {
+            normalization: session.query.normalization(),
+            prompt: None,
+            items: Vec::new(),
+            charged: Decimal::ZERO,
+            queue: TranscribeQueue::new(1),
+        };
+        let (mut client_sender, _client_receiver) = channel(16);
+
+        let item = crate::infsrv_pool::TranscribeItem {
+            text: "hello".to_owned(),
+            speaker: None,
+            node: None,
+            charged: Decimal::new(3, 2),
+        };
+        let transcribed = ((1.0, 3.0), Ok(item));
+        assert!(send_transcribed(&session, &mut context, &mut client_sender, transcribed).await);
+
+        // Speech failed to be transcribed is not charged.
+        let transcribed = ((3.0, 5.0), Err(InfsrvError::Internal));
+        assert!(!send_transcribed(&session, &mut context, &mut client_sender, transcribed).await);
+        assert_eq!(context.charged, Decimal::new(3, 2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_send_to_client_slow() {
+        let session = new_test_session();
+        let (mut sender, mut receiver) = channel(1);
+
+        assert!(send_to_client(&session, &mut sender, Message::Text("1".to_owned())).await);
+        assert_eq!(session.close_frame(), None);
+
+        // The receiver has not drained the previous message, so the sending stalls.
+        assert!(!send_to_client(&session, &mut sender, Message::Text("2".to_owned())).await);
+        assert_eq!(session.close_frame().unwrap().code, close_code::AGAIN);
+
+        assert_eq!(receiver.next().await, Some(Message::Text("1".to_owned())));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_create_reader_pong_timeout() {
+        let session = Arc::new(new_test_session());
+        let (_sender, receiver) = channel::<std::result::Result<Message, axum::Error>>(1);
+        let (mut reader, join_handle) =
+            AudioStreamProcessor::create_reader(session.clone(), receiver);
+
+        let start = Instant::now();
+        assert!(reader.read(&mut [0; 16]).await.is_err());
+        join_handle.await.unwrap();
+        assert_eq!(start.elapsed(), session.pong_timeout());
+        assert_eq!(
+            session.close_reason.lock().unwrap().unwrap(),
+            CloseReason::PongTimeout
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_create_reader_idle_timeout() {
+        let session = Arc::new(new_test_session());
+        let (mut sender, receiver) = channel(1);
+        let (mut reader, join_handle) =
+            AudioStreamProcessor::create_reader(session.clone(), receiver);
+
+        // Pongs keep the connection alive, but only audio resets the idle timer.
+        let start = Instant::now();
+        tokio::spawn(async move {
+            for i in 1.. {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                let msg = if i == 3 {
+                    Message::Binary(vec![1, 2, 3])
+                } else {
+                    Message::Pong(Vec::new())
+                };
+                if sender.send(Ok(msg)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut buf = [0; 16];
+        assert_eq!(reader.read(&mut buf).await.unwrap(), 3);
+        assert!(reader.read(&mut buf).await.is_err());
+        join_handle.await.unwrap();
+        assert_eq!(
+            start.elapsed(),
+            Duration::from_secs(30) + session.idle_timeout()
+        );
+        assert_eq!(
+            session.close_reason.lock().unwrap().unwrap(),
+            CloseReason::IdleTimeout
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_client_closed_without_audio() {
+        let session = Arc::new(new_test_session());
+        let (mut client_sender, client_receiver) = channel(1);
+        client_sender.send(Ok(Message::Close(None))).await.unwrap();
+        drop(client_sender);
+        let (infsrv_sender, mut infsrv_receiver) = tokio::sync::mpsc::channel(16);
+        let ring_buffer = Arc::new(Mutex::new(RingBuffer::with_capacity(SAMPLE_RATE, 16)));
+        let (_limit_sender, limit_receiver) = unbounded_channel();
+
+        AudioStreamProcessor::new(true, None)
+            .process(
+                &session,
+                infsrv_sender,
+                client_receiver,
+                ring_buffer,
+                limit_receiver,
+            )
+            .await;
+
+        // Nothing is sent to infsrv, its stream is just closed.
+        assert_eq!(infsrv_receiver.recv().await, None);
+        assert!(!session.terminated.load(Ordering::SeqCst));
+        let frame = session.close_frame().unwrap();
+        assert_eq!(frame.code, close_code::POLICY);
+        assert_eq!(frame.reason, "no audio received");
+    }
+
+    #[tokio::test]
+    async fn test_process_client_cancelled() {
+        let session = Arc::new(new_test_session());
+        let (mut client_sender, client_receiver) = channel(4);
+        let cancel = r#"{"action":"cancel"}"#.to_owned();
+        client_sender
+            .send(Ok(Message::Text("hello".to_owned())))
+            .await
+            .unwrap();
+        client_sender.send(Ok(Message::Text(cancel))).await.unwrap();
+        let (infsrv_sender, mut infsrv_receiver) = tokio::sync::mpsc::channel(16);
+        let ring_buffer = Arc::new(Mutex::new(RingBuffer::with_capacity(SAMPLE_RATE, 16)));
+        let (_limit_sender, limit_receiver) = unbounded_channel();
+
+        // The client is not awaited to close its stream.
+        AudioStreamProcessor::new(true, None)
+            .process(
+                &session,
+                infsrv_sender,
+                client_receiver,
+                ring_buffer,
+                limit_receiver,
+            )
+            .await;
+
+        assert_eq!(infsrv_receiver.recv().await, None);
+        assert!(!session.terminated.load(Ordering::SeqCst));
+        let frame = session.close_frame().unwrap();
+        assert_eq!(frame.code, close_code::NORMAL);
+        assert_eq!(frame.reason, "cancelled by client");
+        drop(client_sender);
+    }
+
+    #[tokio::test]
+    async fn test_process_segments_cancelled() {
+        let session = Arc::new(new_test_session());
+        let (infsrv_sender, infsrv_receiver) = tokio::sync::mpsc::channel(16);
+        let (client_sender, mut client_receiver) = channel(16);
+        let ring_buffer = Arc::new(Mutex::new(RingBuffer::with_capacity(SAMPLE_RATE, 16)));
+        let (limit_sender, _limit_receiver) = unbounded_channel();
+
+        session.cancel();
+        let outcome = process_segments(
+            session.clone(),
+            client_sender,
+            infsrv_receiver,
+            watch::channel(Decimal::ZERO).1,
+            ring_buffer,
+            limit_sender,
+        )
+        .await;
+
+        assert!(!outcome.done);
+        assert_eq!(outcome.transcript, None);
+        assert!(matches!(
+            client_receiver.next().await,
+            Some(Message::Close(Some(CloseFrame {
+                code: close_code::NORMAL,
+                ..
+            })))
+        ));
+        drop(infsrv_sender);
+    }
+
+    #[tokio::test]
+    async fn test_process_segments_overlapping() {
+        let session = Arc::new(new_test_session());
+        let (infsrv_sender, infsrv_receiver) = tokio::sync::mpsc::channel(16);
+        for (begin, end) in [(0.0, 2.5), (2.0, 3.0), (2.5, 2.9)] {
+            infsrv_sender
+                .send(Ok(SegmentItem::Void { begin, end }))
+                .await
+                .unwrap();
+        }
+        drop(infsrv_sender);
+        let (client_sender, _client_receiver) = channel(16);
+        let ring_buffer = Arc::new(Mutex::new(RingBuffer::with_capacity(SAMPLE_RATE, 16)));
+        let (limit_sender, _limit_receiver) = unbounded_channel();
+
+        let outcome = process_segments(
+            session.clone(),
+            client_sender,
+            infsrv_receiver,
+            watch::channel(Decimal::new(3, 3)).1,
+            ring_buffer,
+            limit_sender,
+        )
+        .await;
+        // The overlap is clamped and the segment within delivered ones is ignored.
+        assert_eq!(outcome.total_seconds, 3.0);
+        assert_eq!(outcome.segment_stats.void_segments, 2);
+        // Only the amount charged for segmentation is reported without speech.
+        assert_eq!(outcome.total_cost, Decimal::new(3, 3));
+    }
+
+    #[tokio::test]
+    async fn test_process_segments_too_many() {
+        let session = Arc::new(Session {
+            server: new_test_server_with_args(&["--max-session-segments=3"]),
+            ..new_test_session()
+        });
+        let (infsrv_sender, infsrv_receiver) = tokio::sync::mpsc::channel(16);
+        for i in 0..10 {
+            let (begin, end) = (i as f32, i as f32 + 1.0);
+            infsrv_sender
+                .send(Ok(SegmentItem::Void { begin, end }))
+                .await
+                .unwrap();
+        }
+        let (client_sender, mut client_receiver) = channel(16);
+        let ring_buffer = Arc::new(Mutex::new(RingBuffer::with_capacity(SAMPLE_RATE, 16)));
+        let (limit_sender, mut limit_receiver) = unbounded_channel();
+
+        let outcome = process_segments(
+            session.clone(),
+            client_sender,
+            infsrv_receiver,
+            watch::channel(Decimal::ZERO).1,
+            ring_buffer,
+            limit_sender,
+        )
+        .await;
+        assert!(!outcome.done);
+        assert_eq!(outcome.total_seconds, 3.0);
+        assert_eq!(outcome.transcript, None);
+
+        assert_eq!(
+            session.close_reason.lock().unwrap().unwrap(),
+            CloseReason::TooManySegments
+        );
+        let mut consumed = Vec::new();
+        while let Ok(time) = limit_receiver.try_recv() {
+            consumed.push(time);
+        }
+        assert_eq!(consumed.last(), Some(&3.0));
+        let Some(Message::Close(Some(frame))) = client_receiver.next().await else {
+            panic!("no close frame");
+        };
+        assert_eq!(frame.code, close_code::POLICY);
+    }
+
+    #[test]
+    fn test_decode_ogg_vorbis_malformed() {
+        let error_message = |data: &[u8]| match decode_ogg_vorbis(data, 16384, 60) {
+            Err(Error::BadRequest(message)) => message,
+            _ => panic!("unexpected result"),
         };
-        let mut writer = WavWriter::new(Cursor::new(&mut data), spec).unwrap();
 
-        for i in begin_index..end_index {
-            writer.write_sample(self.deque[i]).unwrap();
+        assert_eq!(error_message(b""), "no audio in file");
+        assert_eq!(error_message(b"not an ogg stream"), "no audio in file");
+
+        let mut data = Vec::new();
+        {
+            let mut writer = PacketWriter::new(&mut data);
+            for i in 0..4u8 {
+                writer
+                    .write_packet(vec![i], 1, PacketWriteEndInfo::EndPage, 0)
+                    .unwrap();
+            }
         }
+        assert_eq!(error_message(&data), "malformed audio file");
+    }
+
+    #[test]
+    fn test_transcribe_item_speaker() {
+        let mut item = TranscribeItem {
+            begin: 1.0,
+            end: 2.5,
+            text: "hello".to_owned(),
+            speaker: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&item).unwrap(),
+            json!({"begin": 1.0, "end": 2.5, "text": "hello"})
+        );
+
+        item.speaker = Some("SPEAKER_00".to_owned());
+        assert_eq!(
+            serde_json::to_value(&item).unwrap()["speaker"],
+            "SPEAKER_00"
+        );
+    }
+
+    #[test]
+    fn test_file_progress() {
+        assert_eq!(file_progress(0.0, 0.0), 0.0);
+        assert_eq!(file_progress(0.5, 0.0), SEGMENTATION_PROGRESS_SHARE / 2.0);
+        assert_eq!(file_progress(1.0, 0.0), SEGMENTATION_PROGRESS_SHARE);
+        assert_eq!(file_progress(1.0, 1.0), 1.0);
+        assert_eq!(file_progress(1.5, 1.1), 1.0);
+        assert!(file_progress(1.0, 0.5) > SEGMENTATION_PROGRESS_SHARE);
+    }
+
+    #[test]
+    fn test_accepts_ndjson() {
+        let headers = |accept: &[&str]| {
+            let mut headers = HeaderMap::new();
+            for value in accept {
+                headers.append(ACCEPT, HeaderValue::from_str(value).unwrap());
+            }
+            headers
+        };
+
+        assert!(!accepts_ndjson(&headers(&[])));
+        assert!(!accepts_ndjson(&headers(&["application/json"])));
+        assert!(accepts_ndjson(&headers(&["application/x-ndjson"])));
+        assert!(accepts_ndjson(&headers(&[
+            "application/json, application/x-ndjson;q=0.9"
+        ])));
+        assert!(accepts_ndjson(&headers(&[
+            "text/plain",
+            "application/x-ndjson"
+        ])));
+    }
+
+    #[test]
+    fn test_resolve_codec() {
+        let vorbis = HeaderValue::from_static("audio/ogg; codecs=vorbis");
+        let opus = HeaderValue::from_static("audio/ogg; codecs=opus");
+        let error_message = |content_type, codec| match resolve_codec(content_type, codec) {
+            Err(Error::BadRequest(message)) => message,
+            _ => panic!("unexpected result"),
+        };
+
+        assert_eq!(resolve_codec(Some(&vorbis), None).unwrap(), Codec::Vorbis);
+        assert_eq!(
+            resolve_codec(None, Some(Codec::Vorbis)).unwrap(),
+            Codec::Vorbis
+        );
+        assert_eq!(
+            resolve_codec(Some(&vorbis), Some(Codec::Vorbis)).unwrap(),
+            Codec::Vorbis
+        );
+
+        assert_eq!(error_message(None, None), "missing content type or codec");
+        assert_eq!(error_message(Some(&opus), None), "unsupported content type");
+        assert!(serde_json::from_str::<Codec>(r#""opus""#).is_err());
+
+        let lpcm = HeaderValue::from_static("audio/lpcm");
+        assert_eq!(resolve_codec(Some(&lpcm), None).unwrap(), Codec::Lpcm);
+        assert_eq!(
+            error_message(Some(&lpcm), Some(Codec::Vorbis)),
+            "content type conflicts with codec"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_check_pcm_format() {
+        let query = |sample_rate, channels, sample_format| {
+            let mut query = new_test_session().query;
+            query.sample_rate = sample_rate;
+            query.channels = channels;
+            query.sample_format = sample_format;
+            query
+        };
+        let error_message = |codec, query| match check_pcm_format(codec, &query) {
+            Err(Error::BadRequest(message)) => message,
+            _ => panic!("unexpected result"),
+        };
+        let s16le = Some(SampleFormat::S16le);
+
+        assert!(check_pcm_format(Codec::Lpcm, &query(Some(16000), Some(1), s16le)).is_ok());
+        assert!(check_pcm_format(Codec::Vorbis, &query(None, None, None)).is_ok());
+
+        assert_eq!(
+            error_message(Codec::Vorbis, query(Some(16000), None, None)),
+            "pcm format is only applicable to lpcm input"
+        );
+        assert_eq!(
+            error_message(Codec::Lpcm, query(Some(16000), Some(1), None)),
+            "lpcm input requires sample rate, channels and sample format"
+        );
+        assert_eq!(
+            error_message(Codec::Lpcm, query(Some(44100), Some(1), s16le)),
+            "unsupported lpcm sample rate (expected 16000)"
+        );
+        assert_eq!(
+            error_message(Codec::Lpcm, query(Some(16000), Some(2), s16le)),
+            "unsupported lpcm channels (expected 1)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transcribe_query_vad() {
+        let parse = |uri: &str| Query::<TranscribeQuery>::try_from_uri(&uri.parse().unwrap());
+
+        assert_eq!(parse("/transcribe").unwrap().vad, None);
+        assert_eq!(
+            parse("/transcribe?vad=high").unwrap().vad,
+            Some(VadSensitivity::High)
+        );
+        assert!(parse("/transcribe?vad=extreme").is_err());
+
+        assert_eq!(
+            new_test_server().config.vad_sensitivity,
+            VadSensitivity::default()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_transcribe_disallowed_origin() {
+        let server = new_test_server_with_args(&["--ws-allowed-origins=https://app.example.com"]);
+        let request = |origin| {
+            axum::http::Request::get("/transcribe")
+                .header("Authorization", "Bearer malformed")
+                .header("Origin", origin)
+                .header("Connection", "upgrade")
+                .header("Upgrade", "websocket")
+                .header("Sec-WebSocket-Version", "13")
+                .header("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (status, json) = send_request(server.clone(), request("https://evil.com")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(json["error"]["code"], "forbidden");
+
+        // An allowed origin passes on to authentication.
+        let (status, _) = send_request(server, request("https://app.example.com")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
 
-        writer.finalize().unwrap();
-        assert_eq!(data.len(), capacity);
-        data
+    #[tokio::test]
+    async fn test_handle_transcribe_post_unauthorized() {
+        let request = axum::http::Request::post("/transcribe")
+            .header("Authorization", "Bearer malformed")
+            .body(Body::empty())
+            .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
     }
 }
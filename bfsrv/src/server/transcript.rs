@@ -0,0 +1,230 @@
+use crate::{
+    data::transcript::Transcript,
+    server::{middleware::Auth, Error, Result, Server},
+    util::signature::{sign, verify},
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Json,
+};
+use axum_extra::extract::WithRejection;
+use serde::Deserialize;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use uuid::Uuid;
+
+/// Transcript GET request query.
+#[derive(Deserialize)]
+pub struct TranscriptQuery {
+    expires: Option<i64>,
+    signature: Option<String>,
+}
+
+/// Handle transcript GET requests.
+/// Transcripts are served to their owners or by a signed URL without auth.
+pub async fn handle_transcript_get(
+    State(server): State<Arc<Server>>,
+    WithRejection(Path(id), _): WithRejection<Path<Uuid>, Error>,
+    WithRejection(Query(query), _): WithRejection<Query<TranscriptQuery>, Error>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    use Error::*;
+    let user = match (query.expires, &query.signature) {
+        (Some(expires), Some(signature)) => {
+            let secret = server.config.transcript_url_secret.as_deref();
+            check_signed_url(secret, id, expires, signature, OffsetDateTime::now_utc())?;
+            None
+        }
+        (None, None) => Some(Auth::create(&server, &headers).await?.user()?),
+        _ => {
+            return Err(BadRequest(
+                "both expires and signature must be specified".to_owned(),
+            ))
+        }
+    };
+
+    let client = server.pg_pool.get().await?;
+    let transcript = Transcript::get(&client, id).await?;
+    let transcript = check_transcript_visible(transcript, user)?;
+
+    Ok(Json(json!({
+        "id": transcript.id,
+        "createdAt": transcript.created_at.format(&Rfc3339).unwrap(),
+        "tariff": transcript.tariff,
+        "items": transcript.items,
+        "totalSeconds": transcript.total_seconds,
+        "totalCost": transcript.total_cost,
+    }))
+    .into_response())
+}
+
+/// Handle transcript URL POST requests.
+pub async fn handle_transcript_url_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Path(id), _): WithRejection<Path<Uuid>, Error>,
+) -> Result<Response> {
+    let user = auth.user()?;
+
+    let Some(secret) = &server.config.transcript_url_secret else {
+        return Err(Error::Forbidden(
+            "signed transcript URLs are disabled".to_owned(),
+        ));
+    };
+
+    let client = server.pg_pool.get().await?;
+    let transcript = Transcript::get(&client, id).await?;
+    check_transcript_visible(transcript, Some(user))?;
+
+    let ttl = Duration::from_secs(server.config.transcript_url_ttl_secs);
+    let expires_at = OffsetDateTime::now_utc() + ttl;
+    let expires = expires_at.unix_timestamp();
+    let signature = sign_transcript_url(secret, id, expires);
+
+    Ok(Json(json!({
+        "url": format!("/transcript/{id}?expires={expires}&signature={signature}"),
+        "expiresAt": expires_at.format(&Rfc3339).unwrap(),
+    }))
+    .into_response())
+}
+
+/// Sign a transcript URL expiring at a given Unix time.
+fn sign_transcript_url(secret: &str, id: Uuid, expires: i64) -> String {
+    sign(secret, format!("{id}:{expires}").as_bytes())
+}
+
+/// Check that a signed transcript URL is not expired or tampered with.
+fn check_signed_url(
+    secret: Option<&str>,
+    id: Uuid,
+    expires: i64,
+    signature: &str,
+    now: OffsetDateTime,
+) -> Result<()> {
+    use Error::*;
+    let Some(secret) = secret else {
+        return Err(Forbidden("signed transcript URLs are disabled".to_owned()));
+    };
+    if !verify(secret, format!("{id}:{expires}").as_bytes(), signature) {
+        return Err(Forbidden("invalid transcript URL signature".to_owned()));
+    }
+    if expires <= now.unix_timestamp() {
+        return Err(Forbidden("transcript URL expired".to_owned()));
+    }
+    Ok(())
+}
+
+/// Check that a found transcript can be seen by a given user (if any).
+/// Transcripts of other users are reported as not found to avoid leaking them.
+fn check_transcript_visible(
+    transcript: Option<Transcript>,
+    user: Option<Uuid>,
+) -> Result<Transcript> {
+    match transcript {
+        Some(transcript) if user.is_none_or(|u| transcript.user == u) => Ok(transcript),
+        _ => Err(Error::TranscriptNotFound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::{new_test_server_with_args, send_request};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use rust_decimal::Decimal;
+
+    #[test]
+    fn test_check_signed_url() {
+        let id = Uuid::new_v4();
+        let now = OffsetDateTime::now_utc();
+        let expires = now.unix_timestamp() + 60;
+        let signature = sign_transcript_url("secret", id, expires);
+        let check = |secret, id, expires, signature: &str, now| {
+            check_signed_url(secret, id, expires, signature, now).map_err(|e| e.to_string())
+        };
+
+        assert!(check(Some("secret"), id, expires, &signature, now).is_ok());
+        assert_eq!(
+            check(
+                Some("secret"),
+                id,
+                expires,
+                &signature,
+                now + Duration::from_secs(60)
+            ),
+            Err("access forbidden (transcript URL expired)".to_owned())
+        );
+        let tampered = "access forbidden (invalid transcript URL signature)".to_owned();
+        assert_eq!(
+            check(Some("secret"), id, expires + 1, &signature, now),
+            Err(tampered.clone())
+        );
+        assert_eq!(
+            check(Some("secret"), Uuid::new_v4(), expires, &signature, now),
+            Err(tampered.clone())
+        );
+        assert_eq!(
+            check(Some("other"), id, expires, &signature, now),
+            Err(tampered)
+        );
+        assert!(check(None, id, expires, &signature, now).is_err());
+    }
+
+    #[test]
+    fn test_check_transcript_visible() {
+        let user = Uuid::new_v4();
+        let transcript = || {
+            Some(Transcript::new(
+                user,
+                "basic".to_owned(),
+                json!([]),
+                1.0,
+                Decimal::ONE,
+            ))
+        };
+
+        assert!(check_transcript_visible(transcript(), Some(user)).is_ok());
+        assert!(check_transcript_visible(transcript(), None).is_ok());
+        assert!(matches!(
+            check_transcript_visible(transcript(), Some(Uuid::new_v4())),
+            Err(Error::TranscriptNotFound)
+        ));
+        assert!(matches!(
+            check_transcript_visible(None, Some(user)),
+            Err(Error::TranscriptNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_router_transcript_signed_url_rejected() {
+        let server = new_test_server_with_args(&["--transcript-url-secret=secret"]);
+        let id = Uuid::new_v4();
+        let get = |expires: i64, signature: Option<String>| {
+            let mut uri = format!("/transcript/{id}?expires={expires}");
+            if let Some(signature) = signature {
+                uri = format!("{uri}&signature={signature}");
+            }
+            Request::get(uri).body(Body::empty()).unwrap()
+        };
+
+        let expires = OffsetDateTime::now_utc().unix_timestamp() + 60;
+        let signature = sign_transcript_url("secret", id, expires);
+        let (status, json) = send_request(server.clone(), get(expires + 1, Some(signature))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(json["error"]["code"], "forbidden");
+
+        let expired = expires - 120;
+        let signature = sign_transcript_url("secret", id, expired);
+        let (status, _) = send_request(server.clone(), get(expired, Some(signature))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = send_request(server, get(expires, None)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{
+    data::session::{Session, SessionCursor},
+    server::{middleware::Auth, Error, Result, Server},
+};
+use axum::{
+    body::Body,
+    extract::{Query, State},
+    http::header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::WithRejection;
+use futures::{stream, StreamExt};
+use serde::Deserialize;
+use std::{sync::Arc, time::Duration};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use uuid::Uuid;
+
+/// Number of sessions fetched from the database per export page.
+const EXPORT_PAGE_LIMIT: i64 = 1000;
+
+/// Header row of usage CSV exports.
+const CSV_HEADER: &str = "id,user,startedAt,endedAt,tariff,totalSeconds,totalCost\n";
+
+/// Usage export GET request query.
+#[derive(Deserialize)]
+pub struct UsageExportQuery {
+    #[serde(with = "time::serde::rfc3339")]
+    from: OffsetDateTime,
+    #[serde(with = "time::serde::rfc3339")]
+    to: OffsetDateTime,
+    format: Option<String>,
+    user: Option<Uuid>,
+    all: Option<bool>,
+}
+
+/// Handle usage export GET requests streaming sessions started within a range.
+/// Users export their own usage, admins can export usage of any user or of all users.
+pub async fn handle_usage_export_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Query(query), _): WithRejection<Query<UsageExportQuery>, Error>,
+) -> Result<Response> {
+    use Error::*;
+    if query.format.as_deref().is_some_and(|f| f != "csv") {
+        return Err(BadRequest("unsupported export format".to_owned()));
+    }
+    let max_span = Duration::from_secs(server.config.max_usage_export_days * 24 * 60 * 60);
+    check_export_range(query.from, query.to, max_span)?;
+
+    let own = auth.user()?;
+    let user = match (query.user, query.all.unwrap_or_default()) {
+        (Some(_), true) => {
+            return Err(BadRequest("both user and all specified".to_owned()));
+        }
+        (None, false) => Some(own),
+        (Some(user), false) if user == own => Some(own),
+        (user, _) => {
+            let client = server.pg_pool.get().await?;
+            auth.admin(&client).await?;
+            user
+        }
+    };
+
+    let (from, to) = (query.from, query.to);
+    let pages = stream::try_unfold(Some(None), move |after| {
+        let server = server.clone();
+        async move {
+            // None state means the previous page was the last one.
+            let Some(after) = after else {
+                return Ok(None);
+            };
+            let client = server.pg_pool.get().await?;
+            let sessions =
+                Session::find_started_between(&client, user, from, to, after, EXPORT_PAGE_LIMIT)
+                    .await?;
+            let next = (sessions.len() as i64 == EXPORT_PAGE_LIMIT)
+                .then(|| sessions.last().map(SessionCursor::of));
+            let chunk: String = sessions.iter().map(get_usage_csv_row).collect();
+            Ok::<_, Error>(Some((chunk, next)))
+        }
+    });
+    let body = stream::once(async { Ok(CSV_HEADER.to_owned()) }).chain(pages);
+
+    let filename = format!("usage-{}-{}.csv", query.from.date(), query.to.date());
+    Ok((
+        [
+            (CONTENT_TYPE, "text/csv".to_owned()),
+            (
+                CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{filename}\""),
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+/// Check that an export range is not empty and does not exceed a given span.
+fn check_export_range(from: OffsetDateTime, to: OffsetDateTime, max_span: Duration) -> Result<()> {
+    use Error::*;
+    if from >= to {
+        return Err(BadRequest("from must precede to".to_owned()));
+    }
+    if to - from > max_span {
+        return Err(BadRequest(format!(
+            "export range exceeds {} days",
+            max_span.as_secs() / (24 * 60 * 60)
+        )));
+    }
+    Ok(())
+}
+
+fn get_usage_csv_row(session: &Session) -> String {
+    format!(
+        "{},{},{},{},{},{},{}\n",
+        session.id,
+        session.user,
+        session.started_at.format(&Rfc3339).unwrap(),
+        session.ended_at.format(&Rfc3339).unwrap(),
+        escape_csv_field(&session.tariff),
+        session.total_seconds,
+        session.total_cost,
+    )
+}
+
+/// Quote a CSV field if it contains separators, quotes or line breaks.
+fn escape_csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::capability::TaskType,
+        server::tests::{new_test_server, send_request},
+    };
+    use axum::http::{Request, StatusCode};
+    use rust_decimal::Decimal;
+
+    fn time(s: &str) -> OffsetDateTime {
+        OffsetDateTime::parse(s, &Rfc3339).unwrap()
+    }
+
+    #[test]
+    fn test_check_export_range() {
+        let day = Duration::from_secs(24 * 60 * 60);
+        let from = time("2024-01-01T00:00:00Z");
+        let check =
+            |to, max_span| check_export_range(from, to, max_span).map_err(|e| e.to_string());
+
+        assert!(check(time("2024-01-31T00:00:00Z"), day * 31).is_ok());
+        assert!(check(time("2024-02-01T00:00:00Z"), day * 31).is_ok());
+        assert_eq!(
+            check(time("2024-02-01T00:00:01Z"), day * 31),
+            Err("bad request (export range exceeds 31 days)".to_owned())
+        );
+        assert_eq!(
+            check(from, day),
+            Err("bad request (from must precede to)".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_get_usage_csv_row() {
+        let session = Session {
+            id: Uuid::nil(),
+            user: Uuid::nil(),
+            tariff: "basic, \"fast\"".to_owned(),
+            task: TaskType::Transcribe,
+            started_at: time("2024-01-01T12:00:00Z"),
+            ended_at: time("2024-01-01T12:01:30Z"),
+            total_seconds: 90.5,
+            total_cost: Decimal::new(123, 2),
+            close_reason: "client closed".to_owned(),
+            nodes: Vec::new(),
+            transcript: None,
+            segment_stats: None,
+        };
+
+        let nil = Uuid::nil();
+        assert_eq!(
+            get_usage_csv_row(&session),
+            format!(
+                "{nil},{nil},2024-01-01T12:00:00Z,2024-01-01T12:01:30Z,\"basic, \"\"fast\"\"\",90.5,1.23\n"
+            )
+        );
+        assert_eq!(escape_csv_field("basic"), "basic");
+    }
+
+    #[tokio::test]
+    async fn test_handle_usage_export_get_unauthorized() {
+        let request =
+            Request::get("/usage/export?from=2024-01-01T00:00:00Z&to=2024-02-01T00:00:00Z")
+                .body(Body::empty())
+                .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+}
@@ -1,17 +1,29 @@
 use crate::{
-    data::{campaign::Campaign, token::Token, user::User},
-    server::{middleware::Auth, Error, Result, Server},
+    currency_format::CurrencyFormat,
+    data::{balance_adjustment::BalanceAdjustment, campaign::Campaign, token::Token, user::User},
+    server::{
+        middleware::{Auth, RealIpAddress},
+        Error, Result, Server, TX_RETRY_POLICY,
+    },
+    util::{fmt::ErrorChainDisplay, http::accept_languages, retry::retry_on_serialization_failure},
 };
 use axum::{
-    extract::State,
+    extract::{Path, Query, State},
+    http::{header::ACCEPT_LANGUAGE, HeaderMap},
     response::{IntoResponse, Response},
     Json,
 };
 use axum_extra::extract::WithRejection;
+use deadpool_postgres::{Client, Transaction};
+use lettre::Address as EmailAddress;
+use log::{info, warn};
+use rust_decimal::Decimal;
 use serde::Deserialize;
 use serde_json::json;
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use time::{format_description::well_known::Rfc3339, Date, OffsetDateTime, Time};
+use tokio_postgres::IsolationLevel;
+use uuid::Uuid;
 
 /// Body payload for POST-request.
 #[derive(Deserialize)]
@@ -20,8 +32,20 @@ pub struct PostRequestPayload {
     promo_code: Option<String>,
 }
 
+/// User GET request query.
+#[derive(Deserialize)]
+pub struct UserQuery {
+    formatted: Option<bool>,
+    display: Option<String>,
+}
+
 /// Handle user GET requests.
-pub async fn handle_user_get(State(server): State<Arc<Server>>, auth: Auth) -> Result<Response> {
+pub async fn handle_user_get(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    headers: HeaderMap,
+    WithRejection(Query(query), _): WithRejection<Query<UserQuery>, Error>,
+) -> Result<Response> {
     let user_id = auth.user()?;
 
     use Error::*;
@@ -30,69 +54,495 @@ pub async fn handle_user_get(State(server): State<Arc<Server>>, auth: Auth) -> R
         return Err(Internal("user not found".to_owned()));
     };
 
+    let mut item = get_user_item(&user);
+    if query.formatted.unwrap_or_default() {
+        let languages = headers
+            .get(ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .map(accept_languages)
+            .unwrap_or_default();
+        let format = CurrencyFormat::new(&server.config.currency, &languages);
+        item["formatted"] = json!({
+            "balance": format.format(user.balance),
+            "creditLimit": format.format(user.credit_limit),
+        });
+    }
+    if let Some(display) = query.display {
+        let currency = display.to_uppercase();
+        let balance = match server
+            .currency_converter
+            .convert_between(&server.config.currency, &currency, user.balance)
+            .await
+        {
+            Ok(balance) => balance,
+            Err(err) => {
+                warn!(
+                    "failed to convert balance to {currency}: {}",
+                    ErrorChainDisplay(&err)
+                );
+                None
+            }
+        };
+        item["display"] = get_display_item(&currency, balance);
+    }
+
+    Ok(Json(json!({ "user": item })).into_response())
+}
+
+/// Balance estimate in a display currency (or a note if it can't be converted).
+fn get_display_item(currency: &str, balance: Option<Decimal>) -> serde_json::Value {
+    match balance {
+        Some(balance) => json!({
+            "currency": currency,
+            "balance": balance,
+            "estimate": true,
+        }),
+        None => json!({
+            "currency": currency,
+            "note": "no exchange rate for display currency",
+        }),
+    }
+}
+
+fn get_user_item(user: &User) -> serde_json::Value {
     let mut json = json!({
-        "user": {
-            "id": user.id,
-            "createdAt": user.created_at.format(&Rfc3339).unwrap(),
-            "email": user.email,
-            "campaign": user.campaign,
-            "balance": user.balance,
-        }
+        "id": user.id,
+        "createdAt": user.created_at.format(&Rfc3339).unwrap(),
+        "email": user.email,
+        "campaign": user.campaign,
+        "balance": user.balance,
+        "callbackSecret": user.callback_secret,
+        "isTrial": user.is_trial,
+        "creditLimit": user.credit_limit,
     });
     if let Some(referrer) = user.referrer {
         json["referrer"] = json!(referrer);
     }
-
-    Ok(Json(json).into_response())
+    json
 }
 
-/// Handle user POST requests.
+/// Handle user POST requests. A trial user who requested the email confirmation
+/// is upgraded to a full one keeping the balance instead of registering a new user.
 pub async fn handle_user_post(
     State(server): State<Arc<Server>>,
     mut auth: Auth,
     WithRejection(Json(payload), _): WithRejection<Json<PostRequestPayload>, Error>,
 ) -> Result<Response> {
     use Error::*;
-    let Some(email) = &auth.token.email else {
+    let Some(email) = auth.token.email.clone() else {
         return Err(Unauthorized("no email confirmed".to_owned()));
     };
 
     let mut client = server.pg_pool.get().await?;
-    if User::get_by_email(&client, email).await?.is_some() {
+    if User::get_by_email(&client, &email).await?.is_some() {
         return Err(EmailAlreadyRegistered);
     }
 
-    let promo_code = payload.promo_code.as_deref().unwrap_or("default");
-    let Some(campaign) = Campaign::find_by_promo_code(&client, promo_code).await? else {
+    let trial = match auth.token.user {
+        Some(id) => User::get(&client, id).await?.filter(|u| u.is_trial),
+        None => None,
+    };
+
+    let campaign = match (&trial, payload.promo_code.as_deref()) {
+        (Some(_), Some(_)) => {
+            return Err(BadRequest(
+                "promo code not applicable to trial users".to_owned(),
+            ));
+        }
+        (Some(_), None) => None,
+        (None, promo_code) => {
+            let promo_code = promo_code.unwrap_or("default");
+            let Some(campaign) = Campaign::find_by_promo_code(&client, promo_code).await? else {
+                return Err(CampaignNotFound);
+            };
+            if !campaign.is_redeemable(OffsetDateTime::now_utc()) {
+                return Err(CampaignExpired);
+            }
+            Some(campaign)
+        }
+    };
+
+    let trial_id = trial.as_ref().map(|t| t.id);
+    let registration = try_register_user(
+        &mut client,
+        &server,
+        &mut auth.token,
+        email.clone(),
+        trial,
+        campaign,
+    )
+    .await;
+
+    let (user_id, token_id, access_token) = match registration {
+        // The registration may have been completed by a concurrent request after
+        // the check above (e.g. a double-clicked confirmation).
+        Err(err @ (EmailAlreadyRegistered | TrialAlreadyUpgraded)) => {
+            let registered = User::get_by_email(&client, &email).await?;
+            let Some(user_id) = concurrently_registered_user(registered.as_ref(), trial_id) else {
+                return Err(err);
+            };
+            let tx = client.build_transaction().start().await?;
+            let (token_id, access_token) =
+                issue_user_token(&tx, &server, &mut auth.token, user_id).await?;
+            tx.commit().await?;
+            info!("issued token for concurrently registered user {user_id}");
+            (user_id, token_id, access_token)
+        }
+        result => result?,
+    };
+
+    Ok(Json(json!({ "id": user_id, "tokenId": token_id, "token": access_token })).into_response())
+}
+
+async fn try_register_user(
+    client: &mut Client,
+    server: &Server,
+    confirmation: &mut Token,
+    email: EmailAddress,
+    trial: Option<User>,
+    campaign: Option<Campaign>,
+) -> Result<(Uuid, Uuid, String)> {
+    let tx = client.build_transaction().start().await?;
+
+    // The email may have been taken concurrently by a different account.
+    use Error::*;
+    let to_registration_error = |err: crate::data::Error| {
+        if err.is_unique_violation() {
+            EmailAlreadyRegistered
+        } else {
+            err.into()
+        }
+    };
+
+    let user_id = if let Some(campaign) = campaign {
+        if !Campaign::redeem(&tx, campaign.id).await? {
+            return Err(CampaignExpired);
+        }
+
+        let mut user = User::new(
+            email,
+            confirmation.user,
+            campaign.id,
+            campaign.initial_balance,
+        );
+        user.insert(&tx).await.map_err(to_registration_error)?;
+        user.id
+    } else {
+        let trial = trial.unwrap();
+        let upgraded = User::upgrade_trial(&tx, trial.id, &email)
+            .await
+            .map_err(to_registration_error)?;
+        if !upgraded {
+            return Err(TrialAlreadyUpgraded);
+        }
+        info!("upgraded trial user {}", trial.id);
+        trial.id
+    };
+
+    let (token_id, access_token) = issue_user_token(&tx, server, confirmation, user_id).await?;
+
+    tx.commit().await?;
+    Ok((user_id, token_id, access_token))
+}
+
+/// Get a user registered with the email by a concurrent request which a failed
+/// registration may be treated as idempotent to. A trial user is upgraded only by
+/// itself, so an account registered by someone else is not picked for it
+/// (leaving the trial one orphaned).
+fn concurrently_registered_user(registered: Option<&User>, trial: Option<Uuid>) -> Option<Uuid> {
+    let registered = registered?;
+    match trial {
+        Some(trial) if trial != registered.id => None,
+        _ => Some(registered.id),
+    }
+}
+
+/// Expire an email confirmation token issuing a never-expiring admin token
+/// for a given user, returns its ID and access token.
+async fn issue_user_token(
+    tx: &Transaction<'_>,
+    server: &Server,
+    confirmation: &mut Token,
+    user_id: Uuid,
+) -> Result<(Uuid, String)> {
+    confirmation.expires_at = OffsetDateTime::now_utc();
+    confirmation.update(tx).await?;
+    server.auth_cache.invalidate(confirmation.id);
+
+    let never = OffsetDateTime::new_utc(Date::MAX, Time::MIDNIGHT);
+    let mut token = Token::new(
+        never,
+        Some("admin".to_owned()),
+        Some(user_id),
+        true,
+        confirmation.ip_address,
+        None,
+    );
+    let key = token.insert(tx, server.config.bcrypt_work_factor).await?;
+    Ok((token.id, Auth::compose_access_token(token.id, key)))
+}
+
+/// Handle user trial POST requests (anonymous registration with a short-lived token).
+pub async fn handle_user_trial_post(
+    State(server): State<Arc<Server>>,
+    RealIpAddress(ip_address): RealIpAddress,
+) -> Result<Response> {
+    let config = &server.config;
+    let now = OffsetDateTime::now_utc();
+
+    use Error::*;
+    let mut client = server.pg_pool.get().await?;
+    if let Some(token) = Token::find_last_with_ip_address(&client, ip_address).await? {
+        if token.created_at > now - Duration::from_secs(config.trial_ip_interval_secs) {
+            return Err(BadRequest("too frequent trial requests".to_owned()));
+        }
+    }
+
+    let Some(campaign) = Campaign::find_by_promo_code(&client, &config.trial_promo_code).await?
+    else {
         return Err(CampaignNotFound);
     };
+    if !campaign.is_redeemable(now) {
+        return Err(CampaignExpired);
+    }
 
     let tx = client.build_transaction().start().await?;
 
-    auth.token.expires_at = OffsetDateTime::now_utc();
-    auth.token.update(&tx).await?;
+    if !Campaign::redeem(&tx, campaign.id).await? {
+        return Err(CampaignExpired);
+    }
 
     let mut user = User::new(
-        email.clone(),
-        auth.token.user,
+        trial_email(Uuid::new_v4()),
+        None,
         campaign.id,
         campaign.initial_balance,
     );
+    user.is_trial = true;
     user.insert(&tx).await?;
 
-    let never = OffsetDateTime::new_utc(Date::MAX, Time::MIDNIGHT);
+    let expires_at = now + Duration::from_secs(config.trial_token_ttl_secs);
     let mut token = Token::new(
-        never,
-        Some("admin".to_owned()),
+        expires_at,
+        Some("trial".to_owned()),
         Some(user.id),
-        true,
-        auth.token.ip_address,
+        false,
+        ip_address,
         None,
     );
-    let key = token.insert(&tx).await?;
+    let key = token.insert(&tx, config.bcrypt_work_factor).await?;
 
     tx.commit().await?;
 
+    info!("registered trial user {}", user.id);
     let access_token = Auth::compose_access_token(token.id, key);
-    Ok(Json(json!({ "id": user.id, "tokenId": token.id, "token": access_token })).into_response())
+    Ok(Json(json!({
+        "id": user.id,
+        "tokenId": token.id,
+        "token": access_token,
+        "expiresAt": expires_at.format(&Rfc3339).unwrap(),
+    }))
+    .into_response())
+}
+
+/// Placeholder email of a trial user (under a reserved TLD, so it is never deliverable).
+fn trial_email(id: Uuid) -> EmailAddress {
+    format!("trial-{}@trial.invalid", id.simple())
+        .parse()
+        .unwrap()
+}
+
+/// Body payload for adjust POST-request.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdjustPostRequestPayload {
+    amount: Decimal,
+    reason: String,
+    allow_negative: Option<bool>,
+}
+
+/// Handle user balance adjustment POST requests.
+pub async fn handle_user_adjust_post(
+    State(server): State<Arc<Server>>,
+    auth: Auth,
+    WithRejection(Path(user_id), _): WithRejection<Path<Uuid>, Error>,
+    WithRejection(Json(payload), _): WithRejection<Json<AdjustPostRequestPayload>, Error>,
+) -> Result<Response> {
+    let mut client = server.pg_pool.get().await?;
+    auth.admin(&client).await?;
+
+    if payload.reason.is_empty() {
+        return Err(Error::BadRequest("empty adjustment reason".to_owned()));
+    }
+
+    let (token, payload) = (auth.token.id, &payload);
+    let (adjustment, balance) =
+        retry_on_serialization_failure(TX_RETRY_POLICY, &mut client, |client| async move {
+            let result = try_adjust_balance_atomically(client, user_id, token, payload).await;
+            (client, result)
+        })
+        .await?;
+
+    Ok(Json(json!({ "id": adjustment.id, "balance": balance })).into_response())
+}
+
+async fn try_adjust_balance_atomically(
+    client: &mut Client,
+    user_id: Uuid,
+    token: Uuid,
+    payload: &AdjustPostRequestPayload,
+) -> Result<(BalanceAdjustment, Decimal)> {
+    let tx = client
+        .build_transaction()
+        .isolation_level(IsolationLevel::Serializable)
+        .start()
+        .await?;
+
+    use Error::*;
+    let Some(mut user) = User::get(&tx, user_id).await? else {
+        return Err(UserNotFound);
+    };
+
+    user.balance += payload.amount;
+    if user.balance < Decimal::ZERO && !payload.allow_negative.unwrap_or_default() {
+        return Err(BadRequest("balance would become negative".to_owned()));
+    }
+    user.update(&tx).await?;
+
+    let mut adjustment =
+        BalanceAdjustment::new(user_id, payload.amount, payload.reason.clone(), token);
+    adjustment.insert(&tx).await?;
+
+    tx.commit().await?;
+
+    info!(
+        "adjusted balance of user {user_id} by {} ({})",
+        payload.amount, payload.reason
+    );
+    Ok((adjustment, user.balance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::{new_test_server, new_test_server_with_args, send_request};
+    use axum::{body::Body, http::StatusCode};
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_get_user_item() {
+        let mut user = User::new(
+            "john.smith@gmail.com".parse().unwrap(),
+            None,
+            Uuid::nil(),
+            Decimal::from_str("1.23").unwrap(),
+        );
+        user.created_at = OffsetDateTime::UNIX_EPOCH;
+
+        let item = get_user_item(&user);
+        assert_eq!(
+            item,
+            json!({
+                "id": Uuid::nil(),
+                "createdAt": "1970-01-01T00:00:00Z",
+                "email": "john.smith@gmail.com",
+                "campaign": Uuid::nil(),
+                "balance": "1.23",
+                "callbackSecret": "",
+                "isTrial": false,
+                "creditLimit": "0",
+            })
+        );
+
+        let referrer = Uuid::new_v4();
+        user.referrer = Some(referrer);
+        let item = get_user_item(&user);
+        assert_eq!(item["referrer"], json!(referrer));
+    }
+
+    #[test]
+    fn test_concurrently_registered_user() {
+        let mut user = User::new(
+            "john.smith@gmail.com".parse().unwrap(),
+            None,
+            Uuid::nil(),
+            Decimal::ZERO,
+        );
+        user.id = Uuid::from_u128(1);
+
+        assert_eq!(concurrently_registered_user(None, None), None);
+        assert_eq!(
+            concurrently_registered_user(None, Some(Uuid::from_u128(1))),
+            None
+        );
+        assert_eq!(
+            concurrently_registered_user(Some(&user), None),
+            Some(user.id)
+        );
+
+        // The same trial user has been upgraded concurrently.
+        assert_eq!(
+            concurrently_registered_user(Some(&user), Some(Uuid::from_u128(1))),
+            Some(user.id)
+        );
+
+        // The email has been taken by another user, so the trial stays as is.
+        assert_eq!(
+            concurrently_registered_user(Some(&user), Some(Uuid::from_u128(2))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_get_display_item() {
+        assert_eq!(
+            get_display_item("EUR", Some(Decimal::from_str("0.92").unwrap())),
+            json!({ "currency": "EUR", "balance": "0.92", "estimate": true })
+        );
+        assert_eq!(
+            get_display_item("XYZ", None),
+            json!({ "currency": "XYZ", "note": "no exchange rate for display currency" })
+        );
+    }
+
+    #[test]
+    fn test_trial_email() {
+        let email = trial_email(Uuid::nil());
+        assert_eq!(
+            email.to_string(),
+            "trial-00000000000000000000000000000000@trial.invalid"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle_user_trial_post_disabled() {
+        let request = || {
+            axum::http::Request::post("/user/trial")
+                .header("X-Real-IP", "127.0.0.1")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (status, json) = send_request(new_test_server(), request()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json["error"]["code"], "handler_not_found");
+
+        // The route exists when enabled (failing here on the lazy database connection).
+        let server = new_test_server_with_args(&["--trial-enabled"]);
+        let (status, _) = send_request(server, request()).await;
+        assert_ne!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_handle_user_get_unauthorized() {
+        let request = axum::http::Request::get("/user")
+            .header("Authorization", "Bearer malformed")
+            .body(Body::empty())
+            .unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
 }
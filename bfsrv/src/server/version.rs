@@ -0,0 +1,53 @@
+use crate::data::migration::MIGRATIONS;
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Crate version.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit hash the binary is built from ("unknown" if not available).
+pub const GIT_COMMIT: &str = env!("BFSRV_GIT_COMMIT");
+
+/// Build timestamp (seconds since Unix epoch).
+pub const BUILD_TIMESTAMP: &str = env!("BFSRV_BUILD_TIMESTAMP");
+
+/// Handle version GET requests.
+pub async fn handle_version_get() -> Response {
+    let built_at = BUILD_TIMESTAMP
+        .parse()
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .map(|t| t.format(&Rfc3339).unwrap());
+
+    Json(json!({
+        "version": VERSION,
+        "gitCommit": GIT_COMMIT,
+        "builtAt": built_at,
+        "schemaVersion": MIGRATIONS.last().map(|m| m.version),
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::server::tests::{new_test_server, send_request};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+
+    #[tokio::test]
+    async fn test_handle_version_get() {
+        let request = Request::get("/version").body(Body::empty()).unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+        assert!(!json["gitCommit"].as_str().unwrap().is_empty());
+        assert!(json["builtAt"].is_string());
+        assert!(json["schemaVersion"].as_i64().unwrap() > 0);
+    }
+}
@@ -0,0 +1,65 @@
+use crate::{
+    data::token::Token,
+    server::{middleware::Auth, Result},
+};
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use time::format_description::well_known::Rfc3339;
+
+/// Handle whoami GET requests (introspection of the authenticated token).
+pub async fn handle_whoami_get(auth: Auth) -> Result<Response> {
+    Ok(Json(json!({ "token": get_token_item(&auth.token) })).into_response())
+}
+
+fn get_token_item(token: &Token) -> serde_json::Value {
+    json!({
+        "id": token.id,
+        "label": token.label,
+        "user": token.user,
+        "isAdmin": token.is_admin,
+        "expiresAt": token.expires_at.format(&Rfc3339).unwrap(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::tests::{new_test_server, send_request};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_get_token_item() {
+        let mut token = Token::new(
+            OffsetDateTime::from_unix_timestamp(1717359656).unwrap(),
+            Some("ci".to_owned()),
+            Some(Uuid::nil()),
+            true,
+            [127, 0, 0, 1].into(),
+            None,
+        );
+        token.hash = "secret".to_owned();
+
+        let item = get_token_item(&token);
+        assert_eq!(item["label"], "ci");
+        assert_eq!(item["user"], Uuid::nil().to_string());
+        assert_eq!(item["isAdmin"], true);
+        assert_eq!(item["expiresAt"], "2024-06-02T20:20:56Z");
+        assert!(item.get("hash").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handle_whoami_get_unauthorized() {
+        let request = Request::get("/whoami").body(Body::empty()).unwrap();
+        let (status, json) = send_request(new_test_server(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(json["error"]["code"], "unauthorized");
+    }
+}
@@ -0,0 +1,169 @@
+use log::{info, warn};
+use std::{collections::HashMap, fmt::Display, hash::Hash, sync::Mutex, time::Duration};
+use tokio::time::Instant;
+
+/// Circuit breaker parameters.
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitBreakerParams {
+    /// Number of consecutive failures which opens a circuit.
+    pub failures: u32,
+    /// Period consecutive failures must fit into.
+    pub window: Duration,
+    /// Period an open circuit stays open before a probe is allowed.
+    pub cooldown: Duration,
+}
+
+#[derive(Debug)]
+struct Circuit {
+    failures: u32,
+    first_failed_at: Instant,
+    /// Time the circuit was opened at (or the last probe was allowed at).
+    opened_at: Option<Instant>,
+}
+
+/// In-memory circuit breaker tracking failures of given targets (e.g. nodes).
+/// An open circuit excludes its target until a cooldown elapses, then it lets
+/// a single probe through (once per cooldown) until a success closes it.
+pub struct CircuitBreaker<K> {
+    params: CircuitBreakerParams,
+    circuits: Mutex<HashMap<K, Circuit>>,
+}
+
+impl<K: Copy + Display + Eq + Hash> CircuitBreaker<K> {
+    /// Create a new CircuitBreaker instance.
+    pub fn new(params: CircuitBreakerParams) -> Self {
+        Self {
+            params,
+            circuits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get targets with open circuits. A target which cooldown has elapsed
+    /// is omitted (to be probed) and its cooldown is restarted.
+    pub fn excluded(&self) -> Vec<K> {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let mut excluded = Vec::new();
+        for (target, circuit) in circuits.iter_mut() {
+            let Some(opened_at) = circuit.opened_at else {
+                continue;
+            };
+            if now.duration_since(opened_at) < self.params.cooldown {
+                excluded.push(*target);
+            } else {
+                info!("probing circuit of {target}");
+                circuit.opened_at = Some(now);
+            }
+        }
+        excluded
+    }
+
+    /// Record a successful request to a target closing its circuit.
+    pub fn succeed(&self, target: K) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.remove(&target) {
+            if circuit.opened_at.is_some() {
+                info!("closed circuit of {target}");
+            }
+        }
+    }
+
+    /// Record a failed request to a target (which keeps an open circuit open).
+    pub fn fail(&self, target: K) {
+        let now = Instant::now();
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits.entry(target).or_insert(Circuit {
+            failures: 0,
+            first_failed_at: now,
+            opened_at: None,
+        });
+
+        if circuit.opened_at.is_some() {
+            circuit.opened_at = Some(now);
+            return;
+        }
+        if now.duration_since(circuit.first_failed_at) > self.params.window {
+            circuit.failures = 0;
+            circuit.first_failed_at = now;
+        }
+        circuit.failures += 1;
+        if circuit.failures >= self.params.failures {
+            warn!(
+                "opened circuit of {target} after {} failures",
+                circuit.failures
+            );
+            circuit.opened_at = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::advance;
+
+    fn new_breaker() -> CircuitBreaker<u32> {
+        CircuitBreaker::new(CircuitBreakerParams {
+            failures: 3,
+            window: Duration::from_secs(10),
+            cooldown: Duration::from_secs(30),
+        })
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_opens() {
+        let breaker = new_breaker();
+        breaker.fail(1);
+        breaker.fail(1);
+        breaker.fail(2);
+        assert!(breaker.excluded().is_empty());
+
+        breaker.fail(1);
+        assert_eq!(breaker.excluded(), [1]);
+
+        breaker.succeed(1);
+        assert!(breaker.excluded().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_window() {
+        let breaker = new_breaker();
+        breaker.fail(1);
+        breaker.fail(1);
+        advance(Duration::from_secs(11)).await;
+        breaker.fail(1);
+        breaker.fail(1);
+        assert!(breaker.excluded().is_empty());
+
+        breaker.succeed(1);
+        breaker.fail(1);
+        breaker.fail(1);
+        assert!(breaker.excluded().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_circuit_breaker_probe() {
+        let breaker = new_breaker();
+        for _ in 0..3 {
+            breaker.fail(1);
+        }
+
+        advance(Duration::from_secs(29)).await;
+        assert_eq!(breaker.excluded(), [1]);
+        advance(Duration::from_secs(1)).await;
+        assert!(breaker.excluded().is_empty());
+        assert_eq!(breaker.excluded(), [1]);
+
+        // A failed probe keeps the circuit open for another cooldown.
+        advance(Duration::from_secs(10)).await;
+        breaker.fail(1);
+        advance(Duration::from_secs(29)).await;
+        assert_eq!(breaker.excluded(), [1]);
+        advance(Duration::from_secs(1)).await;
+        assert!(breaker.excluded().is_empty());
+
+        breaker.succeed(1);
+        assert!(breaker.excluded().is_empty());
+        assert!(breaker.excluded().is_empty());
+    }
+}
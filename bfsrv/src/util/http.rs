@@ -0,0 +1,75 @@
+use reqwest::ClientBuilder;
+use std::time::Duration;
+
+/// Create an HTTP client builder with given connect and total request timeouts.
+pub fn client_builder(connect_timeout: Duration, timeout: Duration) -> ClientBuilder {
+    reqwest::Client::builder()
+        .connect_timeout(connect_timeout)
+        .timeout(timeout)
+}
+
+/// Parse an Accept-Language header value into language tags ordered
+/// by decreasing quality (tags with zero or malformed quality are skipped).
+pub fn accept_languages(value: &str) -> Vec<String> {
+    let mut languages: Vec<(String, f32)> = value
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let tag = parts.next().filter(|t| !t.is_empty())?;
+            let quality = match parts.find_map(|p| p.strip_prefix("q=")) {
+                Some(q) => q.parse().ok().filter(|q| *q > 0.0)?,
+                None => 1.0,
+            };
+            Some((tag.to_owned(), quality))
+        })
+        .collect();
+    // Sorting is stable, so equal qualities keep the header order.
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    languages.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    #[test]
+    fn test_accept_languages() {
+        assert!(accept_languages("").is_empty());
+        assert_eq!(accept_languages("es"), ["es"]);
+        assert_eq!(
+            accept_languages("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.7, *;q=0.5"),
+            ["fr-CH", "fr", "en", "de", "*"]
+        );
+        assert_eq!(
+            accept_languages("en;q=0.5, ru, es;q=0.9"),
+            ["ru", "es", "en"]
+        );
+        assert_eq!(accept_languages("en;q=0, ru;q=x, es"), ["es"]);
+    }
+
+    #[tokio::test]
+    async fn test_client_builder_timeout() {
+        // Accept connections and read requests without ever responding.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0; 1024];
+                let _ = stream.read(&mut buf).await;
+                streams.push(stream);
+            }
+        });
+
+        let client = client_builder(Duration::from_secs(1), Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let err = client
+            .get(format!("http://{address}/slow"))
+            .send()
+            .await
+            .unwrap_err();
+        assert!(err.is_timeout());
+    }
+}
@@ -1 +1,7 @@
+pub mod circuit_breaker;
 pub mod fmt;
+pub mod http;
+pub mod periodic;
+pub mod retry;
+pub mod signature;
+pub mod text;
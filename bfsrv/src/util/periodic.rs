@@ -0,0 +1,117 @@
+use crate::util::{fmt::ErrorChainDisplay, retry::FailureBackoff};
+use log::{debug, error, warn};
+use std::{error::Error, future::Future, time::Duration};
+use tokio::{
+    sync::oneshot::{channel, Sender},
+    time::{interval_at, Instant},
+};
+
+/// Number of consecutive failures after which runs start being skipped.
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+/// Maximum number of runs skipped after a failure.
+const MAX_SKIPPED_RUNS: u32 = 16;
+
+/// Background task which runs an operation periodically until dropped.
+/// Runs of an operation which keeps failing are backed off.
+pub struct PeriodicTask {
+    stop_sender: Option<Sender<()>>,
+}
+
+impl PeriodicTask {
+    /// Spawn a new PeriodicTask which runs an operation every period
+    /// starting at a given instant. The action describes the operation in logs
+    /// (e.g. "poll payments").
+    pub fn spawn<F, Fut, E>(
+        action: &'static str,
+        start: Instant,
+        period: Duration,
+        mut f: F,
+    ) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send,
+        E: Error,
+    {
+        let (stop_sender, mut stop_receiver) = channel::<()>();
+
+        let mut interval = interval_at(start, period);
+        tokio::spawn(async move {
+            let mut backoff = FailureBackoff::new(MAX_CONSECUTIVE_FAILURES, MAX_SKIPPED_RUNS);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        if backoff.skip() {
+                            continue;
+                        }
+                        match f().await {
+                            Ok(()) => backoff.succeed(),
+                            Err(err) => {
+                                error!("failed to {action}: {}", ErrorChainDisplay(&err));
+                                let skips = backoff.fail();
+                                if skips > 0 {
+                                    warn!("backing off for {skips} runs after repeated failures");
+                                }
+                            }
+                        }
+                    },
+                    _ = &mut stop_receiver => {
+                        debug!("stopped periodic task to {action}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Self {
+            stop_sender: Some(stop_sender),
+        }
+    }
+}
+
+impl Drop for PeriodicTask {
+    fn drop(&mut self) {
+        let stop_sender = self.stop_sender.take().unwrap();
+        let _ = stop_sender.send(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicU32, Ordering},
+            Arc,
+        },
+    };
+    use tokio::time::sleep;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_periodic_task() {
+        let period = Duration::from_secs(1);
+        let runs = Arc::new(AtomicU32::new(0));
+
+        let runs_cloned = runs.clone();
+        let task = PeriodicTask::spawn("fail", Instant::now() + period, period, move || {
+            let runs = runs_cloned.clone();
+            async move {
+                runs.fetch_add(1, Ordering::SeqCst);
+                Err::<(), _>(io::Error::other("fake"))
+            }
+        });
+
+        // Nothing runs before the start instant.
+        sleep(period / 2).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+
+        // Five failed runs, then one skipped and one more failed run
+        // followed by two skipped ones.
+        sleep(period * 9).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 6);
+
+        drop(task);
+        sleep(period * 100).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 6);
+    }
+}
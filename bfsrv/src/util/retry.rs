@@ -0,0 +1,236 @@
+use std::{future::Future, time::Duration};
+use tokio::time::interval;
+use tokio_postgres::error::SqlState;
+
+/// Operation retry policy.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts (including the first one).
+    pub attempts: u32,
+    /// Interval between attempts.
+    pub interval: Duration,
+}
+
+impl RetryPolicy {
+    /// Create a new RetryPolicy instance.
+    pub const fn new(attempts: u32, interval: Duration) -> Self {
+        Self { attempts, interval }
+    }
+}
+
+/// Backoff of a periodic task which keeps failing (e.g. when the database is gone).
+/// Once consecutive failures reach a threshold, the task skips a number of runs
+/// which doubles with every further failure up to a given maximum.
+#[derive(Clone, Debug)]
+pub struct FailureBackoff {
+    threshold: u32,
+    max_skips: u32,
+    failures: u32,
+    skips_left: u32,
+}
+
+impl FailureBackoff {
+    /// Create a new FailureBackoff instance.
+    pub const fn new(threshold: u32, max_skips: u32) -> Self {
+        Self {
+            threshold,
+            max_skips,
+            failures: 0,
+            skips_left: 0,
+        }
+    }
+
+    /// Check if the current run should be skipped.
+    pub fn skip(&mut self) -> bool {
+        if self.skips_left == 0 {
+            return false;
+        }
+        self.skips_left -= 1;
+        true
+    }
+
+    /// Record a successful run.
+    pub fn succeed(&mut self) {
+        self.failures = 0;
+        self.skips_left = 0;
+    }
+
+    /// Record a failed run. Returns the number of runs to be skipped.
+    pub fn fail(&mut self) -> u32 {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures < self.threshold {
+            return 0;
+        }
+        let exp = (self.failures - self.threshold).min(31);
+        self.skips_left = (1u32 << exp).min(self.max_skips);
+        self.skips_left
+    }
+}
+
+/// Error which can be caused by a transaction serialization failure.
+pub trait SerializationFailure {
+    /// Check if the error is caused by a serialization failure.
+    fn is_serialization_failure(&self) -> bool;
+}
+
+impl SerializationFailure for tokio_postgres::Error {
+    fn is_serialization_failure(&self) -> bool {
+        self.code() == Some(&SqlState::T_R_SERIALIZATION_FAILURE)
+    }
+}
+
+impl SerializationFailure for crate::data::Error {
+    fn is_serialization_failure(&self) -> bool {
+        matches!(self, Self::Postgres(e) if e.is_serialization_failure())
+    }
+}
+
+/// Run an operation retrying it while it fails due to serialization failures.
+/// A given context (e.g. a mutable database client reference) is passed
+/// to every attempt which has to return it back along with the result.
+pub async fn retry_on_serialization_failure<C, T, E, Fut>(
+    policy: RetryPolicy,
+    ctx: C,
+    f: impl FnMut(C) -> Fut,
+) -> Result<T, E>
+where
+    E: SerializationFailure,
+    Fut: Future<Output = (C, Result<T, E>)>,
+{
+    retry_while(policy, ctx, E::is_serialization_failure, f).await
+}
+
+/// Run an operation retrying it while it fails with errors matching a predicate.
+/// A given context is passed to every attempt which has to return it back.
+pub async fn retry_while<C, T, E, Fut>(
+    policy: RetryPolicy,
+    mut ctx: C,
+    should_retry: impl Fn(&E) -> bool,
+    mut f: impl FnMut(C) -> Fut,
+) -> Result<T, E>
+where
+    Fut: Future<Output = (C, Result<T, E>)>,
+{
+    let mut interval = interval(policy.interval);
+    let mut remains = policy.attempts.max(1);
+
+    loop {
+        interval.tick().await;
+
+        let result;
+        (ctx, result) = f(ctx).await;
+        if !result.as_ref().is_err_and(&should_retry) {
+            break result;
+        }
+
+        remains -= 1;
+        if remains == 0 {
+            break result;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[derive(Clone, Debug, PartialEq)]
+    enum FakeError {
+        Serialization,
+        Other,
+    }
+
+    impl SerializationFailure for FakeError {
+        fn is_serialization_failure(&self) -> bool {
+            *self == Self::Serialization
+        }
+    }
+
+    /// Run a fake operation which fails with given errors and then succeeds.
+    /// Returns the result and the number of attempts.
+    async fn run(policy: RetryPolicy, errors: Vec<FakeError>) -> (Result<u32, FakeError>, u32) {
+        let mut errors = errors.into_iter();
+        let mut calls = 0;
+        let result = retry_on_serialization_failure(policy, &mut calls, |calls| {
+            *calls += 1;
+            let result = match errors.next() {
+                Some(err) => Err(err),
+                None => Ok(*calls),
+            };
+            async move { (calls, result) }
+        })
+        .await;
+        (result, calls)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_on_serialization_failure() {
+        use FakeError::*;
+        let policy = RetryPolicy::new(5, Duration::from_millis(10));
+
+        assert_eq!(run(policy, vec![]).await, (Ok(1), 1));
+        assert_eq!(run(policy, vec![Serialization; 4]).await, (Ok(5), 5));
+        assert_eq!(
+            run(policy, vec![Serialization; 5]).await,
+            (Err(Serialization), 5)
+        );
+        assert_eq!(
+            run(policy, vec![Serialization, Other]).await,
+            (Err(Other), 2)
+        );
+
+        let single = RetryPolicy::new(0, Duration::from_millis(10));
+        assert_eq!(
+            run(single, vec![Serialization]).await,
+            (Err(Serialization), 1)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_interval() {
+        let policy = RetryPolicy::new(4, Duration::from_millis(10));
+        let start = Instant::now();
+        let (result, _) = run(policy, vec![FakeError::Serialization; 3]).await;
+        assert_eq!(result, Ok(4));
+        assert_eq!(start.elapsed(), Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_failure_backoff() {
+        let mut backoff = FailureBackoff::new(3, 4);
+        assert!(!backoff.skip());
+        assert_eq!(backoff.fail(), 0);
+        assert_eq!(backoff.fail(), 0);
+        assert_eq!(backoff.fail(), 1);
+        assert!(backoff.skip());
+        assert!(!backoff.skip());
+        assert_eq!(backoff.fail(), 2);
+        assert_eq!(backoff.fail(), 4);
+        assert_eq!(backoff.fail(), 4);
+        assert!(backoff.skip());
+
+        backoff.succeed();
+        assert!(!backoff.skip());
+        assert_eq!(backoff.fail(), 0);
+
+        let mut backoff = FailureBackoff::new(1, u32::MAX);
+        for _ in 0..40 {
+            backoff.fail();
+        }
+        assert_eq!(backoff.fail(), 1 << 31);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_while() {
+        let policy = RetryPolicy::new(10, Duration::from_millis(1));
+        let result: Result<(), _> = retry_while(
+            policy,
+            0,
+            |e: &u32| *e < 3,
+            |calls| async move { (calls + 1, Err(calls + 1)) },
+        )
+        .await;
+        assert_eq!(result, Err(3));
+    }
+}
@@ -0,0 +1,66 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// Sign a message with a given secret (hex-encoded HMAC-SHA256).
+pub fn sign(secret: &str, message: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(message);
+    let mut signature = String::with_capacity(64);
+    for byte in mac.finalize().into_bytes() {
+        signature.push_str(&format!("{byte:02x}"));
+    }
+    signature
+}
+
+/// Verify a hex-encoded message signature in constant time.
+pub fn verify(secret: &str, message: &[u8], signature: &str) -> bool {
+    let Some(signature) = decode_hex(signature) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(message);
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_verify() {
+        // RFC 4231, test case 2.
+        let signature = "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843";
+        assert_eq!(sign("Jefe", b"what do ya want for nothing?"), signature);
+
+        assert!(verify("Jefe", b"what do ya want for nothing?", signature));
+        assert!(verify(
+            "Jefe",
+            b"what do ya want for nothing?",
+            &signature.to_ascii_uppercase()
+        ));
+        assert!(!verify("Jefe", b"what do ya want for nothing!", signature));
+        assert!(!verify("jefe", b"what do ya want for nothing?", signature));
+        assert!(!verify(
+            "Jefe",
+            b"what do ya want for nothing?",
+            &signature[1..]
+        ));
+        assert!(!verify(
+            "Jefe",
+            b"what do ya want for nothing?",
+            &signature[2..]
+        ));
+        assert!(!verify("Jefe", b"what do ya want for nothing?", "zz"));
+        assert!(!verify("Jefe", b"what do ya want for nothing?", "ё"));
+    }
+}
@@ -0,0 +1,133 @@
+use postgres_types::{FromSql, ToSql};
+use serde::{Deserialize, Serialize};
+use unicode_properties::{GeneralCategory, GeneralCategoryGroup, UnicodeGeneralCategory};
+
+/// Letter case of output text.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ToSql, FromSql)]
+#[postgres(name = "text_casing", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum Casing {
+    /// Keep the case produced by the model.
+    #[default]
+    Original,
+    Lower,
+}
+
+/// Post-processing of transcribed text (which is kept intact by default).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextNormalization {
+    /// Keep punctuation.
+    pub punctuation: bool,
+    pub casing: Casing,
+}
+
+impl Default for TextNormalization {
+    fn default() -> Self {
+        Self {
+            punctuation: true,
+            casing: Casing::Original,
+        }
+    }
+}
+
+impl TextNormalization {
+    /// Normalize a given text. Stripped dashes separate words, other punctuation
+    /// (e.g. apostrophes) is just removed, then whitespace is collapsed.
+    pub fn apply(&self, text: &str) -> String {
+        let text = if self.punctuation {
+            text.to_owned()
+        } else {
+            let stripped: String = text
+                .chars()
+                .filter_map(|c| match c.general_category() {
+                    GeneralCategory::DashPunctuation => Some(' '),
+                    _ if c.general_category_group() == GeneralCategoryGroup::Punctuation => None,
+                    _ => Some(c),
+                })
+                .collect();
+            stripped.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+
+        match self.casing {
+            Casing::Original => text,
+            Casing::Lower => text.to_lowercase(),
+        }
+    }
+}
+
+/// Sanitize a text to prompt transcription with: control characters become
+/// spaces, whitespace is collapsed and only a tail of up to a given size
+/// (in bytes) is kept without splitting characters.
+pub fn sanitize_prompt(text: &str, max_size: usize) -> String {
+    let text: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.len() <= max_size {
+        return text;
+    }
+
+    let mut start = text.len() - max_size;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text[start..].trim_start().to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_normalization() {
+        let normalize = |punctuation, casing, text| {
+            TextNormalization {
+                punctuation,
+                casing,
+            }
+            .apply(text)
+        };
+        use Casing::*;
+
+        let text = " Hello, world! Don't stop.";
+        assert_eq!(TextNormalization::default().apply(text), text);
+        assert_eq!(normalize(true, Lower, text), " hello, world! don't stop.");
+        assert_eq!(normalize(false, Original, text), "Hello world Dont stop");
+        assert_eq!(normalize(false, Lower, text), "hello world dont stop");
+
+        assert_eq!(
+            normalize(false, Original, "well-known — yes"),
+            "well known yes"
+        );
+        assert_eq!(normalize(false, Original, "a - b"), "a b");
+        assert_eq!(
+            normalize(false, Original, "«Привет», — сказал он…"),
+            "Привет сказал он"
+        );
+        assert_eq!(normalize(false, Original, "¿Qué? ¡Sí!"), "Qué Sí");
+        assert_eq!(normalize(false, Original, "你好，世界。"), "你好世界");
+        // Symbols are kept, but percent sign is punctuation in Unicode.
+        assert_eq!(
+            normalize(false, Original, "50% of $10 + €5"),
+            "50 of $10 + €5"
+        );
+        assert_eq!(normalize(true, Lower, "ÀÉÎ ΣΊΣΥΦΟΣ"), "àéî σίσυφος");
+        assert_eq!(normalize(false, Original, "..."), "");
+    }
+
+    #[test]
+    fn test_sanitize_prompt() {
+        assert_eq!(
+            sanitize_prompt("Hello,\tworld!\r\n\u{7}", 64),
+            "Hello, world!"
+        );
+        assert_eq!(sanitize_prompt("one two three", 9), "two three");
+        assert_eq!(sanitize_prompt("one two three", 0), "");
+
+        // "ж" takes 2 bytes, so a cut in its middle skips it.
+        assert_eq!(sanitize_prompt("ab жж", 3), "ж");
+        assert_eq!(sanitize_prompt("ab жж", 4), "жж");
+        assert_eq!(sanitize_prompt("日本語", 5), "語");
+    }
+}
@@ -66,19 +66,23 @@ class SegmentProducer:
         min_speech_duration: float,
         max_segment_duration: float,
         time_epsilon: float,
+        flush_void_duration: float = 0,
     ) -> None:
+        # pylint: disable=too-many-arguments
         assert min_speech_duration <= max_segment_duration
 
         self._window_duration = window_duration
         self._min_speech_duration = min_speech_duration
         self._max_segment_duration = max_segment_duration
         self._time_epsilon = time_epsilon
+        self._flush_void_duration = flush_void_duration
 
         self._trailing_begin = 0
         self._trailing_kind = KIND_VOID
         self._time_offset = 0
 
         self._trailing_segment: Segment | None = None
+        self._trailing_speech_end = 0
 
     def next_window(
         self,
@@ -157,8 +161,19 @@ class SegmentProducer:
     ) -> List[Segment]:
         merged_segments = []
         for segment in segments:
-            if self._trailing_segment is not None:
+            if self._trailing_segment is not None and \
+                    self._is_flushing_void(segment):
+                # finalize short speech at once instead of merging the void
+                speech_end = self._trailing_speech_end
+                self._trailing_segment.end = speech_end
+                merged_segments.append(self._trailing_segment)
+                merged_segments.append(
+                    Segment(KIND_VOID, speech_end, segment.end))
+                self._trailing_segment = None
+            elif self._trailing_segment is not None:
                 self._trailing_segment.end = segment.end
+                if segment.kind == KIND_SPEECH:
+                    self._trailing_speech_end = segment.end
                 if self._trailing_segment.duration() >= \
                         self._min_speech_duration:
                     merged_segments.append(self._trailing_segment)
@@ -166,6 +181,7 @@ class SegmentProducer:
             elif segment.kind == KIND_SPEECH and \
                     segment.duration() < self._min_speech_duration:
                 self._trailing_segment = segment
+                self._trailing_speech_end = segment.end
             else:
                 merged_segments.append(segment)
 
@@ -174,6 +190,27 @@ class SegmentProducer:
 
         return merged_segments
 
+    def _is_flushing_void(self, segment: Segment) -> bool:
+        return self._flush_void_duration > 0 and \
+            segment.kind == KIND_VOID and \
+            segment.end - self._trailing_speech_end >= \
+            self._flush_void_duration
+
+
+def drop_short_intervals(
+    intervals: List[Tuple[float, float]],
+    min_duration: float,
+    window_duration: float,
+) -> List[Tuple[float, float]]:
+    """
+    Drop in-window speech intervals shorter than a given duration (e.g. noise).
+    Intervals touching window edges are kept as they may continue
+    in adjacent windows.
+    """
+    return [(begin, end) for begin, end in intervals
+            if end - begin >= min_duration
+            or begin <= 0 or end >= window_duration]
+
 
 def _append_segment(segments: List[Segment],
                     kind: str, begin: int, end: int) -> None:
@@ -17,7 +17,7 @@ from capability import CapabilitySet
 from server.common import (
     CAPABILITIES_HEADER, TERMINATOR_HEADER, find_request_capability
 )
-from segment import ChunkDivider, SegmentProducer
+from segment import ChunkDivider, SegmentProducer, drop_short_intervals
 import util
 
 _SAMPLE_SIZES = {'i16': 2, 'i32': 4, 'f32': 4}
@@ -32,6 +32,8 @@ class _Context:
     num_channels: int
     sample_rate: float
     sample_type: str
+    window_duration: float
+    min_duration_on: float
     pipeline: Pipeline
     segment_producer: SegmentProducer
 
@@ -64,6 +66,8 @@ class SegmentHandler:  # pylint: disable=too-few-public-methods
         sample_rate: float = Query(..., alias='sr'),
         sample_type: str = Query(..., alias='st'),
         window_duration: float = Query(alias='wd', default=5),
+        min_duration_on: float = Query(alias='mdon', default=0),
+        flush_void_duration: float = Query(alias='fvd', default=0),
         capabilities: str = Header(..., alias=CAPABILITIES_HEADER),
         content_type: str = Header(...),
         terminator: str | None = Header(
@@ -122,6 +126,20 @@ class SegmentHandler:  # pylint: disable=too-few-public-methods
                 '(window duration secs) query parameter')
             return
 
+        if min_duration_on < 0 or min_duration_on > 2:
+            await websocket.close(
+                status.WS_1002_PROTOCOL_ERROR,
+                "malformed or unsupported 'mdon' "
+                '(min speech duration on secs) query parameter')
+            return
+
+        if flush_void_duration < 0 or flush_void_duration > 60:
+            await websocket.close(
+                status.WS_1002_PROTOCOL_ERROR,
+                "malformed or unsupported 'fvd' "
+                '(flush void duration secs) query parameter')
+            return
+
         try:
             capability = find_request_capability(
                 self._pipelines.keys(), capabilities)
@@ -139,8 +157,10 @@ class SegmentHandler:  # pylint: disable=too-few-public-methods
             else bytes(terminator, encoding='ISO-8859-1')
 
         segment_producer = SegmentProducer(
-            window_duration, min_speech_duration, max_segment_duration, 0.1)
+            window_duration, min_speech_duration, max_segment_duration, 0.1,
+            flush_void_duration)
         ctx = _Context(websocket, num_channels, sample_rate, sample_type,
+                       window_duration, min_duration_on,
                        self._pipelines[capability], segment_producer)
 
         window_buffer_len = int(
@@ -170,8 +190,10 @@ class SegmentHandler:  # pylint: disable=too-few-public-methods
         annotation = await loop.run_in_executor(
             self._executor, _annotate_window, ctx, data)
 
-        segments = ctx.segment_producer.next_window(
-            _annotation_intervals(annotation), last)
+        intervals = drop_short_intervals(
+            _annotation_intervals(annotation),
+            ctx.min_duration_on, ctx.window_duration)
+        segments = ctx.segment_producer.next_window(intervals, last)
 
         for segment in segments:
             if segment.end - segment.begin > 0.1:
@@ -5,7 +5,8 @@ from typing import Callable, List, Tuple
 import pytest
 
 from segment import (
-    ChunkDivider, Segment, SegmentProducer, KIND_SPEECH, KIND_VOID
+    ChunkDivider, Segment, SegmentProducer, KIND_SPEECH, KIND_VOID,
+    drop_short_intervals
 )
 
 
@@ -41,6 +42,15 @@ async def test_chunk_divider() -> None:
     assert_consumed()
 
 
+def test_drop_short_intervals() -> None:
+    """Perform drop_short_intervals sanity test."""
+    intervals = [(0, 0.1), (1, 1.2), (2, 3), (4, 4.3), (4.9, 5)]
+    assert drop_short_intervals(intervals, 0, 5) == intervals
+    assert drop_short_intervals(intervals, 0.25, 5) == \
+        [(0, 0.1), (2, 3), (4, 4.3), (4.9, 5)]
+    assert drop_short_intervals(intervals, 2, 5) == [(0, 0.1), (4.9, 5)]
+
+
 def test_segment_producer() -> None:
     """Perform SegmentProducer sanity test."""
     producer = SegmentProducer(100, 5, 150, 2)
@@ -121,3 +131,24 @@ def test_segment_producer_min_speech_duration() -> None:
     segments = producer.next_window([(80, 90)], last=True)  # 200-300
     assert segments == [Segment(KIND_VOID, 200, 280),
                         Segment(KIND_SPEECH, 280, 300)]
+
+
+def test_segment_producer_flush_void_duration() -> None:
+    """Perform SegmentProducer flush_void_duration test."""
+    producer = SegmentProducer(100, 60, 150, 2, 30)
+
+    # a void shorter than 30 is merged into short speech
+    segments = producer.next_window([(0, 10), (20, 30), (75, 99)])  # 0-100
+    assert segments == [Segment(KIND_SPEECH, 0, 30),
+                        Segment(KIND_VOID, 30, 75)]
+
+    segments = producer.next_window([(1, 10), (60, 75)])  # 100-200
+    assert segments == [Segment(KIND_SPEECH, 75, 110),
+                        Segment(KIND_VOID, 110, 160)]
+
+    # a void split across windows flushes short speech once it reaches 30
+    segments = producer.next_window([(20, 30)], last=True)  # 200-300
+    assert segments == [Segment(KIND_SPEECH, 160, 175),
+                        Segment(KIND_VOID, 175, 220),
+                        Segment(KIND_SPEECH, 220, 230),
+                        Segment(KIND_VOID, 230, 300)]
@@ -69,6 +69,11 @@ if [ -z "$SMTP_FROM" ]; then
     echo "no SMTP_FROM env var is set, using $SMTP_FROM"
 fi
 
+if [ -z "$SMTP_FROM_NAME" ]; then
+    SMTP_FROM_NAME=Blobfish
+    echo "no SMTP_FROM_NAME env var is set, using $SMTP_FROM_NAME"
+fi
+
 if [ -z "$SMTP_USERNAME" ]; then
     echo "no SMTP_USERNAME env var is set"
     exit 1
@@ -159,7 +164,6 @@ ssh root@$SSH_ADDRESS DATABASE_NAME=$DATABASE_NAME '
     if [ $? -eq 0 ]; then
         export PGPASSWORD=root
         psql -U root -d postgres -c "CREATE DATABASE $DATABASE_NAME"
-        psql -U root -d $DATABASE_NAME -f blobfish/bfsrv/schema.sql
     fi
 '
 
@@ -185,6 +189,7 @@ After=postgresql.service
 Type=simple
 Environment="CURRENCY=$CURRENCY"
 Environment="DATABASE_URL=$DATABASE_URL"
+Environment="MIGRATE_ON_STARTUP=true"
 Environment="SERVER_ADDRESS=$SERVER_ADDRESS"
 Environment="PAYPAL_CANCEL_URL=$PAYPAL_CANCEL_URL"
 Environment="PAYPAL_CLIENT_ID=$PAYPAL_CLIENT_ID"
@@ -193,6 +198,7 @@ Environment="PAYPAL_SANDBOX=$PAYPAL_SANDBOX"
 Environment="PAYPAL_SECRET_KEY=$PAYPAL_SECRET_KEY"
 Environment="RUST_LOG=$RUST_LOG"
 Environment="SMTP_FROM=$SMTP_FROM"
+Environment="SMTP_FROM_NAME=$SMTP_FROM_NAME"
 Environment="SMTP_USERNAME=$SMTP_USERNAME"
 Environment="SMTP_PASSWORD=$SMTP_PASSWORD"
 Environment="SMTP_RELAY=$SMTP_RELAY"